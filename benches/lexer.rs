@@ -0,0 +1,182 @@
+//! Throughput of the lexer on representative corpora, with a regression gate.
+//!
+//! ```text
+//! cargo bench --bench lexer [-- [--save] [--check] [--tolerance PERCENT] [--baseline PATH] [CATEGORY...]]
+//! ```
+//!
+//! Every corpus is a seed file of `benches/corpus` repeated up to a few MiB,
+//! and its throughput is the best of several timed runs of [`lex`].
+//! `--save` stores the results as the baseline, and `--check` exits with an error
+//! if a category got slower than its baseline by more than the tolerance (10% by default).
+//! Baselines depend on the machine, so they are kept in the target directory.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    hint::black_box,
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
+
+use csussus::{lexer::lex, source::FileId};
+
+/// Categories of code, with the seed of their corpus.
+const CORPORA: &[(&str, &str)] = &[
+    ("operators", include_str!("corpus/operators.sus")),
+    ("strings", include_str!("corpus/strings.sus")),
+    ("idents", include_str!("corpus/idents.sus")),
+    ("sample", include_str!("../Cඞඞ.sus")),
+];
+
+/// Size that every corpus is repeated up to.
+const CORPUS_SIZE: usize = 4 << 20;
+
+/// Minimum number of timed runs, and minimum time spent running them.
+const MIN_RUNS: usize = 5;
+const MIN_DURATION: Duration = Duration::from_secs(1);
+
+struct Args {
+    save: bool,
+    check: bool,
+    /// Allowed slowdown, as a fraction of the baseline
+    tolerance: f64,
+    baseline: PathBuf,
+    categories: Vec<String>,
+}
+
+fn main() {
+    let args = parse_args();
+
+    let baselines = match fs::read_to_string(&args.baseline) {
+        Ok(text) => parse_baselines(&text),
+        Err(_) if !args.check => BTreeMap::new(),
+        Err(e) => exit_with_error(&format!("Cannot read {}: {e}", args.baseline.display())),
+    };
+
+    let mut results = BTreeMap::new();
+    let mut regressions = Vec::new();
+    for &(category, seed) in CORPORA {
+        if !args.categories.is_empty() && !args.categories.iter().any(|c| c == category) {
+            continue;
+        }
+
+        let corpus = seed.repeat(CORPUS_SIZE.div_ceil(seed.len()));
+        let throughput = measure(&corpus);
+        results.insert(category.to_string(), throughput);
+
+        match baselines.get(category) {
+            Some(&baseline) => {
+                let change = (throughput / baseline - 1.0) * 100.0;
+                println!(
+                    "{category:<10} {throughput:>7.3} GB/s  ({change:+.1}% from {baseline:.3})"
+                );
+                if throughput < baseline * (1.0 - args.tolerance) {
+                    regressions.push(category);
+                }
+            }
+            None => println!("{category:<10} {throughput:>7.3} GB/s"),
+        }
+    }
+
+    if args.save {
+        // categories that weren't run keep their previous baseline
+        let mut baselines = baselines;
+        baselines.extend(results);
+        if let Some(dir) = args.baseline.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Err(e) = fs::write(&args.baseline, format_baselines(&baselines)) {
+            exit_with_error(&format!("Cannot write {}: {e}", args.baseline.display()));
+        }
+        println!("Saved baseline to {}", args.baseline.display());
+    }
+
+    if args.check && !regressions.is_empty() {
+        exit_with_error(&format!(
+            "Throughput regressed by more than {}% in: {}",
+            args.tolerance * 100.0,
+            regressions.join(", ")
+        ));
+    }
+}
+
+/// Best throughput of lexing the corpus, in GB/s.
+fn measure(corpus: &str) -> f64 {
+    // warm up the caches and the page tables of the arenas
+    black_box(lex(FileId::default(), black_box(corpus)));
+
+    let mut best = Duration::MAX;
+    let (mut runs, start) = (0, Instant::now());
+    while runs < MIN_RUNS || start.elapsed() < MIN_DURATION {
+        let run_start = Instant::now();
+        let tokens = lex(FileId::default(), black_box(corpus));
+        best = best.min(run_start.elapsed());
+        black_box(tokens);
+        runs += 1;
+    }
+
+    corpus.len() as f64 / best.as_secs_f64() / 1e9
+}
+
+fn parse_args() -> Args {
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target"));
+
+    let mut args = Args {
+        save: false,
+        check: false,
+        tolerance: 0.1,
+        baseline: target_dir.join("lexer-bench-baseline.txt"),
+        categories: Vec::new(),
+    };
+
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            // passed by `cargo bench`
+            "--bench" => {}
+            "--save" => args.save = true,
+            "--check" => args.check = true,
+            "--tolerance" => {
+                let percent = argv.next().and_then(|p| p.parse::<f64>().ok());
+                match percent {
+                    Some(percent) if percent >= 0.0 => args.tolerance = percent / 100.0,
+                    _ => exit_with_error("--tolerance expects a positive percentage"),
+                }
+            }
+            "--baseline" => match argv.next() {
+                Some(path) => args.baseline = PathBuf::from(path),
+                None => exit_with_error("--baseline expects a path"),
+            },
+            _ if CORPORA.iter().any(|&(category, _)| category == arg) => {
+                args.categories.push(arg);
+            }
+            _ => exit_with_error(&format!("Unknown argument: {arg}")),
+        }
+    }
+
+    args
+}
+
+/// Parses baselines stored as lines of `category throughput`.
+fn parse_baselines(text: &str) -> BTreeMap<String, f64> {
+    let lines = text.lines().filter_map(|line| {
+        let (category, throughput) = line.split_once(' ')?;
+        Some((category.to_string(), throughput.trim().parse().ok()?))
+    });
+    lines.collect()
+}
+
+fn format_baselines(baselines: &BTreeMap<String, f64>) -> String {
+    let lines = baselines
+        .iter()
+        .map(|(category, throughput)| format!("{category} {throughput}\n"));
+    lines.collect()
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
@@ -0,0 +1,27 @@
+//! Lexes arbitrary code in every mode, checking the invariants of the tokens.
+
+#![no_main]
+
+use std::str;
+
+use csussus::{
+    lexer::{lex_checked, LexMode, LexOptions},
+    source::FileId,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(code) = str::from_utf8(data) else {
+        return;
+    };
+
+    for mode in [LexMode::Normal, LexMode::PreserveTrivia] {
+        let options = LexOptions {
+            mode,
+            ..LexOptions::default()
+        };
+        if let Err(violations) = lex_checked(FileId::default(), code, options) {
+            panic!("{violations:?}");
+        }
+    }
+});
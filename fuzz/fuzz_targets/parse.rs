@@ -0,0 +1,19 @@
+//! Parses arbitrary code, checking the invariants of the AST and that printing it is a fixpoint.
+
+#![no_main]
+
+use std::str;
+
+use csussus::{lexer::lex, parser::parse_checked, source::FileId};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(code) = str::from_utf8(data) else {
+        return;
+    };
+
+    let tokens = lex(FileId::default(), code);
+    if let Err(violations) = parse_checked(&tokens) {
+        panic!("{violations:?}");
+    }
+});
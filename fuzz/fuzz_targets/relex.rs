@@ -0,0 +1,52 @@
+//! Applies an arbitrary edit to arbitrary code, checking that re-lexing it
+//! gives valid tokens that are the same as lexing the new code from scratch.
+//!
+//! The input is two bytes for the start and length of the replaced range,
+//! then the code and the inserted text separated by a NUL byte.
+
+#![no_main]
+
+use std::str;
+
+use csussus::{
+    lexer::{lex, TextEdit},
+    source::FileId,
+};
+use libfuzzer_sys::fuzz_target;
+
+fn floor_char_boundary(s: &str, mut idx: usize) -> usize {
+    idx = idx.min(s.len());
+    while !s.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&[start, removed], rest)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+    let Ok(rest) = str::from_utf8(rest) else {
+        return;
+    };
+
+    let (code, inserted) = rest.split_once('\0').unwrap_or((rest, ""));
+    let start = floor_char_boundary(code, start as usize);
+    let end = floor_char_boundary(code, start + removed as usize);
+    let new_code = format!("{}{inserted}{}", &code[..start], &code[end..]);
+
+    let mut tokens = lex(FileId::default(), code);
+    let edit = TextEdit {
+        range: start..end,
+        new_len: inserted.len(),
+    };
+    tokens.relex_range(&new_code, edit);
+
+    let violations = tokens.check_invariants();
+    assert!(violations.is_empty(), "{violations:?}");
+
+    let lexed = lex(FileId::default(), &new_code);
+    assert_eq!(tokens.types.as_slice(), lexed.types.as_slice());
+    assert_eq!(tokens.spans.as_slice(), lexed.spans.as_slice());
+    assert_eq!(tokens.line_breaks.as_slice(), lexed.line_breaks.as_slice());
+});
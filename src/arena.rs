@@ -3,10 +3,16 @@
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 mod unix {
     use std::{
-        ffi::{c_int, c_long, c_void},
+        ffi::{c_int, c_long, c_ulong, c_void},
+        fs::File,
+        io,
+        os::{fd::AsRawFd, unix::fs::FileExt},
         ptr,
     };
 
+    #[cfg(target_os = "linux")]
+    use std::fs;
+
     const PROT_NONE: c_int = 0;
     const PROT_READ: c_int = 1;
     const PROT_WRITE: c_int = 2;
@@ -27,6 +33,40 @@ mod unix {
     #[cfg(target_os = "macos")]
     const SC_PAGE_SIZE: c_int = 29;
 
+    #[cfg(target_os = "linux")]
+    const MADV_HUGEPAGE: c_int = 14;
+
+    pub const VIRTUAL_MEMORY: bool = true;
+
+    #[cfg(target_os = "linux")]
+    type RlimT = c_ulong;
+    #[cfg(target_os = "macos")]
+    type RlimT = u64;
+
+    #[cfg(target_os = "linux")]
+    const RLIMIT_AS: c_int = 9;
+    #[cfg(target_os = "macos")]
+    const RLIMIT_AS: c_int = 5;
+
+    #[cfg(target_os = "linux")]
+    const RLIM_INFINITY: RlimT = !0;
+    #[cfg(target_os = "macos")]
+    const RLIM_INFINITY: RlimT = (1 << 63) - 1;
+
+    #[repr(C)]
+    struct RLimit {
+        rlim_cur: RlimT,
+        rlim_max: RlimT,
+    }
+
+    /// Size of transparent huge pages, `None` where they can't be asked for.
+    // PMD size on x86_64 and on aarch64 with 4 KiB pages, the most common setups
+    #[cfg(target_os = "linux")]
+    pub const HUGE_PAGE_SIZE: Option<usize> = Some(2 * 1024 * 1024);
+    // regular memory never uses superpages on macOS
+    #[cfg(target_os = "macos")]
+    pub const HUGE_PAGE_SIZE: Option<usize> = None;
+
     extern "C" {
         pub fn mmap(
             addr: *mut c_void,
@@ -39,8 +79,12 @@ mod unix {
 
         pub fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
         pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        #[cfg(target_os = "linux")]
+        pub fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
 
         pub fn sysconf(name: c_int) -> c_long;
+
+        fn getrlimit(resource: c_int, rlim: *mut RLimit) -> c_int;
     }
 
     pub unsafe fn vm_reserve(size_aligned: usize) -> *mut u8 {
@@ -53,34 +97,139 @@ mod unix {
             0,
         ) as *mut u8;
 
+        // MAP_FAILED
         if reserved as usize == !0 {
-            panic!("vm_reserve: mmap failed");
+            return ptr::null_mut();
         }
 
         reserved
     }
 
+    pub unsafe fn vm_reserve_aligned(size_aligned: usize, align: usize) -> *mut u8 {
+        let reserved = vm_reserve(size_aligned + align);
+        if reserved.is_null() {
+            return reserved;
+        }
+
+        // the unaligned head and tail are given back
+        let aligned = super::ceil_align_ptr(reserved, align);
+        let head = aligned.offset_from(reserved) as usize;
+        if head > 0 {
+            munmap(reserved as _, head);
+        }
+        if align - head > 0 {
+            munmap(aligned.byte_add(size_aligned) as _, align - head);
+        }
+        aligned
+    }
+
     pub unsafe fn vm_release(addr: *mut u8, size_aligned: usize) {
         munmap(addr as _, size_aligned);
     }
 
-    pub unsafe fn vm_commit(addr: *mut u8, size_aligned: usize) {
-        mprotect(addr as _, size_aligned, PROT_READ | PROT_WRITE);
+    pub unsafe fn vm_commit(addr: *mut u8, size_aligned: usize) -> bool {
+        mprotect(addr as _, size_aligned, PROT_READ | PROT_WRITE) == 0
+    }
+
+    /// Asks for the committed pages to be backed by huge pages, which is only a hint.
+    pub unsafe fn vm_advise_huge_pages(addr: *mut u8, size_aligned: usize) {
+        #[cfg(target_os = "linux")]
+        madvise(addr as _, size_aligned, MADV_HUGEPAGE);
     }
 
     pub unsafe fn vm_uncommit(addr: *mut u8, size_aligned: usize) {
-        mprotect(addr as _, size_aligned, PROT_NONE);
+        // mapping the pages again gives their physical memory back, unlike `mprotect`
+        mmap(
+            addr as _,
+            size_aligned,
+            PROT_NONE,
+            MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED,
+            -1,
+            0,
+        );
     }
 
     pub unsafe fn os_page_size() -> usize {
         sysconf(SC_PAGE_SIZE) as usize
     }
+
+    /// Soft limit on the address space of the process (`ulimit -v`), if any.
+    pub fn addr_space_rlimit() -> Option<usize> {
+        let mut rlim = RLimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { getrlimit(RLIMIT_AS, &mut rlim) } != 0 || rlim.rlim_cur == RLIM_INFINITY {
+            return None;
+        }
+        Some(usize::try_from(rlim.rlim_cur).unwrap_or(usize::MAX))
+    }
+
+    /// Whether committing memory fails when there isn't enough of it,
+    /// rather than when it gets touched (`vm.overcommit_memory = 2` on Linux).
+    pub fn strict_overcommit() -> bool {
+        #[cfg(target_os = "linux")]
+        return fs::read_to_string("/proc/sys/vm/overcommit_memory")
+            .is_ok_and(|policy| policy.trim() == "2");
+        #[cfg(target_os = "macos")]
+        return false;
+    }
+
+    /// Maps a whole file read-only, returning null on failure (with the error in `errno`).
+    pub unsafe fn map_file(file: &File, len: usize) -> *mut u8 {
+        let mapped = mmap(
+            ptr::null_mut(),
+            len,
+            PROT_READ,
+            MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        ) as *mut u8;
+
+        // MAP_FAILED
+        if mapped as usize == !0 {
+            return ptr::null_mut();
+        }
+
+        mapped
+    }
+
+    pub unsafe fn unmap_file(addr: *mut u8, len: usize) {
+        munmap(addr as _, len);
+    }
+
+    /// Maps part of a file copy-on-write over reserved address space, as if it was committed.
+    pub unsafe fn vm_map_file_at(
+        addr: *mut u8,
+        size_aligned: usize,
+        file: &File,
+        offset: u64,
+    ) -> bool {
+        let mapped = mmap(
+            addr as _,
+            size_aligned,
+            PROT_READ | PROT_WRITE,
+            MAP_PRIVATE | MAP_FIXED,
+            file.as_raw_fd(),
+            offset as isize,
+        );
+
+        // MAP_FAILED
+        mapped as usize != !0
+    }
+
+    pub fn read_file_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        file.read_exact_at(buf, offset)
+    }
 }
 
 #[cfg(target_family = "windows")]
 mod windows {
     use std::{
         ffi::{c_int, c_void},
+        fs::File,
+        io,
+        os::windows::{fs::FileExt, io::AsRawHandle},
         ptr,
     };
 
@@ -91,8 +240,16 @@ mod windows {
     const MEM_RELEASE: u32 = 0x00008000;
 
     const PAGE_NOACCESS: u32 = 0x01;
+    const PAGE_READONLY: u32 = 0x02;
     const PAGE_READWRITE: u32 = 0x04;
 
+    const FILE_MAP_READ: u32 = 0x0004;
+
+    pub const VIRTUAL_MEMORY: bool = true;
+
+    /// Large pages need a privilege and must be committed upfront, which defeats the purpose.
+    pub const HUGE_PAGE_SIZE: Option<usize> = None;
+
     #[repr(C)]
     #[derive(Clone, Copy)]
     #[allow(non_snake_case)]
@@ -134,24 +291,60 @@ mod windows {
         pub fn VirtualFree(lpAddress: *mut c_void, dwSize: usize, dwFreeType: u32) -> bool;
 
         pub fn GetSystemInfo(lpSystemInfo: &mut SystemInfo);
+
+        pub fn CreateFileMappingW(
+            hFile: *mut c_void,
+            lpFileMappingAttributes: *const c_void,
+            flProtect: u32,
+            dwMaximumSizeHigh: u32,
+            dwMaximumSizeLow: u32,
+            lpName: *const u16,
+        ) -> *mut c_void;
+
+        pub fn MapViewOfFile(
+            hFileMappingObject: *mut c_void,
+            dwDesiredAccess: u32,
+            dwFileOffsetHigh: u32,
+            dwFileOffsetLow: u32,
+            dwNumberOfBytesToMap: usize,
+        ) -> *mut c_void;
+
+        pub fn UnmapViewOfFile(lpBaseAddress: *const c_void) -> bool;
+
+        pub fn CloseHandle(hObject: *mut c_void) -> bool;
     }
 
     pub unsafe fn vm_reserve(size_aligned: usize) -> *mut u8 {
         VirtualAlloc(ptr::null_mut(), size_aligned, MEM_RESERVE, PAGE_NOACCESS) as _
     }
 
-    pub unsafe fn vm_release(addr: *mut u8, size_aligned: usize) {
-        VirtualFree(addr as _, size_aligned, MEM_RELEASE);
+    pub unsafe fn vm_reserve_aligned(size_aligned: usize, align: usize) -> *mut u8 {
+        // part of a reservation can't be released, so the aligned part is reserved on its own
+        let reserved = vm_reserve(size_aligned + align);
+        if reserved.is_null() {
+            return reserved;
+        }
+        vm_release(reserved, 0);
+
+        let aligned = super::ceil_align_ptr(reserved, align);
+        VirtualAlloc(aligned as _, size_aligned, MEM_RESERVE, PAGE_NOACCESS) as _
+    }
+
+    pub unsafe fn vm_release(addr: *mut u8, _size_aligned: usize) {
+        // the whole reservation is released, and its size must be 0 to do so
+        VirtualFree(addr as _, 0, MEM_RELEASE);
     }
 
-    pub unsafe fn vm_commit(addr: *mut u8, size_aligned: usize) {
-        VirtualAlloc(addr as _, size_aligned, MEM_COMMIT, PAGE_READWRITE);
+    pub unsafe fn vm_commit(addr: *mut u8, size_aligned: usize) -> bool {
+        !VirtualAlloc(addr as _, size_aligned, MEM_COMMIT, PAGE_READWRITE).is_null()
     }
 
     pub unsafe fn vm_uncommit(addr: *mut u8, size_aligned: usize) {
         VirtualFree(addr as _, size_aligned, MEM_DECOMMIT);
     }
 
+    pub unsafe fn vm_advise_huge_pages(_addr: *mut u8, _size_aligned: usize) {}
+
     pub unsafe fn os_page_size() -> usize {
         let mut system_info = SystemInfo {
             dummy: DummySystemInfoUnion { dwOemId: 0 },
@@ -169,27 +362,173 @@ mod windows {
         GetSystemInfo(&mut system_info);
         system_info.dwPageSize as usize
     }
+
+    /// Windows has no limit on the address space of a process.
+    pub fn addr_space_rlimit() -> Option<usize> {
+        None
+    }
+
+    /// Windows never overcommits, but reserving doesn't count against the commit limit,
+    /// so there is nothing to report.
+    pub fn strict_overcommit() -> bool {
+        false
+    }
+
+    /// Maps a whole file read-only, returning null on failure (with the error in `GetLastError`).
+    pub unsafe fn map_file(file: &File, len: usize) -> *mut u8 {
+        let mapping = CreateFileMappingW(
+            file.as_raw_handle() as _,
+            ptr::null(),
+            PAGE_READONLY,
+            0,
+            0,
+            ptr::null(),
+        );
+        if mapping.is_null() {
+            return ptr::null_mut();
+        }
+
+        // the view keeps the mapping alive
+        let view = MapViewOfFile(mapping, FILE_MAP_READ, 0, 0, len);
+        CloseHandle(mapping);
+        view as _
+    }
+
+    pub unsafe fn unmap_file(addr: *mut u8, _len: usize) {
+        UnmapViewOfFile(addr as _);
+    }
+
+    /// Views can't be mapped over a reservation without placeholders (Windows 10 and later),
+    /// so files are always read instead.
+    pub unsafe fn vm_map_file_at(
+        _addr: *mut u8,
+        _size_aligned: usize,
+        _file: &File,
+        _offset: u64,
+    ) -> bool {
+        false
+    }
+
+    pub fn read_file_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match file.seek_read(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
 }
 
+/// Targets without virtual memory, like WebAssembly in browsers: address space can never
+/// be reserved, so every vector is a growable buffer on the heap, and files are read
+/// instead of being mapped.
 #[cfg(not(any(target_os = "linux", target_os = "macos", target_family = "windows")))]
-compile_error!("Operating system not supported");
+mod heap_only {
+    use std::{
+        fs::File,
+        io::{self, Read, Seek, SeekFrom},
+        ptr,
+    };
+
+    pub const VIRTUAL_MEMORY: bool = false;
+
+    pub const HUGE_PAGE_SIZE: Option<usize> = None;
+
+    pub unsafe fn vm_reserve(_size_aligned: usize) -> *mut u8 {
+        ptr::null_mut()
+    }
+
+    pub unsafe fn vm_reserve_aligned(_size_aligned: usize, _align: usize) -> *mut u8 {
+        ptr::null_mut()
+    }
+
+    // nothing is ever reserved, so there is nothing to commit nor release either
+    pub unsafe fn vm_release(_addr: *mut u8, _size_aligned: usize) {}
+
+    pub unsafe fn vm_commit(_addr: *mut u8, _size_aligned: usize) -> bool {
+        false
+    }
+
+    pub unsafe fn vm_advise_huge_pages(_addr: *mut u8, _size_aligned: usize) {}
+
+    pub unsafe fn vm_uncommit(_addr: *mut u8, _size_aligned: usize) {}
+
+    /// Size of the pages of WebAssembly memory.
+    pub unsafe fn os_page_size() -> usize {
+        64 * 1024
+    }
+
+    pub fn addr_space_rlimit() -> Option<usize> {
+        None
+    }
+
+    pub fn strict_overcommit() -> bool {
+        false
+    }
+
+    pub unsafe fn map_file(_file: &File, _len: usize) -> *mut u8 {
+        ptr::null_mut()
+    }
+
+    pub unsafe fn unmap_file(_addr: *mut u8, _len: usize) {}
+
+    pub unsafe fn vm_map_file_at(
+        _addr: *mut u8,
+        _size_aligned: usize,
+        _file: &File,
+        _offset: u64,
+    ) -> bool {
+        false
+    }
+
+    pub fn read_file_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    }
+}
 
 use std::{
-    cell::{Cell, OnceCell},
+    alloc::{self, Layout},
+    cell::{Cell, OnceCell, UnsafeCell},
+    collections::HashMap,
+    error::Error,
     fmt::{self, Debug},
+    fs::File,
+    io::{self, Write},
     marker::PhantomData,
     mem,
-    ops::{Index, IndexMut},
-    slice,
-    sync::atomic::{AtomicUsize, Ordering},
+    ops::{Deref, DerefMut, Index, IndexMut},
+    ptr::{self, NonNull},
+    slice::{self, SliceIndex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
 };
 
-#[cfg(target_family = "unix")]
+use crate::memory::ArrayMemory;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 use unix::*;
 
 #[cfg(target_family = "windows")]
 use windows::*;
 
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_family = "windows")))]
+use heap_only::*;
+
+/// Whether arenas reserve address space, rather than every vector being a growable buffer
+/// on the heap. It is never the case on 32-bit targets, whose address space is too small
+/// to reserve much of it, on targets without virtual memory like WebAssembly, nor under Miri.
+pub const RESERVES_ADDR_SPACE: bool =
+    VIRTUAL_MEMORY && cfg!(target_pointer_width = "64") && !cfg!(miri);
+
 fn page_size() -> usize {
     static mut PAGE_SIZE: usize = 0;
 
@@ -207,45 +546,140 @@ const PAGES_PER_COMMIT: usize = 16;
 pub const KIB: usize = 1024;
 pub const MIB: usize = 1024 * KIB;
 pub const GIB: usize = 1024 * MIB;
+#[cfg(target_pointer_width = "64")]
 pub const TIB: usize = 1024 * GIB;
 
+/// Initial address space of vectors that aren't given one, like when collecting an iterator.
+pub const DEFAULT_ADDR_SPACE_SIZE: usize = GIB;
+
+/// Snapshots at least this large are mapped instead of read, see [`ArenaVec::map_snapshot`].
+pub const SNAPSHOT_MAP_THRESHOLD: usize = MIB;
+/// Alignment in files that snapshots can be mapped from on every OS,
+/// which is the allocation granularity on Windows and a multiple of page sizes elsewhere.
+pub const SNAPSHOT_ALIGN: usize = 64 * KIB;
+
+/// The address space of an arena couldn't be reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReserveError {
+    /// Size of the address space that was requested
+    pub size: usize,
+}
+
+impl fmt::Display for ReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cannot reserve {} bytes of address space", self.size)?;
+        match addr_space_rlimit() {
+            Some(rlimit) if RESERVES_ADDR_SPACE => write!(
+                f,
+                " (limited to {rlimit} bytes, raise the limit with `ulimit -v`)"
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Error for ReserveError {}
+
 pub struct Arena {
     base_addr: *mut u8,
     end_addr: *mut u8,
     page_size: usize,
+    /// Whether committed memory is backed by transparent huge pages
+    huge_pages: bool,
     uncommitted_addr: Cell<*mut u8>,
     bump_addr: Cell<*mut u8>,
 }
 
+// the arena is the only owner of its memory
+unsafe impl Send for Arena {}
+
 impl Arena {
+    /// Reserves the address space of the arena, panicking if it can't.
     pub fn new(addr_space_size: usize) -> Self {
+        match Self::try_new(addr_space_size) {
+            Ok(arena) => arena,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Reserves the address space of the arena, which fails with strict overcommit settings
+    /// or address space limits, and always fails on some targets (see [`RESERVES_ADDR_SPACE`]).
+    pub fn try_new(addr_space_size: usize) -> Result<Self, ReserveError> {
+        Self::try_new_with(addr_space_size, false)
+    }
+
+    /// Reserves the address space of an arena whose memory is backed by transparent
+    /// huge pages, which means fewer TLB misses when going through a lot of it.
+    ///
+    /// Memory is then committed by whole huge pages, so small arenas take more of it.
+    /// Huge pages are only a hint, that is only given on Linux.
+    pub fn try_with_huge_pages(addr_space_size: usize) -> Result<Self, ReserveError> {
+        Self::try_new_with(addr_space_size, true)
+    }
+
+    fn try_new_with(addr_space_size: usize, huge_pages: bool) -> Result<Self, ReserveError> {
         unsafe {
-            let page_size = page_size();
-            let addr_space_size = ceil_align(addr_space_size, page_size);
+            let error = ReserveError {
+                size: addr_space_size,
+            };
+
+            // vectors fall back to the heap instead
+            if !RESERVES_ADDR_SPACE {
+                return Err(error);
+            }
+
+            let huge_page_size = HUGE_PAGE_SIZE.filter(|_| huge_pages);
+            let align = huge_page_size.unwrap_or(0).max(page_size());
+            if addr_space_size > isize::MAX as usize - 2 * align {
+                return Err(error);
+            }
+            let addr_space_size = ceil_align(addr_space_size, align);
+
+            let base_addr = match huge_page_size {
+                // only aligned huge pages can back the memory
+                Some(huge_page_size) => vm_reserve_aligned(addr_space_size, huge_page_size),
+                None => vm_reserve(addr_space_size),
+            };
+            if base_addr.is_null() {
+                return Err(error);
+            }
 
-            let base_addr = vm_reserve(addr_space_size);
             let end_addr = base_addr.byte_add(addr_space_size);
             let uncommitted_addr = Cell::new(base_addr);
             let bump_addr = Cell::new(base_addr);
 
-            Arena {
+            Ok(Arena {
                 base_addr,
                 end_addr,
-                page_size,
+                page_size: page_size(),
+                huge_pages: huge_page_size.is_some(),
                 uncommitted_addr,
                 bump_addr,
-            }
+            })
         }
     }
 
     #[inline]
     fn alloc_granularity(&self) -> usize {
-        unsafe { self.page_size * PAGES_PER_COMMIT }
+        match HUGE_PAGE_SIZE {
+            Some(huge_page_size) if self.huge_pages => huge_page_size,
+            _ => self.page_size * PAGES_PER_COMMIT,
+        }
+    }
+
+    /// Whether the memory of the arena is backed by huge pages, see [`Arena::try_with_huge_pages`].
+    #[inline]
+    pub fn uses_huge_pages(&self) -> bool {
+        self.huge_pages
     }
 
+    /// Allocates a value in the arena.
+    ///
+    /// Values are never dropped, only their memory is freed along with the arena,
+    /// hence the `Copy` bound. [`ArenaVec`] keeps track of its elements to drop them.
     #[inline]
     #[allow(clippy::mut_from_ref)]
-    pub fn alloc<T>(&self, value: T) -> &mut T {
+    pub fn alloc<T: Copy>(&self, value: T) -> &mut T {
         unsafe {
             let ptr = self.alloc_region(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
             ptr.write(value);
@@ -253,9 +687,10 @@ impl Arena {
         }
     }
 
+    /// Allocates a slice in the arena, with the same guarantees as [`Arena::alloc`].
     #[inline]
     #[allow(clippy::mut_from_ref)]
-    pub fn alloc_slice<T>(&self, size: usize) -> &mut [T] {
+    pub fn alloc_slice<T: Copy>(&self, size: usize) -> &mut [T] {
         unsafe {
             let ptr = self.alloc_region(size * mem::size_of::<T>(), mem::align_of::<T>());
             std::slice::from_raw_parts_mut(ptr as *mut T, size)
@@ -264,38 +699,136 @@ impl Arena {
 
     unsafe fn alloc_region(&self, size: usize, align: usize) -> *mut u8 {
         let addr = ceil_align_ptr(self.bump_addr.get(), align);
+        if addr > self.end_addr || size > self.end_addr.offset_from(addr) as usize {
+            panic!("Arena is out of memory");
+        }
         let next_bump_addr = addr.byte_add(size);
+        self.commit_to(next_bump_addr);
+        self.bump_addr.set(next_bump_addr);
 
-        // if next_bump_addr > self.end_addr {
-        //     panic!("Arena is out of memory");
-        // }
+        addr
+    }
 
-        // commit pages we don't have yet
-        if next_bump_addr >= self.uncommitted_addr.get() {
-            let alloc_granularity = self.alloc_granularity();
-            let uncommit_end_addr = ceil_align_ptr(next_bump_addr, alloc_granularity);
-            let commit_size = uncommit_end_addr.offset_from(self.uncommitted_addr.get()) as usize;
-            vm_commit(self.uncommitted_addr.get(), commit_size);
-            self.uncommitted_addr.set(uncommit_end_addr);
+    /// Commits the pages up to `end_addr` that we don't have yet,
+    /// without going past the reserved address space.
+    #[inline]
+    unsafe fn commit_to(&self, end_addr: *mut u8) {
+        if end_addr < self.uncommitted_addr.get() {
+            return;
         }
 
-        self.bump_addr.set(next_bump_addr);
+        let alloc_granularity = self.alloc_granularity();
+        let uncommit_end_addr = ceil_align_ptr(end_addr, alloc_granularity).min(self.end_addr);
+        let commit_size = uncommit_end_addr.offset_from(self.uncommitted_addr.get()) as usize;
+        if commit_size > 0 && !vm_commit(self.uncommitted_addr.get(), commit_size) {
+            commit_failed("arena");
+        }
+        // uncommitted pages lose the hint, so it is given again every time
+        if self.huge_pages && commit_size > 0 {
+            vm_advise_huge_pages(self.uncommitted_addr.get(), commit_size);
+        }
+        self.uncommitted_addr.set(uncommit_end_addr);
+    }
 
-        addr
+    /// Allocates `size` bytes at the start of the empty arena, filled with the bytes of a file
+    /// from `offset`. If `map` is true, they are mapped copy-on-write instead of being read
+    /// when large enough and from an offset aligned to pages.
+    ///
+    /// # Safety
+    ///
+    /// If `map` is true, the file must not be written to nor truncated while the arena
+    /// is around, as pages that weren't written to keep showing the file.
+    unsafe fn alloc_from_file(
+        &self,
+        file: &File,
+        offset: u64,
+        size: usize,
+        map: bool,
+    ) -> io::Result<*mut u8> {
+        assert_eq!(self.used_bytes(), 0, "Arena is not empty");
+        if size > self.reserved_bytes() {
+            return Err(io::ErrorKind::OutOfMemory.into());
+        }
+
+        unsafe {
+            let size_aligned = ceil_align(size, self.page_size);
+            let can_map = map
+                && size >= SNAPSHOT_MAP_THRESHOLD
+                && offset.is_multiple_of(self.page_size as u64);
+            if can_map && vm_map_file_at(self.base_addr, size_aligned, file, offset) {
+                self.uncommitted_addr
+                    .set(self.base_addr.byte_add(size_aligned));
+                self.bump_addr.set(self.base_addr.byte_add(size));
+                return Ok(self.base_addr);
+            }
+
+            let ptr = self.alloc_region(size, 1);
+            read_file_at(file, slice::from_raw_parts_mut(ptr, size), offset)?;
+            Ok(ptr)
+        }
+    }
+
+    /// Size of the reserved address space, which is as much as can be allocated.
+    #[inline]
+    pub fn reserved_bytes(&self) -> usize {
+        unsafe { self.end_addr.offset_from(self.base_addr) as usize }
+    }
+
+    /// Number of bytes that can still be allocated.
+    #[inline]
+    pub fn remaining_bytes(&self) -> usize {
+        unsafe { self.end_addr.offset_from(self.bump_addr.get()) as usize }
+    }
+
+    /// Number of bytes allocated so far.
+    #[inline]
+    pub fn used_bytes(&self) -> usize {
+        unsafe { self.bump_addr.get().offset_from(self.base_addr) as usize }
+    }
+
+    /// Number of bytes of physical memory committed so far, always at least [`Arena::used_bytes`].
+    #[inline]
+    pub fn committed_bytes(&self) -> usize {
+        unsafe { self.uncommitted_addr.get().offset_from(self.base_addr) as usize }
+    }
+
+    /// Gives the committed pages past the last allocation back to the OS,
+    /// once an arena is known to be done growing.
+    ///
+    /// With huge pages, only whole huge pages are given back so that they aren't split.
+    pub fn shrink_to_fit(&mut self) {
+        let granularity = match HUGE_PAGE_SIZE {
+            Some(huge_page_size) if self.huge_pages => huge_page_size,
+            _ => self.page_size,
+        };
+
+        unsafe {
+            let shrunk_addr = ceil_align_ptr(self.bump_addr.get(), granularity).min(self.end_addr);
+            if shrunk_addr < self.uncommitted_addr.get() {
+                let uncommit_size = self.uncommitted_addr.get().offset_from(shrunk_addr) as usize;
+                vm_uncommit(shrunk_addr, uncommit_size);
+                self.uncommitted_addr.set(shrunk_addr);
+            }
+        }
     }
 
+    /// Frees everything at once, giving the committed memory back to the OS.
     pub fn free_all(&mut self) {
         unsafe {
-            let uncommitted_addr = ceil_align_ptr(self.bump_addr.get(), self.alloc_granularity());
-            let uncommit_size = uncommitted_addr.offset_from(self.base_addr) as usize;
-            vm_uncommit(self.base_addr, uncommit_size);
+            // pages can stay committed past the bump address after shrinking
+            let uncommit_size = self.committed_bytes();
+            if uncommit_size > 0 {
+                vm_uncommit(self.base_addr, uncommit_size);
+            }
         }
 
+        self.uncommitted_addr.set(self.base_addr);
         self.bump_addr.set(self.base_addr);
     }
 }
 
 impl Drop for Arena {
+    /// Releases the whole reserved address space.
     fn drop(&mut self) {
         unsafe {
             vm_release(
@@ -306,6 +839,18 @@ impl Drop for Arena {
     }
 }
 
+/// Panics because memory couldn't be committed, saying why if overcommit is to blame.
+#[cold]
+fn commit_failed(what: &str) -> ! {
+    match strict_overcommit() {
+        true => panic!(
+            "Cannot commit memory for the {what}, as overcommit is disabled \
+             (`vm.overcommit_memory = 2`)"
+        ),
+        false => panic!("Cannot commit memory for the {what}"),
+    }
+}
+
 #[inline]
 unsafe fn ceil_align_ptr<T>(ptr: *mut T, to: usize) -> *mut T {
     ceil_align(ptr as usize, to) as *mut T
@@ -317,109 +862,2276 @@ fn ceil_align(value: usize, to: usize) -> usize {
     (value as isize + (-(value as isize) & (to as isize - 1))) as usize
 }
 
-// vector
+// limits
+
+/// How much address space the process can reserve, see [`probe_limits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Limits {
+    /// Soft limit on the address space of the process (`ulimit -v`), if any
+    pub addr_space_rlimit: Option<usize>,
+    /// Whether committing memory fails when there isn't enough of it (Linux with
+    /// `vm.overcommit_memory = 2`), which arenas can run into when they grow
+    pub strict_overcommit: bool,
+    /// Largest reservation that could be made when probing, `0` where arenas can't be reserved
+    pub max_reservation: usize,
+}
 
-/// A very rudimentary dynamic array backed by an arena.
-pub struct ArenaVec<T> {
-    arena: Arena,
-    _data: PhantomData<T>,
+/// Largest reservation tried when probing, more than fits in the address space of most CPUs.
+#[cfg(target_pointer_width = "64")]
+const PROBE_MAX_RESERVATION: usize = 64 * TIB;
+#[cfg(target_pointer_width = "32")]
+const PROBE_MAX_RESERVATION: usize = 2 * GIB;
+
+/// Probes how much address space can actually be reserved, with the limits that apply.
+///
+/// This is done once, the first time it is called (ideally at startup, before the address
+/// space fills up), by reserving as much as possible and releasing it right away.
+/// [`Reservation::FromCodeLen`](crate::lexer::Reservation::FromCodeLen) uses it to keep
+/// token arrays within the limits, so that they don't fall back to the heap early.
+pub fn probe_limits() -> Limits {
+    static LIMITS: OnceLock<Limits> = OnceLock::new();
+    *LIMITS.get_or_init(|| Limits {
+        addr_space_rlimit: addr_space_rlimit(),
+        strict_overcommit: strict_overcommit(),
+        max_reservation: probe_max_reservation(),
+    })
 }
 
-impl<T> ArenaVec<T> {
-    pub fn new(addr_space_size: usize) -> Self {
-        Self {
-            arena: Arena::new(addr_space_size),
-            _data: PhantomData,
-        }
+/// Searches for the largest reservation that can be made, within 1/64th of it.
+fn probe_max_reservation() -> usize {
+    if !RESERVES_ADDR_SPACE {
+        return 0;
     }
 
-    pub fn add(&self, value: T) {
-        self.arena.alloc(value);
-    }
+    let can_reserve = |size: usize| unsafe {
+        let addr = vm_reserve(size);
+        if !addr.is_null() {
+            vm_release(addr, size);
+        }
+        !addr.is_null()
+    };
 
-    pub fn get(&self, idx: usize) -> Option<&T> {
-        if idx >= self.len() {
-            None
-        } else {
-            unsafe {
-                let ptr = self.arena.base_addr.byte_add(idx * mem::size_of::<T>());
-                Some(&*(ptr as *const T))
-            }
+    let mut size = PROBE_MAX_RESERVATION;
+    while !can_reserve(size) {
+        size /= 2;
+        if size < page_size() {
+            return 0;
         }
     }
 
-    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
-        if idx >= self.len() {
-            None
-        } else {
-            unsafe {
-                let ptr = self.arena.base_addr.byte_add(idx * mem::size_of::<T>());
-                Some(&mut *(ptr as *mut T))
-            }
+    // the limit is between `size` and twice as much
+    let mut step = size / 2;
+    while step >= (size / 64).max(page_size()) && size < PROBE_MAX_RESERVATION {
+        if can_reserve(size + step) {
+            size += step;
         }
+        step /= 2;
     }
+    size
+}
 
-    pub fn as_slice(&self) -> &[T] {
-        unsafe { &*slice::from_raw_parts_mut(self.arena.base_addr as _, self.len()) }
+/// A reservation is larger than what the process can reserve, see [`Limits::check_reservation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LimitError {
+    /// Size of the reservation that was checked
+    pub size: usize,
+    pub limits: Limits,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (size, limits) = (self.size, &self.limits);
+        write!(f, "Cannot reserve {size} bytes of address space")?;
+        if !RESERVES_ADDR_SPACE {
+            return write!(f, " on this target");
+        }
+
+        write!(f, ", at most {} bytes can be", limits.max_reservation)?;
+        match limits.addr_space_rlimit {
+            Some(rlimit) => write!(
+                f,
+                " (limited to {rlimit} bytes, raise the limit with `ulimit -v`)"
+            ),
+            None => Ok(()),
+        }
     }
+}
 
-    pub fn as_mut_slice(&mut self) -> &mut [T] {
-        unsafe { &mut *slice::from_raw_parts_mut(self.arena.base_addr as _, self.len()) }
+impl Error for LimitError {}
+
+impl Limits {
+    /// Checks that `size` bytes of address space can be reserved, with an error
+    /// that says which limit to raise otherwise.
+    pub fn check_reservation(&self, size: usize) -> Result<(), LimitError> {
+        match size <= self.max_reservation {
+            true => Ok(()),
+            false => Err(LimitError {
+                size,
+                limits: *self,
+            }),
+        }
     }
+}
 
-    pub fn iter(&self) -> impl Iterator<Item = &'_ T> {
-        self.as_slice().iter()
+// file mappings
+
+/// A whole file mapped read-only in memory, whose pages are only read from disk when accessed.
+///
+/// The OS doesn't prevent the file from being modified while it is mapped, which is why
+/// [`FileMapping::new`] is unsafe: other processes writing to it change the mapped bytes.
+pub struct FileMapping {
+    /// Dangling for empty files, which can't be mapped
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// the mapping is read-only
+unsafe impl Send for FileMapping {}
+unsafe impl Sync for FileMapping {}
+
+impl FileMapping {
+    /// Maps the file, which fails with [`io::ErrorKind::Unsupported`] on targets
+    /// without virtual memory.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to nor truncated while it is mapped, by this process
+    /// or any other: the bytes would change behind shared references to them,
+    /// and accessing the pages past the end of the truncated file crashes.
+    pub unsafe fn new(file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        let len = usize::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        if len == 0 {
+            return Ok(Self {
+                ptr: NonNull::dangling(),
+                len,
+            });
+        }
+
+        match NonNull::new(unsafe { map_file(file, len) }) {
+            Some(ptr) => Ok(Self { ptr, len }),
+            None if !VIRTUAL_MEMORY => Err(io::ErrorKind::Unsupported.into()),
+            None => Err(io::Error::last_os_error()),
+        }
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = &'_ mut T> {
-        self.as_mut_slice().iter_mut()
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
     }
 
+    #[inline]
     pub fn len(&self) -> usize {
-        let bytes = unsafe { (self.arena.bump_addr.get()).byte_offset_from(self.arena.base_addr) };
-        bytes as usize / mem::size_of::<T>()
+        self.len
     }
 
+    #[inline]
     pub fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
-
-    pub fn clear(&mut self) {
-        self.arena.free_all();
+        self.len == 0
     }
 }
 
-impl<T> Index<usize> for ArenaVec<T> {
-    type Output = T;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        if let Some(value) = self.get(index) {
-            value
-        } else {
-            panic!("Index out of bounds: {index} >= {}", self.len());
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { unmap_file(self.ptr.as_ptr(), self.len) };
         }
     }
 }
 
-impl<T> IndexMut<usize> for ArenaVec<T> {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        let len = self.len();
-
-        if index >= len {
-            panic!("Index out of bounds: {index} >= {len}");
-        } else {
-            unsafe {
-                let ptr = self.arena.base_addr.byte_add(index * mem::size_of::<T>());
-                &mut *(ptr as *mut T)
-            }
-        }
+impl fmt::Debug for FileMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileMapping")
+            .field("len", &self.len)
+            .finish_non_exhaustive()
     }
 }
 
-impl<T: Debug> fmt::Debug for ArenaVec<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_list().entries(self.iter()).finish()
-    }
+// vector
+
+/// Length of an [`ArenaVec`] at some point, see [`ArenaVec::checkpoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Mark {
+    len: usize,
+}
+
+/// A very rudimentary dynamic array backed by an arena.
+///
+/// When the arena is full, the elements are moved to one with twice as much address space.
+/// If it can't be reserved (from the start or when growing), they go to a growable buffer
+/// on the heap instead, with the same API.
+///
+/// Elements that are `Copy` can be added through a shared reference with [`ArenaVec::add`],
+/// so the previous arenas and buffers are kept until the vector is mutably borrowed,
+/// so that references to elements stay valid. They then point to copies of the elements
+/// that are never dropped nor written to. Other elements would be duplicated by moving them
+/// while they are borrowed, so they are added through a mutable one with [`ArenaVec::push`].
+///
+/// Unlike values allocated directly in an [`Arena`], elements are dropped
+/// when the vector is truncated, cleared or dropped.
+pub struct ArenaVec<T> {
+    /// Only replaced when growing, while nothing borrows it
+    backend: UnsafeCell<Backend<T>>,
+    /// Backends that the elements were moved out of, only freeing their memory when dropped
+    retired: Cell<Vec<Backend<T>>>,
+    max_len: usize,
+}
+
+enum Backend<T> {
+    Arena(Arena, PhantomData<T>),
+    Heap(HeapBuffer<T>),
+}
+
+/// An [`ArenaVec`] already has as many elements as it can, see [`ArenaVec::with_max_len`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CapacityError {
+    pub max_len: usize,
+}
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ArenaVec is full ({} elements at most)", self.max_len)
+    }
+}
+
+impl Error for CapacityError {}
+
+impl<T> ArenaVec<T> {
+    /// Creates a vector backed by an arena, or by the heap if the arena can't be reserved.
+    ///
+    /// The address space is only the initial one, the vector grows past it as needed.
+    pub fn new(addr_space_size: usize) -> Self {
+        Self::with_max_len(addr_space_size, usize::MAX)
+    }
+
+    /// Creates a vector like [`ArenaVec::new`] that can't have more than `max_len` elements.
+    pub fn with_max_len(addr_space_size: usize, max_len: usize) -> Self {
+        let backend = match Arena::try_new(addr_space_size) {
+            Ok(arena) => Backend::Arena(arena, PhantomData),
+            Err(_) => Backend::Heap(HeapBuffer::new()),
+        };
+        Self::with_backend(backend, max_len)
+    }
+
+    /// Creates a vector like [`ArenaVec::new`] backed by huge pages when possible,
+    /// see [`Arena::try_with_huge_pages`].
+    pub fn with_huge_pages(addr_space_size: usize) -> Self {
+        let backend = match Arena::try_with_huge_pages(addr_space_size) {
+            Ok(arena) => Backend::Arena(arena, PhantomData),
+            Err(_) => Backend::Heap(HeapBuffer::new()),
+        };
+        Self::with_backend(backend, usize::MAX)
+    }
+
+    /// Creates a vector backed by an arena, without falling back to the heap.
+    pub fn try_new(addr_space_size: usize) -> Result<Self, ReserveError> {
+        let arena = Arena::try_new(addr_space_size)?;
+        Ok(Self::with_backend(
+            Backend::Arena(arena, PhantomData),
+            usize::MAX,
+        ))
+    }
+
+    /// Creates a vector backed by a growable buffer on the heap.
+    pub fn on_heap() -> Self {
+        Self::with_backend(Backend::Heap(HeapBuffer::new()), usize::MAX)
+    }
+
+    /// Creates a vector on the heap that starts with room for a single element,
+    /// so that growing moves the elements as often as possible. It is meant for tests.
+    pub fn on_small_heap() -> Self {
+        Self::with_backend(Backend::Heap(HeapBuffer::with_min_capacity(1)), usize::MAX)
+    }
+
+    fn with_backend(backend: Backend<T>, max_len: usize) -> Self {
+        // zero-sized elements never need memory, and an arena couldn't count them
+        let backend = match backend {
+            Backend::Arena(..) if mem::size_of::<T>() == 0 => Backend::Heap(HeapBuffer::new()),
+            backend => backend,
+        };
+        Self {
+            backend: UnsafeCell::new(backend),
+            retired: Cell::new(Vec::new()),
+            max_len,
+        }
+    }
+
+    #[inline]
+    fn backend(&self) -> &Backend<T> {
+        // SAFETY: the backend is only replaced in `grow_arena`, which no borrow outlives
+        unsafe { &*self.backend.get() }
+    }
+
+    /// Whether the vector fell back to the heap (or was created there).
+    #[inline]
+    pub fn is_on_heap(&self) -> bool {
+        matches!(self.backend(), Backend::Heap(_))
+    }
+
+    /// Maximum number of elements, see [`ArenaVec::with_max_len`].
+    #[inline]
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Adds an element, panicking if the vector is full.
+    ///
+    /// References to elements can be kept across calls, so elements must be `Copy`
+    /// to be moved while borrowed. Use [`ArenaVec::push`] for the others:
+    ///
+    /// ```compile_fail
+    /// # use std::cell::Cell;
+    /// # use csussus::arena::ArenaVec;
+    /// let vec = ArenaVec::on_small_heap();
+    /// vec.add(Cell::new(Some(Box::new(1))));
+    /// ```
+    #[inline]
+    pub fn add(&self, value: T)
+    where
+        T: Copy,
+    {
+        if let Err(e) = self.try_add(value) {
+            panic!("{e}");
+        }
+    }
+
+    /// Adds an element, unless the vector already has [`ArenaVec::max_len`] elements.
+    #[inline]
+    pub fn try_add(&self, value: T) -> Result<(), CapacityError>
+    where
+        T: Copy,
+    {
+        // SAFETY: copies of elements can be left behind
+        unsafe { self.add_unchecked(value) }
+    }
+
+    /// Adds an element of any type, panicking if the vector is full.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        if let Err(e) = self.try_push(value) {
+            panic!("{e}");
+        }
+    }
+
+    /// Adds an element of any type, unless the vector already has [`ArenaVec::max_len`] elements.
+    #[inline]
+    pub fn try_push(&mut self, value: T) -> Result<(), CapacityError> {
+        // SAFETY: nothing borrows the elements
+        unsafe { self.add_unchecked(value) }
+    }
+
+    /// Adds an element, which can move the others.
+    ///
+    /// # Safety
+    ///
+    /// Either nothing borrows the elements, or they are `Copy`.
+    #[inline]
+    unsafe fn add_unchecked(&self, value: T) -> Result<(), CapacityError> {
+        if self.len() >= self.max_len {
+            return Err(CapacityError {
+                max_len: self.max_len,
+            });
+        }
+
+        if let Backend::Arena(arena, _) = self.backend() {
+            if arena.remaining_bytes() < mem::size_of::<T>() {
+                self.grow_arena(1);
+            }
+        }
+
+        match self.backend() {
+            Backend::Arena(arena, _) => unsafe {
+                // the element is dropped by the vector, not by the arena
+                let ptr = arena.alloc_region(mem::size_of::<T>(), mem::align_of::<T>());
+                (ptr as *mut T).write(value);
+            },
+            Backend::Heap(heap) => heap.push(value),
+        }
+        Ok(())
+    }
+
+    /// Adds copies of the elements of a slice, panicking if the vector can't hold them all.
+    ///
+    /// Room is made for all of them at once, so like for [`ArenaVec::push_n`], the vector
+    /// only grows and commits memory once. Elements are `Copy` so that nothing can add
+    /// to the vector while they are written.
+    pub fn extend_from_slice(&self, values: &[T])
+    where
+        T: Copy,
+    {
+        if let Err(e) = self.try_extend_from_slice(values) {
+            panic!("{e}");
+        }
+    }
+
+    /// Adds copies of the elements of a slice, unless the vector would have more than
+    /// [`ArenaVec::max_len`] elements, in which case none of them are added.
+    pub fn try_extend_from_slice(&self, values: &[T]) -> Result<(), CapacityError>
+    where
+        T: Copy,
+    {
+        let end = self.reserve(values.len())?;
+        unsafe {
+            let ptr = self.base_ptr().add(self.len());
+            ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
+            self.set_len(end);
+        }
+        Ok(())
+    }
+
+    /// Adds `n` copies of a value, panicking if the vector can't hold them all.
+    pub fn push_n(&self, value: T, n: usize)
+    where
+        T: Copy,
+    {
+        let end = match self.reserve(n) {
+            Ok(end) => end,
+            Err(e) => panic!("{e}"),
+        };
+        unsafe {
+            let ptr = self.base_ptr().add(self.len());
+            for i in 0..n {
+                ptr.add(i).write(value);
+            }
+            self.set_len(end);
+        }
+    }
+
+    /// Resizes the vector to `len` elements, adding the results of `f` or dropping the last ones.
+    pub fn resize_with(&mut self, len: usize, mut f: impl FnMut() -> T) {
+        let old_len = self.len();
+        if len <= old_len {
+            self.truncate(len);
+            return;
+        }
+
+        if let Err(e) = self.reserve(len - old_len) {
+            panic!("{e}");
+        }
+        let ptr = self.base_ptr();
+        // elements are only counted once written, in case `f` panics
+        let mut guard = SetLenOnDrop {
+            vec: self,
+            len: old_len,
+        };
+        while guard.len < len {
+            unsafe { ptr.add(guard.len).write(f()) };
+            guard.len += 1;
+        }
+    }
+
+    /// Removes the last element and returns it, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let len = self.len().checked_sub(1)?;
+        unsafe {
+            let value = self.base_ptr().add(len).read();
+            self.set_len(len);
+            Some(value)
+        }
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut T> {
+        let idx = self.len().checked_sub(1)?;
+        self.get_mut(idx)
+    }
+
+    /// Makes room for `additional` more elements, growing and committing memory
+    /// once for all of them. Returns the length that the vector will have.
+    fn reserve(&self, additional: usize) -> Result<usize, CapacityError> {
+        let len = self.len();
+        let end = match len.checked_add(additional) {
+            Some(end) if end <= self.max_len => end,
+            _ => {
+                return Err(CapacityError {
+                    max_len: self.max_len,
+                })
+            }
+        };
+
+        let size = additional.saturating_mul(mem::size_of::<T>());
+        if let Backend::Arena(arena, _) = self.backend() {
+            if arena.remaining_bytes() < size {
+                self.grow_arena(additional);
+            }
+        }
+
+        match self.backend() {
+            Backend::Arena(arena, _) => unsafe { arena.commit_to(arena.bump_addr.get().add(size)) },
+            Backend::Heap(heap) if end > heap.capacity.get() => {
+                heap.grow_to(heap.capacity.get().saturating_mul(2).max(end));
+            }
+            Backend::Heap(_) => {}
+        }
+        Ok(end)
+    }
+
+    /// Moves the elements to an arena with twice as much address space (or enough for
+    /// `additional` more elements), or to the heap.
+    #[cold]
+    fn grow_arena(&self, additional: usize) {
+        let len = self.len();
+        let used_bytes = len * mem::size_of::<T>();
+        let Backend::Arena(arena, _) = self.backend() else {
+            return;
+        };
+
+        let max_bytes = self.max_len.saturating_mul(mem::size_of::<T>());
+        let needed_bytes = (len.saturating_add(additional)).saturating_mul(mem::size_of::<T>());
+        let addr_space_size = (arena.reserved_bytes().saturating_mul(2))
+            .max(needed_bytes)
+            .min(max_bytes);
+        let backend = match Arena::try_new_with(addr_space_size, arena.huge_pages) {
+            Ok(arena) => unsafe {
+                let ptr = arena.alloc_region(used_bytes, mem::align_of::<T>()) as *mut T;
+                ptr::copy_nonoverlapping(self.base_ptr(), ptr, len);
+                Backend::Arena(arena, PhantomData)
+            },
+            Err(_) => {
+                let heap = HeapBuffer::new();
+                heap.grow_to(len.saturating_mul(2).max(len.saturating_add(additional)));
+                unsafe { ptr::copy_nonoverlapping(self.base_ptr(), heap.ptr.get().as_ptr(), len) };
+                heap.len.set(len);
+                Backend::Heap(heap)
+            }
+        };
+
+        // SAFETY: nothing borrows the backend, borrowed elements are in the retired one
+        let old_backend = unsafe { mem::replace(&mut *self.backend.get(), backend) };
+        let mut retired = self.retired.take();
+        retired.push(old_backend);
+        self.retired.set(retired);
+    }
+
+    #[inline]
+    fn base_ptr(&self) -> *mut T {
+        match self.backend() {
+            Backend::Arena(arena, _) => arena.base_addr as *mut T,
+            Backend::Heap(heap) => heap.ptr.get().as_ptr(),
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx >= self.len() {
+            None
+        } else {
+            unsafe { Some(&*self.base_ptr().add(idx)) }
+        }
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        if idx >= self.len() {
+            None
+        } else {
+            unsafe { Some(&mut *self.base_ptr().add(idx)) }
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.base_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.base_ptr(), self.len()) }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'_ T> {
+        self.as_slice().iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &'_ mut T> {
+        self.as_mut_slice().iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        match self.backend() {
+            Backend::Arena(arena, _) => arena.used_bytes() / mem::size_of::<T>(),
+            Backend::Heap(heap) => heap.len.get(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bytes taken by the elements.
+    #[inline]
+    pub fn used_bytes(&self) -> usize {
+        self.len() * mem::size_of::<T>()
+    }
+
+    /// Number of bytes of physical memory committed for the elements,
+    /// including where they were before growing until the vector is mutably borrowed.
+    pub fn committed_bytes(&self) -> usize {
+        self.sum_backends(|backend| match backend {
+            Backend::Arena(arena, _) => arena.committed_bytes(),
+            Backend::Heap(heap) => heap.committed_bytes(),
+        })
+    }
+
+    /// Number of bytes of address space reserved for the elements, with the same caveat as
+    /// [`ArenaVec::committed_bytes`]. Heap buffers only reserve what they allocate.
+    pub fn reserved_bytes(&self) -> usize {
+        self.sum_backends(|backend| match backend {
+            Backend::Arena(arena, _) => arena.reserved_bytes(),
+            Backend::Heap(heap) => heap.committed_bytes(),
+        })
+    }
+
+    fn sum_backends(&self, f: impl Fn(&Backend<T>) -> usize) -> usize {
+        let retired = self.retired.take();
+        let sum = (retired.iter().chain([self.backend()])).map(f).sum();
+        self.retired.set(retired);
+        sum
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+        match self.backend.get_mut() {
+            Backend::Arena(arena, _) => arena.free_all(),
+            Backend::Heap(heap) => *heap = HeapBuffer::with_min_capacity(heap.min_capacity),
+        }
+    }
+
+    /// Gives the memory that isn't taken by the elements back, like where they were
+    /// before growing and the committed pages past the last one.
+    ///
+    /// It is meant for when the vector is done growing, e.g. after a phase of a
+    /// long-lived process like a language server, so that it doesn't keep its peak memory.
+    pub fn shrink_to_fit(&mut self) {
+        self.retired.get_mut().clear();
+        match self.backend.get_mut() {
+            Backend::Arena(arena, _) => arena.shrink_to_fit(),
+            Backend::Heap(heap) => {
+                heap.free_retired();
+                heap.shrink_to_fit();
+            }
+        }
+    }
+
+    /// Current length of the vector, to roll back to with [`ArenaVec::truncate_to`].
+    #[inline]
+    pub fn checkpoint(&self) -> Mark {
+        Mark { len: self.len() }
+    }
+
+    /// Drops the elements added since the checkpoint, like trial nodes of speculative parsing.
+    ///
+    /// Their memory is reused by the next elements, so backtracking doesn't waste any.
+    /// Nothing happens if the vector is already shorter than at the checkpoint.
+    #[inline]
+    pub fn truncate_to(&mut self, mark: Mark) {
+        self.truncate(mark.len);
+    }
+
+    /// Shortens the vector to its first `len` elements, dropping the rest.
+    pub fn truncate(&mut self, len: usize) {
+        // nothing can reference the previous backends anymore
+        self.retired.get_mut().clear();
+        if let Backend::Heap(heap) = self.backend.get_mut() {
+            heap.free_retired();
+        }
+
+        let old_len = self.len();
+        if len >= old_len {
+            return;
+        }
+
+        unsafe {
+            let tail = slice::from_raw_parts_mut(self.base_ptr().add(len), old_len - len);
+            self.set_len(len);
+
+            // nothing to go through for plain data like tokens
+            if mem::needs_drop::<T>() {
+                ptr::drop_in_place(tail);
+            }
+        }
+    }
+
+    /// Sets the length without dropping nor initializing anything, from 0 to the current
+    /// length or up to elements written in memory made room for by [`ArenaVec::reserve`].
+    unsafe fn set_len(&self, len: usize) {
+        let base_addr = self.base_ptr();
+        match self.backend() {
+            Backend::Arena(arena, _) => arena.bump_addr.set(base_addr.add(len) as *mut u8),
+            Backend::Heap(heap) => heap.len.set(len),
+        }
+    }
+}
+
+/// Sets the length of a vector when dropped, so that it accounts for the elements
+/// written so far even if writing the next one panics.
+struct SetLenOnDrop<'a, T> {
+    vec: &'a mut ArenaVec<T>,
+    len: usize,
+}
+
+impl<T> Drop for SetLenOnDrop<'_, T> {
+    fn drop(&mut self) {
+        unsafe { self.vec.set_len(self.len) };
+    }
+}
+
+impl<T> Drop for ArenaVec<T> {
+    /// Drops the elements, the backends then giving their memory back.
+    fn drop(&mut self) {
+        self.truncate(0);
+    }
+}
+
+/// Growable buffer on the heap, for when an arena can't be reserved, see [`ArenaVec`].
+struct HeapBuffer<T> {
+    ptr: Cell<NonNull<T>>,
+    capacity: Cell<usize>,
+    len: Cell<usize>,
+    /// Previous buffers whose elements were moved, with their capacity
+    retired: Cell<Vec<(NonNull<T>, usize)>>,
+    /// Capacity of the first buffer
+    min_capacity: usize,
+}
+
+// the buffer is the only owner of its elements
+unsafe impl<T: Send> Send for HeapBuffer<T> {}
+
+impl<T> HeapBuffer<T> {
+    fn new() -> Self {
+        let min_capacity = PAGES_PER_COMMIT * page_size() / mem::size_of::<T>().max(1);
+        Self::with_min_capacity(min_capacity)
+    }
+
+    fn with_min_capacity(min_capacity: usize) -> Self {
+        let capacity = match mem::size_of::<T>() {
+            0 => usize::MAX,
+            _ => 0,
+        };
+
+        Self {
+            ptr: Cell::new(NonNull::dangling()),
+            capacity: Cell::new(capacity),
+            len: Cell::new(0),
+            retired: Cell::new(Vec::new()),
+            min_capacity,
+        }
+    }
+
+    fn push(&self, value: T) {
+        let len = self.len.get();
+        if len == self.capacity.get() {
+            self.grow();
+        }
+
+        unsafe { self.ptr.get().as_ptr().add(len).write(value) };
+        self.len.set(len + 1);
+    }
+
+    /// Moves the elements to a buffer twice as large, keeping the previous one.
+    #[cold]
+    fn grow(&self) {
+        self.grow_to(self.capacity.get() * 2);
+    }
+
+    /// Moves the elements to a buffer with at least the given capacity, keeping the previous one.
+    fn grow_to(&self, capacity: usize) {
+        // zero-sized elements never need memory
+        if mem::size_of::<T>() == 0 {
+            return;
+        }
+
+        let (old_ptr, old_capacity) = (self.ptr.get(), self.capacity.get());
+        let capacity = capacity.max(self.min_capacity).max(1);
+
+        let layout = Layout::array::<T>(capacity).expect("ArenaVec is too large");
+        let ptr = unsafe { alloc::alloc(layout) as *mut T };
+        let Some(ptr) = NonNull::new(ptr) else {
+            alloc::handle_alloc_error(layout);
+        };
+
+        unsafe { ptr::copy_nonoverlapping(old_ptr.as_ptr(), ptr.as_ptr(), self.len.get()) };
+        if old_capacity > 0 {
+            let mut retired = self.retired.take();
+            retired.push((old_ptr, old_capacity));
+            self.retired.set(retired);
+        }
+
+        self.ptr.set(ptr);
+        self.capacity.set(capacity);
+    }
+
+    fn committed_bytes(&self) -> usize {
+        let retired = self.retired.take();
+        let retired_capacity: usize = retired.iter().map(|&(_, capacity)| capacity).sum();
+        self.retired.set(retired);
+
+        (self.capacity.get() + retired_capacity) * mem::size_of::<T>()
+    }
+
+    /// Moves the elements to a buffer with exactly their number as capacity.
+    fn shrink_to_fit(&mut self) {
+        let (len, capacity) = (self.len.get(), self.capacity.get());
+        if mem::size_of::<T>() == 0 || len == capacity {
+            return;
+        }
+
+        let ptr = match len {
+            0 => NonNull::dangling(),
+            _ => unsafe {
+                let layout = Layout::array::<T>(len).unwrap();
+                let ptr = alloc::alloc(layout) as *mut T;
+                let Some(ptr) = NonNull::new(ptr) else {
+                    alloc::handle_alloc_error(layout);
+                };
+                ptr::copy_nonoverlapping(self.ptr.get().as_ptr(), ptr.as_ptr(), len);
+                ptr
+            },
+        };
+
+        unsafe { dealloc_array(self.ptr.get(), capacity) };
+        self.ptr.set(ptr);
+        self.capacity.set(len);
+    }
+
+    fn free_retired(&mut self) {
+        for (ptr, capacity) in self.retired.get_mut().drain(..) {
+            unsafe { dealloc_array(ptr, capacity) };
+        }
+    }
+}
+
+impl<T> Drop for HeapBuffer<T> {
+    /// Frees the buffers, the elements being dropped by [`ArenaVec`].
+    fn drop(&mut self) {
+        self.free_retired();
+        if mem::size_of::<T>() > 0 {
+            unsafe { dealloc_array(self.ptr.get(), self.capacity.get()) };
+        }
+    }
+}
+
+/// Frees a buffer allocated for `capacity` elements, if any.
+unsafe fn dealloc_array<T>(ptr: NonNull<T>, capacity: usize) {
+    if capacity > 0 {
+        alloc::dealloc(
+            ptr.as_ptr() as *mut u8,
+            Layout::array::<T>(capacity).unwrap(),
+        );
+    }
+}
+
+/// Indexing like slices, by position or by range.
+impl<T, I: SliceIndex<[T]>> Index<I> for ArenaVec<T> {
+    type Output = I::Output;
+
+    #[inline]
+    fn index(&self, index: I) -> &Self::Output {
+        &self.as_slice()[index]
+    }
+}
+
+impl<T, I: SliceIndex<[T]>> IndexMut<I> for ArenaVec<T> {
+    #[inline]
+    fn index_mut(&mut self, index: I) -> &mut Self::Output {
+        &mut self.as_mut_slice()[index]
+    }
+}
+
+impl<T: Debug> fmt::Debug for ArenaVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> Deref for ArenaVec<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T> DerefMut for ArenaVec<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T> Extend<T> for ArenaVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a> Extend<&'a T> for ArenaVec<T> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<T> FromIterator<T> for ArenaVec<T> {
+    /// Collects the elements in a vector with [`DEFAULT_ADDR_SPACE_SIZE`], which it grows past.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = ArenaVec::new(DEFAULT_ADDR_SPACE_SIZE);
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<'a, T> IntoIterator for &'a ArenaVec<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut ArenaVec<T> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_mut_slice().iter_mut()
+    }
+}
+
+impl<T> IntoIterator for ArenaVec<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        let end = self.len();
+        // the iterator owns the elements now, the vector only keeps their memory
+        unsafe { self.set_len(0) };
+        IntoIter {
+            vec: self,
+            start: 0,
+            end,
+        }
+    }
+}
+
+/// Iterator that moves the elements out of an [`ArenaVec`].
+pub struct IntoIter<T> {
+    /// Has a length of 0, its elements being the ones from `start` to `end`
+    vec: ArenaVec<T>,
+    start: usize,
+    end: usize,
+}
+
+impl<T> IntoIter<T> {
+    /// Elements that are left.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.vec.base_ptr().add(self.start), self.end - self.start) }
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+
+        let value = unsafe { self.vec.base_ptr().add(self.start).read() };
+        self.start += 1;
+        Some(value)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+
+        self.end -= 1;
+        unsafe { Some(self.vec.base_ptr().add(self.end).read()) }
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> Drop for IntoIter<T> {
+    /// Drops the elements that are left, the vector then giving their memory back.
+    fn drop(&mut self) {
+        if mem::needs_drop::<T>() {
+            let left = ptr::slice_from_raw_parts_mut(
+                unsafe { self.vec.base_ptr().add(self.start) },
+                self.end - self.start,
+            );
+            unsafe { ptr::drop_in_place(left) };
+        }
+    }
+}
+
+impl<T: Debug> fmt::Debug for IntoIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IntoIter").field(&self.as_slice()).finish()
+    }
+}
+
+// snapshots
+
+/// Plain data whose bytes can be written to disk and read back, see [`ArenaVec::write_snapshot`].
+///
+/// # Safety
+///
+/// The type must have no padding nor pointers, and any bytes that [`Snapshot::is_valid`]
+/// accepts must be a valid value.
+pub unsafe trait Snapshot: Copy {
+    /// Whether the bytes of an element are a valid value, for types like enums
+    /// that can't hold any bytes.
+    #[inline]
+    fn is_valid(_bytes: &[u8]) -> bool {
+        true
+    }
+}
+
+unsafe impl Snapshot for u8 {}
+unsafe impl Snapshot for u16 {}
+unsafe impl Snapshot for u32 {}
+unsafe impl Snapshot for u64 {}
+unsafe impl Snapshot for usize {}
+unsafe impl Snapshot for i8 {}
+unsafe impl Snapshot for i16 {}
+unsafe impl Snapshot for i32 {}
+unsafe impl Snapshot for i64 {}
+unsafe impl Snapshot for isize {}
+
+impl<T: Snapshot> ArenaVec<T> {
+    /// Writes the bytes of the elements, in the native byte order.
+    pub fn write_snapshot(&self, out: &mut impl Write) -> io::Result<()> {
+        let slice = self.as_slice();
+        // SAFETY: snapshot types have no padding
+        let bytes =
+            unsafe { slice::from_raw_parts(slice.as_ptr() as *const u8, self.used_bytes()) };
+        out.write_all(bytes)
+    }
+
+    /// Loads `len` elements written by [`ArenaVec::write_snapshot`] at `offset` in a file,
+    /// reading them in memory that the vector owns.
+    ///
+    /// Returns an [`io::ErrorKind::InvalidData`] error if an element isn't valid.
+    pub fn from_snapshot(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        // SAFETY: nothing is mapped
+        unsafe { Self::load_snapshot(file, offset, len, false) }
+    }
+
+    /// Loads elements like [`ArenaVec::from_snapshot`], mapping snapshots of at least
+    /// [`SNAPSHOT_MAP_THRESHOLD`] bytes at an offset aligned to pages (like multiples
+    /// of [`SNAPSHOT_ALIGN`]) copy-on-write, so that only the pages that are accessed get read.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to nor truncated while the vector is around, by this
+    /// process or any other. Elements are only validated once, and pages that the vector
+    /// didn't write to keep showing what is written to the file, while truncating it
+    /// makes accessing them crash.
+    pub unsafe fn map_snapshot(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        Self::load_snapshot(file, offset, len, true)
+    }
+
+    /// See [`ArenaVec::map_snapshot`] for the safety contract if `map` is true.
+    pub(crate) unsafe fn load_snapshot(
+        file: &File,
+        offset: u64,
+        len: usize,
+        map: bool,
+    ) -> io::Result<Self> {
+        let size = (len.checked_mul(mem::size_of::<T>())).ok_or(io::ErrorKind::OutOfMemory)?;
+        match offset.checked_add(size as u64) {
+            Some(end) if end <= file.metadata()?.len() => {}
+            _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+
+        let validate = |ptr: *const u8| {
+            // SAFETY: the bytes are initialized, and only seen as elements once validated
+            let bytes = unsafe { slice::from_raw_parts(ptr, size) };
+            let elements = bytes.chunks_exact(mem::size_of::<T>().max(1));
+            match elements.into_iter().all(T::is_valid) {
+                true => Ok(()),
+                false => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid element in snapshot",
+                )),
+            }
+        };
+
+        match Arena::try_new(size.max(DEFAULT_ADDR_SPACE_SIZE)) {
+            Ok(arena) => {
+                validate(arena.alloc_from_file(file, offset, size, map)?)?;
+                Ok(Self::with_backend(
+                    Backend::Arena(arena, PhantomData),
+                    usize::MAX,
+                ))
+            }
+            Err(_) => {
+                let heap = HeapBuffer::new();
+                heap.grow_to(len);
+                let ptr = heap.ptr.get().as_ptr() as *mut u8;
+                read_file_at(
+                    file,
+                    unsafe { slice::from_raw_parts_mut(ptr, size) },
+                    offset,
+                )?;
+                validate(ptr)?;
+                heap.len.set(len);
+                Ok(Self::with_backend(Backend::Heap(heap), usize::MAX))
+            }
+        }
+    }
+}
+
+// allocation policies
+
+/// Where the memory of the [`ArenaVec`]s of a pass comes from, like the tokens of
+/// [`lex_in`](crate::lexer::lex_in), so that embedders can choose a memory policy.
+pub trait ArenaAlloc {
+    /// Creates an empty vector, `addr_space_size` being a hint of how large it can get.
+    fn new_vec<T>(&self, addr_space_size: usize) -> ArenaVec<T>;
+}
+
+/// Arenas that reserve address space and commit it as needed, the default policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct VmAlloc {
+    /// Whether the arenas are backed by huge pages, see [`Arena::try_with_huge_pages`]
+    pub huge_pages: bool,
+}
+
+impl ArenaAlloc for VmAlloc {
+    fn new_vec<T>(&self, addr_space_size: usize) -> ArenaVec<T> {
+        match self.huge_pages {
+            true => ArenaVec::with_huge_pages(addr_space_size),
+            false => ArenaVec::new(addr_space_size),
+        }
+    }
+}
+
+/// Growable buffers on the heap, like `Vec`s, for targets where reserving address space
+/// is frowned upon or not possible.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HeapAlloc;
+
+impl ArenaAlloc for HeapAlloc {
+    fn new_vec<T>(&self, _addr_space_size: usize) -> ArenaVec<T> {
+        ArenaVec::on_heap()
+    }
+}
+
+/// Buffers on the heap that start as small as possible, see [`ArenaVec::on_small_heap`].
+///
+/// Elements are moved around a lot, which shakes out bugs in tests (and under Miri,
+/// where it doesn't allocate more than needed).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TestAlloc;
+
+impl ArenaAlloc for TestAlloc {
+    fn new_vec<T>(&self, _addr_space_size: usize) -> ArenaVec<T> {
+        ArenaVec::on_small_heap()
+    }
+}
+
+// shared vector
+
+/// An append-only vector backed by an arena, to which threads can add elements concurrently.
+///
+/// Adding claims a slot with a single atomic `fetch_add`, so it never locks nor waits
+/// on other threads. The price is that it can't grow past its address space, and that
+/// elements can only be read through a mutable borrow, once no thread can be adding any.
+/// [`SyncArenaVec::into_arena_vec`] then turns it into a regular [`ArenaVec`].
+pub struct SyncArenaVec<T> {
+    /// Only used for its address space until the vector is mutably borrowed,
+    /// its bump and commit addresses being updated from the atomics then
+    arena: Arena,
+    max_len: usize,
+    /// Number of slots claimed, which can go past `max_len` when the vector is full
+    claimed: AtomicUsize,
+    /// Number of elements written to their slot
+    written: AtomicUsize,
+    committed_bytes: AtomicUsize,
+    _elements: PhantomData<T>,
+}
+
+// elements are moved in from any thread, but only borrowed through `&mut self`
+unsafe impl<T: Send> Send for SyncArenaVec<T> {}
+unsafe impl<T: Send> Sync for SyncArenaVec<T> {}
+
+impl<T> SyncArenaVec<T> {
+    /// Reserves the address space of the vector, panicking if it can't.
+    pub fn new(addr_space_size: usize) -> Self {
+        match Self::try_new(addr_space_size) {
+            Ok(vec) => vec,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Reserves the address space of the vector, which holds as many elements as fit in it.
+    ///
+    /// There is no heap fallback, so this always fails where [`RESERVES_ADDR_SPACE`] is false.
+    pub fn try_new(addr_space_size: usize) -> Result<Self, ReserveError> {
+        let arena = Arena::try_new(addr_space_size)?;
+        let max_len = match mem::size_of::<T>() {
+            0 => usize::MAX,
+            size => arena.reserved_bytes() / size,
+        };
+
+        Ok(Self {
+            arena,
+            max_len,
+            claimed: AtomicUsize::new(0),
+            written: AtomicUsize::new(0),
+            committed_bytes: AtomicUsize::new(0),
+            _elements: PhantomData,
+        })
+    }
+
+    /// Maximum number of elements, as many as fit in the address space.
+    #[inline]
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Adds an element and returns its index, panicking if the vector is full.
+    #[inline]
+    pub fn add(&self, value: T) -> usize {
+        match self.try_add(value) {
+            Ok(idx) => idx,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Adds an element and returns its index, unless the vector is full.
+    ///
+    /// Indices follow the order in which threads claimed their slot.
+    pub fn try_add(&self, value: T) -> Result<usize, CapacityError> {
+        let idx = self.claimed.fetch_add(1, Ordering::Relaxed);
+        if idx >= self.max_len {
+            return Err(CapacityError {
+                max_len: self.max_len,
+            });
+        }
+
+        let size = mem::size_of::<T>();
+        let end = (idx + 1) * size;
+        if end > self.committed_bytes.load(Ordering::Acquire) {
+            self.commit(end);
+        }
+
+        unsafe { (self.arena.base_addr as *mut T).add(idx).write(value) };
+        self.written.fetch_add(1, Ordering::Release);
+        Ok(idx)
+    }
+
+    /// Commits the pages up to `end`, which other threads can be doing at the same time.
+    ///
+    /// Committing pages that are already committed does nothing, so threads racing
+    /// to commit the same pages don't need to agree on who does it.
+    #[cold]
+    fn commit(&self, end: usize) {
+        let arena = &self.arena;
+        let alloc_granularity = arena.alloc_granularity();
+        let start = self.committed_bytes.load(Ordering::Acquire) / alloc_granularity;
+        let start = start * alloc_granularity;
+        let end = ceil_align(end, alloc_granularity).min(arena.reserved_bytes());
+
+        let committed = unsafe { vm_commit(arena.base_addr.byte_add(start), end - start) };
+        if !committed {
+            commit_failed("SyncArenaVec");
+        }
+        if arena.huge_pages {
+            unsafe { vm_advise_huge_pages(arena.base_addr.byte_add(start), end - start) };
+        }
+        self.committed_bytes.fetch_max(end, Ordering::Release);
+    }
+
+    /// Number of elements, including the ones other threads are still adding.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.claimed.load(Ordering::Relaxed).min(self.max_len)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bytes of physical memory committed for the elements.
+    #[inline]
+    pub fn committed_bytes(&self) -> usize {
+        self.committed_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Whether every claimed slot has its element, which is only false
+    /// if a thread panicked while adding one.
+    fn is_complete(&mut self) -> bool {
+        *self.written.get_mut() == self.len()
+    }
+
+    /// The elements, in order of their index.
+    ///
+    /// Panics if a thread panicked while adding an element.
+    pub fn as_slice(&mut self) -> &[T] {
+        self.as_mut_slice()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        assert!(
+            self.is_complete(),
+            "An element of the SyncArenaVec is missing"
+        );
+        unsafe { slice::from_raw_parts_mut(self.arena.base_addr as *mut T, self.len()) }
+    }
+
+    /// Turns the vector into a regular [`ArenaVec`] with the same elements, without moving them.
+    ///
+    /// Panics if a thread panicked while adding an element.
+    pub fn into_arena_vec(mut self) -> ArenaVec<T> {
+        assert!(
+            self.is_complete(),
+            "An element of the SyncArenaVec is missing"
+        );
+
+        let used_bytes = self.len() * mem::size_of::<T>();
+        let committed_bytes = self.committed_bytes();
+        let mut this = mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is never used again nor dropped
+        let arena = unsafe { ptr::read(&this.arena) };
+
+        unsafe {
+            (arena.bump_addr).set(arena.base_addr.byte_add(used_bytes));
+            (arena.uncommitted_addr).set(arena.base_addr.byte_add(committed_bytes));
+        }
+        ArenaVec::with_backend(Backend::Arena(arena, PhantomData), usize::MAX)
+    }
+}
+
+impl<T> Drop for SyncArenaVec<T> {
+    /// Drops the elements, the arena then giving its memory back.
+    fn drop(&mut self) {
+        // elements are leaked rather than dropping a slot that was never written
+        if mem::needs_drop::<T>() && self.is_complete() {
+            unsafe { ptr::drop_in_place(self.as_mut_slice()) };
+        }
+    }
+}
+
+impl<T> fmt::Debug for SyncArenaVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncArenaVec")
+            .field("len", &self.len())
+            .field("max_len", &self.max_len)
+            .finish()
+    }
+}
+
+// bytes
+
+/// An arena for allocations of mixed types and sizes, like strings, child lists of AST nodes
+/// or encoded tables, where an [`ArenaVec`] only holds elements of a single type.
+///
+/// When its arena is full, one with twice as much address space is reserved, previous
+/// allocations staying where they are. If it can't be reserved (from the start or when
+/// growing), memory comes from chunks on the heap instead. Like with [`Arena`],
+/// values are never dropped, their memory is only freed along with the whole arena.
+pub struct ByteArena {
+    /// Chunks that memory was allocated from, the last one being the current one.
+    /// Only pushed to while nothing borrows it, allocations pointing into the chunks
+    chunks: UnsafeCell<Vec<Chunk>>,
+}
+
+enum Chunk {
+    Arena(Arena),
+    Heap(HeapChunk),
+}
+
+/// Chunk of memory on the heap, for when an arena can't be reserved, see [`ByteArena`].
+struct HeapChunk {
+    ptr: NonNull<u8>,
+    size: usize,
+    used: Cell<usize>,
+}
+
+/// Alignment of heap chunks, larger alignments being padded for within the chunk.
+const HEAP_CHUNK_ALIGN: usize = 16;
+
+// the arena is the only owner of its memory
+unsafe impl Send for ByteArena {}
+
+impl ByteArena {
+    /// Creates an arena backed by virtual memory, or by the heap if it can't be reserved.
+    ///
+    /// The address space is only the initial one, the arena grows past it as needed.
+    pub fn new(addr_space_size: usize) -> Self {
+        let chunk = match Arena::try_new(addr_space_size) {
+            Ok(arena) => Chunk::Arena(arena),
+            Err(_) => Chunk::Heap(HeapChunk::new(HeapChunk::MIN_SIZE)),
+        };
+        Self::with_chunk(chunk)
+    }
+
+    /// Creates an arena whose memory comes from chunks on the heap.
+    pub fn on_heap() -> Self {
+        Self::with_chunk(Chunk::Heap(HeapChunk::new(HeapChunk::MIN_SIZE)))
+    }
+
+    fn with_chunk(chunk: Chunk) -> Self {
+        Self {
+            chunks: UnsafeCell::new(vec![chunk]),
+        }
+    }
+
+    #[inline]
+    fn chunks(&self) -> &[Chunk] {
+        // SAFETY: chunks are only pushed in `grow`, which no borrow outlives
+        unsafe { &*self.chunks.get() }
+    }
+
+    #[inline]
+    fn current_chunk(&self) -> &Chunk {
+        self.chunks().last().unwrap()
+    }
+
+    /// Whether the arena fell back to the heap (or was created there).
+    #[inline]
+    pub fn is_on_heap(&self) -> bool {
+        matches!(self.current_chunk(), Chunk::Heap(_))
+    }
+
+    /// Allocates uninitialized memory with the size and alignment of the layout.
+    #[inline]
+    pub fn alloc_layout(&self, layout: Layout) -> NonNull<u8> {
+        match self.current_chunk().try_alloc(layout) {
+            Some(ptr) => ptr,
+            None => self.grow(layout),
+        }
+    }
+
+    /// Allocates a value, with the same guarantees as [`Arena::alloc`].
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T: Copy>(&self, value: T) -> &mut T {
+        unsafe {
+            let ptr = self.alloc_layout(Layout::new::<T>()).as_ptr() as *mut T;
+            ptr.write(value);
+            &mut *ptr
+        }
+    }
+
+    /// Allocates a copy of a slice, like the children of a node once they are all parsed.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        unsafe {
+            let layout = Layout::for_value(values);
+            let ptr = self.alloc_layout(layout).as_ptr() as *mut T;
+            ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
+            slice::from_raw_parts_mut(ptr, values.len())
+        }
+    }
+
+    /// Allocates a copy of a string.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, s: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(s.as_bytes());
+        // SAFETY: the bytes were copied from a string
+        unsafe { std::str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Allocates from a new chunk large enough for the layout, with twice as much
+    /// address space as the current one (or twice its size on the heap).
+    #[cold]
+    fn grow(&self, layout: Layout) -> NonNull<u8> {
+        let min_size = layout.size().saturating_add(layout.align());
+        let chunk = match self.current_chunk() {
+            Chunk::Arena(arena) => {
+                let addr_space_size = arena.reserved_bytes().saturating_mul(2).max(min_size);
+                match Arena::try_new(addr_space_size) {
+                    Ok(arena) => Chunk::Arena(arena),
+                    Err(_) => Chunk::Heap(HeapChunk::new(min_size.max(HeapChunk::MIN_SIZE))),
+                }
+            }
+            Chunk::Heap(heap) => {
+                Chunk::Heap(HeapChunk::new(heap.size.saturating_mul(2).max(min_size)))
+            }
+        };
+
+        // SAFETY: nothing borrows the chunks, allocations point to the memory behind them
+        unsafe { (*self.chunks.get()).push(chunk) };
+        (self.current_chunk().try_alloc(layout)).expect("ByteArena is out of memory")
+    }
+
+    /// Number of bytes allocated so far, including padding for alignment.
+    pub fn used_bytes(&self) -> usize {
+        self.sum_chunks(|chunk| match chunk {
+            Chunk::Arena(arena) => arena.used_bytes(),
+            Chunk::Heap(heap) => heap.used.get(),
+        })
+    }
+
+    /// Number of bytes of physical memory committed so far.
+    pub fn committed_bytes(&self) -> usize {
+        self.sum_chunks(|chunk| match chunk {
+            Chunk::Arena(arena) => arena.committed_bytes(),
+            Chunk::Heap(heap) => heap.size,
+        })
+    }
+
+    /// Number of bytes of address space reserved so far.
+    /// Heap chunks only reserve what they allocate.
+    pub fn reserved_bytes(&self) -> usize {
+        self.sum_chunks(|chunk| match chunk {
+            Chunk::Arena(arena) => arena.reserved_bytes(),
+            Chunk::Heap(heap) => heap.size,
+        })
+    }
+
+    fn sum_chunks(&self, f: impl Fn(&Chunk) -> usize) -> usize {
+        self.chunks().iter().map(f).sum()
+    }
+
+    /// Frees everything at once, only keeping the current chunk to allocate from.
+    pub fn clear(&mut self) {
+        let chunks = self.chunks.get_mut();
+        let current = chunks.len() - 1;
+        chunks.drain(..current);
+        match &mut chunks[0] {
+            Chunk::Arena(arena) => arena.free_all(),
+            Chunk::Heap(heap) => heap.used.set(0),
+        }
+    }
+
+    /// Gives the committed pages past the last allocation back, see [`Arena::shrink_to_fit`].
+    pub fn shrink_to_fit(&mut self) {
+        for chunk in self.chunks.get_mut() {
+            if let Chunk::Arena(arena) = chunk {
+                arena.shrink_to_fit();
+            }
+        }
+    }
+}
+
+impl Chunk {
+    #[inline]
+    fn try_alloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        match self {
+            Chunk::Arena(arena) => unsafe {
+                let addr = ceil_align_ptr(arena.bump_addr.get(), layout.align());
+                if addr > arena.end_addr
+                    || layout.size() > arena.end_addr.offset_from(addr) as usize
+                {
+                    return None;
+                }
+                let ptr = arena.alloc_region(layout.size(), layout.align());
+                Some(NonNull::new_unchecked(ptr))
+            },
+            Chunk::Heap(heap) => heap.try_alloc(layout),
+        }
+    }
+}
+
+impl HeapChunk {
+    /// Size of the first heap chunk.
+    const MIN_SIZE: usize = 64 * KIB;
+
+    fn new(size: usize) -> Self {
+        let layout =
+            Layout::from_size_align(size, HEAP_CHUNK_ALIGN).expect("ByteArena is too large");
+        let ptr = unsafe { alloc::alloc(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            alloc::handle_alloc_error(layout);
+        };
+
+        Self {
+            ptr,
+            size,
+            used: Cell::new(0),
+        }
+    }
+
+    #[inline]
+    fn try_alloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let addr = self.ptr.as_ptr() as usize + self.used.get();
+        let start = ceil_align(addr, layout.align()) - self.ptr.as_ptr() as usize;
+        if start > self.size || layout.size() > self.size - start {
+            return None;
+        }
+
+        self.used.set(start + layout.size());
+        // SAFETY: the allocation is within the chunk
+        Some(unsafe { NonNull::new_unchecked(self.ptr.as_ptr().add(start)) })
+    }
+}
+
+impl Drop for HeapChunk {
+    fn drop(&mut self) {
+        unsafe {
+            alloc::dealloc(
+                self.ptr.as_ptr(),
+                Layout::from_size_align_unchecked(self.size, HEAP_CHUNK_ALIGN),
+            );
+        }
+    }
+}
+
+impl fmt::Debug for ByteArena {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ByteArena")
+            .field("used_bytes", &self.used_bytes())
+            .field("chunks", &self.chunks().len())
+            .finish()
+    }
+}
+
+// nodes
+
+/// Handle to a node of a [`NodeArena`], as small as an index and as cheap to compare.
+pub struct NodeId<T> {
+    idx: u32,
+    _node: PhantomData<fn() -> T>,
+}
+
+impl<T> NodeId<T> {
+    /// Index of the node in its arena, in order of allocation.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.idx as usize
+    }
+
+    /// Handle of the same node once the nodes of its arena are appended to an arena
+    /// that had `offset` of them.
+    #[inline]
+    pub fn offset(self, offset: usize) -> Self {
+        Self {
+            idx: self.idx + offset as u32,
+            _node: PhantomData,
+        }
+    }
+}
+
+// manual impls, as derives would require `T` to implement the traits as well
+impl<T> Clone for NodeId<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for NodeId<T> {}
+
+impl<T> PartialEq for NodeId<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx
+    }
+}
+
+impl<T> Eq for NodeId<T> {}
+
+impl<T> PartialOrd for NodeId<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for NodeId<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.idx.cmp(&other.idx)
+    }
+}
+
+impl<T> std::hash::Hash for NodeId<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.idx.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for NodeId<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeId({})", self.idx)
+    }
+}
+
+/// Arena of nodes of a single type, like the ones of an AST, referenced by [`NodeId`].
+///
+/// Nodes are bump-allocated in an [`ArenaVec`], so allocating one never goes to the heap
+/// and they are all freed at once. A node can be allocated while others are borrowed,
+/// and handles stay valid for as long as the arena lives.
+pub struct NodeArena<T> {
+    nodes: ArenaVec<T>,
+}
+
+impl<T> NodeArena<T> {
+    pub fn new(addr_space_size: usize) -> Self {
+        Self {
+            // handles are 32-bit
+            nodes: ArenaVec::with_max_len(addr_space_size, u32::MAX as usize),
+        }
+    }
+
+    /// Allocates a node, panicking if the arena already has `u32::MAX` nodes.
+    #[inline]
+    pub fn alloc(&self, node: T) -> NodeId<T>
+    where
+        T: Copy,
+    {
+        let idx = self.nodes.len() as u32;
+        self.nodes.add(node);
+        NodeId {
+            idx,
+            _node: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, id: NodeId<T>) -> &T {
+        &self.nodes[id.index()]
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, id: NodeId<T>) -> &mut T {
+        &mut self.nodes[id.index()]
+    }
+
+    /// Goes through the nodes in order of allocation.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId<T>, &'_ T)> {
+        (self.nodes.iter().enumerate()).map(|(idx, node)| {
+            let id = NodeId {
+                idx: idx as u32,
+                _node: PhantomData,
+            };
+            (id, node)
+        })
+    }
+
+    /// Handles of the nodes in order of allocation, which don't borrow the arena.
+    pub fn ids(&self) -> impl Iterator<Item = NodeId<T>> {
+        (0..self.nodes.len() as u32).map(|idx| NodeId {
+            idx,
+            _node: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Frees all the nodes at once, invalidating their handles.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+
+    /// Gives the memory that isn't taken by the nodes back, see [`ArenaVec::shrink_to_fit`].
+    pub fn shrink_to_fit(&mut self) {
+        self.nodes.shrink_to_fit();
+    }
+
+    /// Memory taken by the nodes.
+    pub fn memory(&self) -> ArrayMemory {
+        ArrayMemory::of(&self.nodes)
+    }
+
+    /// Current number of nodes, to roll back to with [`NodeArena::truncate_to`].
+    #[inline]
+    pub fn checkpoint(&self) -> Mark {
+        self.nodes.checkpoint()
+    }
+
+    /// Frees the nodes allocated since the checkpoint, invalidating their handles:
+    /// they refer to the next nodes that get allocated instead.
+    #[inline]
+    pub fn truncate_to(&mut self, mark: Mark) {
+        self.nodes.truncate_to(mark);
+    }
+
+    /// Nodes as a slice, indexed by [`NodeId::index`].
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        self.nodes.as_slice()
+    }
+}
+
+impl<T> Index<NodeId<T>> for NodeArena<T> {
+    type Output = T;
+
+    fn index(&self, id: NodeId<T>) -> &Self::Output {
+        self.get(id)
+    }
+}
+
+impl<T> IndexMut<NodeId<T>> for NodeArena<T> {
+    fn index_mut(&mut self, id: NodeId<T>) -> &mut Self::Output {
+        self.get_mut(id)
+    }
+}
+
+impl<T: Debug> fmt::Debug for NodeArena<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// slices
+
+/// Handle to a slice of a [`SliceArena`], small enough for nodes to store their
+/// child lists by value.
+pub struct ArenaSlice<T> {
+    start: u32,
+    len: u32,
+    _elem: PhantomData<fn() -> T>,
+}
+
+impl<T> ArenaSlice<T> {
+    /// Slice without elements, which any arena resolves to `&[]`.
+    pub const EMPTY: Self = Self {
+        start: 0,
+        len: 0,
+        _elem: PhantomData,
+    };
+
+    #[inline]
+    pub fn len(self) -> usize {
+        self.len as usize
+    }
+
+    #[inline]
+    pub fn is_empty(self) -> bool {
+        self.len == 0
+    }
+
+    /// Handle of the same slice once the elements of its arena are appended to an arena
+    /// that had `offset` of them, the empty slice staying [`ArenaSlice::EMPTY`].
+    #[inline]
+    pub fn offset(self, offset: usize) -> Self {
+        match self.len {
+            0 => Self::EMPTY,
+            _ => Self {
+                start: self.start + offset as u32,
+                ..self
+            },
+        }
+    }
+
+    #[inline]
+    fn range(self) -> std::ops::Range<usize> {
+        self.start as usize..self.start as usize + self.len as usize
+    }
+}
+
+impl<T> Default for ArenaSlice<T> {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+// manual impls, as derives would require `T` to implement the traits as well
+impl<T> Clone for ArenaSlice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ArenaSlice<T> {}
+
+impl<T> PartialEq for ArenaSlice<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.start, self.len) == (other.start, other.len)
+    }
+}
+
+impl<T> Eq for ArenaSlice<T> {}
+
+impl<T> std::hash::Hash for ArenaSlice<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.start, self.len).hash(state);
+    }
+}
+
+impl<T> fmt::Debug for ArenaSlice<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ArenaSlice({:?})", self.range())
+    }
+}
+
+/// Arena of slices of a single type, like the child lists of AST nodes, referenced by
+/// [`ArenaSlice`].
+///
+/// The children of a node are only known once they are all parsed, along with their own
+/// children. So slices are built with [`SliceArena::small_vec`], whose elements are pushed
+/// to a scratch stack and copied to the arena in one go once it's frozen. Lists being built
+/// can be nested, but only the innermost one can be pushed to.
+pub struct SliceArena<T> {
+    elements: ArenaVec<T>,
+    /// Elements of the vectors being built, from the outermost to the innermost one
+    scratch: ArenaVec<T>,
+    /// Number of vectors being built
+    depth: Cell<usize>,
+}
+
+impl<T: Copy> SliceArena<T> {
+    pub fn new(addr_space_size: usize) -> Self {
+        Self {
+            // handles are 32-bit
+            elements: ArenaVec::with_max_len(addr_space_size, u32::MAX as usize),
+            scratch: ArenaVec::new(addr_space_size),
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Allocates a copy of a slice, panicking if the arena would have more than
+    /// `u32::MAX` elements.
+    ///
+    /// Empty slices are all [`ArenaSlice::EMPTY`], wherever they are allocated.
+    pub fn alloc(&self, values: &[T]) -> ArenaSlice<T> {
+        if values.is_empty() {
+            return ArenaSlice::EMPTY;
+        }
+        let start = self.elements.len() as u32;
+        self.elements.extend_from_slice(values);
+        ArenaSlice {
+            start,
+            len: values.len() as u32,
+            _elem: PhantomData,
+        }
+    }
+
+    /// Starts building a slice, which is allocated once [`ArenaSmallVec::freeze`] is called.
+    ///
+    /// Until it's frozen or dropped, it is the innermost vector: the ones started before
+    /// can't be pushed to.
+    pub fn small_vec(&self) -> ArenaSmallVec<'_, T> {
+        let depth = self.depth.get() + 1;
+        self.depth.set(depth);
+        ArenaSmallVec {
+            arena: self,
+            start: self.scratch.len(),
+            len: 0,
+            depth,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, slice: ArenaSlice<T>) -> &[T] {
+        &self.elements[slice.range()]
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, slice: ArenaSlice<T>) -> &mut [T] {
+        &mut self.elements[slice.range()]
+    }
+
+    /// Number of elements of all the slices.
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Elements of all the slices, in order of allocation.
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        self.elements.as_slice()
+    }
+
+    /// Frees all the slices at once, invalidating their handles.
+    pub fn clear(&mut self) {
+        self.elements.clear();
+        self.scratch.clear();
+        *self.depth.get_mut() = 0;
+    }
+
+    /// Gives the memory that isn't taken by the slices back, see [`ArenaVec::shrink_to_fit`].
+    pub fn shrink_to_fit(&mut self) {
+        self.elements.shrink_to_fit();
+        self.scratch.shrink_to_fit();
+    }
+
+    /// Memory taken by the slices, including the stack that they are built on.
+    pub fn memory(&self) -> ArrayMemory {
+        ArrayMemory::of(&self.elements) + ArrayMemory::of(&self.scratch)
+    }
+}
+
+impl<T: Copy> Index<ArenaSlice<T>> for SliceArena<T> {
+    type Output = [T];
+
+    fn index(&self, slice: ArenaSlice<T>) -> &Self::Output {
+        self.get(slice)
+    }
+}
+
+impl<T: Copy> IndexMut<ArenaSlice<T>> for SliceArena<T> {
+    fn index_mut(&mut self, slice: ArenaSlice<T>) -> &mut Self::Output {
+        self.get_mut(slice)
+    }
+}
+
+impl<T: Debug> fmt::Debug for SliceArena<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SliceArena")
+            .field("elements", &self.elements.as_slice())
+            .field("depth", &self.depth.get())
+            .finish()
+    }
+}
+
+/// Slice being built in a [`SliceArena`], see [`SliceArena::small_vec`].
+///
+/// Dropping it without freezing it discards its elements, like when parsing fails halfway.
+pub struct ArenaSmallVec<'a, T: Copy> {
+    arena: &'a SliceArena<T>,
+    /// Index of the first element in the scratch stack
+    start: usize,
+    len: usize,
+    depth: usize,
+}
+
+impl<T: Copy> ArenaSmallVec<'_, T> {
+    /// Adds an element, panicking if a vector started after this one is still being built.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        self.assert_innermost();
+        self.arena.scratch.add(value);
+        self.len += 1;
+    }
+
+    /// Adds copies of the elements of a slice, panicking like [`ArenaSmallVec::push`].
+    pub fn extend_from_slice(&mut self, values: &[T]) {
+        self.assert_innermost();
+        self.arena.scratch.extend_from_slice(values);
+        self.len += values.len();
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        &self.arena.scratch[self.start..self.start + self.len]
+    }
+
+    /// Allocates the elements in the arena as one contiguous slice.
+    pub fn freeze(self) -> ArenaSlice<T> {
+        // the elements are taken off the scratch stack when `self` is dropped
+        self.arena.alloc(self.as_slice())
+    }
+
+    #[inline]
+    fn assert_innermost(&self) {
+        assert_eq!(
+            self.arena.depth.get(),
+            self.depth,
+            "Cannot push to a small vector while a nested one is being built"
+        );
+    }
+}
+
+impl<T: Copy> Drop for ArenaSmallVec<'_, T> {
+    fn drop(&mut self) {
+        // vectors dropped out of order leave their elements on the stack until it's cleared
+        if self.arena.depth.get() == self.depth {
+            // SAFETY: the elements are `Copy` and only borrowed through `self`,
+            // as the vectors started after this one have been dropped
+            unsafe { self.arena.scratch.set_len(self.start) };
+            self.arena.depth.set(self.depth - 1);
+        }
+    }
+}
+
+impl<T: Copy + Debug> fmt::Debug for ArenaSmallVec<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+
+// parallel iteration
+
+#[cfg(feature = "rayon")]
+mod parallel {
+    use std::marker::PhantomData;
+
+    use rayon::prelude::*;
+
+    use super::{ArenaVec, NodeArena, NodeId};
+
+    impl<T: Sync> ArenaVec<T> {
+        /// Iterates over the elements on the threads of the rayon pool.
+        pub fn par_iter(&self) -> rayon::slice::Iter<'_, T> {
+            self.as_slice().par_iter()
+        }
+    }
+
+    impl<T: Send> ArenaVec<T> {
+        pub fn par_iter_mut(&mut self) -> rayon::slice::IterMut<'_, T> {
+            self.as_mut_slice().par_iter_mut()
+        }
+    }
+
+    impl<'a, T: Sync> IntoParallelIterator for &'a ArenaVec<T> {
+        type Item = &'a T;
+        type Iter = rayon::slice::Iter<'a, T>;
+
+        fn into_par_iter(self) -> Self::Iter {
+            self.par_iter()
+        }
+    }
+
+    impl<'a, T: Send> IntoParallelIterator for &'a mut ArenaVec<T> {
+        type Item = &'a mut T;
+        type Iter = rayon::slice::IterMut<'a, T>;
+
+        fn into_par_iter(self) -> Self::Iter {
+            self.par_iter_mut()
+        }
+    }
+
+    impl<T: Sync> NodeArena<T> {
+        /// Iterates over the nodes and their ID on the threads of the rayon pool.
+        pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = (NodeId<T>, &'_ T)> {
+            let nodes = self.as_slice().par_iter().enumerate();
+            nodes.map(|(idx, node)| {
+                let id = NodeId {
+                    idx: idx as u32,
+                    _node: PhantomData,
+                };
+                (id, node)
+            })
+        }
+    }
+}
+
+// symbols
+
+/// Interned string, see [`Interner`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Index of the symbol in its interner, in order of interning.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Where the string of a symbol is, and the previous symbol whose string has the same hash.
+#[derive(Debug, Clone, Copy)]
+struct SymbolEntry {
+    start: u32,
+    len: u32,
+    next_same_hash: Option<Symbol>,
+}
+
+/// Deduplicates strings like identifiers into [`Symbol`]s, which compare as integers.
+///
+/// Strings are copied next to each other in arena memory, and symbols with the same hash
+/// are chained through their entries, so interning a new string never goes to the heap
+/// besides growing the hash table. There can be at most 4 GiB of interned strings.
+pub struct Interner {
+    bytes: ArenaVec<u8>,
+    entries: ArenaVec<SymbolEntry>,
+    /// Last interned symbol for each hash
+    by_hash: HashMap<u64, Symbol>,
+}
+
+impl Interner {
+    pub fn new(addr_space_size: usize) -> Self {
+        Self {
+            bytes: ArenaVec::with_max_len(addr_space_size, u32::MAX as usize),
+            entries: ArenaVec::with_max_len(addr_space_size / 4, u32::MAX as usize),
+            by_hash: HashMap::new(),
+        }
+    }
+
+    /// Symbol of the string, interning it if it wasn't already.
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        let hash = hash_str(s);
+        if let Some(symbol) = self.find(hash, s) {
+            return symbol;
+        }
+
+        let start = self.bytes.len() as u32;
+        self.bytes.extend(s.as_bytes());
+
+        let symbol = Symbol(self.entries.len() as u32);
+        self.entries.add(SymbolEntry {
+            start,
+            len: s.len() as u32,
+            next_same_hash: self.by_hash.insert(hash, symbol),
+        });
+        symbol
+    }
+
+    /// Symbol of the string if it was interned.
+    pub fn get(&self, s: &str) -> Option<Symbol> {
+        self.find(hash_str(s), s)
+    }
+
+    fn find(&self, hash: u64, s: &str) -> Option<Symbol> {
+        let mut symbol = self.by_hash.get(&hash).copied();
+        while let Some(candidate) = symbol {
+            if self.resolve(candidate) == s {
+                return Some(candidate);
+            }
+            symbol = self.entries[candidate.index()].next_same_hash;
+        }
+        None
+    }
+
+    /// String of a symbol, panicking if it comes from another interner.
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        let entry = self.entries[symbol.index()];
+        let start = entry.start as usize;
+        let bytes = &self.bytes.as_slice()[start..start + entry.len as usize];
+
+        // SAFETY: only whole strings are interned
+        unsafe { std::str::from_utf8_unchecked(bytes) }
+    }
+
+    /// Number of interned strings.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Debug for Interner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbols = (0..self.len() as u32).map(|idx| (Symbol(idx), self.resolve(Symbol(idx))));
+        f.debug_map().entries(symbols).finish()
+    }
+}
+
+/// FNV-1a hash, fast on short strings like identifiers.
+fn hash_str(s: &str) -> u64 {
+    (s.bytes()).fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
 }
 
 #[cfg(test)]
@@ -455,3 +3167,712 @@ mod tests_ceil_align {
         assert_eq!(ceil_align(19, 16), 32);
     }
 }
+
+#[cfg(test)]
+mod tests_limits {
+    use crate::arena::{probe_limits, Arena, MIB, RESERVES_ADDR_SPACE};
+
+    #[test]
+    fn probe() {
+        let limits = probe_limits();
+        assert_eq!(probe_limits(), limits);
+        assert_eq!(limits.max_reservation > 0, RESERVES_ADDR_SPACE);
+        if RESERVES_ADDR_SPACE {
+            assert!(Arena::try_new(limits.max_reservation / 2).is_ok());
+        }
+
+        assert_eq!(limits.check_reservation(0), Ok(()));
+        let error = limits.check_reservation(usize::MAX).unwrap_err();
+        assert_eq!((error.size, error.limits), (usize::MAX, limits));
+        let message = error.to_string();
+        assert!(message.starts_with(&format!("Cannot reserve {} bytes", usize::MAX)));
+        assert_eq!(
+            message.contains("ulimit -v"),
+            limits.addr_space_rlimit.is_some() && RESERVES_ADDR_SPACE
+        );
+        assert!(limits.check_reservation(MIB).is_ok() || !RESERVES_ADDR_SPACE);
+    }
+}
+
+#[cfg(test)]
+mod tests_arena_vec {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::arena::{ArenaVec, CapacityError, MIB, RESERVES_ADDR_SPACE};
+
+    #[test]
+    fn heap_fallback() {
+        // more address space than there is
+        assert!(ArenaVec::<u8>::try_new(usize::MAX / 2).is_err());
+        let vec = ArenaVec::new(usize::MAX / 2);
+        assert!(vec.is_on_heap());
+        assert_eq!(ArenaVec::<u8>::new(MIB).is_on_heap(), !RESERVES_ADDR_SPACE);
+
+        // references to elements stay valid after the buffer grows
+        vec.add(0usize);
+        let first = &vec[0];
+        for i in 1..100_000 {
+            vec.add(i);
+        }
+        assert_eq!(*first, 0);
+        assert!(vec.iter().copied().eq(0..100_000));
+        assert!(vec.committed_bytes() > vec.used_bytes());
+    }
+
+    struct Counted(Rc<Cell<usize>>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn arena_drops() {
+        let drops = Rc::new(Cell::new(0));
+        let mut vec = ArenaVec::new(MIB);
+        for _ in 0..1000 {
+            vec.push(Counted(drops.clone()));
+        }
+
+        vec.truncate(400);
+        assert_eq!(drops.get(), 600);
+        drop(vec);
+        assert_eq!(drops.get(), 1000);
+    }
+
+    #[test]
+    fn arena_clear() {
+        let mut vec = ArenaVec::new(MIB);
+        for i in 0..10_000u64 {
+            vec.add(i);
+        }
+        vec.truncate(10);
+        assert!(vec.committed_bytes() > 0);
+
+        vec.clear();
+        assert_eq!((vec.len(), vec.committed_bytes()), (0, 0));
+
+        // the memory can be used again
+        vec.add(1);
+        assert_eq!(vec.as_slice(), [1]);
+    }
+
+    #[test]
+    fn grows_past_reservation() {
+        let vec = ArenaVec::new(1);
+        vec.add(0u64);
+        let first = &vec[0];
+        for i in 1..100_000 {
+            vec.add(i);
+        }
+
+        assert_eq!(vec.is_on_heap(), !RESERVES_ADDR_SPACE);
+        assert_eq!(*first, 0);
+        assert!(vec.iter().copied().eq(0..100_000));
+    }
+
+    #[test]
+    fn max_len() {
+        let vec = ArenaVec::with_max_len(MIB, 3);
+        for i in 0..3 {
+            assert_eq!(vec.try_add(i), Ok(()));
+        }
+
+        let error = vec.try_add(3).unwrap_err();
+        assert_eq!(error, CapacityError { max_len: 3 });
+        assert_eq!(error.to_string(), "ArenaVec is full (3 elements at most)");
+        assert_eq!(vec.as_slice(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn bulk_operations() {
+        for mut vec in [ArenaVec::new(1), ArenaVec::on_small_heap()] {
+            vec.add(0u32);
+            let first = &vec[0];
+            vec.extend_from_slice(&[1, 2, 3]);
+            vec.push_n(7, 10_000);
+            // the slice can come from the vector itself
+            vec.extend_from_slice(&vec[..4]);
+            assert_eq!(*first, 0);
+            assert_eq!(vec.len(), 10_008);
+            assert_eq!(vec[..4], [0, 1, 2, 3]);
+            assert!(vec[4..10_004].iter().all(|&n| n == 7));
+            assert_eq!(vec[10_004..], [0, 1, 2, 3]);
+
+            assert_eq!(vec.pop(), Some(3));
+            *vec.last_mut().unwrap() = 9;
+            let mut next = 100;
+            vec.resize_with(10_010, || {
+                next += 1;
+                next
+            });
+            assert_eq!(vec[10_005..], [1, 9, 101, 102, 103]);
+            vec.resize_with(2, || unreachable!());
+            assert_eq!(vec.as_slice(), [0, 1]);
+
+            vec.truncate(0);
+            assert_eq!((vec.pop(), vec.last_mut()), (None, None));
+        }
+
+        let vec = ArenaVec::with_max_len(MIB, 3);
+        vec.add(0);
+        assert_eq!(
+            vec.try_extend_from_slice(&[1, 2, 3]),
+            Err(CapacityError { max_len: 3 })
+        );
+        assert_eq!(vec.try_extend_from_slice(&[1, 2]), Ok(()));
+        assert_eq!(vec.as_slice(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn resize_with_panic() {
+        let drops = Rc::new(Cell::new(0));
+        let mut vec = ArenaVec::on_small_heap();
+        let mut count = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vec.resize_with(10, || {
+                count += 1;
+                if count == 5 {
+                    panic!("oops");
+                }
+                Counted(drops.clone())
+            });
+        }));
+
+        // the elements written before the panic are kept, and dropped once
+        assert!(result.is_err());
+        assert_eq!(vec.len(), 4);
+        drop(vec);
+        assert_eq!(drops.get(), 4);
+    }
+
+    #[test]
+    fn heap_drops() {
+        let drops = Rc::new(Cell::new(0));
+        let mut vec = ArenaVec::on_heap();
+        for _ in 0..100_000 {
+            vec.push(Counted(drops.clone()));
+        }
+        // previous buffers are freed once nothing can reference them
+        let committed = vec.committed_bytes();
+        vec.truncate(40_000);
+        assert_eq!((drops.get(), vec.len()), (60_000, 40_000));
+        assert!(vec.committed_bytes() < committed);
+
+        drop(vec);
+        assert_eq!(drops.get(), 100_000);
+    }
+
+    #[test]
+    fn interior_mutability() {
+        // elements that own memory and can be written through shared references
+        // are only added through a mutable one, so none are duplicated when moving them
+        let mut vec = ArenaVec::on_small_heap();
+        for i in 0..1000 {
+            vec.push(Cell::new(Some(Box::new(i))));
+            let first = &vec[0];
+            let taken = first.take();
+            first.set(taken.map(|n| Box::new(*n + 1)));
+        }
+        assert_eq!(vec[0].take().as_deref(), Some(&1000));
+        assert!((vec.iter().skip(1)).all(|cell| cell.take().is_some()));
+    }
+
+    #[test]
+    fn std_traits() {
+        let mut vec: ArenaVec<u32> = (0..10).collect();
+        vec.extend([10, 11]);
+        vec.extend(&[12]);
+
+        // slice methods through `Deref`
+        assert_eq!(vec.first(), Some(&0));
+        assert!(vec.contains(&12));
+        vec.reverse();
+        vec[0] = 42;
+        for n in &mut vec {
+            *n += 1;
+        }
+        assert!((&vec)
+            .into_iter()
+            .eq(&[43, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1]));
+        assert_eq!(format!("{:?}", &vec[..3]), "[43, 12, 11]");
+    }
+
+    #[test]
+    fn into_iter() {
+        let drops = Rc::new(Cell::new(0));
+        let vec: ArenaVec<_> = (0..100).map(|_| Counted(drops.clone())).collect();
+
+        let mut iter = vec.into_iter();
+        assert_eq!(iter.len(), 100);
+        drop(iter.next());
+        drop(iter.next_back());
+        assert_eq!((drops.get(), iter.as_slice().len()), (2, 98));
+
+        // the elements that are left are dropped with the iterator, only once
+        drop(iter);
+        assert_eq!(drops.get(), 100);
+
+        let vec: ArenaVec<_> = ["a", "b", "c"].into_iter().collect();
+        assert_eq!(vec.into_iter().rev().collect::<Vec<_>>(), ["c", "b", "a"]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter() {
+        use rayon::prelude::*;
+
+        let mut vec: ArenaVec<u64> = (0..1_000_000).collect();
+        vec.par_iter_mut().for_each(|n| *n *= 2);
+        assert_eq!(vec.par_iter().sum::<u64>(), 999_999 * 1_000_000);
+        assert_eq!(
+            (&vec).into_par_iter().filter(|&&n| n % 4 == 0).count(),
+            500_000
+        );
+    }
+
+    #[test]
+    fn huge_pages() {
+        let vec = ArenaVec::with_huge_pages(MIB);
+        for i in 0..1_000_000u64 {
+            vec.add(i);
+        }
+        assert!(vec.iter().copied().eq(0..1_000_000));
+
+        #[cfg(target_os = "linux")]
+        if RESERVES_ADDR_SPACE {
+            use crate::arena::Arena;
+
+            let huge_page_size = 2 * MIB;
+            assert_eq!(vec.committed_bytes() % huge_page_size, 0);
+
+            let arena = Arena::try_with_huge_pages(MIB).unwrap();
+            assert!(arena.uses_huge_pages());
+            assert_eq!(arena.reserved_bytes(), huge_page_size);
+            let ptr = arena.alloc(1u8) as *mut u8;
+            assert_eq!(ptr as usize % huge_page_size, 0);
+            assert_eq!(arena.committed_bytes(), huge_page_size);
+        }
+    }
+
+    #[test]
+    fn checkpoints() {
+        let drops = Rc::new(Cell::new(0));
+        let mut vec = ArenaVec::new(MIB);
+        vec.push(Counted(drops.clone()));
+
+        let mark = vec.checkpoint();
+        for _ in 0..10 {
+            vec.push(Counted(drops.clone()));
+        }
+        let inner = vec.checkpoint();
+        vec.push(Counted(drops.clone()));
+
+        vec.truncate_to(inner);
+        assert_eq!((vec.len(), drops.get()), (11, 1));
+        vec.truncate_to(mark);
+        assert_eq!((vec.len(), drops.get()), (1, 11));
+
+        // rolling back to a later checkpoint does nothing
+        vec.truncate_to(inner);
+        assert_eq!(vec.len(), 1);
+        assert!(mark < inner);
+    }
+
+    #[test]
+    fn small_heap() {
+        let vec = ArenaVec::on_small_heap();
+        vec.add(0u8);
+        let first = &vec[0];
+        assert_eq!(vec.committed_bytes(), 1);
+
+        for i in 1..=255 {
+            vec.add(i);
+        }
+        assert_eq!(*first, 0);
+        assert!(vec.iter().copied().eq(0..=255));
+        // every buffer so far is kept: 1 + 2 + 4 + ... + 256
+        assert_eq!(vec.committed_bytes(), 511);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut vec = ArenaVec::new(MIB);
+        vec.extend_from_slice(&[(); 3]);
+        vec.add(());
+        assert_eq!((vec.len(), vec.committed_bytes()), (4, 0));
+        assert_eq!(vec.pop(), Some(()));
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut vec = ArenaVec::new(MIB);
+        vec.extend(0..100_000u64);
+        vec.truncate(10);
+        assert!(vec.committed_bytes() >= 100_000 * 8);
+
+        vec.shrink_to_fit();
+        assert!(vec.iter().copied().eq(0..10));
+        assert!(vec.committed_bytes() >= vec.used_bytes());
+        assert!(vec.committed_bytes() <= 64 * 1024);
+
+        // the vector still grows afterwards
+        vec.extend(10..100_000);
+        assert!(vec.iter().copied().eq(0..100_000));
+
+        let mut vec = ArenaVec::on_small_heap();
+        vec.extend(0..100u8);
+        vec.shrink_to_fit();
+        assert_eq!(vec.committed_bytes(), 100);
+        vec.clear();
+        vec.shrink_to_fit();
+        assert_eq!(vec.committed_bytes(), 0);
+        vec.add(1);
+        assert_eq!(vec.as_slice(), [1]);
+    }
+}
+
+#[cfg(test)]
+mod tests_sync_arena_vec {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use crate::arena::{SyncArenaVec, KIB, MIB};
+
+    #[test]
+    #[cfg_attr(
+        any(miri, target_pointer_width = "32"),
+        ignore = "arenas can't reserve address space"
+    )]
+    fn concurrent_adds() {
+        let vec = SyncArenaVec::new(16 * MIB);
+        thread::scope(|scope| {
+            for thread in 0..8 {
+                let vec = &vec;
+                scope.spawn(move || {
+                    for i in 0..50_000 {
+                        vec.add(thread * 1_000_000 + i);
+                    }
+                });
+            }
+        });
+        assert_eq!(vec.len(), 400_000);
+
+        let mut vec = vec.into_arena_vec();
+        vec.as_mut_slice().sort_unstable();
+        let expected = (0..8).flat_map(|thread| (0..50_000).map(move |i| thread * 1_000_000 + i));
+        assert!(vec.iter().copied().eq(expected));
+
+        // it's a regular vector from now on
+        vec.add(42);
+        assert_eq!(vec.len(), 400_001);
+    }
+
+    #[test]
+    #[cfg_attr(
+        any(miri, target_pointer_width = "32"),
+        ignore = "arenas can't reserve address space"
+    )]
+    fn full() {
+        let vec = SyncArenaVec::<u64>::new(64 * KIB);
+        let max_len = vec.max_len();
+        assert!(max_len >= 64 * KIB / 8);
+
+        let failures = Mutex::new(0);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..max_len as u64 {
+                        if vec.try_add(i).is_err() {
+                            *failures.lock().unwrap() += 1;
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(vec.len(), max_len);
+        assert_eq!(*failures.lock().unwrap(), 3 * max_len);
+        assert_eq!(
+            vec.try_add(0).unwrap_err().to_string(),
+            format!("ArenaVec is full ({max_len} elements at most)")
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        any(miri, target_pointer_width = "32"),
+        ignore = "arenas can't reserve address space"
+    )]
+    fn drops() {
+        let counter = Arc::new(());
+        let vec = SyncArenaVec::new(MIB);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..1000).for_each(|_| _ = vec.add(counter.clone())));
+            }
+        });
+        assert_eq!(Arc::strong_count(&counter), 4001);
+        drop(vec);
+        assert_eq!(Arc::strong_count(&counter), 1);
+
+        let vec = SyncArenaVec::new(MIB);
+        vec.add(counter.clone());
+        let vec = vec.into_arena_vec();
+        assert_eq!(Arc::strong_count(&counter), 2);
+        drop(vec);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+}
+
+#[cfg(test)]
+mod tests_byte_arena {
+    use std::{alloc::Layout, mem};
+
+    use crate::arena::{ByteArena, KIB};
+
+    #[test]
+    fn mixed_types() {
+        for arena in [ByteArena::new(KIB), ByteArena::on_heap()] {
+            let byte = arena.alloc(1u8);
+            let word = arena.alloc(2u64);
+            let name = arena.alloc_str("ඞ sus");
+            let children = arena.alloc_slice_copy(&[3u32, 4, 5]);
+            let empty = arena.alloc_slice_copy::<u16>(&[]);
+
+            assert_eq!((*byte, *word), (1, 2));
+            assert_eq!(word as *mut u64 as usize % mem::align_of::<u64>(), 0);
+            assert_eq!(name, "ඞ sus");
+            assert_eq!(children, [3, 4, 5]);
+            assert!(empty.is_empty());
+
+            let page = arena.alloc_layout(Layout::from_size_align(100, 4 * KIB).unwrap());
+            assert_eq!(page.as_ptr() as usize % (4 * KIB), 0);
+            assert!(arena.used_bytes() >= 1 + 8 + 7 + 12 + 100);
+        }
+    }
+
+    #[test]
+    fn growth() {
+        let mut arena = ByteArena::new(KIB);
+        let first = arena.alloc_str("first");
+        let strings: Vec<&str> = (0..10_000)
+            .map(|i| &*arena.alloc_str(&i.to_string()))
+            .collect();
+
+        // previous allocations stay where they are
+        assert_eq!(first, "first");
+        assert!(strings.iter().enumerate().all(|(i, s)| *s == i.to_string()));
+        let large = arena.alloc_slice_copy(&[7u8; 1 << 20]);
+        assert!(large.iter().all(|&b| b == 7));
+        assert!(arena.reserved_bytes() >= arena.committed_bytes());
+        assert!(arena.committed_bytes() >= arena.used_bytes());
+
+        arena.clear();
+        assert_eq!(arena.used_bytes(), 0);
+        assert_eq!(arena.alloc_str("again"), "again");
+    }
+
+    #[test]
+    fn heap_growth() {
+        let arena = ByteArena::on_heap();
+        let values: Vec<&u64> = (0..100_000u64).map(|i| &*arena.alloc(i)).collect();
+        assert!(values.iter().copied().copied().eq(0..100_000));
+        assert!(arena.is_on_heap());
+        assert_eq!(arena.reserved_bytes(), arena.committed_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests_node_arena {
+    use crate::arena::{NodeArena, NodeId, MIB};
+
+    #[derive(Clone, Copy)]
+    enum Expr {
+        Num(u64),
+        Add(NodeId<Expr>, NodeId<Expr>),
+    }
+
+    fn eval(arena: &NodeArena<Expr>, id: NodeId<Expr>) -> u64 {
+        let (mut stack, mut sum) = (vec![id], 0);
+        while let Some(id) = stack.pop() {
+            match arena[id] {
+                Expr::Num(n) => sum += n,
+                Expr::Add(a, b) => stack.extend([a, b]),
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn nodes() {
+        let mut arena = NodeArena::new(MIB);
+        let one = arena.alloc(Expr::Num(1));
+        let first = &arena[one];
+
+        // nodes can be allocated while others are borrowed
+        let mut sum = arena.alloc(Expr::Num(0));
+        for _ in 0..100_000 {
+            sum = arena.alloc(Expr::Add(sum, one));
+        }
+        assert!(matches!(first, Expr::Num(1)));
+        assert_eq!(eval(&arena, sum), 100_000);
+        assert_eq!((arena.len(), sum.index()), (100_002, 100_001));
+
+        arena[one] = Expr::Num(2);
+        assert_eq!(eval(&arena, sum), 200_000);
+        assert_eq!(arena.iter().next().map(|(id, _)| id), Some(one));
+        assert_eq!(format!("{one:?}"), "NodeId(0)");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_iter() {
+        use rayon::prelude::*;
+
+        let arena = NodeArena::new(MIB);
+        let ids: Vec<_> = (0..10_000).map(|i| arena.alloc(Expr::Num(i))).collect();
+        let found: Vec<_> = (arena.par_iter())
+            .filter_map(|(id, expr)| matches!(expr, Expr::Num(n) if n % 1000 == 0).then_some(id))
+            .collect();
+        assert_eq!(found, ids.iter().copied().step_by(1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn speculative_nodes() {
+        let mut arena = NodeArena::new(MIB);
+        let one = arena.alloc(Expr::Num(1));
+
+        // a trial parse that doesn't pan out
+        let mark = arena.checkpoint();
+        let two = arena.alloc(Expr::Num(2));
+        arena.alloc(Expr::Add(one, two));
+        arena.truncate_to(mark);
+        assert_eq!(arena.len(), 1);
+
+        let sum = arena.alloc(Expr::Add(one, one));
+        assert_eq!(eval(&arena, sum), 2);
+    }
+}
+
+#[cfg(test)]
+mod tests_slice_arena {
+    use crate::arena::{ArenaSlice, SliceArena, MIB};
+
+    #[derive(Clone, Copy)]
+    enum Item {
+        Num(u32),
+        List(ArenaSlice<Item>),
+    }
+
+    /// Parses nested lists of digits, like `1[23[]]4`.
+    fn parse(arena: &SliceArena<Item>, code: &mut std::str::Chars) -> ArenaSlice<Item> {
+        let mut items = arena.small_vec();
+        while let Some(ch) = code.next() {
+            match ch {
+                '[' => items.push(Item::List(parse(arena, code))),
+                ']' => break,
+                _ => items.push(Item::Num(ch.to_digit(10).unwrap())),
+            }
+        }
+        items.freeze()
+    }
+
+    fn sum(arena: &SliceArena<Item>, list: ArenaSlice<Item>) -> u32 {
+        (arena[list].iter())
+            .map(|item| match *item {
+                Item::Num(n) => n,
+                Item::List(list) => sum(arena, list),
+            })
+            .sum()
+    }
+
+    #[test]
+    fn nested_lists() {
+        let mut arena = SliceArena::new(MIB);
+        let list = parse(&arena, &mut "1[23[]]4[5]".chars());
+        assert_eq!(sum(&arena, list), 15);
+        assert_eq!(list.len(), 4);
+
+        // inner lists are frozen first, and each list is contiguous
+        assert_eq!(arena.len(), 4 + 3 + 1);
+        assert_eq!(format!("{list:?}"), "ArenaSlice(4..8)");
+        let Item::List(inner) = arena[list][1] else {
+            panic!()
+        };
+        assert_eq!(format!("{inner:?}"), "ArenaSlice(0..3)");
+        assert!(matches!(arena[inner][2], Item::List(empty) if empty.is_empty()));
+
+        arena[list][0] = Item::Num(6);
+        assert_eq!(sum(&arena, list), 20);
+        assert!(arena[ArenaSlice::EMPTY].is_empty());
+
+        arena.clear();
+        assert!(arena.is_empty());
+    }
+
+    #[test]
+    fn dropped_lists() {
+        let arena = SliceArena::new(MIB);
+        let mut outer = arena.small_vec();
+        outer.push(1);
+        {
+            let mut inner = arena.small_vec();
+            inner.extend_from_slice(&[2, 3]);
+            assert_eq!(inner.as_slice(), [2, 3]);
+        }
+        // the elements of the dropped list are discarded
+        outer.push(4);
+        assert_eq!(outer.as_slice(), [1, 4]);
+
+        let outer = outer.freeze();
+        let other = arena.alloc(&[5, 6]);
+        assert_eq!((&arena[outer], &arena[other]), (&[1, 4][..], &[5, 6][..]));
+    }
+
+    #[test]
+    #[should_panic = "Cannot push to a small vector while a nested one is being built"]
+    fn push_outer() {
+        let arena = SliceArena::new(MIB);
+        let mut outer = arena.small_vec();
+        let _inner = arena.small_vec();
+        outer.push(1);
+    }
+}
+
+#[cfg(test)]
+mod tests_interner {
+    use crate::arena::{Interner, MIB};
+
+    #[test]
+    fn dedup() {
+        let mut interner = Interner::new(MIB);
+        let a = interner.intern("main");
+        let b = interner.intern("ඞ");
+        assert_eq!(interner.intern("main"), a);
+        assert_ne!(a, b);
+        assert_eq!(interner.intern(""), interner.intern(""));
+
+        assert_eq!((interner.resolve(a), interner.resolve(b)), ("main", "ඞ"));
+        assert_eq!(interner.get("ඞ"), Some(b));
+        assert_eq!(interner.get("sus"), None);
+        assert_eq!(interner.len(), 3);
+    }
+
+    #[test]
+    fn many_symbols() {
+        let mut interner = Interner::new(1);
+        let symbols: Vec<_> = (0..50_000)
+            .map(|i| interner.intern(&format!("ident_{i}")))
+            .collect();
+
+        for (i, &symbol) in symbols.iter().enumerate() {
+            assert_eq!(symbol.index(), i);
+            assert_eq!(interner.resolve(symbol), format!("ident_{i}"));
+            assert_eq!(interner.intern(&format!("ident_{i}")), symbol);
+        }
+    }
+}
@@ -0,0 +1,773 @@
+//! Abstract syntax tree, as built by the [parser](crate::parser).
+//!
+//! Every kind of node is stored in a [`NodeTable`], which keeps the nodes and their spans
+//! in separate arrays like [`Tokens`] does, and nodes refer to each other through handles.
+//! Lists of nodes, like the statements of a block, are [`ArenaSlice`]s of handles.
+
+use std::{
+    fmt::{self, Debug},
+    ops::{Index, IndexMut},
+};
+
+use crate::{
+    arena::{ArenaSlice, ArenaVec, NodeArena, NodeId, SliceArena},
+    lexer::{Span, Tokens},
+    memory::ArrayMemory,
+};
+
+mod attr;
+mod emit;
+mod place;
+mod print;
+mod visit;
+
+pub use attr::{Attr, AttrKind, TokenRange, ATTRIBUTES};
+pub use emit::AstFormat;
+pub use visit::{
+    walk_ast, walk_ast_mut, walk_expr, walk_expr_mut, walk_item, walk_item_mut, walk_pattern,
+    walk_pattern_mut, walk_stmt, walk_stmt_mut, walk_type, walk_type_mut, Visitor, VisitorMut,
+};
+
+pub type ItemId = NodeId<Item>;
+pub type StmtId = NodeId<Stmt>;
+pub type ExprId = NodeId<Expr>;
+pub type TypeId = NodeId<Type>;
+pub type PatternId = NodeId<Pattern>;
+
+/// Abstract syntax tree of a file.
+///
+/// Identifiers and literals are stored as spans of the code, see [`Tokens::resolve`].
+#[derive(Debug)]
+pub struct Ast {
+    /// Top-level items, in order of appearance
+    pub items: NodeTable<Item>,
+    pub stmts: NodeTable<Stmt>,
+    pub exprs: NodeTable<Expr>,
+    pub types: NodeTable<Type>,
+    pub patterns: NodeTable<Pattern>,
+    /// Statements and deferred statements of blocks
+    pub stmt_lists: SliceArena<StmtId>,
+    /// Arguments of calls and elements of arrays and tuples
+    pub expr_lists: SliceArena<ExprId>,
+    pub params: SliceArena<Param>,
+    pub closure_params: SliceArena<ClosureParam>,
+    pub captures: SliceArena<Capture>,
+    pub fields: SliceArena<Field>,
+    pub field_inits: SliceArena<FieldInit>,
+    pub variants: SliceArena<Variant>,
+    pub arms: SliceArena<Arm>,
+    pub generics: SliceArena<GenericParam>,
+    /// Arguments of generic types, parameters of function types and elements of tuples
+    pub type_lists: SliceArena<TypeId>,
+    /// Segments of module paths and of attribute paths
+    pub paths: SliceArena<Span>,
+    /// Alternatives of or-patterns
+    pub pattern_lists: SliceArena<PatternId>,
+    /// Text around the holes of interpolated strings
+    pub string_parts: SliceArena<Span>,
+    pub holes: SliceArena<Hole>,
+    /// Attributes of items, fields and statements, and of the file
+    pub attrs: SliceArena<Attr>,
+    /// Inner attributes at the start of the file, like `#![doc("...")]`
+    pub file_attrs: ArenaSlice<Attr>,
+    /// Attributes of the statements that have some, by statement in order, see [`Ast::stmt_attrs`]
+    pub stmt_attrs: ArenaVec<(StmtId, ArenaSlice<Attr>)>,
+}
+
+impl Ast {
+    pub(crate) fn new(tokens: &Tokens) -> Self {
+        // there are fewer nodes of each kind than tokens
+        let max_nodes = tokens.types.len();
+        let reservation = tokens.options.reservation;
+        Self {
+            items: NodeTable::new(reservation.size_for::<Item>(max_nodes)),
+            stmts: NodeTable::new(reservation.size_for::<Stmt>(max_nodes)),
+            exprs: NodeTable::new(reservation.size_for::<Expr>(max_nodes)),
+            types: NodeTable::new(reservation.size_for::<Type>(max_nodes)),
+            patterns: NodeTable::new(reservation.size_for::<Pattern>(max_nodes)),
+            stmt_lists: SliceArena::new(reservation.size_for::<StmtId>(max_nodes)),
+            expr_lists: SliceArena::new(reservation.size_for::<ExprId>(max_nodes)),
+            params: SliceArena::new(reservation.size_for::<Param>(max_nodes)),
+            closure_params: SliceArena::new(reservation.size_for::<ClosureParam>(max_nodes)),
+            captures: SliceArena::new(reservation.size_for::<Capture>(max_nodes)),
+            fields: SliceArena::new(reservation.size_for::<Field>(max_nodes)),
+            field_inits: SliceArena::new(reservation.size_for::<FieldInit>(max_nodes)),
+            variants: SliceArena::new(reservation.size_for::<Variant>(max_nodes)),
+            arms: SliceArena::new(reservation.size_for::<Arm>(max_nodes)),
+            generics: SliceArena::new(reservation.size_for::<GenericParam>(max_nodes)),
+            type_lists: SliceArena::new(reservation.size_for::<TypeId>(max_nodes)),
+            paths: SliceArena::new(reservation.size_for::<Span>(max_nodes)),
+            pattern_lists: SliceArena::new(reservation.size_for::<PatternId>(max_nodes)),
+            string_parts: SliceArena::new(reservation.size_for::<Span>(max_nodes)),
+            holes: SliceArena::new(reservation.size_for::<Hole>(max_nodes)),
+            attrs: SliceArena::new(reservation.size_for::<Attr>(max_nodes)),
+            file_attrs: ArenaSlice::EMPTY,
+            stmt_attrs: ArenaVec::new(
+                reservation.size_for::<(StmtId, ArenaSlice<Attr>)>(max_nodes),
+            ),
+        }
+    }
+
+    /// Memory taken by the nodes and lists of the tree.
+    pub fn memory(&self) -> ArrayMemory {
+        let tables = [
+            self.items.memory(),
+            self.stmts.memory(),
+            self.exprs.memory(),
+            self.types.memory(),
+            self.patterns.memory(),
+        ];
+        let lists = [
+            self.stmt_lists.memory(),
+            self.expr_lists.memory(),
+            self.params.memory(),
+            self.closure_params.memory(),
+            self.captures.memory(),
+            self.fields.memory(),
+            self.field_inits.memory(),
+            self.variants.memory(),
+            self.arms.memory(),
+            self.generics.memory(),
+            self.type_lists.memory(),
+            self.paths.memory(),
+            self.pattern_lists.memory(),
+            self.string_parts.memory(),
+            self.holes.memory(),
+            self.attrs.memory(),
+        ];
+        let stmt_attrs = ArrayMemory::of(&self.stmt_attrs);
+        tables.into_iter().chain(lists).sum::<ArrayMemory>() + stmt_attrs
+    }
+}
+
+/// Nodes of a single kind along with their spans, in parallel arrays indexed by [`NodeId`].
+///
+/// Passes that only look at the nodes don't load their spans and the other way around,
+/// so nodes stay small and packed together.
+pub struct NodeTable<T> {
+    nodes: NodeArena<T>,
+    spans: ArenaVec<Span>,
+}
+
+impl<T> NodeTable<T> {
+    /// Creates a table with the address space of its nodes, the one of the spans
+    /// being in proportion to it.
+    pub fn new(addr_space_size: usize) -> Self {
+        let max_nodes = addr_space_size / size_of::<T>().max(1);
+        Self {
+            nodes: NodeArena::new(addr_space_size),
+            spans: ArenaVec::new(max_nodes.saturating_mul(size_of::<Span>())),
+        }
+    }
+
+    /// Allocates a node, panicking if the table already has `u32::MAX` nodes.
+    #[inline]
+    pub fn alloc(&self, node: T, span: Span) -> NodeId<T>
+    where
+        T: Copy,
+    {
+        let id = self.nodes.alloc(node);
+        self.spans.add(span);
+        id
+    }
+
+    #[inline]
+    pub fn get(&self, id: NodeId<T>) -> &T {
+        self.nodes.get(id)
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, id: NodeId<T>) -> &mut T {
+        self.nodes.get_mut(id)
+    }
+
+    #[inline]
+    pub fn span(&self, id: NodeId<T>) -> Span {
+        self.spans[id.index()]
+    }
+
+    /// Goes through the nodes in order of allocation.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId<T>, &'_ T)> {
+        self.nodes.iter()
+    }
+
+    /// Handles of the nodes in order of allocation, which don't borrow the table.
+    pub fn ids(&self) -> impl Iterator<Item = NodeId<T>> {
+        self.nodes.ids()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Nodes as a slice, indexed by [`NodeId::index`].
+    #[inline]
+    pub fn as_slice(&self) -> &[T] {
+        self.nodes.as_slice()
+    }
+
+    /// Spans of the nodes, indexed by [`NodeId::index`].
+    #[inline]
+    pub fn spans(&self) -> &[Span] {
+        self.spans.as_slice()
+    }
+
+    /// Memory taken by the nodes and their spans.
+    pub fn memory(&self) -> ArrayMemory {
+        self.nodes.memory() + ArrayMemory::of(&self.spans)
+    }
+}
+
+impl<T> Index<NodeId<T>> for NodeTable<T> {
+    type Output = T;
+
+    fn index(&self, id: NodeId<T>) -> &Self::Output {
+        self.get(id)
+    }
+}
+
+impl<T> IndexMut<NodeId<T>> for NodeTable<T> {
+    fn index_mut(&mut self, id: NodeId<T>) -> &mut Self::Output {
+        self.get_mut(id)
+    }
+}
+
+impl<T: Debug> Debug for NodeTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nodes = self.iter().map(|(id, node)| (id, (node, self.span(id))));
+        f.debug_map().entries(nodes).finish()
+    }
+}
+
+/// Declaration of the form `Name :: ...`, or import of the form `use path::to::Name;`.
+#[derive(Debug, Clone, Copy)]
+pub struct Item {
+    /// Attributes before the item, like `#[inline]`
+    pub attrs: ArenaSlice<Attr>,
+    pub is_pub: bool,
+    /// Empty for [`ItemKind::Error`]
+    pub name: Span,
+    /// Parameters between `[]` after the keyword, like `T` in `fn[T]` or `struct[T]`
+    pub generics: ArenaSlice<GenericParam>,
+    pub kind: ItemKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ItemKind {
+    Fn {
+        params: ArenaSlice<Param>,
+        /// Type after `>-`, `None` for functions that return nothing
+        ret: Option<TypeId>,
+        /// Block, or expression after `->`
+        body: ExprId,
+    },
+    Struct {
+        packed: bool,
+        /// Backing integer type, like `u32` in `packed struct u32`
+        repr: Option<TypeId>,
+        fields: ArenaSlice<Field>,
+    },
+    Enum {
+        /// Type of the discriminants, like `u8` in `enum u8`
+        repr: Option<TypeId>,
+        variants: ArenaSlice<Variant>,
+    },
+    Union {
+        fields: ArenaSlice<Field>,
+    },
+    /// Constant, like `const N : usize = 4;`
+    Const {
+        /// Type after the `:`, `None` when it is inferred like in `const N := 4;`
+        ty: Option<TypeId>,
+        value: ExprId,
+    },
+    /// Value that lives for the whole program, like `static COUNTER : u32 = 0;`
+    Static {
+        ty: Option<TypeId>,
+        value: ExprId,
+    },
+    /// Import of a module or of an item of a module, named after the last segment of its path
+    Use {
+        path: ArenaSlice<Span>,
+    },
+    /// Item that has a parsing error, spanning the code skipped up to the next item
+    Error,
+}
+
+impl ItemKind {
+    /// Initializer of a constant or static, which is evaluated at compile time.
+    pub fn const_init(&self) -> Option<ExprId> {
+        match *self {
+            ItemKind::Const { value, .. } | ItemKind::Static { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GenericParam {
+    pub name: Span,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub pattern: PatternId,
+    pub ty: TypeId,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Field {
+    pub attrs: ArenaSlice<Attr>,
+    /// Whether the field can be accessed outside of the module of its type
+    pub is_pub: bool,
+    pub name: Span,
+    pub ty: TypeId,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Variant {
+    pub name: Span,
+    /// Type of the payload, `None` for variants of type `()`
+    pub ty: Option<TypeId>,
+    pub discriminant: Option<ExprId>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stmt {
+    /// Declaration of a variable, like `rw i : usize = 0;`
+    Let {
+        access: Access,
+        pattern: PatternId,
+        /// Type after the `:`, `None` when it is inferred like in `i := 0;`
+        ty: Option<TypeId>,
+        value: ExprId,
+    },
+    /// Assignment to a place, compound assignments like `a += b;` having an operator
+    Assign {
+        target: ExprId,
+        op: Option<BinaryOp>,
+        value: ExprId,
+    },
+    /// Expression without a semicolon, either ending with a block or the last one of its block
+    Expr(ExprId),
+    /// Expression followed by a semicolon
+    Semi(ExprId),
+    /// Expression run when its block is exited, see [`Block::defers`]
+    Defer(ExprId),
+    /// Statement that has a parsing error, spanning the code skipped up to its end
+    Error,
+}
+
+/// How a variable can be accessed, as given by the `r` and `w` markers before its name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// No marker, the variable being read-only
+    #[default]
+    Implicit,
+    Read,      // r
+    Write,     // w
+    ReadWrite, // rw
+}
+
+impl Access {
+    pub fn can_read(self) -> bool {
+        self != Access::Write
+    }
+
+    pub fn can_write(self) -> bool {
+        matches!(self, Access::Write | Access::ReadWrite)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Expr {
+    /// Identifier, which is the span of the expression
+    Ident,
+    /// Literal, which is the span of the expression
+    Lit(LitKind),
+    /// Interpolated string like `$"{a} + {b:?}"`, with a text part before each hole
+    /// and one after the last, which can be empty. Escapes are left as they are in the parts.
+    InterpolatedString {
+        parts: ArenaSlice<Span>,
+        holes: ArenaSlice<Hole>,
+    },
+    Block(Block),
+    Call {
+        callee: ExprId,
+        args: ArenaSlice<ExprId>,
+    },
+    /// Struct built from its fields, like `Point { x: 1, y, ..origin }`
+    Struct {
+        ty: TypeId,
+        fields: ArenaSlice<FieldInit>,
+        /// Struct that the fields that are not listed are taken from
+        base: Option<ExprId>,
+    },
+    /// Access to a field, like `a.b`
+    Field {
+        expr: ExprId,
+        name: Span,
+    },
+    /// Access to a field of a tuple, like `t.0`, whose index is a span of decimal digits
+    TupleField {
+        expr: ExprId,
+        index: Span,
+    },
+    /// Call of a method like `value.method(a, b)`, which is the call `method(value, a, b)`
+    /// whose arguments start with the receiver
+    MethodCall {
+        method: Span,
+        args: ArenaSlice<ExprId>,
+    },
+    /// Function defined in an expression, like `|x, y| x + y` or `fn[&total](x : u32) -> total + x`
+    Closure {
+        /// Variables listed between `[]` after `fn`, `None` without a list,
+        /// the variables being captured as they are used
+        captures: Option<ArenaSlice<Capture>>,
+        params: ArenaSlice<ClosureParam>,
+        /// Type after `>-`, `None` when it is inferred
+        ret: Option<TypeId>,
+        body: ExprId,
+    },
+    /// Array of the listed elements, like `[1, 2, 3]`
+    Array(ArenaSlice<ExprId>),
+    /// Tuple of the listed elements, like `(a, b)` or `(a,)`, `()` being [`LitKind::Unit`]
+    Tuple(ArenaSlice<ExprId>),
+    /// Array of a value repeated, like `[0; 32]`
+    ArrayRepeat {
+        value: ExprId,
+        len: ExprId,
+    },
+    Index {
+        expr: ExprId,
+        index: ExprId,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: ExprId,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: ExprId,
+        rhs: ExprId,
+    },
+    /// `_` as an argument, which makes its call a partial application: a closure with
+    /// a parameter for each placeholder, like `f(_, a)` being `|x| f(x, a)`
+    Placeholder,
+    /// Operator applied to a missing left operand, like `(+ 1)` being `|x| x + 1`
+    Section {
+        op: BinaryOp,
+        rhs: ExprId,
+    },
+    If {
+        cond: ExprId,
+        then: ExprId,
+        /// Expression after `else`, which is another `If` in `else if` chains
+        otherwise: Option<ExprId>,
+    },
+    While {
+        label: Option<Span>,
+        cond: ExprId,
+        body: ExprId,
+    },
+    /// `do body while cond`, which runs its body before checking its condition
+    DoWhile {
+        label: Option<Span>,
+        body: ExprId,
+        cond: ExprId,
+    },
+    Loop {
+        label: Option<Span>,
+        body: ExprId,
+    },
+    /// `for x, i in iter do body`, which runs its body for every element `x` at the index `i`
+    For {
+        label: Option<Span>,
+        pattern: PatternId,
+        /// Binding of the index of the element, after a `,`
+        index: Option<PatternId>,
+        iter: ForIter,
+        body: ExprId,
+    },
+    Match {
+        scrutinee: ExprId,
+        arms: ArenaSlice<Arm>,
+    },
+    /// Exit of a loop, only `loop` being able to evaluate to its value
+    Break {
+        /// Label of the loop to exit, the innermost one if `None`
+        label: Option<Span>,
+        value: Option<ExprId>,
+    },
+    Continue {
+        /// Label of the loop to continue, the innermost one if `None`
+        label: Option<Span>,
+    },
+    /// Explicit exit of the function, the value of its body being returned implicitly,
+    /// see [`Block::tail`]
+    Return {
+        value: Option<ExprId>,
+    },
+}
+
+impl Expr {
+    /// Label of a loop, to which `break` and `continue` are resolved through its name.
+    pub fn loop_label(&self) -> Option<Span> {
+        match *self {
+            Expr::While { label, .. }
+            | Expr::DoWhile { label, .. }
+            | Expr::Loop { label, .. }
+            | Expr::For { label, .. } => label,
+            _ => None,
+        }
+    }
+
+    pub fn is_loop(&self) -> bool {
+        matches!(
+            self,
+            Expr::While { .. } | Expr::DoWhile { .. } | Expr::Loop { .. } | Expr::For { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block {
+    pub stmts: ArenaSlice<StmtId>,
+    /// [`Stmt::Defer`]s of the block in the order they run, which is the reverse of theirs.
+    ///
+    /// Exiting the block after `n` of them, at its end or through a `break`, runs the last `n`.
+    pub defers: ArenaSlice<StmtId>,
+    /// Value of the block, which is the expression of its last statement when that has no `;`.
+    ///
+    /// Blocks without one evaluate to `()`, and a function whose body has one returns it
+    /// as if through a [`Expr::Return`].
+    pub tail: Option<ExprId>,
+}
+
+/// What a `for` loop goes through, which tells how it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ForIter {
+    /// `start..end`, counting up from `start` to `end` excluded like a `while` loop would
+    Range { start: ExprId, end: ExprId },
+    /// Value whose elements are taken one by one from its iterator
+    Iter(ExprId),
+}
+
+/// Parameter of a closure, whose type can be inferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClosureParam {
+    pub pattern: PatternId,
+    pub ty: Option<TypeId>,
+}
+
+/// Variable in the capture list of a closure, like `total` or `&total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capture {
+    pub name: Span,
+    /// Whether the variable is captured by reference rather than copied
+    pub by_ref: bool,
+}
+
+/// Field of a struct literal, like `x: 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FieldInit {
+    pub name: Span,
+    /// Identifier expression of the same span when the field is shorthand for `x: x`
+    pub value: ExprId,
+}
+
+/// Hole of an interpolated string, like `{x:?}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hole {
+    pub expr: ExprId,
+    /// Format spec after the expression, like `:?`
+    pub spec: Option<Span>,
+}
+
+/// Arm of a `match`, like `.Some(x) if x > 0 => x`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Arm {
+    pub pattern: PatternId,
+    /// Condition after the `if` of the pattern
+    pub guard: Option<ExprId>,
+    pub body: ExprId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LitKind {
+    Num,
+    String,
+    ByteString,
+    CString,
+    Char,
+    ByteChar,
+    True,
+    False,
+    /// `none`, the missing value of optional types
+    None,
+    /// `()`, the only value of the unit type
+    Unit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,      // -
+    BitNot,   // ~
+    Not,      // not
+    PreIncr,  // ++a
+    PreDecr,  // --a
+    PostIncr, // a++
+    PostDecr, // a--
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Or,  // or
+    Xor, // xor
+    And, // and
+
+    Eq, // ==
+    Ne, // !=
+    Lt, // <
+    Gt, // >
+    Le, // <=
+    Ge, // >=
+
+    BitOr,  // |
+    BitXor, // ^
+    BitAnd, // &
+    Shl,    // <<
+    Shr,    // >>
+
+    Add, // +
+    Sub, // -
+    Mul, // *
+    Div, // /
+    Mod, // %
+    Pow, // **
+}
+
+impl BinaryOp {
+    /// Whether the operator compares its operands, which can't be chained like `a < b < c`.
+    pub fn is_comparison(self) -> bool {
+        use BinaryOp as B;
+
+        matches!(self, B::Eq | B::Ne | B::Lt | B::Gt | B::Le | B::Ge)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    /// Type referred to by name, which is its span
+    Named,
+    /// Generic type given arguments, like `Map[K, V]`
+    Generic {
+        base: TypeId,
+        args: ArenaSlice<TypeId>,
+    },
+    /// Array of a fixed length, like `[u8; 4]`
+    Array { elem: TypeId, len: ExprId },
+    /// Slice of any length, like `[u8]`
+    Slice(TypeId),
+    /// Pointer to a value, like `*T`
+    Pointer(TypeId),
+    /// Reference to a value, like `&T`
+    Ref(TypeId),
+    /// Value that can be missing, like `?T`
+    Optional(TypeId),
+    /// Function, like `fn(T, U) >- R`
+    Fn {
+        params: ArenaSlice<TypeId>,
+        ret: Option<TypeId>,
+    },
+    /// Tuple of values, like `(T, U)`, the unit type being `()`
+    Tuple(ArenaSlice<TypeId>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    /// Binding of the value to a name, which is the span of the pattern
+    Ident,
+    /// `_`, which matches anything without binding it
+    Wildcard,
+    /// Literal, which is the span of the pattern
+    Lit(LitKind),
+    /// Variant of an enum, like `KeyboardEvent.KeyPress(code)` or `.Windowed`
+    Variant {
+        /// Enum before the `.`, `None` when it is inferred from the matched value
+        ty: Option<TypeId>,
+        name: Span,
+        /// Pattern of the payload in parentheses, if any
+        payload: Option<PatternId>,
+    },
+    /// Alternatives separated by `|`, matching if any of them does
+    Or(ArenaSlice<PatternId>),
+}
+
+#[cfg(test)]
+mod tests_ast {
+    use crate::{
+        arena::MIB,
+        ast::{Expr, NodeTable, Pattern},
+        lexer::{lex, Span},
+        parser::parse_file,
+        source::FileId,
+    };
+
+    #[test]
+    fn node_table() {
+        let mut table = NodeTable::new(MIB);
+        let a = table.alloc('a', Span { start: 0, len: 1 });
+        let b = table.alloc('b', Span { start: 2, len: 3 });
+        assert_eq!((table[b], table.span(b)), ('b', Span { start: 2, len: 3 }));
+
+        table[a] = 'c';
+        assert_eq!(
+            (table.as_slice(), table.spans().len()),
+            (&['c', 'b'][..], 2)
+        );
+        assert_eq!(
+            format!("{table:?}"),
+            "{NodeId(0): ('c', Span { start: 0, len: 1 }), NodeId(1): ('b', Span { start: 2, len: 3 })}"
+        );
+
+        let memory = table.memory();
+        assert_eq!(memory.used, 2 * (size_of::<char>() + size_of::<Span>()));
+        assert!(memory.committed >= memory.used && memory.reserved >= memory.committed);
+    }
+
+    #[test]
+    fn memory() {
+        let tokens = lex(FileId::default(), "f :: fn(a: int) { g(a, 1); }");
+        let (ast, _) = parse_file(&tokens);
+        let memory = ast.memory();
+        let exprs = ast.exprs.memory();
+        assert_eq!(
+            exprs.used,
+            ast.exprs.len() * (size_of::<Expr>() + size_of::<Span>())
+        );
+        assert!(memory.used > exprs.used + ast.params.memory().used);
+        assert!(memory.committed >= memory.used);
+    }
+
+    #[test]
+    fn spans() {
+        let code = "f :: fn(x : i32, _ : u8) { g(x); loop {} }";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let slices = |spans: &[Span]| -> Vec<&str> {
+            spans.iter().map(|span| &code[span.range()]).collect()
+        };
+        assert_eq!(slices(ast.items.spans()), [code]);
+        assert_eq!(slices(ast.stmts.spans()), ["g(x);", "loop {}"]);
+        assert_eq!(slices(ast.types.spans()), ["i32", "u8"]);
+        assert_eq!(slices(ast.patterns.spans()), ["x", "_"]);
+        assert_eq!(ast.patterns.as_slice(), [Pattern::Ident, Pattern::Wildcard]);
+    }
+}
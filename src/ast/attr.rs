@@ -0,0 +1,152 @@
+//! Attributes like `#[inline]` or `#[repr(C)]`, which are metadata of items, fields and statements.
+//!
+//! The parser only keeps the tokens of their arguments, and the passes that look for
+//! an attribute parse them the way that attribute needs, so that adding one only takes
+//! a new entry in [`ATTRIBUTES`].
+
+use std::ops::Range;
+
+use super::{Ast, StmtId};
+use crate::{arena::ArenaSlice, lexer::Span};
+
+/// Attribute like `inline(always)`, one of those listed in a `#[...]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Attr {
+    /// Attribute that the path names, `None` for unknown ones, which are reported
+    pub kind: Option<AttrKind>,
+    /// Segments of the path, in [`Ast::paths`]
+    pub path: ArenaSlice<Span>,
+    /// Tokens between the parentheses after the path, if there are parentheses
+    pub args: Option<TokenRange>,
+    pub span: Span,
+}
+
+/// Tokens given by their indices in [`Tokens`](crate::lexer::Tokens).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TokenRange {
+    pub start: u32,
+    pub len: u32,
+}
+
+impl TokenRange {
+    #[inline]
+    pub fn range(self) -> Range<usize> {
+        self.start as usize..self.start as usize + self.len as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttrKind {
+    /// `#[inline]` or `#[inline(always)]`, a hint to inline a function where it is called
+    Inline,
+    /// `#[cold]`, a hint that a function is rarely called
+    Cold,
+    /// `#[repr(C)]`, the layout of a type
+    Repr,
+    /// `#[test]`, a function run by the test harness
+    Test,
+    /// `#[deprecated]` or `#[deprecated("reason")]`, an item whose uses are warned about
+    Deprecated,
+    /// `#[doc("text")]`, documentation of an item, or of the file as `#![doc("text")]`
+    Doc,
+}
+
+/// Every attribute the compiler knows about, by name.
+pub const ATTRIBUTES: &[(&str, AttrKind)] = &[
+    ("inline", AttrKind::Inline),
+    ("cold", AttrKind::Cold),
+    ("repr", AttrKind::Repr),
+    ("test", AttrKind::Test),
+    ("deprecated", AttrKind::Deprecated),
+    ("doc", AttrKind::Doc),
+];
+
+impl AttrKind {
+    /// Attribute that the segments of a path name, if any.
+    ///
+    /// Known attributes are single names, longer paths being left to other tools.
+    pub fn from_path(segments: &[&str]) -> Option<Self> {
+        let [name] = segments else {
+            return None;
+        };
+        (ATTRIBUTES.iter()).find_map(|&(known, kind)| (known == *name).then_some(kind))
+    }
+
+    pub fn name(self) -> &'static str {
+        (ATTRIBUTES.iter())
+            .find_map(|&(name, kind)| (kind == self).then_some(name))
+            .unwrap_or_default()
+    }
+}
+
+impl Ast {
+    /// Attributes before the statement, which are kept apart as few statements have some.
+    pub fn stmt_attrs(&self, id: StmtId) -> ArenaSlice<Attr> {
+        let stmt_attrs = self.stmt_attrs.as_slice();
+        match stmt_attrs.binary_search_by_key(&id, |&(stmt, _)| stmt) {
+            Ok(idx) => stmt_attrs[idx].1,
+            Err(_) => ArenaSlice::EMPTY,
+        }
+    }
+
+    /// First attribute of the given kind among attributes, like the `#[repr]` of an item.
+    pub fn find_attr(&self, attrs: ArenaSlice<Attr>, kind: AttrKind) -> Option<&Attr> {
+        self.attrs[attrs]
+            .iter()
+            .find(|attr| attr.kind == Some(kind))
+    }
+}
+
+#[cfg(test)]
+mod tests_attr {
+    use crate::{
+        ast::{AttrKind, ATTRIBUTES},
+        lexer::{lex, TokenType},
+        parser::parse_file,
+        source::FileId,
+    };
+
+    #[test]
+    fn registry() {
+        for &(name, kind) in ATTRIBUTES {
+            assert_eq!(AttrKind::from_path(&[name]), Some(kind));
+            assert_eq!(kind.name(), name);
+        }
+        assert_eq!(AttrKind::from_path(&["inlined"]), None);
+        assert_eq!(AttrKind::from_path(&["tool", "inline"]), None);
+    }
+
+    #[test]
+    fn queries() {
+        let code = "#[inline, repr(C, align(4))]\nf :: fn() { #[cold] g(); h(); }";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let (_, item) = ast.items.iter().next().unwrap();
+        assert!(ast.find_attr(item.attrs, AttrKind::Inline).is_some());
+        assert!(ast.find_attr(item.attrs, AttrKind::Test).is_none());
+
+        let repr = ast.find_attr(item.attrs, AttrKind::Repr).unwrap();
+        let args = &tokens.types.as_slice()[repr.args.unwrap().range()];
+        assert_eq!(
+            args,
+            [
+                TokenType::Ident,
+                TokenType::Comma,
+                TokenType::Ident,
+                TokenType::LParens,
+                TokenType::Num,
+                TokenType::RParens,
+            ]
+        );
+        assert_eq!(&code[repr.span.range()], "repr(C, align(4))");
+
+        let stmts: Vec<_> = ast.stmts.ids().collect();
+        assert_eq!(ast.attrs[ast.stmt_attrs(stmts[0])].len(), 1);
+        assert!(ast
+            .find_attr(ast.stmt_attrs(stmts[0]), AttrKind::Cold)
+            .is_some());
+        assert!(ast.stmt_attrs(stmts[1]).is_empty());
+    }
+}
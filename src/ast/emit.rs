@@ -0,0 +1,867 @@
+//! Dumps of the AST, to snapshot the output of the parser and for tools that don't link the crate.
+
+use std::{
+    fmt::Debug,
+    io::{self, Write},
+};
+
+use super::{
+    Ast, Attr, Expr, ExprId, Field, ForIter, ItemId, ItemKind, Pattern, PatternId, Stmt, StmtId,
+    Type, TypeId,
+};
+use crate::{
+    arena::ArenaSlice,
+    lexer::{write_json_span, write_json_str, Span, Tokens},
+    parser::ParseError,
+};
+
+/// Format in which an AST can be emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AstFormat {
+    /// S-expressions, one per item, followed by the diagnostics as comments
+    Sexp,
+    /// One JSON object with the items and diagnostics, every node having its span
+    Json,
+}
+
+impl Ast {
+    /// Writes the AST of the tokens in the given format.
+    pub fn emit(
+        &self,
+        format: AstFormat,
+        tokens: &Tokens,
+        diagnostics: &[ParseError],
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        match format {
+            AstFormat::Sexp => self.write_sexp(tokens, diagnostics, out),
+            AstFormat::Json => self.write_json(tokens, diagnostics, out),
+        }
+    }
+
+    /// Writes every item as an S-expression, with its fields named:
+    ///
+    /// ```text
+    /// (Fn :pub false :name add :generics []
+    ///   :params [
+    ///     (Param :pattern (Ident :name a) :ty (Named :name i32))]
+    ///   :ret (Named :name i32)
+    ///   :body (Ident :name a))
+    /// ; 2:0: Expected item, found Num
+    /// ```
+    ///
+    /// Fields that are `None` are left out, and so are attributes on nodes that have none.
+    /// The attributes of the file come first, as a `(File :attrs [...])`.
+    pub fn write_sexp(
+        &self,
+        tokens: &Tokens,
+        diagnostics: &[ParseError],
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let builder = Builder { ast: self, tokens };
+        if !self.file_attrs.is_empty() {
+            let mut fields = Vec::new();
+            builder.push_attrs(&mut fields, self.file_attrs);
+            let file = Tree::Node {
+                kind: "File",
+                span: None,
+                fields,
+            };
+            write_sexp(out, &file, 0)?;
+            out.write_all(b"\n")?;
+        }
+        for (id, _) in self.items.iter() {
+            write_sexp(out, &builder.item(id), 0)?;
+            out.write_all(b"\n")?;
+        }
+        for diagnostic in diagnostics {
+            writeln!(out, "; {diagnostic}")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the items and diagnostics as a single JSON object:
+    ///
+    /// ```json
+    /// {"file":0,"items":[{"kind":"Fn","start":0,"end":28,"line":1,"col":0,"pub":false,...}],
+    ///  "diagnostics":[{"kind":"ExpectedItem","message":"Expected item, found Num","start":29,...}]}
+    /// ```
+    ///
+    /// Nodes have the same fields as in [`Ast::write_sexp`], `None` being `null`.
+    /// The parts of nodes that don't have a span of their own, like fields and match arms, have none.
+    pub fn write_json(
+        &self,
+        tokens: &Tokens,
+        diagnostics: &[ParseError],
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let builder = Builder { ast: self, tokens };
+        write!(out, "{{\"file\":{},", tokens.file.index())?;
+        if !self.file_attrs.is_empty() {
+            out.write_all(b"\"attrs\":")?;
+            write_json(out, tokens, &builder.attrs(self.file_attrs))?;
+            out.write_all(b",")?;
+        }
+        out.write_all(b"\"items\":[")?;
+        for (i, (id, _)) in self.items.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write_json(out, tokens, &builder.item(id))?;
+        }
+
+        out.write_all(b"],\"diagnostics\":[")?;
+        for (i, diagnostic) in diagnostics.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write!(out, "{{\"kind\":\"{:?}\",\"message\":", diagnostic.kind)?;
+            write_json_str(out, &diagnostic.message())?;
+            out.write_all(b",")?;
+            write_json_span(out, &diagnostic.span)?;
+            out.write_all(b"}")?;
+        }
+        out.write_all(b"]}\n")
+    }
+}
+
+/// Node of the AST in a form that is independent of the output format.
+enum Tree<'a> {
+    Node {
+        kind: &'static str,
+        span: Option<Span>,
+        fields: Vec<(&'static str, Tree<'a>)>,
+    },
+    List(Vec<Tree<'a>>),
+    /// Slice of the code, like a name or a literal
+    Code(&'a str),
+    /// Slice of the code that is text, like the parts of an interpolated string,
+    /// quoted so that its spaces show
+    Text(&'a str),
+    /// Variant of an enum of the AST, like an operator
+    Tag(String),
+    Bool(bool),
+    None,
+}
+
+impl Tree<'_> {
+    /// Whether the tree fits on the line of its parent, which is when it has no children.
+    fn is_leaf(&self) -> bool {
+        match self {
+            Tree::Node { fields, .. } => (fields.iter()).all(|(_, field)| field.is_atom()),
+            Tree::List(elements) => elements.is_empty(),
+            _ => true,
+        }
+    }
+
+    fn is_atom(&self) -> bool {
+        !matches!(self, Tree::Node { .. } | Tree::List(_))
+    }
+}
+
+fn tag(value: impl Debug) -> Tree<'static> {
+    Tree::Tag(format!("{value:?}"))
+}
+
+/// Turns nodes into [`Tree`]s.
+struct Builder<'t, 'a> {
+    ast: &'t Ast,
+    tokens: &'t Tokens<'a>,
+}
+
+impl<'a> Builder<'_, 'a> {
+    fn code(&self, span: Span) -> Tree<'a> {
+        Tree::Code(&self.tokens.code[span.range()])
+    }
+
+    fn text(&self, span: Span) -> Tree<'a> {
+        Tree::Text(&self.tokens.code[span.range()])
+    }
+
+    fn item(&self, id: ItemId) -> Tree<'a> {
+        let ast = self.ast;
+        let item = &ast.items[id];
+        let generics = ast.generics[item.generics].iter();
+        let mut fields = Vec::new();
+        self.push_attrs(&mut fields, item.attrs);
+        fields.extend([
+            ("pub", Tree::Bool(item.is_pub)),
+            ("name", self.code(item.name)),
+            (
+                "generics",
+                Tree::List(generics.map(|g| self.code(g.name)).collect()),
+            ),
+        ]);
+
+        let kind = match item.kind {
+            ItemKind::Fn { params, ret, body } => {
+                let params = ast.params[params].iter().map(|param| Tree::Node {
+                    kind: "Param",
+                    span: None,
+                    fields: vec![
+                        ("pattern", self.pattern(param.pattern)),
+                        ("ty", self.ty(param.ty)),
+                    ],
+                });
+                fields.push(("params", Tree::List(params.collect())));
+                fields.push(("ret", self.opt(ret, Self::ty)));
+                fields.push(("body", self.expr(body)));
+                "Fn"
+            }
+            ItemKind::Struct {
+                packed,
+                repr,
+                fields: struct_fields,
+            } => {
+                fields.push(("packed", Tree::Bool(packed)));
+                fields.push(("repr", self.opt(repr, Self::ty)));
+                fields.push(("fields", self.fields(struct_fields)));
+                "Struct"
+            }
+            ItemKind::Enum { repr, variants } => {
+                let variants = ast.variants[variants].iter().map(|variant| Tree::Node {
+                    kind: "Variant",
+                    span: None,
+                    fields: vec![
+                        ("name", self.code(variant.name)),
+                        ("ty", self.opt(variant.ty, Self::ty)),
+                        ("discriminant", self.opt(variant.discriminant, Self::expr)),
+                    ],
+                });
+                fields.push(("repr", self.opt(repr, Self::ty)));
+                fields.push(("variants", Tree::List(variants.collect())));
+                "Enum"
+            }
+            ItemKind::Union {
+                fields: union_fields,
+            } => {
+                fields.push(("fields", self.fields(union_fields)));
+                "Union"
+            }
+            ItemKind::Const { ty, value } | ItemKind::Static { ty, value } => {
+                fields.push(("ty", self.opt(ty, Self::ty)));
+                fields.push(("value", self.expr(value)));
+                match item.kind {
+                    ItemKind::Const { .. } => "Const",
+                    _ => "Static",
+                }
+            }
+            ItemKind::Use { path } => {
+                let path = ast.paths[path].iter().map(|&segment| self.code(segment));
+                fields.push(("path", Tree::List(path.collect())));
+                "Use"
+            }
+            ItemKind::Error => {
+                fields.clear();
+                "Error"
+            }
+        };
+        Tree::Node {
+            kind,
+            span: Some(ast.items.span(id)),
+            fields,
+        }
+    }
+
+    fn fields(&self, fields: ArenaSlice<Field>) -> Tree<'a> {
+        let fields = self.ast.fields[fields].iter().map(|field| {
+            let mut fields = Vec::new();
+            self.push_attrs(&mut fields, field.attrs);
+            fields.extend([
+                ("pub", Tree::Bool(field.is_pub)),
+                ("name", self.code(field.name)),
+                ("ty", self.ty(field.ty)),
+            ]);
+            Tree::Node {
+                kind: "Field",
+                span: None,
+                fields,
+            }
+        });
+        Tree::List(fields.collect())
+    }
+
+    fn attrs(&self, attrs: ArenaSlice<Attr>) -> Tree<'a> {
+        let ast = self.ast;
+        let attrs = ast.attrs[attrs].iter().map(|attr| {
+            let path = ast.paths[attr.path]
+                .iter()
+                .map(|&segment| self.code(segment));
+            let args = attr.args.map_or(Tree::None, |args| {
+                let spans = &self.tokens.spans.as_slice()[args.range()];
+                match (spans.first(), spans.last()) {
+                    (Some(first), Some(last)) => self.text(Span {
+                        start: first.start,
+                        len: last.end() as u32 - first.start,
+                    }),
+                    _ => Tree::Text(""),
+                }
+            });
+            Tree::Node {
+                kind: "Attr",
+                span: Some(attr.span),
+                fields: vec![
+                    ("known", Tree::Bool(attr.kind.is_some())),
+                    ("path", Tree::List(path.collect())),
+                    ("args", args),
+                ],
+            }
+        });
+        Tree::List(attrs.collect())
+    }
+
+    /// Adds the attributes to the fields of a node, unless there are none.
+    fn push_attrs(&self, fields: &mut Vec<(&'static str, Tree<'a>)>, attrs: ArenaSlice<Attr>) {
+        if !attrs.is_empty() {
+            fields.push(("attrs", self.attrs(attrs)));
+        }
+    }
+
+    fn stmt(&self, id: StmtId) -> Tree<'a> {
+        let (kind, mut fields) = match self.ast.stmts[id] {
+            Stmt::Let {
+                access,
+                pattern,
+                ty,
+                value,
+            } => (
+                "Let",
+                vec![
+                    ("access", tag(access)),
+                    ("pattern", self.pattern(pattern)),
+                    ("ty", self.opt(ty, Self::ty)),
+                    ("value", self.expr(value)),
+                ],
+            ),
+            Stmt::Assign { target, op, value } => (
+                "Assign",
+                vec![
+                    ("op", op.map_or(Tree::None, tag)),
+                    ("target", self.expr(target)),
+                    ("value", self.expr(value)),
+                ],
+            ),
+            Stmt::Expr(expr) => ("Expr", vec![("expr", self.expr(expr))]),
+            Stmt::Semi(expr) => ("Semi", vec![("expr", self.expr(expr))]),
+            Stmt::Defer(expr) => ("Defer", vec![("expr", self.expr(expr))]),
+            Stmt::Error => ("Error", vec![]),
+        };
+        let mut attrs = Vec::new();
+        self.push_attrs(&mut attrs, self.ast.stmt_attrs(id));
+        fields.splice(0..0, attrs);
+        Tree::Node {
+            kind,
+            span: Some(self.ast.stmts.span(id)),
+            fields,
+        }
+    }
+
+    fn expr(&self, id: ExprId) -> Tree<'a> {
+        let ast = self.ast;
+        let exprs = |ids: &[ExprId]| Tree::List(ids.iter().map(|&id| self.expr(id)).collect());
+        let label = |label: Option<Span>| label.map_or(Tree::None, |label| self.code(label));
+
+        let (kind, fields) = match ast.exprs[id] {
+            Expr::Ident => ("Ident", vec![("name", self.code(ast.exprs.span(id)))]),
+            Expr::Lit(kind) => (
+                "Lit",
+                vec![
+                    ("kind", tag(kind)),
+                    ("value", self.code(ast.exprs.span(id))),
+                ],
+            ),
+            Expr::InterpolatedString { parts, holes } => {
+                let parts = ast.string_parts[parts].iter();
+                let holes = ast.holes[holes].iter().map(|hole| Tree::Node {
+                    kind: "Hole",
+                    span: None,
+                    fields: vec![
+                        ("expr", self.expr(hole.expr)),
+                        ("spec", self.opt(hole.spec, Self::code)),
+                    ],
+                });
+                (
+                    "InterpolatedString",
+                    vec![
+                        (
+                            "parts",
+                            Tree::List(parts.map(|&part| self.text(part)).collect()),
+                        ),
+                        ("holes", Tree::List(holes.collect())),
+                    ],
+                )
+            }
+            Expr::Block(block) => {
+                let stmts = ast.stmt_lists[block.stmts].iter();
+                let stmts = Tree::List(stmts.map(|&stmt| self.stmt(stmt)).collect());
+                ("Block", vec![("stmts", stmts)])
+            }
+            Expr::Call { callee, args } => (
+                "Call",
+                vec![
+                    ("callee", self.expr(callee)),
+                    ("args", exprs(&ast.expr_lists[args])),
+                ],
+            ),
+            Expr::Struct { ty, fields, base } => {
+                let fields = ast.field_inits[fields].iter().map(|field| Tree::Node {
+                    kind: "FieldInit",
+                    span: None,
+                    fields: vec![
+                        ("name", self.code(field.name)),
+                        ("value", self.expr(field.value)),
+                    ],
+                });
+                (
+                    "Struct",
+                    vec![
+                        ("ty", self.ty(ty)),
+                        ("fields", Tree::List(fields.collect())),
+                        ("base", self.opt(base, Self::expr)),
+                    ],
+                )
+            }
+            Expr::Field { expr, name } => (
+                "Field",
+                vec![("expr", self.expr(expr)), ("name", self.code(name))],
+            ),
+            Expr::TupleField { expr, index } => (
+                "TupleField",
+                vec![("expr", self.expr(expr)), ("index", self.code(index))],
+            ),
+            Expr::Closure {
+                captures,
+                params,
+                ret,
+                body,
+            } => {
+                let captures = captures.map_or(Tree::None, |captures| {
+                    let captures = ast.captures[captures].iter().map(|capture| Tree::Node {
+                        kind: "Capture",
+                        span: None,
+                        fields: vec![
+                            ("name", self.code(capture.name)),
+                            ("by_ref", Tree::Bool(capture.by_ref)),
+                        ],
+                    });
+                    Tree::List(captures.collect())
+                });
+                let params = ast.closure_params[params].iter().map(|param| Tree::Node {
+                    kind: "Param",
+                    span: None,
+                    fields: vec![
+                        ("pattern", self.pattern(param.pattern)),
+                        ("ty", self.opt(param.ty, Self::ty)),
+                    ],
+                });
+                (
+                    "Closure",
+                    vec![
+                        ("captures", captures),
+                        ("params", Tree::List(params.collect())),
+                        ("ret", self.opt(ret, Self::ty)),
+                        ("body", self.expr(body)),
+                    ],
+                )
+            }
+            Expr::MethodCall { method, args } => (
+                "MethodCall",
+                vec![
+                    ("method", self.code(method)),
+                    ("args", exprs(&ast.expr_lists[args])),
+                ],
+            ),
+            Expr::Array(elements) => (
+                "Array",
+                vec![("elements", exprs(&ast.expr_lists[elements]))],
+            ),
+            Expr::Tuple(elements) => (
+                "Tuple",
+                vec![("elements", exprs(&ast.expr_lists[elements]))],
+            ),
+            Expr::ArrayRepeat { value, len } => (
+                "ArrayRepeat",
+                vec![("value", self.expr(value)), ("len", self.expr(len))],
+            ),
+            Expr::Index { expr, index } => (
+                "Index",
+                vec![("expr", self.expr(expr)), ("index", self.expr(index))],
+            ),
+            Expr::Unary { op, expr } => ("Unary", vec![("op", tag(op)), ("expr", self.expr(expr))]),
+            Expr::Binary { op, lhs, rhs } => (
+                "Binary",
+                vec![
+                    ("op", tag(op)),
+                    ("lhs", self.expr(lhs)),
+                    ("rhs", self.expr(rhs)),
+                ],
+            ),
+            Expr::Placeholder => ("Placeholder", vec![]),
+            Expr::Section { op, rhs } => {
+                ("Section", vec![("op", tag(op)), ("rhs", self.expr(rhs))])
+            }
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => (
+                "If",
+                vec![
+                    ("cond", self.expr(cond)),
+                    ("then", self.expr(then)),
+                    ("else", self.opt(otherwise, Self::expr)),
+                ],
+            ),
+            Expr::While {
+                label: l,
+                cond,
+                body,
+            } => (
+                "While",
+                vec![
+                    ("label", label(l)),
+                    ("cond", self.expr(cond)),
+                    ("body", self.expr(body)),
+                ],
+            ),
+            Expr::DoWhile {
+                label: l,
+                body,
+                cond,
+            } => (
+                "DoWhile",
+                vec![
+                    ("label", label(l)),
+                    ("body", self.expr(body)),
+                    ("cond", self.expr(cond)),
+                ],
+            ),
+            Expr::Loop { label: l, body } => {
+                ("Loop", vec![("label", label(l)), ("body", self.expr(body))])
+            }
+            Expr::For {
+                label: l,
+                pattern,
+                index,
+                iter,
+                body,
+            } => {
+                let mut fields = vec![
+                    ("label", label(l)),
+                    ("pattern", self.pattern(pattern)),
+                    ("index", self.opt(index, Self::pattern)),
+                ];
+                match iter {
+                    ForIter::Range { start, end } => {
+                        fields.push(("start", self.expr(start)));
+                        fields.push(("end", self.expr(end)));
+                    }
+                    ForIter::Iter(iter) => fields.push(("iter", self.expr(iter))),
+                }
+                fields.push(("body", self.expr(body)));
+                ("For", fields)
+            }
+            Expr::Match { scrutinee, arms } => {
+                let arms = ast.arms[arms].iter().map(|arm| Tree::Node {
+                    kind: "Arm",
+                    span: None,
+                    fields: vec![
+                        ("pattern", self.pattern(arm.pattern)),
+                        ("guard", self.opt(arm.guard, Self::expr)),
+                        ("body", self.expr(arm.body)),
+                    ],
+                });
+                (
+                    "Match",
+                    vec![
+                        ("scrutinee", self.expr(scrutinee)),
+                        ("arms", Tree::List(arms.collect())),
+                    ],
+                )
+            }
+            Expr::Break { label: l, value } => (
+                "Break",
+                vec![("label", label(l)), ("value", self.opt(value, Self::expr))],
+            ),
+            Expr::Continue { label: l } => ("Continue", vec![("label", label(l))]),
+            Expr::Return { value } => ("Return", vec![("value", self.opt(value, Self::expr))]),
+        };
+        Tree::Node {
+            kind,
+            span: Some(ast.exprs.span(id)),
+            fields,
+        }
+    }
+
+    fn ty(&self, id: TypeId) -> Tree<'a> {
+        let ast = self.ast;
+        let types = |ids: &[TypeId]| Tree::List(ids.iter().map(|&id| self.ty(id)).collect());
+        let (kind, fields) = match ast.types[id] {
+            Type::Named => ("Named", vec![("name", self.code(ast.types.span(id)))]),
+            Type::Generic { base, args } => (
+                "Generic",
+                vec![
+                    ("base", self.ty(base)),
+                    ("args", types(&ast.type_lists[args])),
+                ],
+            ),
+            Type::Array { elem, len } => (
+                "Array",
+                vec![("elem", self.ty(elem)), ("len", self.expr(len))],
+            ),
+            Type::Slice(elem) => ("Slice", vec![("elem", self.ty(elem))]),
+            Type::Pointer(pointee) => ("Pointer", vec![("pointee", self.ty(pointee))]),
+            Type::Ref(referent) => ("Ref", vec![("referent", self.ty(referent))]),
+            Type::Optional(ty) => ("Optional", vec![("ty", self.ty(ty))]),
+            Type::Fn { params, ret } => (
+                "Fn",
+                vec![
+                    ("params", types(&ast.type_lists[params])),
+                    ("ret", self.opt(ret, Self::ty)),
+                ],
+            ),
+            Type::Tuple(elems) => ("Tuple", vec![("elems", types(&ast.type_lists[elems]))]),
+        };
+        Tree::Node {
+            kind,
+            span: Some(ast.types.span(id)),
+            fields,
+        }
+    }
+
+    fn pattern(&self, id: PatternId) -> Tree<'a> {
+        let ast = self.ast;
+        let (kind, fields) = match ast.patterns[id] {
+            Pattern::Ident => ("Ident", vec![("name", self.code(ast.patterns.span(id)))]),
+            Pattern::Wildcard => ("Wildcard", vec![]),
+            Pattern::Lit(kind) => (
+                "Lit",
+                vec![
+                    ("kind", tag(kind)),
+                    ("value", self.code(ast.patterns.span(id))),
+                ],
+            ),
+            Pattern::Variant { ty, name, payload } => (
+                "Variant",
+                vec![
+                    ("ty", self.opt(ty, Self::ty)),
+                    ("name", self.code(name)),
+                    ("payload", self.opt(payload, Self::pattern)),
+                ],
+            ),
+            Pattern::Or(alternatives) => {
+                let alternatives = ast.pattern_lists[alternatives].iter();
+                let alternatives = alternatives.map(|&alternative| self.pattern(alternative));
+                (
+                    "Or",
+                    vec![("alternatives", Tree::List(alternatives.collect()))],
+                )
+            }
+        };
+        Tree::Node {
+            kind,
+            span: Some(ast.patterns.span(id)),
+            fields,
+        }
+    }
+
+    fn opt<T>(&self, node: Option<T>, tree: impl Fn(&Self, T) -> Tree<'a>) -> Tree<'a> {
+        node.map_or(Tree::None, |node| tree(self, node))
+    }
+}
+
+/// Writes a tree at the given indentation, children that aren't leaves going on their own lines.
+fn write_sexp(out: &mut impl Write, tree: &Tree, indent: usize) -> io::Result<()> {
+    let newline = |out: &mut dyn Write| write!(out, "\n{:1$}", "", indent + 2);
+    match tree {
+        Tree::Node { kind, fields, .. } => {
+            write!(out, "({kind}")?;
+            let mut is_multiline = false;
+            for (name, field) in fields {
+                if let Tree::None = field {
+                    continue;
+                }
+                is_multiline |= !field.is_leaf();
+                match is_multiline {
+                    true => newline(out)?,
+                    false => out.write_all(b" ")?,
+                }
+                write!(out, ":{name} ")?;
+                write_sexp(out, field, indent + 2)?;
+            }
+            out.write_all(b")")
+        }
+        Tree::List(elements) => {
+            out.write_all(b"[")?;
+            for element in elements {
+                newline(out)?;
+                write_sexp(out, element, indent + 2)?;
+            }
+            out.write_all(b"]")
+        }
+        Tree::Code(code) => out.write_all(code.as_bytes()),
+        Tree::Text(text) => write!(out, "{text:?}"),
+        Tree::Tag(tag) => out.write_all(tag.as_bytes()),
+        Tree::Bool(b) => write!(out, "{b}"),
+        Tree::None => out.write_all(b"nil"),
+    }
+}
+
+fn write_json(out: &mut impl Write, tokens: &Tokens, tree: &Tree) -> io::Result<()> {
+    match tree {
+        Tree::Node { kind, span, fields } => {
+            write!(out, "{{\"kind\":\"{kind}\"")?;
+            if let Some(span) = span {
+                let span = tokens.resolve(*span);
+                write!(
+                    out,
+                    ",\"start\":{},\"end\":{},\"line\":{},\"col\":{}",
+                    span.start,
+                    span.end(),
+                    span.line,
+                    span.col
+                )?;
+            }
+            for (name, field) in fields {
+                write!(out, ",\"{name}\":")?;
+                write_json(out, tokens, field)?;
+            }
+            out.write_all(b"}")
+        }
+        Tree::List(elements) => {
+            out.write_all(b"[")?;
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                write_json(out, tokens, element)?;
+            }
+            out.write_all(b"]")
+        }
+        Tree::Code(code) | Tree::Text(code) => write_json_str(out, code),
+        Tree::Tag(tag) => write!(out, "\"{tag}\""),
+        Tree::Bool(b) => write!(out, "{b}"),
+        Tree::None => out.write_all(b"null"),
+    }
+}
+
+#[cfg(test)]
+mod tests_emit {
+    use crate::{
+        ast::AstFormat,
+        lexer::lex,
+        parser::{parse_file, MAX_CHAIN_DEPTH, MAX_NESTING_DEPTH},
+        source::FileId,
+    };
+
+    fn emit(code: &str, format: AstFormat) -> String {
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        let mut out = Vec::new();
+        ast.emit(format, &tokens, &diagnostics, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sexp() {
+        let code =
+            "add :: fn[T](a : T) >- T { rw b := a; b += 1; b }\nE :: enum u8 { A = 1, B }\n1";
+        let expected = r#"(Fn :pub false :name add
+  :generics [
+    T]
+  :params [
+    (Param :pattern (Ident :name a) :ty (Named :name T))]
+  :ret (Named :name T)
+  :body (Block
+    :stmts [
+      (Let :access ReadWrite :pattern (Ident :name b) :value (Ident :name a))
+      (Assign :op Add :target (Ident :name b) :value (Lit :kind Num :value 1))
+      (Expr :expr (Ident :name b))]))
+(Enum :pub false :name E :generics [] :repr (Named :name u8)
+  :variants [
+    (Variant :name A :discriminant (Lit :kind Num :value 1))
+    (Variant :name B)])
+(Error)
+; 3:0: Expected Ident, found Num
+"#;
+        assert_eq!(emit(code, AstFormat::Sexp), expected);
+    }
+
+    #[test]
+    fn json() {
+        let expected = concat!(
+            r#"{"file":0,"items":[{"kind":"Fn","start":0,"end":16,"line":1,"col":0,"#,
+            r#""pub":true,"name":"f","generics":[],"params":[],"ret":null,"#,
+            r#""body":{"kind":"Block","start":14,"end":16,"line":1,"col":14,"stmts":[]}},"#,
+            r#"{"kind":"Error","start":17,"end":23,"line":2,"col":0}],"#,
+            r#""diagnostics":[{"kind":"ExpectedToken(DoubleColon)","#,
+            r#""message":"Expected DoubleColon, found String","#,
+            r#""start":19,"end":23,"line":2,"col":2,"slice":"\"\\\"\""}]}"#,
+            "\n",
+        );
+        assert_eq!(
+            emit("pub f :: fn() {}\ng \"\\\"\"", AstFormat::Json),
+            expected
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        let code = "#![doc(\"x\")]\n#[inline, tool::x(a, b)] f :: fn() { #[cold] g(); }";
+        let expected = r#"(File
+  :attrs [
+    (Attr :known true
+      :path [
+        doc]
+      :args "\"x\"")])
+(Fn
+  :attrs [
+    (Attr :known true
+      :path [
+        inline])
+    (Attr :known false
+      :path [
+        tool
+        x]
+      :args "a, b")]
+  :pub false
+  :name f
+  :generics []
+  :params []
+  :body (Block
+    :stmts [
+      (Semi
+        :attrs [
+          (Attr :known true
+            :path [
+              cold])]
+        :expr (Call :callee (Ident :name g) :args []))]))
+; 2:10: Unknown attribute `tool::x`
+"#;
+        assert_eq!(emit(code, AstFormat::Sexp), expected);
+        assert!(emit(code, AstFormat::Json).starts_with(concat!(
+            r#"{"file":0,"attrs":[{"kind":"Attr","start":3,"end":11,"line":1,"col":3,"#,
+            r#""known":true,"path":["doc"],"args":"\"x\""}],"items":["#
+        )));
+    }
+
+    #[test]
+    fn deep_trees() {
+        // as deep as the parser allows, which doesn't overflow the stack of a test thread
+        let nesting = MAX_NESTING_DEPTH / 2 - 1;
+        let code = format!(
+            "f :: fn() -> {}g{}{};",
+            "-(".repeat(nesting),
+            ".a".repeat(MAX_CHAIN_DEPTH),
+            ")".repeat(nesting)
+        );
+        for format in [AstFormat::Sexp, AstFormat::Json] {
+            let emitted = emit(&code, format);
+            assert!(emitted.contains("Field") && !emitted.contains("Nested too deeply"));
+        }
+
+        // longer chains are errors
+        let code = format!("f :: fn() -> g{};", ".a".repeat(100_000));
+        assert!(emit(&code, AstFormat::Sexp).contains("Nested too deeply"));
+    }
+}
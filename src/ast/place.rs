@@ -0,0 +1,95 @@
+//! Places, the expressions that denote memory that can be written to, like `a.b[i]`.
+//!
+//! `++`, `--` and assignments write to their operand, which must then be a place.
+//! So `5++` and `f() = 1` parse, and are rejected by the passes after the parser,
+//! which find them with [`Ast::misplaced_operands`].
+
+use super::{walk_ast, walk_expr, walk_stmt, Ast, Expr, ExprId, Stmt, StmtId, UnaryOp, Visitor};
+use crate::{
+    diag::{Diagnostic, DiagnosticSink, MISPLACED_OPERAND},
+    source::FileId,
+};
+
+impl UnaryOp {
+    /// Whether the operator writes to its operand, which must then be a place.
+    pub fn needs_place(self) -> bool {
+        matches!(
+            self,
+            UnaryOp::PreIncr | UnaryOp::PreDecr | UnaryOp::PostIncr | UnaryOp::PostDecr
+        )
+    }
+}
+
+impl Ast {
+    /// Whether the expression is a place: a variable, a field of a place, or an element.
+    ///
+    /// Elements are places whatever is indexed, as indexing can go through a pointer.
+    pub fn is_place(&self, id: ExprId) -> bool {
+        match self.exprs[id] {
+            Expr::Ident | Expr::Index { .. } => true,
+            Expr::Field { expr, .. } | Expr::TupleField { expr, .. } => self.is_place(expr),
+            _ => false,
+        }
+    }
+
+    /// Operands of `++` and `--` and targets of assignments that are not places,
+    /// in order of appearance.
+    pub fn misplaced_operands(&self) -> Vec<ExprId> {
+        struct Misplaced(Vec<ExprId>);
+
+        impl Visitor for Misplaced {
+            fn visit_stmt(&mut self, ast: &Ast, id: StmtId) {
+                if let Stmt::Assign { target, .. } = ast.stmts[id] {
+                    if !ast.is_place(target) {
+                        self.0.push(target);
+                    }
+                }
+                walk_stmt(self, ast, id);
+            }
+
+            fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+                // the operand of a postfix operator comes first
+                walk_expr(self, ast, id);
+                if let Expr::Unary { op, expr } = ast.exprs[id] {
+                    if op.needs_place() && !ast.is_place(expr) {
+                        self.0.push(expr);
+                    }
+                }
+            }
+        }
+
+        let mut misplaced = Misplaced(Vec::new());
+        walk_ast(&mut misplaced, self);
+        misplaced.0
+    }
+
+    /// Reports the operands that should be places but aren't, in the file of the AST.
+    pub fn report_misplaced_operands(&self, file: FileId, sink: &mut DiagnosticSink) {
+        for id in self.misplaced_operands() {
+            let span = self.exprs.span(id);
+            let diagnostic = Diagnostic::error(file, span, "Cannot write to this expression");
+            sink.emit(diagnostic.with_code(MISPLACED_OPERAND).with_note(
+                "Only variables, their fields and elements can be assigned or incremented",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_place {
+    use crate::{lexer::lex, parser::parse_file, source::FileId};
+
+    #[test]
+    fn misplaced_operands() {
+        let code =
+            "f :: fn() { a++; --a.b[0].c; t.0 = 1; 5++; f() = 2; a.f()--; ++a++; (a + b).c += 1; }";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let misplaced: Vec<_> = (ast.misplaced_operands().into_iter())
+            .map(|id| &code[ast.exprs.span(id).range()])
+            .collect();
+        assert_eq!(misplaced, ["5", "f()", "a.f()", "a++", "(a + b).c"]);
+    }
+}
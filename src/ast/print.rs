@@ -0,0 +1,994 @@
+//! Printing of the AST back to code, for tools that generate code and to test the parser.
+//!
+//! The code is laid out the same way whatever its original layout, and parentheses are only
+//! written where the parser wouldn't group the nodes the same way without them. What the parser
+//! desugars is printed desugared, like pipelines as the calls they are. So parsing the printed
+//! code gives the same tree, and printing that tree gives the same code again.
+
+use super::{
+    Access, Ast, Attr, BinaryOp, Block, Expr, ExprId, ForIter, ItemId, ItemKind, LitKind, Pattern,
+    PatternId, Stmt, StmtId, Type, TypeId, UnaryOp,
+};
+use crate::{
+    arena::ArenaSlice,
+    lexer::{Span, Tokens},
+    parser::{infix_power, prefix_power},
+};
+
+const INDENT: &str = "    ";
+
+impl Ast {
+    /// Code of the AST parsed from the tokens, blocks being indented with 4 spaces.
+    ///
+    /// Items and statements that have errors are printed as the code they were parsed from.
+    pub fn print(&self, tokens: &Tokens) -> String {
+        let mut printer = Printer {
+            ast: self,
+            tokens,
+            out: String::new(),
+            indent: 0,
+            struct_literals: true,
+        };
+        printer.file();
+        printer.out
+    }
+}
+
+struct Printer<'t, 'a> {
+    ast: &'t Ast,
+    tokens: &'t Tokens<'a>,
+    out: String,
+    /// Number of blocks the current line is in
+    indent: usize,
+    /// Whether a struct literal can be printed without parentheses, as in the parser
+    struct_literals: bool,
+}
+
+impl Printer<'_, '_> {
+    fn file(&mut self) {
+        let ast = self.ast;
+        if !ast.file_attrs.is_empty() {
+            self.attrs("#![", ast.file_attrs);
+            self.out.push('\n');
+        }
+        for (i, id) in ast.items.ids().enumerate() {
+            if i > 0 || !ast.file_attrs.is_empty() {
+                self.out.push('\n');
+            }
+            self.item(id);
+            self.out.push('\n');
+        }
+    }
+
+    fn code(&mut self, span: Span) {
+        self.out.push_str(&self.tokens.code[span.range()]);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Prints the elements one per line between braces, each followed by a comma.
+    fn lines<T: Copy>(&mut self, elements: &[T], mut line: impl FnMut(&mut Self, T)) {
+        if elements.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push('{');
+        self.indent += 1;
+        for &element in elements {
+            self.newline();
+            line(self, element);
+            self.out.push(',');
+        }
+        self.indent -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    /// Prints the elements separated by commas.
+    fn commas<T: Copy>(&mut self, elements: &[T], mut element: impl FnMut(&mut Self, T)) {
+        for (i, &e) in elements.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            element(self, e);
+        }
+    }
+
+    fn attrs(&mut self, open: &str, attrs: ArenaSlice<Attr>) {
+        let ast = self.ast;
+        if attrs.is_empty() {
+            return;
+        }
+        self.out.push_str(open);
+        self.commas(&ast.attrs[attrs], |printer, attr| {
+            for (i, &segment) in ast.paths[attr.path].iter().enumerate() {
+                if i > 0 {
+                    printer.out.push_str("::");
+                }
+                printer.code(segment);
+            }
+            if let Some(args) = attr.args {
+                // the arguments are tokens, which are written as they are in the code
+                let spans = &printer.tokens.spans.as_slice()[args.range()];
+                printer.out.push('(');
+                if let (Some(first), Some(last)) = (spans.first(), spans.last()) {
+                    let code = &printer.tokens.code[first.start as usize..last.end()];
+                    printer.out.push_str(code);
+                }
+                printer.out.push(')');
+            }
+        });
+        self.out.push(']');
+    }
+
+    fn item(&mut self, id: ItemId) {
+        let ast = self.ast;
+        let item = ast.items[id];
+        if let ItemKind::Error = item.kind {
+            return self.code(ast.items.span(id));
+        }
+
+        if !item.attrs.is_empty() {
+            self.attrs("#[", item.attrs);
+            self.newline();
+        }
+        if item.is_pub {
+            self.out.push_str("pub ");
+        }
+        let generics = |printer: &mut Self| {
+            if !item.generics.is_empty() {
+                printer.out.push('[');
+                let generics = &ast.generics[item.generics];
+                printer.commas(generics, |printer, generic| printer.code(generic.name));
+                printer.out.push(']');
+            }
+        };
+
+        match item.kind {
+            ItemKind::Fn { params, ret, body } => {
+                self.code(item.name);
+                self.out.push_str(" :: fn");
+                generics(self);
+                self.out.push('(');
+                self.commas(&ast.params[params], |printer, param| {
+                    printer.pattern(param.pattern);
+                    printer.out.push_str(" : ");
+                    printer.ty(param.ty);
+                });
+                self.out.push(')');
+                if let Some(ret) = ret {
+                    self.out.push_str(" >- ");
+                    self.ty(ret);
+                }
+                if let Expr::Block(block) = ast.exprs[body] {
+                    self.out.push(' ');
+                    self.block(block);
+                } else {
+                    self.out.push_str(" -> ");
+                    self.expr(body);
+                    self.out.push(';');
+                }
+            }
+            ItemKind::Struct {
+                packed,
+                repr,
+                fields,
+            } => {
+                self.code(item.name);
+                self.out.push_str(if packed {
+                    " :: packed struct"
+                } else {
+                    " :: struct"
+                });
+                generics(self);
+                self.repr(repr);
+                self.fields(fields);
+            }
+            ItemKind::Enum { repr, variants } => {
+                self.code(item.name);
+                self.out.push_str(" :: enum");
+                generics(self);
+                self.repr(repr);
+                self.lines(&ast.variants[variants], |printer, variant| {
+                    printer.code(variant.name);
+                    if let Some(ty) = variant.ty {
+                        printer.out.push_str(" : ");
+                        printer.ty(ty);
+                    }
+                    if let Some(discriminant) = variant.discriminant {
+                        printer.out.push_str(" = ");
+                        printer.expr(discriminant);
+                    }
+                });
+            }
+            ItemKind::Union { fields } => {
+                self.code(item.name);
+                self.out.push_str(" :: union");
+                generics(self);
+                self.out.push(' ');
+                self.fields(fields);
+            }
+            ItemKind::Const { ty, value } | ItemKind::Static { ty, value } => {
+                let keyword = match item.kind {
+                    ItemKind::Const { .. } => "const ",
+                    _ => "static ",
+                };
+                self.out.push_str(keyword);
+                self.code(item.name);
+                self.typed_value(ty, value);
+            }
+            ItemKind::Use { path } => {
+                self.out.push_str("use ");
+                for (i, &segment) in ast.paths[path].iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str("::");
+                    }
+                    self.code(segment);
+                }
+                self.out.push(';');
+            }
+            ItemKind::Error => unreachable!(),
+        }
+    }
+
+    /// Type before the `{` of a struct or an enum, followed by a space either way.
+    fn repr(&mut self, repr: Option<TypeId>) {
+        self.out.push(' ');
+        if let Some(repr) = repr {
+            self.ty(repr);
+            self.out.push(' ');
+        }
+    }
+
+    fn fields(&mut self, fields: ArenaSlice<super::Field>) {
+        let ast = self.ast;
+        self.lines(&ast.fields[fields], |printer, field| {
+            if !field.attrs.is_empty() {
+                printer.attrs("#[", field.attrs);
+                printer.out.push(' ');
+            }
+            if field.is_pub {
+                printer.out.push_str("pub ");
+            }
+            printer.code(field.name);
+            printer.out.push_str(" : ");
+            printer.ty(field.ty);
+        });
+    }
+
+    /// Type and value of a declaration, like ` : u32 = 0;` or ` := 0;`.
+    fn typed_value(&mut self, ty: Option<TypeId>, value: ExprId) {
+        match ty {
+            Some(ty) => {
+                self.out.push_str(" : ");
+                self.ty(ty);
+                self.out.push_str(" = ");
+            }
+            None => self.out.push_str(" := "),
+        }
+        self.expr(value);
+        self.out.push(';');
+    }
+
+    fn block(&mut self, block: Block) {
+        let ast = self.ast;
+        let stmts = &ast.stmt_lists[block.stmts];
+        if stmts.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        let outer = std::mem::replace(&mut self.struct_literals, true);
+        self.out.push('{');
+        self.indent += 1;
+        for (i, &stmt) in stmts.iter().enumerate() {
+            self.newline();
+            self.stmt(stmt, i + 1 == stmts.len());
+        }
+        self.indent -= 1;
+        self.newline();
+        self.out.push('}');
+        self.struct_literals = outer;
+    }
+
+    fn stmt(&mut self, id: StmtId, is_last: bool) {
+        let ast = self.ast;
+        let attrs = ast.stmt_attrs(id);
+        if !attrs.is_empty() {
+            self.attrs("#[", attrs);
+            self.out.push(' ');
+        }
+
+        match ast.stmts[id] {
+            Stmt::Let {
+                access,
+                pattern,
+                ty,
+                value,
+            } => {
+                let marker = match access {
+                    Access::Implicit => "",
+                    Access::Read => "r ",
+                    Access::Write => "w ",
+                    Access::ReadWrite => "rw ",
+                };
+                self.out.push_str(marker);
+                self.pattern(pattern);
+                self.typed_value(ty, value);
+            }
+            Stmt::Assign { target, op, value } => {
+                self.expr(target);
+                self.out.push(' ');
+                if let Some(op) = op {
+                    self.out.push_str(binary_op(op));
+                }
+                self.out.push_str("= ");
+                self.expr(value);
+                self.out.push(';');
+            }
+            Stmt::Expr(expr) => {
+                self.expr(expr);
+                // only expressions that end with a block go on without a `;`
+                if !is_last && !self.out.ends_with('}') {
+                    self.out.push(';');
+                }
+            }
+            Stmt::Semi(expr) => {
+                self.expr(expr);
+                self.out.push(';');
+            }
+            Stmt::Defer(expr) => {
+                self.out.push_str("defer ");
+                self.expr(expr);
+                self.out.push(';');
+            }
+            Stmt::Error => self.code(ast.stmts.span(id)),
+        }
+    }
+
+    /// Prints in parentheses, within which struct literals are allowed again.
+    fn parens(&mut self, print: impl FnOnce(&mut Self)) {
+        let outer = std::mem::replace(&mut self.struct_literals, true);
+        self.out.push('(');
+        print(self);
+        self.out.push(')');
+        self.struct_literals = outer;
+    }
+
+    fn with_struct_literals(&mut self, allowed: bool, print: impl FnOnce(&mut Self)) {
+        let outer = std::mem::replace(&mut self.struct_literals, allowed);
+        print(self);
+        self.struct_literals = outer;
+    }
+
+    /// Prints an expression where a whole one is parsed, like an argument.
+    fn expr(&mut self, id: ExprId) {
+        let ast = self.ast;
+        let exprs = |printer: &mut Self, ids: ArenaSlice<ExprId>| {
+            printer.commas(&ast.expr_lists[ids], Self::expr);
+        };
+
+        match ast.exprs[id] {
+            Expr::Lit(LitKind::Unit) => self.out.push_str("()"),
+            Expr::Ident | Expr::Lit(_) => self.code(ast.exprs.span(id)),
+            Expr::Placeholder => self.out.push('_'),
+            Expr::InterpolatedString { parts, holes } => {
+                let parts = &ast.string_parts[parts];
+                self.out.push_str("$\"");
+                self.code(parts[0]);
+                for (&part, hole) in parts[1..].iter().zip(&ast.holes[holes]) {
+                    self.out.push('{');
+                    self.expr(hole.expr);
+                    if let Some(spec) = hole.spec {
+                        self.code(spec);
+                    }
+                    self.out.push('}');
+                    self.code(part);
+                }
+                self.out.push('"');
+            }
+            Expr::Block(block) => self.block(block),
+            Expr::Call { callee, args } => {
+                self.postfix_operand(callee);
+                self.parens(|printer| exprs(printer, args));
+            }
+            Expr::Struct { ty, fields, base } => {
+                let literal = |printer: &mut Self| {
+                    printer.ty(ty);
+                    let fields = &ast.field_inits[fields];
+                    if fields.is_empty() && base.is_none() {
+                        printer.out.push_str(" {}");
+                        return;
+                    }
+                    printer.out.push_str(" { ");
+                    printer.commas(fields, |printer, field| {
+                        printer.code(field.name);
+                        // shorthand fields are the identifier of their name
+                        if ast.exprs.span(field.value) != field.name {
+                            printer.out.push_str(": ");
+                            printer.expr(field.value);
+                        }
+                    });
+                    if let Some(base) = base {
+                        if !fields.is_empty() {
+                            printer.out.push_str(", ");
+                        }
+                        printer.out.push_str("..");
+                        printer.expr(base);
+                    }
+                    printer.out.push_str(" }");
+                };
+                match self.struct_literals {
+                    true => literal(self),
+                    false => self.parens(literal),
+                }
+            }
+            Expr::Field { expr, name } => {
+                self.receiver(expr, false);
+                self.out.push('.');
+                self.code(name);
+            }
+            Expr::TupleField { expr, index } => {
+                self.receiver(expr, true);
+                self.out.push('.');
+                self.code(index);
+            }
+            Expr::MethodCall { method, args } => {
+                let args = &ast.expr_lists[args];
+                self.receiver(args[0], false);
+                self.out.push('.');
+                self.code(method);
+                self.parens(|printer| printer.commas(&args[1..], Self::expr));
+            }
+            Expr::Closure {
+                captures,
+                params,
+                ret,
+                body,
+            } => {
+                let params = &ast.closure_params[params];
+                // `|` also separates the alternatives of patterns
+                let is_or = |pattern| matches!(ast.patterns[pattern], Pattern::Or(_));
+                let param = |printer: &mut Self, param: super::ClosureParam| {
+                    printer.pattern(param.pattern);
+                    if let Some(ty) = param.ty {
+                        printer.out.push_str(" : ");
+                        printer.ty(ty);
+                    }
+                };
+
+                if captures.is_none()
+                    && ret.is_none()
+                    && !params.iter().any(|param| is_or(param.pattern))
+                {
+                    self.out.push('|');
+                    self.commas(params, param);
+                    self.out.push_str("| ");
+                    return self.expr(body);
+                }
+
+                self.out.push_str("fn");
+                if let Some(captures) = captures {
+                    self.out.push('[');
+                    self.commas(&ast.captures[captures], |printer, capture| {
+                        if capture.by_ref {
+                            printer.out.push('&');
+                        }
+                        printer.code(capture.name);
+                    });
+                    self.out.push(']');
+                }
+                self.parens(|printer| printer.commas(params, param));
+                if let Some(ret) = ret {
+                    self.out.push_str(" >- ");
+                    self.ty(ret);
+                }
+                match ast.exprs[body] {
+                    Expr::Block(block) => {
+                        self.out.push(' ');
+                        self.block(block);
+                    }
+                    _ => {
+                        self.out.push_str(" -> ");
+                        self.expr(body);
+                    }
+                }
+            }
+            Expr::Array(elements) => {
+                self.with_struct_literals(true, |printer| {
+                    printer.out.push('[');
+                    exprs(printer, elements);
+                    printer.out.push(']');
+                });
+            }
+            Expr::Tuple(elements) => self.parens(|printer| {
+                exprs(printer, elements);
+                if elements.len() == 1 {
+                    printer.out.push(',');
+                }
+            }),
+            Expr::ArrayRepeat { value, len } => {
+                self.with_struct_literals(true, |printer| {
+                    printer.out.push('[');
+                    printer.expr(value);
+                    printer.out.push_str("; ");
+                    printer.expr(len);
+                    printer.out.push(']');
+                });
+            }
+            Expr::Index { expr, index } => {
+                self.postfix_operand(expr);
+                self.with_struct_literals(true, |printer| {
+                    printer.out.push('[');
+                    printer.expr(index);
+                    printer.out.push(']');
+                });
+            }
+            Expr::Unary { op, expr } => match op {
+                UnaryOp::PostIncr | UnaryOp::PostDecr => {
+                    self.postfix_operand(expr);
+                    self.out.push_str(unary_op(op));
+                }
+                _ => {
+                    self.out.push_str(unary_op(op));
+                    // `- -a` would be lexed as `--a` without the space
+                    let is_prefix =
+                        matches!(ast.exprs[expr], Expr::Unary { op, .. } if is_prefix(op));
+                    if op == UnaryOp::Not || is_prefix {
+                        self.out.push(' ');
+                    }
+                    self.rhs(expr, prefix_power(op));
+                }
+            },
+            Expr::Binary { op, lhs, rhs } => {
+                let (left_power, right_power) = infix_power(op);
+                // comparisons would be chained, which is reported
+                let is_chained = op.is_comparison()
+                    && matches!(ast.exprs[lhs], Expr::Binary { op, .. } if op.is_comparison());
+                match is_chained {
+                    true => self.parens(|printer| printer.expr(lhs)),
+                    false => self.lhs(lhs, left_power),
+                }
+                self.out.push(' ');
+                self.out.push_str(binary_op(op));
+                self.out.push(' ');
+                self.rhs(rhs, right_power);
+            }
+            Expr::Section { op, rhs } => self.parens(|printer| {
+                printer.out.push_str(binary_op(op));
+                printer.out.push(' ');
+                printer.rhs(rhs, infix_power(op).1);
+            }),
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.out.push_str("if ");
+                self.with_struct_literals(false, |printer| printer.expr(cond));
+                self.out.push_str(" then ");
+                // an `else` after an `if` that has none would be its own
+                match otherwise.is_some() && self.dangles(then) {
+                    true => self.parens(|printer| printer.expr(then)),
+                    false => self.expr(then),
+                }
+                if let Some(otherwise) = otherwise {
+                    self.out.push_str(" else ");
+                    self.expr(otherwise);
+                }
+            }
+            Expr::While { label, cond, body } => {
+                self.label(label);
+                self.out.push_str("while ");
+                self.with_struct_literals(false, |printer| printer.expr(cond));
+                self.out.push_str(" do ");
+                self.expr(body);
+            }
+            Expr::DoWhile { label, body, cond } => {
+                self.label(label);
+                self.out.push_str("do ");
+                self.expr(body);
+                self.out.push_str(" while ");
+                self.expr(cond);
+            }
+            Expr::Loop { label, body } => {
+                self.label(label);
+                self.out.push_str("loop ");
+                self.expr(body);
+            }
+            Expr::For {
+                label,
+                pattern,
+                index,
+                iter,
+                body,
+            } => {
+                self.label(label);
+                self.out.push_str("for ");
+                self.pattern(pattern);
+                if let Some(index) = index {
+                    self.out.push_str(", ");
+                    self.pattern(index);
+                }
+                self.out.push_str(" in ");
+                self.with_struct_literals(false, |printer| match iter {
+                    ForIter::Range { start, end } => {
+                        printer.expr(start);
+                        printer.out.push_str("..");
+                        printer.expr(end);
+                    }
+                    ForIter::Iter(iter) => printer.expr(iter),
+                });
+                match ast.exprs[body] {
+                    Expr::Block(block) => {
+                        self.out.push(' ');
+                        self.block(block);
+                    }
+                    _ => {
+                        self.out.push_str(" do ");
+                        self.expr(body);
+                    }
+                }
+            }
+            Expr::Match { scrutinee, arms } => {
+                self.out.push_str("match ");
+                self.with_struct_literals(false, |printer| printer.expr(scrutinee));
+                self.out.push(' ');
+                self.lines(&ast.arms[arms], |printer, arm| {
+                    printer.pattern(arm.pattern);
+                    if let Some(guard) = arm.guard {
+                        printer.out.push_str(" if ");
+                        printer.expr(guard);
+                    }
+                    printer.out.push_str(" => ");
+                    printer.expr(arm.body);
+                });
+            }
+            Expr::Break { label, value } => {
+                self.out.push_str("break");
+                if let Some(label) = label {
+                    self.out.push_str(" @");
+                    self.code(label);
+                }
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+            }
+            Expr::Continue { label } => {
+                self.out.push_str("continue");
+                if let Some(label) = label {
+                    self.out.push_str(" @");
+                    self.code(label);
+                }
+            }
+            Expr::Return { value } => {
+                self.out.push_str("return");
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+            }
+        }
+    }
+
+    fn label(&mut self, label: Option<Span>) {
+        if let Some(label) = label {
+            self.out.push('@');
+            self.code(label);
+            self.out.push(' ');
+        }
+    }
+
+    /// Prints the left operand of an infix operator of the given left binding power.
+    fn lhs(&mut self, id: ExprId, power: u8) {
+        match self.powers(id).1 <= power {
+            true => self.parens(|printer| printer.expr(id)),
+            false => self.expr(id),
+        }
+    }
+
+    /// Prints the right operand of an operator of the given right binding power.
+    fn rhs(&mut self, id: ExprId, power: u8) {
+        match self.powers(id).0 < power {
+            true => self.parens(|printer| printer.expr(id)),
+            false => self.expr(id),
+        }
+    }
+
+    /// Prints the operand of a call, an index or a postfix operator, which binds the tightest.
+    fn postfix_operand(&mut self, id: ExprId) {
+        self.lhs(id, u8::MAX - 1);
+    }
+
+    /// Prints the operand before a `.`, which can't end with a number as the `.` would be
+    /// part of it, except for tuple indices that are split back by the parser, as in `t.0.1`.
+    fn receiver(&mut self, id: ExprId, before_index: bool) {
+        match self.ast.exprs[id] {
+            Expr::Lit(LitKind::Num) => self.parens(|printer| printer.expr(id)),
+            Expr::TupleField { .. } if !before_index => self.parens(|printer| printer.expr(id)),
+            _ => self.postfix_operand(id),
+        }
+    }
+
+    /// Binding powers of an expression as an operand, like those of [`infix_power`]:
+    /// how tightly it holds together on its left, and on its right.
+    ///
+    /// Expressions that end with one that takes every operator after it, like an `if`,
+    /// don't hold together at all on their right.
+    fn powers(&self, id: ExprId) -> (u8, u8) {
+        match self.ast.exprs[id] {
+            Expr::Binary { op, rhs, .. } => {
+                let (left, right) = infix_power(op);
+                (left, right.min(self.tail_power(rhs, right)))
+            }
+            Expr::Unary { op, expr } if is_prefix(op) => {
+                let power = prefix_power(op);
+                (u8::MAX, power.min(self.tail_power(expr, power)))
+            }
+            Expr::If { .. }
+            | Expr::While { .. }
+            | Expr::DoWhile { .. }
+            | Expr::Loop { .. }
+            | Expr::For { .. }
+            | Expr::Closure { .. }
+            | Expr::Break { .. }
+            | Expr::Continue { .. }
+            | Expr::Return { .. } => (u8::MAX, 0),
+            _ => (u8::MAX, u8::MAX),
+        }
+    }
+
+    /// Right binding power of the right operand of an operator, which holds together
+    /// when it is in parentheses.
+    fn tail_power(&self, id: ExprId, power: u8) -> u8 {
+        let (left, right) = self.powers(id);
+        match left < power {
+            true => u8::MAX,
+            false => right,
+        }
+    }
+
+    /// Whether the expression ends with an `if` that has no `else`, without parentheses.
+    fn dangles(&self, id: ExprId) -> bool {
+        match self.ast.exprs[id] {
+            Expr::If {
+                otherwise: None, ..
+            } => true,
+            Expr::If {
+                otherwise: Some(last),
+                ..
+            }
+            | Expr::While { body: last, .. }
+            | Expr::DoWhile { cond: last, .. }
+            | Expr::Loop { body: last, .. }
+            | Expr::For { body: last, .. }
+            | Expr::Closure { body: last, .. }
+            | Expr::Break {
+                value: Some(last), ..
+            }
+            | Expr::Return { value: Some(last) } => self.dangles(last),
+            Expr::Binary { op, rhs, .. } => {
+                self.powers(rhs).0 >= infix_power(op).1 && self.dangles(rhs)
+            }
+            Expr::Unary { op, expr } if is_prefix(op) => {
+                self.powers(expr).0 >= prefix_power(op) && self.dangles(expr)
+            }
+            _ => false,
+        }
+    }
+
+    fn ty(&mut self, id: TypeId) {
+        let ast = self.ast;
+        let types = |printer: &mut Self, ids: ArenaSlice<TypeId>| {
+            printer.commas(&ast.type_lists[ids], Self::ty);
+        };
+
+        match ast.types[id] {
+            Type::Named => self.code(ast.types.span(id)),
+            Type::Generic { base, args } => {
+                self.ty(base);
+                self.out.push('[');
+                types(self, args);
+                self.out.push(']');
+            }
+            Type::Array { elem, len } => {
+                self.out.push('[');
+                self.ty(elem);
+                self.out.push_str("; ");
+                self.expr(len);
+                self.out.push(']');
+            }
+            Type::Slice(elem) => {
+                self.out.push('[');
+                self.ty(elem);
+                self.out.push(']');
+            }
+            Type::Pointer(inner) | Type::Ref(inner) | Type::Optional(inner) => {
+                let sigil = match ast.types[id] {
+                    Type::Pointer(_) => '*',
+                    Type::Ref(_) => '&',
+                    _ => '?',
+                };
+                self.out.push(sigil);
+                self.ty(inner);
+            }
+            Type::Fn { params, ret } => {
+                self.out.push_str("fn(");
+                types(self, params);
+                self.out.push(')');
+                if let Some(ret) = ret {
+                    self.out.push_str(" >- ");
+                    self.ty(ret);
+                }
+            }
+            Type::Tuple(elements) => {
+                self.out.push('(');
+                types(self, elements);
+                if elements.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+        }
+    }
+
+    fn pattern(&mut self, id: PatternId) {
+        let ast = self.ast;
+        match ast.patterns[id] {
+            Pattern::Ident | Pattern::Lit(_) => self.code(ast.patterns.span(id)),
+            Pattern::Wildcard => self.out.push('_'),
+            Pattern::Variant { ty, name, payload } => {
+                if let Some(ty) = ty {
+                    self.ty(ty);
+                }
+                self.out.push('.');
+                self.code(name);
+                if let Some(payload) = payload {
+                    self.out.push('(');
+                    self.pattern(payload);
+                    self.out.push(')');
+                }
+            }
+            Pattern::Or(alternatives) => {
+                for (i, &alternative) in ast.pattern_lists[alternatives].iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(" | ");
+                    }
+                    self.pattern(alternative);
+                }
+            }
+        }
+    }
+}
+
+fn is_prefix(op: UnaryOp) -> bool {
+    !matches!(op, UnaryOp::PostIncr | UnaryOp::PostDecr)
+}
+
+fn unary_op(op: UnaryOp) -> &'static str {
+    match op {
+        UnaryOp::Neg => "-",
+        UnaryOp::BitNot => "~",
+        UnaryOp::Not => "not",
+        UnaryOp::PreIncr | UnaryOp::PostIncr => "++",
+        UnaryOp::PreDecr | UnaryOp::PostDecr => "--",
+    }
+}
+
+fn binary_op(op: BinaryOp) -> &'static str {
+    use BinaryOp as B;
+
+    match op {
+        B::Or => "or",
+        B::Xor => "xor",
+        B::And => "and",
+        B::Eq => "==",
+        B::Ne => "!=",
+        B::Lt => "<",
+        B::Gt => ">",
+        B::Le => "<=",
+        B::Ge => ">=",
+        B::BitOr => "|",
+        B::BitXor => "^",
+        B::BitAnd => "&",
+        B::Shl => "<<",
+        B::Shr => ">>",
+        B::Add => "+",
+        B::Sub => "-",
+        B::Mul => "*",
+        B::Div => "/",
+        B::Mod => "%",
+        B::Pow => "**",
+    }
+}
+
+#[cfg(test)]
+mod tests_print {
+    use crate::{
+        lexer::lex,
+        parser::{parse_file, MAX_CHAIN_DEPTH},
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+    fn print(code: &str) -> String {
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{code:?}: {diagnostics:?}");
+        ast.print(&tokens)
+    }
+
+    /// Prints the body of a function that returns the expression.
+    fn print_expr(code: &str) -> String {
+        let printed = print(&format!("f :: fn() -> {code};"));
+        let printed = printed.strip_prefix("f :: fn() -> ").unwrap();
+        printed.strip_suffix(";\n").unwrap().to_string()
+    }
+
+    #[test]
+    fn layout() {
+        let code = "#![doc(\"p\")]  pub   p::struct[T]{x:T,#[repr(C ,u8)]pub y:u8}\n\
+                    f::fn(x:i32)>-i32{y:=x;r z:u8=1;z+=y;if x then{}g(x);{}}";
+        assert_eq!(
+            print(code),
+            "#![doc(\"p\")]\n\n\
+             pub p :: struct[T] {\n    x : T,\n    #[repr(C ,u8)] pub y : u8,\n}\n\n\
+             f :: fn(x : i32) >- i32 {\n    y := x;\n    r z : u8 = 1;\n    z += y;\n    \
+             if x then {}\n    g(x);\n    {}\n}\n"
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        let cases = [
+            ("(a + b) * c", "(a + b) * c"),
+            ("a + (b * c)", "a + b * c"),
+            ("a - (b - c)", "a - (b - c)"),
+            ("(a < b) == (c < d)", "(a < b) == (c < d)"),
+            ("(a ** b) ** c", "(a ** b) ** c"),
+            ("-(a ** b)", "-a ** b"),
+            ("(-a) ** b", "(-a) ** b"),
+            ("-(-a)", "- -a"),
+            ("not (a and b)", "not (a and b)"),
+            ("(a + b).c", "(a + b).c"),
+            ("(1).a + (1.0).b", "(1).a + (1.0).b"),
+            ("(t.0.1).a + (t.0).f()", "(t.0.1).a + (t.0).f()"),
+            ("(a++)++", "a++++"),
+            ("(if a then b) + c", "(if a then b) + c"),
+            ("a + (if b then c else d)", "a + if b then c else d"),
+            ("(a + if b then c) * d", "(a + if b then c) * d"),
+            (
+                "if a then (if b then c) else d",
+                "if a then (if b then c) else d",
+            ),
+            ("if (P {}) == p then q", "if (P {}) == p then q"),
+            ("f(P { x: 1, y })", "f(P { x: 1, y })"),
+            ("x >- f(_, 1) >- (+ 1)", "(+ 1)(f(_, 1)(x))"),
+            ("((a), (b,), ())", "(a, (b,), ())"),
+            ("fn[&a](x : u8) >- u8 -> x", "fn[&a](x : u8) >- u8 -> x"),
+            ("fn(a | b) -> a", "fn(a | b) -> a"),
+        ];
+        for (code, printed) in cases {
+            assert_eq!(print_expr(code), printed, "{code:?}");
+        }
+    }
+
+    #[test]
+    fn fixpoint() {
+        let printed = print(SAMPLE);
+        assert_eq!(print(&printed), printed);
+    }
+
+    #[test]
+    fn long_chains() {
+        let chain = format!("g{}", ".a()".repeat(MAX_CHAIN_DEPTH));
+        assert_eq!(print_expr(&chain), chain);
+    }
+}
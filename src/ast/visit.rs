@@ -0,0 +1,459 @@
+//! Traversals of the AST, so that passes only handle the nodes they are about.
+//!
+//! A [`Visitor`] is called on every node of a kind and walks its children with the `walk_*`
+//! function of that kind, unless it overrides the method to do otherwise:
+//!
+//! ```
+//! use csussus::ast::{walk_expr, Ast, Expr, ExprId, Visitor};
+//!
+//! /// Counts the calls in the AST.
+//! struct Calls(usize);
+//!
+//! impl Visitor for Calls {
+//!     fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+//!         if let Expr::Call { .. } | Expr::MethodCall { .. } = ast.exprs[id] {
+//!             self.0 += 1;
+//!         }
+//!         walk_expr(self, ast, id);
+//!     }
+//! }
+//! ```
+//!
+//! A [`VisitorMut`] is the same with a mutable AST, so that nodes can be rewritten in place.
+//! Nodes are copied out of the AST before their children are visited, so a visitor can change
+//! a node before walking it to walk its new children, or after to keep the ones it had.
+
+use super::{
+    Ast, ClosureParam, Expr, ExprId, ForIter, Hole, ItemId, ItemKind, Pattern, PatternId, Stmt,
+    StmtId, Type, TypeId,
+};
+
+/// Defines a visitor trait and its walk functions for the given type of AST.
+///
+/// Lists are indexed rather than iterated, so that the AST can be lent to the visitor
+/// between their elements when it's mutable.
+macro_rules! visitor {
+    (
+        $(#[$attr:meta])*
+        trait $Visitor:ident($Ast:ty);
+        $walk_ast:ident, $walk_item:ident, $walk_stmt:ident, $walk_expr:ident,
+        $walk_type:ident, $walk_pattern:ident;
+    ) => {
+        $(#[$attr])*
+        pub trait $Visitor {
+            fn visit_item(&mut self, ast: $Ast, id: ItemId) {
+                $walk_item(self, ast, id);
+            }
+
+            fn visit_stmt(&mut self, ast: $Ast, id: StmtId) {
+                $walk_stmt(self, ast, id);
+            }
+
+            fn visit_expr(&mut self, ast: $Ast, id: ExprId) {
+                $walk_expr(self, ast, id);
+            }
+
+            fn visit_type(&mut self, ast: $Ast, id: TypeId) {
+                $walk_type(self, ast, id);
+            }
+
+            fn visit_pattern(&mut self, ast: $Ast, id: PatternId) {
+                $walk_pattern(self, ast, id);
+            }
+        }
+
+        /// Visits every item, in order of appearance.
+        pub fn $walk_ast<V: $Visitor + ?Sized>(visitor: &mut V, ast: $Ast) {
+            for id in ast.items.ids() {
+                visitor.visit_item(ast, id);
+            }
+        }
+
+        /// Visits the types, patterns and expressions of an item.
+        pub fn $walk_item<V: $Visitor + ?Sized>(visitor: &mut V, ast: $Ast, id: ItemId) {
+            match ast.items[id].kind {
+                ItemKind::Fn { params, ret, body } => {
+                    for i in 0..params.len() {
+                        let param = ast.params[params][i];
+                        visitor.visit_pattern(ast, param.pattern);
+                        visitor.visit_type(ast, param.ty);
+                    }
+                    if let Some(ret) = ret {
+                        visitor.visit_type(ast, ret);
+                    }
+                    visitor.visit_expr(ast, body);
+                }
+                ItemKind::Struct { repr, fields, .. } => {
+                    if let Some(repr) = repr {
+                        visitor.visit_type(ast, repr);
+                    }
+                    for i in 0..fields.len() {
+                        let ty = ast.fields[fields][i].ty;
+                        visitor.visit_type(ast, ty);
+                    }
+                }
+                ItemKind::Enum { repr, variants } => {
+                    if let Some(repr) = repr {
+                        visitor.visit_type(ast, repr);
+                    }
+                    for i in 0..variants.len() {
+                        let variant = ast.variants[variants][i];
+                        if let Some(ty) = variant.ty {
+                            visitor.visit_type(ast, ty);
+                        }
+                        if let Some(discriminant) = variant.discriminant {
+                            visitor.visit_expr(ast, discriminant);
+                        }
+                    }
+                }
+                ItemKind::Union { fields } => {
+                    for i in 0..fields.len() {
+                        let ty = ast.fields[fields][i].ty;
+                        visitor.visit_type(ast, ty);
+                    }
+                }
+                ItemKind::Const { ty, value } | ItemKind::Static { ty, value } => {
+                    if let Some(ty) = ty {
+                        visitor.visit_type(ast, ty);
+                    }
+                    visitor.visit_expr(ast, value);
+                }
+                ItemKind::Use { .. } | ItemKind::Error => {}
+            }
+        }
+
+        pub fn $walk_stmt<V: $Visitor + ?Sized>(visitor: &mut V, ast: $Ast, id: StmtId) {
+            match ast.stmts[id] {
+                Stmt::Let {
+                    pattern, ty, value, ..
+                } => {
+                    visitor.visit_pattern(ast, pattern);
+                    if let Some(ty) = ty {
+                        visitor.visit_type(ast, ty);
+                    }
+                    visitor.visit_expr(ast, value);
+                }
+                Stmt::Assign { target, value, .. } => {
+                    visitor.visit_expr(ast, target);
+                    visitor.visit_expr(ast, value);
+                }
+                Stmt::Expr(expr) | Stmt::Semi(expr) | Stmt::Defer(expr) => {
+                    visitor.visit_expr(ast, expr);
+                }
+                Stmt::Error => {}
+            }
+        }
+
+        /// Visits the children of an expression.
+        ///
+        /// The statements of a block are visited once, its defers being among them.
+        pub fn $walk_expr<V: $Visitor + ?Sized>(visitor: &mut V, ast: $Ast, id: ExprId) {
+            match ast.exprs[id] {
+                Expr::Ident | Expr::Lit(_) | Expr::Placeholder | Expr::Continue { .. } => {}
+                Expr::InterpolatedString { holes, .. } => {
+                    for i in 0..holes.len() {
+                        let Hole { expr, .. } = ast.holes[holes][i];
+                        visitor.visit_expr(ast, expr);
+                    }
+                }
+                Expr::Block(block) => {
+                    for i in 0..block.stmts.len() {
+                        let stmt = ast.stmt_lists[block.stmts][i];
+                        visitor.visit_stmt(ast, stmt);
+                    }
+                }
+                Expr::Call { callee, args } => {
+                    visitor.visit_expr(ast, callee);
+                    for i in 0..args.len() {
+                        let arg = ast.expr_lists[args][i];
+                        visitor.visit_expr(ast, arg);
+                    }
+                }
+                Expr::MethodCall { args, .. } | Expr::Array(args) | Expr::Tuple(args) => {
+                    for i in 0..args.len() {
+                        let arg = ast.expr_lists[args][i];
+                        visitor.visit_expr(ast, arg);
+                    }
+                }
+                Expr::Struct { ty, fields, base } => {
+                    visitor.visit_type(ast, ty);
+                    for i in 0..fields.len() {
+                        let value = ast.field_inits[fields][i].value;
+                        visitor.visit_expr(ast, value);
+                    }
+                    if let Some(base) = base {
+                        visitor.visit_expr(ast, base);
+                    }
+                }
+                Expr::Closure {
+                    params, ret, body, ..
+                } => {
+                    for i in 0..params.len() {
+                        let ClosureParam { pattern, ty } = ast.closure_params[params][i];
+                        visitor.visit_pattern(ast, pattern);
+                        if let Some(ty) = ty {
+                            visitor.visit_type(ast, ty);
+                        }
+                    }
+                    if let Some(ret) = ret {
+                        visitor.visit_type(ast, ret);
+                    }
+                    visitor.visit_expr(ast, body);
+                }
+                Expr::Field { expr, .. }
+                | Expr::TupleField { expr, .. }
+                | Expr::Unary { expr, .. }
+                | Expr::Section { rhs: expr, .. } => {
+                    visitor.visit_expr(ast, expr);
+                }
+                Expr::ArrayRepeat {
+                    value: first,
+                    len: second,
+                }
+                | Expr::Index {
+                    expr: first,
+                    index: second,
+                }
+                | Expr::Binary {
+                    lhs: first,
+                    rhs: second,
+                    ..
+                }
+                | Expr::While {
+                    cond: first,
+                    body: second,
+                    ..
+                }
+                | Expr::DoWhile {
+                    body: first,
+                    cond: second,
+                    ..
+                } => {
+                    visitor.visit_expr(ast, first);
+                    visitor.visit_expr(ast, second);
+                }
+                Expr::If {
+                    cond,
+                    then,
+                    otherwise,
+                } => {
+                    visitor.visit_expr(ast, cond);
+                    visitor.visit_expr(ast, then);
+                    if let Some(otherwise) = otherwise {
+                        visitor.visit_expr(ast, otherwise);
+                    }
+                }
+                Expr::Loop { body, .. } => visitor.visit_expr(ast, body),
+                Expr::For {
+                    pattern,
+                    index,
+                    iter,
+                    body,
+                    ..
+                } => {
+                    visitor.visit_pattern(ast, pattern);
+                    if let Some(index) = index {
+                        visitor.visit_pattern(ast, index);
+                    }
+                    match iter {
+                        ForIter::Range { start, end } => {
+                            visitor.visit_expr(ast, start);
+                            visitor.visit_expr(ast, end);
+                        }
+                        ForIter::Iter(iter) => visitor.visit_expr(ast, iter),
+                    }
+                    visitor.visit_expr(ast, body);
+                }
+                Expr::Match { scrutinee, arms } => {
+                    visitor.visit_expr(ast, scrutinee);
+                    for i in 0..arms.len() {
+                        let arm = ast.arms[arms][i];
+                        visitor.visit_pattern(ast, arm.pattern);
+                        if let Some(guard) = arm.guard {
+                            visitor.visit_expr(ast, guard);
+                        }
+                        visitor.visit_expr(ast, arm.body);
+                    }
+                }
+                Expr::Break { value, .. } | Expr::Return { value } => {
+                    if let Some(value) = value {
+                        visitor.visit_expr(ast, value);
+                    }
+                }
+            }
+        }
+
+        pub fn $walk_type<V: $Visitor + ?Sized>(visitor: &mut V, ast: $Ast, id: TypeId) {
+            match ast.types[id] {
+                Type::Named => {}
+                Type::Generic { base, args } => {
+                    visitor.visit_type(ast, base);
+                    for i in 0..args.len() {
+                        let arg = ast.type_lists[args][i];
+                        visitor.visit_type(ast, arg);
+                    }
+                }
+                Type::Array { elem, len } => {
+                    visitor.visit_type(ast, elem);
+                    visitor.visit_expr(ast, len);
+                }
+                Type::Slice(ty) | Type::Pointer(ty) | Type::Ref(ty) | Type::Optional(ty) => {
+                    visitor.visit_type(ast, ty);
+                }
+                Type::Fn { params, ret } => {
+                    for i in 0..params.len() {
+                        let param = ast.type_lists[params][i];
+                        visitor.visit_type(ast, param);
+                    }
+                    if let Some(ret) = ret {
+                        visitor.visit_type(ast, ret);
+                    }
+                }
+                Type::Tuple(elems) => {
+                    for i in 0..elems.len() {
+                        let elem = ast.type_lists[elems][i];
+                        visitor.visit_type(ast, elem);
+                    }
+                }
+            }
+        }
+
+        pub fn $walk_pattern<V: $Visitor + ?Sized>(visitor: &mut V, ast: $Ast, id: PatternId) {
+            match ast.patterns[id] {
+                Pattern::Ident | Pattern::Wildcard | Pattern::Lit(_) => {}
+                Pattern::Variant { ty, payload, .. } => {
+                    if let Some(ty) = ty {
+                        visitor.visit_type(ast, ty);
+                    }
+                    if let Some(payload) = payload {
+                        visitor.visit_pattern(ast, payload);
+                    }
+                }
+                Pattern::Or(alternatives) => {
+                    for i in 0..alternatives.len() {
+                        let alternative = ast.pattern_lists[alternatives][i];
+                        visitor.visit_pattern(ast, alternative);
+                    }
+                }
+            }
+        }
+    };
+}
+
+visitor! {
+    /// Pass over the nodes of an AST, which it walks in order of appearance by default.
+    trait Visitor(&Ast);
+    walk_ast, walk_item, walk_stmt, walk_expr, walk_type, walk_pattern;
+}
+
+visitor! {
+    /// Pass over the nodes of an AST that can change them, see [`Visitor`].
+    trait VisitorMut(&mut Ast);
+    walk_ast_mut, walk_item_mut, walk_stmt_mut, walk_expr_mut, walk_type_mut, walk_pattern_mut;
+}
+
+#[cfg(test)]
+mod tests_visit {
+    use crate::{
+        ast::{
+            walk_ast, walk_ast_mut, walk_expr, walk_expr_mut, walk_pattern, walk_type, Ast,
+            BinaryOp, Expr, ExprId, PatternId, TypeId, Visitor, VisitorMut,
+        },
+        lexer::{lex, Tokens},
+        parser::parse_file,
+        source::FileId,
+    };
+
+    /// Names of the identifiers of every kind of node.
+    struct Names<'t, 'a> {
+        tokens: &'t Tokens<'a>,
+        names: Vec<&'a str>,
+    }
+
+    impl Visitor for Names<'_, '_> {
+        fn visit_expr(&mut self, ast: &Ast, id: ExprId) {
+            if let Expr::Ident = ast.exprs[id] {
+                self.names
+                    .push(&self.tokens.code[ast.exprs.span(id).range()]);
+            }
+            walk_expr(self, ast, id);
+        }
+
+        fn visit_type(&mut self, ast: &Ast, id: TypeId) {
+            self.names
+                .push(&self.tokens.code[ast.types.span(id).range()]);
+            walk_type(self, ast, id);
+        }
+
+        fn visit_pattern(&mut self, ast: &Ast, id: PatternId) {
+            self.names
+                .push(&self.tokens.code[ast.patterns.span(id).range()]);
+            walk_pattern(self, ast, id);
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let code = concat!(
+            "E :: enum T { A : u8 = a, B }\n",
+            "f :: fn(x : [Y; n]) >- Z { defer d; r : R = match x { p if q => s }; g(h) }",
+        );
+        let tokens = lex(FileId::default(), code);
+        let (ast, _) = parse_file(&tokens);
+
+        let mut visitor = Names {
+            tokens: &tokens,
+            names: Vec::new(),
+        };
+        walk_ast(&mut visitor, &ast);
+        assert_eq!(
+            visitor.names,
+            [
+                "T", "u8", "a", "x", "[Y; n]", "Y", "n", "Z", "d", "r", "R", "x", "p", "q", "s",
+                "g", "h"
+            ]
+        );
+    }
+
+    /// Swaps the operands of additions, counting them.
+    struct Commute(usize);
+
+    impl VisitorMut for Commute {
+        fn visit_expr(&mut self, ast: &mut Ast, id: ExprId) {
+            if let Expr::Binary {
+                op: BinaryOp::Add,
+                lhs,
+                rhs,
+            } = ast.exprs[id]
+            {
+                self.0 += 1;
+                ast.exprs[id] = Expr::Binary {
+                    op: BinaryOp::Add,
+                    lhs: rhs,
+                    rhs: lhs,
+                };
+            }
+            walk_expr_mut(self, ast, id);
+        }
+    }
+
+    #[test]
+    fn visitor_mut() {
+        let code = "f :: fn() -> (a + b) * (c + (d + e));";
+        let tokens = lex(FileId::default(), code);
+        let (mut ast, _) = parse_file(&tokens);
+
+        let mut visitor = Commute(0);
+        walk_ast_mut(&mut visitor, &mut ast);
+        assert_eq!(visitor.0, 3);
+
+        let mut out = Vec::new();
+        ast.write_sexp(&tokens, &[], &mut out).unwrap();
+        let sexp = String::from_utf8(out).unwrap();
+        let names: String = (sexp.split(":name ").skip(2))
+            .map(|name| &name[..1])
+            .collect();
+        // `c + (d + e)` became `(d + e) + c`, whose new left operand was walked
+        assert_eq!(names, "baedc");
+    }
+}
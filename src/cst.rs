@@ -0,0 +1,295 @@
+//! Lossless concrete syntax tree, for tools that work on the code as it's written,
+//! like the formatter, refactorings and IDE features.
+//!
+//! The tree is built alongside the [`Ast`] from the nodes it parsed and from the tokens
+//! they come from: each node of the AST gets a node in the tree, whose children are the
+//! nodes, tokens and trivia within its span, in order. Nothing is thrown away, so writing
+//! the leaves of the tree back gives the exact code, comments and whitespace included,
+//! as long as the tokens were lexed with [`LexMode::PreserveTrivia`](crate::lexer::LexMode).
+//!
+//! Nodes refer back to the AST nodes they come from, so tools look up what the code means
+//! in the AST and change it through the spans of the tree, without parsing it again.
+
+use std::fmt;
+
+use crate::{
+    arena::{ArenaSlice, NodeId, SliceArena},
+    ast::{Ast, ExprId, ItemId, NodeTable, PatternId, StmtId, TypeId},
+    lexer::{Span, Tokens},
+};
+
+pub type CstNodeId = NodeId<CstNode>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CstKind {
+    /// Whole file, the root of the tree
+    File,
+    Item(ItemId),
+    Stmt(StmtId),
+    Expr(ExprId),
+    Type(TypeId),
+    Pattern(PatternId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CstElement {
+    Node(CstNodeId),
+    /// Token by its index in [`Tokens`]
+    Token(u32),
+    /// Trivia by its index in [`Tokens::trivia`]
+    Trivia(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CstNode {
+    pub kind: CstKind,
+    /// Children in [`Cst::elements`], in order of appearance
+    pub children: ArenaSlice<CstElement>,
+}
+
+pub struct Cst {
+    /// Nodes, with the span of the AST node they come from, which leaves out trivia
+    pub nodes: NodeTable<CstNode>,
+    pub elements: SliceArena<CstElement>,
+    /// Node of the whole file, which has the trivia at the end of the file
+    pub root: CstNodeId,
+}
+
+/// Node being filled while going through the tokens.
+struct OpenNode {
+    id: CstNodeId,
+    end: usize,
+    children: Vec<CstElement>,
+}
+
+impl Cst {
+    /// Builds the tree of the AST parsed from the tokens.
+    ///
+    /// Trivia goes to the innermost node with the token it's attached to, so the comments
+    /// right before an item are part of the item, and the trivia at the end of the file
+    /// is part of the root.
+    pub fn build(tokens: &Tokens, ast: &Ast) -> Self {
+        // nodes nest by their spans, parents coming before their children: when spans are
+        // the same, items hold statements which hold expressions, and among nodes of the
+        // same kind children are the ones allocated first
+        let mut spans = Vec::new();
+        spans.extend((ast.items.ids()).map(|id| (ast.items.span(id), 0, CstKind::Item(id))));
+        spans.extend((ast.stmts.ids()).map(|id| (ast.stmts.span(id), 1, CstKind::Stmt(id))));
+        spans.extend((ast.exprs.ids()).map(|id| (ast.exprs.span(id), 2, CstKind::Expr(id))));
+        spans.extend(
+            (ast.patterns.ids()).map(|id| (ast.patterns.span(id), 3, CstKind::Pattern(id))),
+        );
+        spans.extend((ast.types.ids()).map(|id| (ast.types.span(id), 4, CstKind::Type(id))));
+        spans.sort_by_key(|&(span, rank, kind)| {
+            let idx = match kind {
+                CstKind::File => 0,
+                CstKind::Item(id) => id.index(),
+                CstKind::Stmt(id) => id.index(),
+                CstKind::Expr(id) => id.index(),
+                CstKind::Type(id) => id.index(),
+                CstKind::Pattern(id) => id.index(),
+            };
+            (span.start, usize::MAX - span.end(), rank, usize::MAX - idx)
+        });
+
+        let reservation = tokens.options.reservation;
+        let max_nodes = spans.len() + 1;
+        let max_elements = max_nodes + tokens.types.len() + tokens.trivia.len();
+        let mut builder = Builder {
+            nodes: NodeTable::new(reservation.size_for::<CstNode>(max_nodes)),
+            elements: SliceArena::new(reservation.size_for::<CstElement>(max_elements)),
+            stack: Vec::new(),
+        };
+        let file = Span {
+            start: 0,
+            len: tokens.code.len() as u32,
+        };
+        let root = builder.open(CstKind::File, file);
+
+        let mut pending = spans.into_iter().peekable();
+        for (i, span) in tokens.spans.iter().enumerate() {
+            let start = span.start as usize;
+            while let Some((span, _, kind)) =
+                pending.next_if(|(span, ..)| span.start as usize <= start)
+            {
+                builder.close_before(start);
+                builder.open(kind, span);
+            }
+            builder.close_before(start);
+
+            let trivia = tokens.token_trivia.get(i);
+            if let Some(trivia) = trivia {
+                (trivia.leading.clone()).for_each(|t| builder.push(CstElement::Trivia(t as u32)));
+            }
+            builder.push(CstElement::Token(i as u32));
+            if let Some(trivia) = trivia {
+                (trivia.trailing.clone()).for_each(|t| builder.push(CstElement::Trivia(t as u32)));
+            }
+        }
+
+        // nodes after the last token can only be empty
+        for (span, _, kind) in pending {
+            builder.close_before(span.start as usize);
+            builder.open(kind, span);
+        }
+        builder.close_before(usize::MAX);
+        Self {
+            nodes: builder.nodes,
+            elements: builder.elements,
+            root,
+        }
+    }
+
+    #[inline]
+    pub fn kind(&self, id: CstNodeId) -> CstKind {
+        self.nodes[id].kind
+    }
+
+    #[inline]
+    pub fn children(&self, id: CstNodeId) -> &[CstElement] {
+        &self.elements[self.nodes[id].children]
+    }
+
+    /// Span of the node without the trivia around it.
+    #[inline]
+    pub fn span(&self, id: CstNodeId) -> Span {
+        self.nodes.span(id)
+    }
+
+    /// Innermost node whose span has the given position, like the node under the cursor.
+    pub fn node_at(&self, offset: usize) -> CstNodeId {
+        let mut node = self.root;
+        'descend: loop {
+            for &child in self.children(node) {
+                if let CstElement::Node(child) = child {
+                    let span = self.span(child);
+                    if (span.start as usize) <= offset && offset < span.end() {
+                        node = child;
+                        continue 'descend;
+                    }
+                }
+            }
+            return node;
+        }
+    }
+
+    /// Code of the node with all of its trivia, which is the whole code for the root.
+    pub fn text(&self, id: CstNodeId, tokens: &Tokens) -> String {
+        let mut text = String::new();
+        self.write_text(id, tokens, &mut text)
+            .expect("writing to a string can't fail");
+        text
+    }
+
+    pub fn write_text(
+        &self,
+        id: CstNodeId,
+        tokens: &Tokens,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        for &child in self.children(id) {
+            match child {
+                CstElement::Node(child) => self.write_text(child, tokens, out)?,
+                CstElement::Token(idx) => {
+                    out.write_str(&tokens.code[tokens.spans[idx as usize].range()])?
+                }
+                CstElement::Trivia(idx) => out.write_str(tokens.trivia[idx as usize].span.slice)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Builder {
+    nodes: NodeTable<CstNode>,
+    elements: SliceArena<CstElement>,
+    stack: Vec<OpenNode>,
+}
+
+impl Builder {
+    fn open(&mut self, kind: CstKind, span: Span) -> CstNodeId {
+        let node = CstNode {
+            kind,
+            children: ArenaSlice::EMPTY,
+        };
+        let id = self.nodes.alloc(node, span);
+        self.push(CstElement::Node(id));
+        self.stack.push(OpenNode {
+            id,
+            end: span.end(),
+            children: Vec::new(),
+        });
+        id
+    }
+
+    fn push(&mut self, element: CstElement) {
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children.push(element);
+        }
+    }
+
+    /// Closes the nodes that end before a token at the given position, all of them at the end.
+    fn close_before(&mut self, start: usize) {
+        while let Some(node) = self.stack.last() {
+            let is_root = self.stack.len() == 1;
+            if node.end > start || is_root && start != usize::MAX {
+                break;
+            }
+
+            let node = self.stack.pop().unwrap();
+            self.nodes[node.id].children = self.elements.alloc(&node.children);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_cst {
+    use crate::{
+        cst::{Cst, CstElement, CstKind},
+        lexer::{lex_with_mode, LexMode},
+        parser::parse_file,
+        source::FileId,
+    };
+
+    #[test]
+    fn round_trips_sample_file() {
+        const SAMPLE: &str = include_str!("../Cඞඞ.sus");
+        let tokens = lex_with_mode(FileId::default(), SAMPLE, LexMode::PreserveTrivia);
+        let (ast, _) = parse_file(&tokens);
+        let cst = Cst::build(&tokens, &ast);
+
+        assert_eq!(cst.text(cst.root, &tokens), SAMPLE);
+        // every AST node has its node in the tree
+        let ast_nodes = ast.items.len() + ast.stmts.len() + ast.exprs.len();
+        let ast_nodes = ast_nodes + ast.types.len() + ast.patterns.len();
+        assert_eq!(cst.nodes.len(), ast_nodes + 1);
+    }
+
+    #[test]
+    fn nodes() {
+        let code = "// adds\nf :: fn(a: i32) >- i32 {\n    a + 1 // more\n}\n// end\n";
+        let tokens = lex_with_mode(FileId::default(), code, LexMode::PreserveTrivia);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let cst = Cst::build(&tokens, &ast);
+
+        let children = cst.children(cst.root);
+        let CstElement::Node(item) = children[0] else {
+            panic!("expected the item first, got {children:?}");
+        };
+        assert!(matches!(cst.kind(item), CstKind::Item(_)));
+        // the comment before the item is part of it, the one at the end of the file isn't
+        assert!(cst.text(item, &tokens).starts_with("// adds\nf ::"));
+        assert!(cst.text(item, &tokens).ends_with("}"));
+        assert!(cst.text(cst.root, &tokens).ends_with("}\n// end\n"));
+
+        let one = code.find('1').unwrap();
+        let node = cst.node_at(one);
+        assert!(matches!(cst.kind(node), CstKind::Expr(_)));
+        assert_eq!(cst.text(node, &tokens), "1 // more");
+
+        let sum = cst.node_at(code.find('+').unwrap());
+        assert_eq!(&code[cst.span(sum).range()], "a + 1");
+        assert_eq!(cst.node_at(code.find("fn").unwrap() - 1), item);
+    }
+}
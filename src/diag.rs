@@ -0,0 +1,460 @@
+//! Diagnostics, the errors and warnings of every pass in a form that doesn't depend on the pass.
+//!
+//! The lexer, the parser and the passes after it have their own error types, which are
+//! converted into [`Diagnostic`]s and collected in a [`DiagnosticSink`]. The sink sorts them
+//! by position and removes duplicates, as the same mistake can be found by several passes.
+//! They are all shown at once, up to an optional limit of errors, and followed by
+//! a summary like `aborting due to 7 previous errors`, see [`Report`],
+//! or written as JSON for other tools, see [`Diagnostic::write_json`].
+
+use std::{cmp::Reverse, collections::HashSet, fmt};
+
+use crate::{
+    lexer::{LexError, LexErrorKind, Span},
+    parser::{ParseError, ParseErrorKind},
+    source::{FileId, SourceFile, SourceMap},
+};
+
+mod codes;
+mod json;
+mod render;
+
+pub use codes::{explain, ERROR_CODES, MISPLACED_OPERAND};
+pub use json::MessageFormat;
+pub use render::Renderer;
+
+/// How bad a diagnostic is, from the least to the most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Span of code related to a diagnostic, along with what it has to do with it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Replacement of a span of code that would fix a diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub message: String,
+}
+
+/// An error, warning or note about some code.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier of the kind of diagnostic, if it has one
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub file: FileId,
+    /// Span of the code that the diagnostic is about
+    pub primary_span: Span,
+    /// Other spans of code, in the same file
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, file: FileId, span: Span, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            file,
+            primary_span: span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn error(file: FileId, span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, file, span, message)
+    }
+
+    pub fn warning(file: FileId, span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, file, span, message)
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_suggestion(
+        mut self,
+        span: Span,
+        replacement: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        self.suggestions.push(Suggestion {
+            span,
+            replacement: replacement.into(),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Displays the diagnostic on a line, with the path of its file and its character-based
+    /// column, followed by a line for each of its labels, notes and suggestions.
+    pub fn display<'m>(&'m self, source_map: &'m SourceMap) -> impl fmt::Display + 'm {
+        DisplayDiagnostic {
+            source_map,
+            diagnostic: self,
+        }
+    }
+}
+
+struct DisplayDiagnostic<'m> {
+    source_map: &'m SourceMap,
+    diagnostic: &'m Diagnostic,
+}
+
+impl fmt::Display for DisplayDiagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let diagnostic = self.diagnostic;
+        let file = self.source_map.file(diagnostic.file);
+        let position = |span: Span| position(file, span.start as usize);
+
+        let (line, col) = position(diagnostic.primary_span);
+        write!(f, "{}:{line}:{col}: {}", file.path, diagnostic.severity)?;
+        if let Some(code) = diagnostic.code {
+            write!(f, "[{code}]")?;
+        }
+        write!(f, ": {}", diagnostic.message)?;
+
+        for label in &diagnostic.labels {
+            let (line, col) = position(label.span);
+            write!(f, "\n{}:{line}:{col}: {}", file.path, label.message)?;
+        }
+        for note in &diagnostic.notes {
+            write!(f, "\nnote: {note}")?;
+        }
+        for suggestion in &diagnostic.suggestions {
+            let replacement = &suggestion.replacement;
+            write!(f, "\nhelp: {}: `{replacement}`", suggestion.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Line and character-based column of a byte offset in a file.
+fn position(file: &SourceFile, offset: usize) -> (usize, usize) {
+    let (line, col) = file.line_col(offset);
+    (line, file.code[offset - col..offset].chars().count())
+}
+
+impl From<&LexError<'_>> for Diagnostic {
+    fn from(error: &LexError) -> Self {
+        let span = Span::from(&error.span);
+        let diagnostic = Diagnostic::error(error.file, span, error.kind.to_string());
+        let diagnostic = diagnostic.with_code(error.kind.code());
+        match (&error.related, error.kind) {
+            (Some(related), LexErrorKind::UnclosedDelimiter(_)) => {
+                diagnostic.with_label(Span::from(related), "Opened here")
+            }
+            (Some(related), _) => diagnostic.with_label(Span::from(related), "Related to this"),
+            (None, _) => diagnostic,
+        }
+    }
+}
+
+impl From<&ParseError<'_>> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let span = Span::from(&error.span);
+        let diagnostic = match error.kind {
+            ParseErrorKind::ChainedComparison { .. } => {
+                let (rewrite, middle) = error.unchained().unwrap();
+                Diagnostic::error(error.file, span, "Comparisons cannot be chained")
+                    .with_suggestion(span, rewrite, format!("Compare `{middle}` to both sides"))
+            }
+            ParseErrorKind::ItemInBlock { brace, .. } => {
+                Diagnostic::error(error.file, span, "Items are not allowed in blocks")
+                    .with_label(brace, "Block that is likely missing its `}`")
+            }
+            _ => Diagnostic::error(error.file, span, error.message()),
+        };
+        diagnostic.with_code(error.kind.code())
+    }
+}
+
+/// Collection of the diagnostics of a compilation, which every pass reports into.
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticSink {
+    diagnostics: Vec<Diagnostic>,
+    /// Number of errors after which the others are left out of the report
+    error_limit: Option<usize>,
+}
+
+impl DiagnosticSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sink that only reports the first errors, by position, so that a file
+    /// with a lot of them doesn't bury the first ones. Panics if the limit is 0.
+    ///
+    /// The limit only trims the report: passes still run to the end and report
+    /// all of their diagnostics, as the first errors are only known once sorted.
+    pub fn with_error_limit(error_limit: usize) -> Self {
+        assert!(error_limit > 0, "Error limit cannot be 0");
+        Self {
+            diagnostics: Vec::new(),
+            error_limit: Some(error_limit),
+        }
+    }
+
+    pub fn emit(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Number of diagnostics so far, duplicates included.
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn error_count(&self) -> usize {
+        (self.diagnostics.iter())
+            .filter(|d| d.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+    }
+
+    /// The diagnostics by file and position, without duplicates,
+    /// and without those after the last error within the limit.
+    ///
+    /// Diagnostics at the same position are sorted from the most severe,
+    /// and otherwise stay in the order they were reported in.
+    pub fn finish(mut self) -> Report {
+        let mut seen = HashSet::new();
+        self.diagnostics.retain(|d| seen.insert(d.clone()));
+        (self.diagnostics).sort_by_key(|d| (d.file, d.primary_span.start, Reverse(d.severity)));
+
+        let count = |severity| {
+            (self.diagnostics.iter())
+                .filter(|d| d.severity == severity)
+                .count()
+        };
+        let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+        if let Some(limit) = self.error_limit.filter(|&limit| limit < errors) {
+            let mut errors = (self.diagnostics.iter().enumerate())
+                .filter(|(_, d)| d.severity == Severity::Error);
+            let (last, _) = errors.nth(limit - 1).unwrap();
+            self.diagnostics.truncate(last + 1);
+        }
+
+        Report {
+            diagnostics: self.diagnostics,
+            errors,
+            warnings,
+        }
+    }
+}
+
+/// Diagnostics of a compilation once they are all reported, see [`DiagnosticSink::finish`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Diagnostics to show, sorted and within the error limit
+    pub diagnostics: Vec<Diagnostic>,
+    /// Number of errors, including those past the limit
+    pub errors: usize,
+    /// Number of warnings, including those past the limit
+    pub warnings: usize,
+}
+
+impl Report {
+    /// Line that closes the output, like `aborting due to 7 previous errors`,
+    /// along with its severity. There's none if there are no errors and no warnings.
+    pub fn summary(&self) -> Option<(Severity, String)> {
+        let plural = |n: usize, what: &str| match n {
+            1 => what.to_string(),
+            _ => format!("{what}s"),
+        };
+        let warnings = format!("{} {}", self.warnings, plural(self.warnings, "warning"));
+
+        let shown = (self.diagnostics.iter())
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        let mut summary = match self.errors {
+            0 if self.warnings == 0 => return None,
+            0 => return Some((Severity::Warning, format!("{warnings} emitted"))),
+            errors => format!(
+                "aborting due to {errors} previous {}",
+                plural(errors, "error")
+            ),
+        };
+        if shown < self.errors {
+            summary = format!("{summary} (showing the first {shown})");
+        }
+        if self.warnings > 0 {
+            summary = format!("{summary}; {warnings} emitted");
+        }
+        Some((Severity::Error, summary))
+    }
+}
+
+impl Extend<Diagnostic> for DiagnosticSink {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, diagnostics: I) {
+        self.diagnostics.extend(diagnostics);
+    }
+}
+
+#[cfg(test)]
+mod tests_diag {
+    use crate::{
+        diag::{Diagnostic, DiagnosticSink, Severity},
+        lexer::Span,
+        parser::parse_file,
+        source::SourceMap,
+    };
+
+    /// Displayed diagnostics of the code, from every pass.
+    fn diagnose(code: &str) -> Vec<String> {
+        let source_map = SourceMap::new();
+        let file = source_map.add("a.sus", code);
+        let tokens = source_map.lex(file);
+        let (ast, diagnostics) = parse_file(&tokens);
+
+        let mut sink = DiagnosticSink::new();
+        ast.report_misplaced_operands(file, &mut sink);
+        sink.extend(diagnostics.iter().map(Diagnostic::from));
+        sink.extend(tokens.errors.iter().map(Diagnostic::from));
+        (sink.finish().diagnostics.iter())
+            .map(|diagnostic| diagnostic.display(&source_map).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn passes() {
+        assert_eq!(
+            diagnose("f :: fn() {\n\tx := 'ab';\n\t5++;\n}\ng :: fn() {\n\th :: fn() {}"),
+            [
+                "a.sus:2:6: error[E0014]: Char literal must contain exactly one character",
+                "a.sus:3:1: error[E0034]: Cannot write to this expression\n\
+                 note: Only variables, their fields and elements can be assigned or incremented",
+                "a.sus:6:1: error[E0032]: Items are not allowed in blocks\n\
+                 a.sus:5:10: Block that is likely missing its `}`",
+                "a.sus:6:13: error[E0020]: Unclosed `{`\na.sus:5:10: Opened here",
+            ]
+        );
+        assert_eq!(
+            diagnose("f :: fn() -> \"ඞ\" < b < c;"),
+            ["a.sus:1:13: error[E0033]: Comparisons cannot be chained\n\
+              help: Compare `b` to both sides: `\"ඞ\" < b and b < c`"]
+        );
+    }
+
+    #[test]
+    fn sorted_without_duplicates() {
+        let source_map = SourceMap::new();
+        let (a, b) = (source_map.add("a", ""), source_map.add("b", ""));
+        let span = |start| Span { start, len: 1 };
+        let late = Diagnostic::error(a, span(5), "late");
+        let note = Diagnostic::new(Severity::Note, a, span(1), "note");
+        let error = Diagnostic::error(a, span(1), "error").with_code("E0001");
+
+        let mut sink = DiagnosticSink::new();
+        sink.emit(Diagnostic::warning(b, span(0), "other file"));
+        sink.extend([
+            late.clone(),
+            note.clone(),
+            error.clone(),
+            late.clone(),
+            note.clone(),
+        ]);
+        assert_eq!((sink.len(), sink.error_count()), (6, 3));
+        assert!(sink.has_errors());
+
+        let messages: Vec<_> = (sink.finish().diagnostics.into_iter())
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(messages, ["error", "note", "late", "other file"]);
+    }
+
+    #[test]
+    fn error_limit() {
+        let file = SourceMap::new().add("a", "");
+        let at = |start, severity| {
+            let span = Span { start, len: 1 };
+            Diagnostic::new(severity, file, span, start.to_string())
+        };
+        let diagnostics = [
+            at(4, Severity::Error),
+            at(1, Severity::Warning),
+            at(3, Severity::Warning),
+            at(2, Severity::Error),
+            at(0, Severity::Error),
+        ];
+
+        let mut sink = DiagnosticSink::with_error_limit(2);
+        sink.extend(diagnostics.clone());
+        let report = sink.finish();
+        let messages: Vec<_> = report.diagnostics.iter().map(|d| &*d.message).collect();
+        assert_eq!(messages, ["0", "1", "2"]);
+        assert_eq!((report.errors, report.warnings), (3, 2));
+        let summary = "aborting due to 3 previous errors (showing the first 2); 2 warnings emitted";
+        assert_eq!(
+            report.summary(),
+            Some((Severity::Error, summary.to_string()))
+        );
+
+        let summary = |diagnostics: &[Diagnostic]| {
+            let mut sink = DiagnosticSink::with_error_limit(5);
+            sink.extend(diagnostics.iter().cloned());
+            sink.finish().summary().map(|(_, summary)| summary)
+        };
+        assert_eq!(
+            summary(&diagnostics).as_deref(),
+            Some("aborting due to 3 previous errors; 2 warnings emitted")
+        );
+        assert_eq!(
+            summary(&diagnostics[..2]).as_deref(),
+            Some("aborting due to 1 previous error; 1 warning emitted")
+        );
+        assert_eq!(
+            summary(&diagnostics[1..3]).as_deref(),
+            Some("2 warnings emitted")
+        );
+        assert_eq!(summary(&[]), None);
+    }
+}
@@ -0,0 +1,700 @@
+//! Registry of error codes, the stable names of the kinds of diagnostics like `E0014`.
+//!
+//! Every kind of error of every pass has a code here, along with an explanation that
+//! `--explain` prints. Codes are never reused: new kinds get the next code, and the codes
+//! of kinds that are gone stay in the registry, so that old messages can still be explained.
+//!
+//! Explanations are Markdown. Code blocks marked with a code, like ```` ```sus,E0014 ````,
+//! are examples of the error, and the other ones are examples without any error,
+//! which is tested.
+
+use crate::{lexer::LexErrorKind, parser::ParseErrorKind};
+
+/// Code of the error of an operand of `++`, `--` or an assignment that is not a place.
+pub const MISPLACED_OPERAND: &str = "E0034";
+
+impl LexErrorKind {
+    pub const fn code(self) -> &'static str {
+        match self {
+            LexErrorKind::UnfinishedInterpString => "E0001",
+            LexErrorKind::UnfinishedString => "E0002",
+            LexErrorKind::UnfinishedChar => "E0003",
+            LexErrorKind::UnknownToken => "E0004",
+            LexErrorKind::InvalidEscape(_) => "E0005",
+            LexErrorKind::InvalidNumSuffix => "E0006",
+            LexErrorKind::UnexpectedEof => "E0007",
+            LexErrorKind::NestingTooDeep => "E0008",
+            LexErrorKind::TokenTooLong => "E0009",
+            LexErrorKind::InputTooLarge => "E0010",
+            LexErrorKind::MalformedNum => "E0011",
+            LexErrorKind::NumOverflow => "E0012",
+            LexErrorKind::EmptyChar => "E0013",
+            LexErrorKind::MultipleCharsInChar => "E0014",
+            LexErrorKind::NonAsciiByteChar => "E0015",
+            LexErrorKind::NulInCString => "E0016",
+            LexErrorKind::UnclosedAttribute => "E0017",
+            LexErrorKind::EmptyAttribute => "E0018",
+            LexErrorKind::MissingDigits => "E0019",
+            LexErrorKind::UnclosedDelimiter(_) => "E0020",
+            LexErrorKind::UnexpectedDelimiter(_) => "E0021",
+        }
+    }
+}
+
+impl ParseErrorKind {
+    pub const fn code(self) -> &'static str {
+        match self {
+            ParseErrorKind::ExpectedToken(_) => "E0022",
+            ParseErrorKind::ExpectedItem => "E0023",
+            ParseErrorKind::ExpectedExpr => "E0024",
+            ParseErrorKind::ExpectedType => "E0025",
+            ParseErrorKind::ExpectedPattern => "E0026",
+            ParseErrorKind::ExpectedHoleEnd => "E0027",
+            ParseErrorKind::ExpectedLoop => "E0028",
+            ParseErrorKind::ExpectedTupleIndex => "E0029",
+            ParseErrorKind::UnknownAttr => "E0030",
+            ParseErrorKind::NestingTooDeep => "E0031",
+            ParseErrorKind::ItemInBlock { .. } => "E0032",
+            ParseErrorKind::ChainedComparison { .. } => "E0033",
+        }
+    }
+}
+
+/// Explanation of the error with the given code, if it is one.
+pub fn explain(code: &str) -> Option<&'static str> {
+    let idx = ERROR_CODES.binary_search_by_key(&code, |&(code, _)| code);
+    idx.ok().map(|idx| ERROR_CODES[idx].1)
+}
+
+/// Every error code along with its explanation, sorted by code.
+pub const ERROR_CODES: &[(&str, &str)] = &[
+    (
+        "E0001",
+        r#"An interpolated string is not closed before the end of the file.
+
+Erroneous code example:
+
+```sus,E0001
+f :: fn() { s := $"a{b; }
+```
+
+Holes of interpolated strings contain an expression, and are closed with a `}`
+before the string goes on:
+
+```sus
+f :: fn() { s := $"a{b}"; }
+```
+"#,
+    ),
+    (
+        "E0002",
+        r#"A string is not closed before the end of its line or of the file.
+
+Erroneous code example:
+
+```sus,E0002
+f :: fn() { s := "abc; }
+```
+
+Strings end with a `"`, which is escaped as `\"` inside of them:
+
+```sus
+f :: fn() { s := "a\"bc"; }
+```
+"#,
+    ),
+    (
+        "E0003",
+        r#"A char literal is not closed.
+
+Erroneous code example:
+
+```sus,E0003
+f :: fn() { c := 'a; }
+```
+
+Char literals end with a `'`:
+
+```sus
+f :: fn() { c := 'a'; }
+```
+"#,
+    ),
+    (
+        "E0004",
+        r#"Some code is not a token of the language.
+
+Erroneous code example:
+
+```sus,E0004
+f :: fn() { c := § }
+```
+
+Characters that are not part of the syntax can only be in strings, chars and comments:
+
+```sus
+f :: fn() { c := '§'; }
+```
+"#,
+    ),
+    (
+        "E0005",
+        r#"An escape sequence in a string or char literal is invalid.
+
+Erroneous code example:
+
+```sus,E0005
+f :: fn() { s := "\q"; }
+```
+
+The escapes are `\n`, `\r`, `\t`, `\\`, `\0`, `\'` and `\"`, `\x` followed by two
+hex digits, and `\u{...}` with the 1 to 6 hex digits of a unicode scalar value.
+`\u` escapes are not allowed in byte literals, and `\x` escapes above `\x7F`
+are only allowed in them:
+
+```sus
+f :: fn() { s := "\t\x41\u{1F4A9}"; b := b"\xff"; }
+```
+"#,
+    ),
+    (
+        "E0006",
+        r#"A number literal has a suffix that is not a type of number.
+
+Erroneous code example:
+
+```sus,E0006
+f :: fn() { n := 1xyz; }
+```
+
+Suffixes are the integer types `u8` to `u128`, `i8` to `i128`, `usize` and `isize`,
+and the float types `f16`, `f32` and `f64`:
+
+```sus
+f :: fn() { n := 1u16; }
+```
+"#,
+    ),
+    (
+        "E0007",
+        r#"The file ends in the middle of a token.
+
+Erroneous code example:
+
+```sus,E0007
+f :: fn() -> 0x
+```
+
+The last token of the file must be complete:
+
+```sus
+f :: fn() -> 0x1;
+```
+"#,
+    ),
+    (
+        "E0008",
+        r#"Interpolated strings or attributes are nested in each other too deeply.
+
+At most 64 of them can be nested in each other, as in `$"{$"{$"{a}"}"}"`,
+so that lexing doesn't take unbounded memory. Deeply nested strings can be split
+into variables.
+"#,
+    ),
+    (
+        "E0009",
+        r#"A token is longer than the maximum length given to the lexer.
+
+There is no maximum by default. Tools that set one do so to bound the memory taken
+by the tokens of untrusted code.
+"#,
+    ),
+    (
+        "E0010",
+        r#"The file is larger than the lexer can handle.
+
+Spans are 32-bit offsets, so files can't be larger than 4 GiB. Tools can also set
+a lower maximum, to bound the memory taken by the tokens of untrusted code.
+"#,
+    ),
+    (
+        "E0011",
+        r#"A number literal is malformed, like a float with an integer suffix.
+
+Erroneous code example:
+
+```sus,E0011
+f :: fn() { n := 1.5u8; }
+```
+
+Floats can only have the suffixes `f16`, `f32` and `f64`:
+
+```sus
+f :: fn() { n := 1.5f32; }
+```
+"#,
+    ),
+    (
+        "E0012",
+        r#"A number literal is too large for its type.
+
+Erroneous code example:
+
+```sus,E0012
+f :: fn() { n := 300u8; }
+```
+
+The value must fit in the type of the suffix, or in 128 bits without one:
+
+```sus
+f :: fn() { n := 300u16; }
+```
+"#,
+    ),
+    (
+        "E0013",
+        r#"A char literal is empty.
+
+Erroneous code example:
+
+```sus,E0013
+f :: fn() { c := ''; }
+```
+
+Char literals contain exactly one character, use a string for no characters:
+
+```sus
+f :: fn() { s := ""; }
+```
+"#,
+    ),
+    (
+        "E0014",
+        r#"A char literal contains more than one character.
+
+Erroneous code example:
+
+```sus,E0014
+f :: fn() { c := 'ab'; }
+```
+
+Char literals contain exactly one character, use a string for more:
+
+```sus
+f :: fn() { s := "ab"; }
+```
+"#,
+    ),
+    (
+        "E0015",
+        r#"A byte char literal contains a character that is not ASCII.
+
+Erroneous code example:
+
+```sus,E0015
+f :: fn() { c := b'é'; }
+```
+
+Bytes are ASCII characters, other characters take several bytes in UTF-8.
+Bytes above `0x7F` are written with `\x` escapes:
+
+```sus
+f :: fn() { c := b'\xe9'; }
+```
+"#,
+    ),
+    (
+        "E0016",
+        r#"A C string literal contains a NUL byte.
+
+Erroneous code example:
+
+```sus,E0016
+f :: fn() { s := c"a\0b"; }
+```
+
+C strings end at their first NUL byte, which is added after their content,
+so they can't contain one:
+
+```sus
+f :: fn() { s := c"ab"; }
+```
+"#,
+    ),
+    (
+        "E0017",
+        r##"An attribute is not closed.
+
+Erroneous code example:
+
+```sus,E0017
+#[inline
+f :: fn() {}
+```
+
+Attributes end with a `]`:
+
+```sus
+#[inline]
+f :: fn() {}
+```
+"##,
+    ),
+    (
+        "E0018",
+        r##"An attribute is empty.
+
+Erroneous code example:
+
+```sus,E0018
+#[]
+f :: fn() {}
+```
+
+Attributes contain at least a name:
+
+```sus
+#[cold]
+f :: fn() {}
+```
+"##,
+    ),
+    (
+        "E0019",
+        r#"A number literal has a prefix or an exponent but no digits after it.
+
+Erroneous code example:
+
+```sus,E0019
+f :: fn() { n := 0x; m := 1e; }
+```
+
+Prefixes and exponents are followed by at least a digit:
+
+```sus
+f :: fn() { n := 0x0; m := 1e3; }
+```
+"#,
+    ),
+    (
+        "E0020",
+        r#"A delimiter is not closed.
+
+Erroneous code example:
+
+```sus,E0020
+f :: fn() { a := (1; }
+```
+
+Every `(`, `[` and `{` is closed by the matching `)`, `]` or `}`:
+
+```sus
+f :: fn() { a := (1); }
+```
+"#,
+    ),
+    (
+        "E0021",
+        r#"A closing delimiter doesn't close anything.
+
+Erroneous code example:
+
+```sus,E0021
+f :: fn() { a := 1); }
+```
+
+Every `)`, `]` and `}` closes the matching `(`, `[` or `{`:
+
+```sus
+f :: fn() { a := (1); }
+```
+"#,
+    ),
+    (
+        "E0022",
+        r#"A token is missing, like the `;` at the end of a statement.
+
+Erroneous code example:
+
+```sus,E0022
+f :: fn() { a := 1 }
+```
+
+The message tells which token was expected:
+
+```sus
+f :: fn() { a := 1; }
+```
+"#,
+    ),
+    (
+        "E0023",
+        r#"An item doesn't start with a keyword of item.
+
+Erroneous code example:
+
+```sus,E0023
+f :: 1
+```
+
+Items are functions, structs, enums and unions named with `::`. Constants are
+declared with `const`:
+
+```sus
+const f: i32 = 1;
+```
+"#,
+    ),
+    (
+        "E0024",
+        r#"An expression is missing.
+
+Erroneous code example:
+
+```sus,E0024
+f :: fn() { a := ; }
+```
+
+Variables are initialized with an expression:
+
+```sus
+f :: fn() { a := 1; }
+```
+"#,
+    ),
+    (
+        "E0025",
+        r#"A type is missing.
+
+Erroneous code example:
+
+```sus,E0025
+f :: fn(a: 1) {}
+```
+
+Parameters and struct fields are given a type, not a value:
+
+```sus
+f :: fn(a: i32) {}
+```
+"#,
+    ),
+    (
+        "E0026",
+        r#"A pattern is missing.
+
+Erroneous code example:
+
+```sus,E0026
+f :: fn() { x := match a { 1 => 2, + => 3 }; }
+```
+
+Arms of `match` start with a pattern, which is `_` to match anything:
+
+```sus
+f :: fn() { x := match a { 1 => 2, _ => 3 }; }
+```
+"#,
+    ),
+    (
+        "E0027",
+        r#"A hole of an interpolated string has more than an expression.
+
+Erroneous code example:
+
+```sus,E0027
+f :: fn() { a := $"{b c}"; }
+```
+
+Each hole contains one expression, optionally followed by a format spec:
+
+```sus
+f :: fn() { a := $"{b}{c}"; }
+```
+"#,
+    ),
+    (
+        "E0028",
+        r#"A label is not followed by a loop.
+
+Erroneous code example:
+
+```sus,E0028
+f :: fn() { @l a; }
+```
+
+Labels name loops, so that `break` and `continue` can refer to outer ones:
+
+```sus
+f :: fn() { @l loop { break @l; } }
+```
+"#,
+    ),
+    (
+        "E0029",
+        r#"A tuple field is not a decimal index.
+
+Erroneous code example:
+
+```sus,E0029
+f :: fn() { a := t.0x1; }
+```
+
+Fields of tuples are their decimal indices:
+
+```sus
+f :: fn() { a := t.1; }
+```
+"#,
+    ),
+    (
+        "E0030",
+        r##"An attribute is not one of the known attributes.
+
+Erroneous code example:
+
+```sus,E0030
+#[frobnicate]
+f :: fn() {}
+```
+
+The attributes are `inline`, `cold`, `repr`, `test`, `deprecated` and `doc`:
+
+```sus
+#[inline]
+f :: fn() {}
+```
+"##,
+    ),
+    (
+        "E0031",
+        r#"Expressions, types or patterns are nested in each other too deeply.
+
+At most 64 of them can be nested in each other, so that parsing doesn't overflow
+the stack. The same goes for calls, fields, indices and operators that follow each other,
+like in `a.b().c[0] + d`, as each of them wraps the expression before it:
+at most 128 of them can be chained, counting those of the expressions around them.
+Deeply nested expressions and long chains can be split into variables.
+"#,
+    ),
+    (
+        "E0032",
+        r#"An item starts in a block, which is likely missing its `}`.
+
+Erroneous code example:
+
+```sus,E0032
+f :: fn() {
+    if a then {
+        b();
+g :: fn() {}
+```
+
+Items can't be in blocks, so the block before the item likely didn't mean to go on.
+The `{` of that block is pointed at:
+
+```sus
+f :: fn() {
+    if a then {
+        b();
+    }
+}
+g :: fn() {}
+```
+"#,
+    ),
+    (
+        "E0033",
+        r#"Comparisons are chained.
+
+Erroneous code example:
+
+```sus,E0033
+f :: fn() { x := a < b < c; }
+```
+
+`a < b < c` would compare the boolean `a < b` to `c`. To compare `b` to both sides,
+compare it twice:
+
+```sus
+f :: fn() { x := a < b and b < c; }
+```
+"#,
+    ),
+    (
+        "E0034",
+        r#"An expression that is written to is not a place.
+
+Erroneous code example:
+
+```sus,E0034
+f :: fn() { 5++; g() = 1; }
+```
+
+`++`, `--` and assignments write to their operand, which must be a variable,
+a field of a place or an element:
+
+```sus
+f :: fn() { a := 5; a++; t.x = 1; v[0] = 2; }
+```
+"#,
+    ),
+];
+
+#[cfg(test)]
+mod tests_codes {
+    use crate::{
+        diag::{explain, Diagnostic, DiagnosticSink, ERROR_CODES, MISPLACED_OPERAND},
+        lexer::lex,
+        parser::parse_file,
+        source::FileId,
+    };
+
+    /// Codes of the diagnostics of every pass over the code.
+    fn codes(code: &str) -> Vec<&'static str> {
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+
+        let mut sink = DiagnosticSink::new();
+        sink.extend(tokens.errors.iter().map(Diagnostic::from));
+        sink.extend(tokens.num_values().errors.iter().map(Diagnostic::from));
+        sink.extend(diagnostics.iter().map(Diagnostic::from));
+        ast.report_misplaced_operands(tokens.file, &mut sink);
+        let diagnostics = sink.finish().diagnostics.into_iter();
+        diagnostics
+            .map(|diagnostic| diagnostic.code.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn registry() {
+        // codes are numbered in order, without gaps
+        for (i, &(code, explanation)) in ERROR_CODES.iter().enumerate() {
+            assert_eq!(code, format!("E{:04}", i + 1));
+            assert_eq!(explain(code), Some(explanation));
+        }
+        assert!(explain(MISPLACED_OPERAND).is_some());
+        assert_eq!(explain("E9999"), None);
+    }
+
+    #[test]
+    fn examples() {
+        for &(code, explanation) in ERROR_CODES {
+            for block in explanation.split("```").skip(1).step_by(2) {
+                let (info, example) = block.split_once('\n').unwrap();
+                // the file ends with the last line of the example, not after it
+                let example = example.strip_suffix('\n').unwrap();
+                match info.strip_prefix("sus,") {
+                    Some(expected) => {
+                        assert_eq!(expected, code);
+                        assert!(codes(example).contains(&code), "{code}: {example}");
+                    }
+                    None => {
+                        assert_eq!(info, "sus");
+                        assert!(codes(example).is_empty(), "{code}: {example}");
+                    }
+                }
+            }
+        }
+    }
+}
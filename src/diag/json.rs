@@ -0,0 +1,163 @@
+//! Diagnostics as JSON, for editors and CI tools, chosen with `--message-format=json`.
+
+use std::io::{self, Write};
+
+use super::{position, Diagnostic};
+use crate::{
+    lexer::{write_json_str, Span},
+    source::{SourceFile, SourceMap},
+};
+
+/// Format in which diagnostics are written, as chosen with `--message-format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MessageFormat {
+    /// Rendered for humans, see [`Renderer`](super::Renderer)
+    #[default]
+    Human,
+    /// A JSON object per line, written as soon as the diagnostic is shown,
+    /// see [`Diagnostic::write_json`]
+    Json,
+}
+
+impl MessageFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(MessageFormat::Human),
+            "json" => Some(MessageFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Writes the diagnostic as a JSON object on a line:
+    ///
+    /// ```json
+    /// {"severity":"error","code":"E0033","message":"Comparisons cannot be chained","file":"a.sus",
+    ///  "spans":[{"start":13,"end":22,"line":1,"col":13,"end_line":1,"end_col":22,"primary":true,"label":null}],
+    ///  "notes":[],
+    ///  "suggestions":[{"message":"Compare `b` to both sides","replacement":"a < b and b < c","span":{...}}]}
+    /// ```
+    ///
+    /// - `severity` is `"error"`, `"warning"` or `"note"`.
+    /// - `code` is the [error code](super::explain) of the diagnostic, or `null` if it has none.
+    /// - `spans` starts with the primary span, followed by the spans of the labels,
+    ///   whose `label` is their message.
+    /// - `start` and `end` are byte offsets in the file, `end` being exclusive.
+    ///   `line` and `end_line` are 1-based, while `col` and `end_col` are 0-based
+    ///   and count characters, like in [`display`](Self::display).
+    /// - The `span` of a suggestion has the same position fields as the other spans,
+    ///   and is the code that `replacement` replaces.
+    ///
+    /// New fields may be added to the objects, but the existing ones keep their meaning.
+    pub fn write_json(&self, source_map: &SourceMap, out: &mut impl Write) -> io::Result<()> {
+        let file = source_map.file(self.file);
+
+        write!(out, "{{\"severity\":\"{}\",\"code\":", self.severity)?;
+        match self.code {
+            Some(code) => write!(out, "\"{code}\"")?,
+            None => out.write_all(b"null")?,
+        }
+        out.write_all(b",\"message\":")?;
+        write_json_str(out, &self.message)?;
+        out.write_all(b",\"file\":")?;
+        write_json_str(out, &file.path)?;
+
+        out.write_all(b",\"spans\":[{")?;
+        write_json_position(out, file, self.primary_span)?;
+        out.write_all(b",\"primary\":true,\"label\":null}")?;
+        for label in &self.labels {
+            out.write_all(b",{")?;
+            write_json_position(out, file, label.span)?;
+            out.write_all(b",\"primary\":false,\"label\":")?;
+            write_json_str(out, &label.message)?;
+            out.write_all(b"}")?;
+        }
+
+        out.write_all(b"],\"notes\":[")?;
+        for (i, note) in self.notes.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write_json_str(out, note)?;
+        }
+
+        out.write_all(b"],\"suggestions\":[")?;
+        for (i, suggestion) in self.suggestions.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            out.write_all(b"{\"message\":")?;
+            write_json_str(out, &suggestion.message)?;
+            out.write_all(b",\"replacement\":")?;
+            write_json_str(out, &suggestion.replacement)?;
+            out.write_all(b",\"span\":{")?;
+            write_json_position(out, file, suggestion.span)?;
+            out.write_all(b"}}")?;
+        }
+        out.write_all(b"]}\n")
+    }
+}
+
+fn write_json_position(out: &mut impl Write, file: &SourceFile, span: Span) -> io::Result<()> {
+    let (line, col) = position(file, span.start as usize);
+    let (end_line, end_col) = position(file, span.end());
+    write!(
+        out,
+        "\"start\":{},\"end\":{},\"line\":{line},\"col\":{col},\"end_line\":{end_line},\"end_col\":{end_col}",
+        span.start,
+        span.end()
+    )
+}
+
+#[cfg(test)]
+mod tests_json {
+    use crate::{
+        diag::{Diagnostic, MessageFormat},
+        lexer::Span,
+        parser::parse_file,
+        source::SourceMap,
+    };
+
+    fn json(diagnostic: &Diagnostic, source_map: &SourceMap) -> String {
+        let mut out = Vec::new();
+        diagnostic.write_json(source_map, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn schema() {
+        let source_map = SourceMap::new();
+        let file = source_map.add("a.sus", "f :: fn() -> \"ඞ\" < b\n\t< c;");
+        let tokens = source_map.lex(file);
+        let (_, diagnostics) = parse_file(&tokens);
+        assert_eq!(
+            json(&Diagnostic::from(&diagnostics[0]), &source_map),
+            concat!(
+                r#"{"severity":"error","code":"E0033","message":"Comparisons cannot be chained","file":"a.sus","#,
+                r#""spans":[{"start":13,"end":27,"line":1,"col":13,"end_line":2,"end_col":4,"primary":true,"label":null}],"#,
+                r#""notes":[],"suggestions":[{"message":"Compare `b` to both sides","#,
+                r#""replacement":"\"ඞ\" < b and b\n\t< c","span":{"start":13,"end":27,"line":1,"col":13,"end_line":2,"end_col":4}}]}"#,
+                "\n"
+            )
+        );
+
+        let span = |start| Span { start, len: 1 };
+        let diagnostic = Diagnostic::warning(file, span(0), "\"quoted\"")
+            .with_label(span(23), "here")
+            .with_note("a\nb");
+        assert_eq!(
+            json(&diagnostic, &source_map),
+            concat!(
+                r#"{"severity":"warning","code":null,"message":"\"quoted\"","file":"a.sus","#,
+                r#""spans":[{"start":0,"end":1,"line":1,"col":0,"end_line":1,"end_col":1,"primary":true,"label":null},"#,
+                r#"{"start":23,"end":24,"line":2,"col":0,"end_line":2,"end_col":1,"primary":false,"label":"here"}],"#,
+                r#""notes":["a\nb"],"suggestions":[]}"#,
+                "\n"
+            )
+        );
+
+        assert_eq!(MessageFormat::from_name("json"), Some(MessageFormat::Json));
+        assert_eq!(MessageFormat::from_name("short"), None);
+    }
+}
@@ -0,0 +1,507 @@
+//! Rendering of diagnostics for terminals, quoting the code they are about.
+//!
+//! ```text
+//! error: Items are not allowed in blocks
+//!  --> a.sus:6:1
+//!   |
+//! 5 | g :: fn() {
+//!   |           - Block that is likely missing its `}`
+//! 6 |     h :: fn() {}
+//!   |     ^
+//! ```
+//!
+//! Lines are found with the line breaks of the [`SourceFile`]. Tabs are expanded and
+//! wide characters take two columns, so that the markers are under the code they mark.
+//! With colors, the quoted code is highlighted like [`Tokens::write_ansi`] does.
+//!
+//! [`Tokens::write_ansi`]: crate::lexer::Tokens::write_ansi
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, Write},
+};
+
+use super::{position, Diagnostic, Report};
+use crate::{
+    lexer::{lex, Highlight, Span},
+    source::{SourceFile, SourceMap},
+    style::{Painter, Style},
+};
+
+/// Renders diagnostics with the lines of code of their spans, which are underlined
+/// with `^` for the primary span and `-` for labels, the messages of labels next to them.
+pub struct Renderer<'m> {
+    source_map: &'m SourceMap,
+    /// Tabs go to the next multiple of this many columns
+    pub tab_width: usize,
+    /// Colors of the output, which are disabled by default
+    pub painter: Painter,
+}
+
+impl<'m> Renderer<'m> {
+    pub fn new(source_map: &'m SourceMap) -> Self {
+        Self {
+            source_map,
+            tab_width: 4,
+            painter: Painter::PLAIN,
+        }
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "{}", self.display(diagnostic))
+    }
+
+    /// Displays the rendered diagnostic, which ends with a line break.
+    pub fn display<'r>(&'r self, diagnostic: &'r Diagnostic) -> impl fmt::Display + 'r {
+        DisplayRendered {
+            renderer: self,
+            diagnostic,
+        }
+    }
+
+    /// Displays the summary of the report on a line, if it has one.
+    pub fn display_summary(&self, report: &Report) -> Option<String> {
+        let (severity, summary) = report.summary()?;
+        let painter = self.painter;
+        let severity_name = painter.paint(Style::Severity(severity), severity);
+        let summary = painter.paint(Style::Emphasis, format!(": {summary}"));
+        Some(format!("{severity_name}{summary}"))
+    }
+}
+
+struct DisplayRendered<'r, 'm> {
+    renderer: &'r Renderer<'m>,
+    diagnostic: &'r Diagnostic,
+}
+
+/// Span to underline on the quoted lines.
+struct Annotation<'d> {
+    span: Span,
+    /// Empty for the primary span, unless it is also labelled
+    message: &'d str,
+    is_primary: bool,
+}
+
+impl Annotation<'_> {
+    /// First and last lines of the span, a span that is empty or ends with a line break
+    /// being on the line it starts on.
+    fn lines(&self, file: &SourceFile) -> (usize, usize) {
+        let (start, end) = (self.span.start as usize, self.span.end());
+        let last = end.saturating_sub(1).max(start);
+        (file.line_col(start).0, file.line_col(last).0)
+    }
+}
+
+/// Underline of an annotation on a quoted line, in display columns.
+struct Marker<'d> {
+    start: usize,
+    end: usize,
+    /// Message if the span ends on the line
+    message: &'d str,
+    is_primary: bool,
+}
+
+impl fmt::Display for DisplayRendered<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let diagnostic = self.diagnostic;
+        let file = self.renderer.source_map.file(diagnostic.file);
+        let painter = self.renderer.painter;
+
+        let mut title = diagnostic.severity.to_string();
+        if let Some(code) = diagnostic.code {
+            title = format!("{title}[{code}]");
+        }
+        let title = painter.paint(Style::Severity(diagnostic.severity), title);
+        let message = painter.paint(Style::Emphasis, format!(": {}", diagnostic.message));
+        writeln!(f, "{title}{message}")?;
+
+        let primary = Annotation {
+            span: diagnostic.primary_span,
+            message: "",
+            is_primary: true,
+        };
+        let labels = diagnostic.labels.iter().map(|label| Annotation {
+            span: label.span,
+            message: &label.message,
+            is_primary: false,
+        });
+        let annotations: Vec<_> = [primary].into_iter().chain(labels).collect();
+
+        // long spans are quoted from their first and last lines
+        let mut lines = BTreeSet::new();
+        for annotation in &annotations {
+            let (first, last) = annotation.lines(file);
+            match last - first {
+                0..=2 => lines.extend(first..=last),
+                _ => lines.extend([first, last]),
+            }
+        }
+        let gutter = lines.last().map_or(1, |line| line.to_string().len());
+        let margin = painter.paint(Style::Gutter, format!("{:gutter$} |", ""));
+
+        let (line, col) = position(file, diagnostic.primary_span.start as usize);
+        let arrow = painter.paint(Style::Gutter, format!("{:gutter$}-->", ""));
+        writeln!(f, "{arrow} {}:{line}:{col}", file.path)?;
+        writeln!(f, "{margin}")?;
+        let mut previous = None;
+        for line in lines {
+            if previous.is_some_and(|previous| line > previous + 1) {
+                writeln!(f, "{}", painter.paint(Style::Gutter, "..."))?;
+            }
+            self.quote(f, file, line, &annotations, gutter)?;
+            previous = Some(line);
+        }
+
+        if !diagnostic.notes.is_empty() || !diagnostic.suggestions.is_empty() {
+            writeln!(f, "{margin}")?;
+        }
+        let equals = painter.paint(Style::Gutter, format!("{:gutter$} =", ""));
+        for note in &diagnostic.notes {
+            let name = painter.paint(Style::Emphasis, "note");
+            writeln!(f, "{equals} {name}: {note}")?;
+        }
+        for suggestion in &diagnostic.suggestions {
+            let name = painter.paint(Style::Help, "help");
+            let replacement = &suggestion.replacement;
+            writeln!(
+                f,
+                "{equals} {name}: {}: `{replacement}`",
+                suggestion.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl DisplayRendered<'_, '_> {
+    /// Writes a line of code and the markers of the annotations on it.
+    fn quote(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        file: &SourceFile,
+        line: usize,
+        annotations: &[Annotation],
+        gutter: usize,
+    ) -> fmt::Result {
+        let painter = self.renderer.painter;
+        let severity = Style::Severity(self.diagnostic.severity);
+        let code = file.line(line);
+        let line_start = match line {
+            1 => 0,
+            _ => file.line_breaks[line - 2] + 1,
+        };
+
+        // the line is lexed on its own to be highlighted, which is wrong for the lines
+        // in multi-line strings, but cheap
+        let mut highlights = vec![None; code.len()];
+        if painter.enabled {
+            let tokens = lex(file.id, code);
+            let mut pos = 0;
+            for (&ty, span) in tokens.types.iter().zip(tokens.spans.iter()) {
+                // the text between tokens is whitespace, then a comment to the end of the line
+                let gap = &code[pos..span.start as usize];
+                if let Some(comment) = gap.find(|c: char| !c.is_whitespace()) {
+                    highlights[pos + comment..span.start as usize].fill(Some(Highlight::Comment));
+                }
+                highlights[span.range()].fill(ty.highlight());
+                pos = span.end();
+            }
+        }
+
+        // display column of every byte offset of the line, and one past its end,
+        // with the expanded code in pieces of the same highlight
+        let tab_width = self.renderer.tab_width.max(1);
+        let mut pieces: Vec<(Option<Highlight>, String)> = Vec::new();
+        let mut cols = vec![0; code.len() + 1];
+        let mut col = 0;
+        let shown = code.trim_end().len();
+        for (i, ch) in code.char_indices() {
+            cols[i..i + ch.len_utf8()].fill(col);
+            let next = match ch {
+                '\t' => (col / tab_width + 1) * tab_width,
+                _ => col + char_width(ch),
+            };
+            if i < shown {
+                let highlight = highlights[i];
+                if pieces.last().is_none_or(|(last, _)| *last != highlight) {
+                    pieces.push((highlight, String::new()));
+                }
+                let piece = &mut pieces.last_mut().unwrap().1;
+                match ch {
+                    '\t' => piece.extend((col..next).map(|_| ' ')),
+                    _ => piece.push(ch),
+                }
+            }
+            col = next;
+        }
+        cols[code.len()] = col;
+
+        let number = painter.paint(Style::Gutter, format!("{line:>gutter$} |"));
+        write!(f, "{number}")?;
+        if !pieces.is_empty() {
+            f.write_str(" ")?;
+        }
+        for (highlight, text) in &pieces {
+            match highlight {
+                Some(highlight) => write!(f, "{}", painter.paint(Style::Code(*highlight), text))?,
+                None => f.write_str(text)?,
+            }
+        }
+        writeln!(f)?;
+
+        let mut markers = Vec::new();
+        for annotation in annotations {
+            let (first, last) = annotation.lines(file);
+            if !(first..=last).contains(&line) {
+                continue;
+            }
+            // lines after the first one are marked from their indentation
+            let indent = code.len() - code.trim_start().len();
+            let start = match line == first {
+                true => (annotation.span.start as usize - line_start).min(code.len()),
+                false => indent,
+            };
+            let end = match line == last {
+                true => (annotation.span.end() - line_start).min(code.len()),
+                false => code.len(),
+            };
+            let (start, end) = (cols[start], cols[end.max(start)]);
+            markers.push(Marker {
+                start,
+                end: end.max(start + 1),
+                message: if line == last { annotation.message } else { "" },
+                is_primary: annotation.is_primary,
+            });
+        }
+
+        // the primary span is drawn last, over the labels
+        let mut underline = Vec::new();
+        for marker in markers.iter().rev() {
+            if underline.len() < marker.end {
+                underline.resize(marker.end, ' ');
+            }
+            let ch = if marker.is_primary { '^' } else { '-' };
+            underline[marker.start..marker.end].fill(ch);
+        }
+
+        let margin = painter.paint(Style::Gutter, format!("{:gutter$} |", ""));
+        let style = |marker: &Marker| match marker.is_primary {
+            true => severity,
+            false => Style::Secondary,
+        };
+        write!(f, "{margin} ")?;
+        for piece in underline.chunk_by(|a, b| a == b) {
+            let text: String = piece.iter().collect();
+            match piece[0] {
+                '^' => write!(f, "{}", painter.paint(severity, text))?,
+                '-' => write!(f, "{}", painter.paint(Style::Secondary, text))?,
+                _ => f.write_str(&text)?,
+            }
+        }
+
+        // the rightmost message goes after the underline, the others below their start
+        let mut messages: Vec<_> = markers.iter().filter(|m| !m.message.is_empty()).collect();
+        messages.sort_by_key(|marker| marker.start);
+        if let Some(last) = messages.pop() {
+            write!(f, " {}", painter.paint(style(last), last.message))?;
+        }
+        writeln!(f)?;
+
+        if !messages.is_empty() {
+            let connectors = painter.paint(Style::Secondary, connectors(&messages));
+            writeln!(f, "{margin} {connectors}")?;
+        }
+        while let Some(marker) = messages.pop() {
+            let connectors = connectors(&messages);
+            let padding = marker.start - connectors.chars().count().min(marker.start);
+            let message = painter.paint(style(marker), marker.message);
+            let connectors = painter.paint(Style::Secondary, connectors);
+            writeln!(f, "{margin} {connectors}{:padding$}{message}", "")?;
+        }
+        Ok(())
+    }
+}
+
+/// A `|` at the start of each marker, which are sorted by their start.
+fn connectors(markers: &[&Marker]) -> String {
+    let mut line = String::new();
+    for marker in markers {
+        let padding = marker.start - line.chars().count().min(marker.start);
+        line.extend((0..padding).map(|_| ' '));
+        line.push('|');
+    }
+    line
+}
+
+/// Number of columns that a character takes in a terminal, without going through
+/// the whole Unicode tables: wide characters are the East Asian ones and emojis,
+/// and zero-width ones are the common combining marks and invisible characters.
+fn char_width(ch: char) -> usize {
+    match ch {
+        '\u{0}'..='\u{1f}' | '\u{7f}' => 0,
+        '\u{300}'..='\u{36f}'
+        | '\u{200b}'..='\u{200f}'
+        | '\u{20d0}'..='\u{20ff}'
+        | '\u{fe00}'..='\u{fe0f}'
+        | '\u{fe20}'..='\u{fe2f}' => 0,
+        '\u{1100}'..='\u{115f}'
+        | '\u{2e80}'..='\u{303e}'
+        | '\u{3041}'..='\u{33ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{a000}'..='\u{a4cf}'
+        | '\u{ac00}'..='\u{d7a3}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{fe30}'..='\u{fe4f}'
+        | '\u{ff00}'..='\u{ff60}'
+        | '\u{ffe0}'..='\u{ffe6}'
+        | '\u{1f300}'..='\u{1f64f}'
+        | '\u{1f900}'..='\u{1f9ff}'
+        | '\u{20000}'..='\u{2fffd}'
+        | '\u{30000}'..='\u{3fffd}' => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests_render {
+    use crate::{
+        diag::{Diagnostic, DiagnosticSink, Renderer},
+        lexer::Span,
+        parser::parse_file,
+        source::SourceMap,
+        style::Painter,
+    };
+
+    fn span(code: &str, part: &str) -> Span {
+        Span {
+            start: code.find(part).unwrap() as u32,
+            len: part.len() as u32,
+        }
+    }
+
+    #[test]
+    fn alignment() {
+        let code = "f :: fn() {\n\t\"日本\"\tඞ := a + ＢＢ;\n}";
+        let source_map = SourceMap::new();
+        let file = source_map.add("a.sus", code);
+        let diagnostic = Diagnostic::error(file, span(code, "ＢＢ"), "Wide")
+            .with_code("E0001")
+            .with_label(span(code, "a"), "Narrow");
+        assert_eq!(
+            Renderer::new(&source_map).display(&diagnostic).to_string(),
+            "error[E0001]: Wide\n \
+             --> a.sus:2:15\n  \
+               |\n\
+             2 |     \"日本\"  ඞ := a + ＢＢ;\n  \
+               |                  -   ^^^^ Narrow\n"
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let code = "x := a < b < c;";
+        let source_map = SourceMap::new();
+        let file = source_map.add("a.sus", code);
+        let diagnostic = Diagnostic::error(file, span(code, "a < b < c"), "Chained")
+            .with_label(span(code, "a"), "left")
+            .with_label(span(code, "b"), "middle")
+            .with_label(span(code, "c"), "right")
+            .with_note("Comparisons return booleans");
+        assert_eq!(
+            Renderer::new(&source_map).display(&diagnostic).to_string(),
+            "error: Chained\n \
+             --> a.sus:1:5\n  \
+               |\n\
+             1 | x := a < b < c;\n  \
+               |      ^^^^^^^^^ right\n  \
+               |      |   |\n  \
+               |      |   middle\n  \
+               |      left\n  \
+               |\n  \
+               = note: Comparisons return booleans\n"
+        );
+    }
+
+    #[test]
+    fn long_spans() {
+        let code = "f :: fn() {\n    a := 1;\n    b := 2;\n    c := 3;\n    d := 4;\n}\n";
+        let source_map = SourceMap::new();
+        let file = source_map.add("a.sus", code);
+        let body = span(
+            code,
+            "{\n    a := 1;\n    b := 2;\n    c := 3;\n    d := 4;\n}",
+        );
+        let diagnostic =
+            Diagnostic::error(file, span(code, "f"), "Body").with_label(body, "this body");
+        assert_eq!(
+            Renderer::new(&source_map).display(&diagnostic).to_string(),
+            "error: Body\n \
+             --> a.sus:1:0\n  \
+               |\n\
+             1 | f :: fn() {\n  \
+               | ^         -\n\
+             ...\n\
+             6 | }\n  \
+               | - this body\n"
+        );
+    }
+
+    #[test]
+    fn parsed() {
+        let code = "f :: fn() {\r\n\tx := (1;\r\n}";
+        let source_map = SourceMap::new();
+        let file = source_map.add("a.sus", code);
+        let tokens = source_map.lex(file);
+        let (_, diagnostics) = parse_file(&tokens);
+
+        let mut sink = DiagnosticSink::new();
+        sink.extend(tokens.errors.iter().map(Diagnostic::from));
+        sink.extend(diagnostics.iter().map(Diagnostic::from));
+        let renderer = Renderer {
+            tab_width: 2,
+            ..Renderer::new(&source_map)
+        };
+        let rendered: Vec<_> = (sink.finish().diagnostics.iter())
+            .map(|diagnostic| renderer.display(diagnostic).to_string())
+            .collect();
+        assert_eq!(
+            rendered,
+            [
+                "error[E0022]: Expected RParens, found Semi\n \
+                 --> a.sus:2:8\n  \
+                   |\n\
+                 2 |   x := (1;\n  \
+                   |          ^\n",
+                "error[E0020]: Unclosed `(`\n \
+                 --> a.sus:3:0\n  \
+                   |\n\
+                 2 |   x := (1;\n  \
+                   |        - Opened here\n\
+                 3 | }\n  \
+                   | ^\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let code = "a = 1; // c";
+        let source_map = SourceMap::new();
+        let file = source_map.add("a.sus", code);
+        let diagnostic = Diagnostic::error(file, span(code, "1"), "One");
+        let renderer = Renderer {
+            painter: Painter::COLORED,
+            ..Renderer::new(&source_map)
+        };
+        assert_eq!(
+            renderer.display(&diagnostic).to_string(),
+            "\x1b[1;31merror\x1b[0m\x1b[1m: One\x1b[0m\n\
+             \x1b[1;34m -->\x1b[0m a.sus:1:4\n\
+             \x1b[1;34m  |\x1b[0m\n\
+             \x1b[1;34m1 |\x1b[0m a = \x1b[33m1\x1b[0m; \x1b[90m// c\x1b[0m\n\
+             \x1b[1;34m  |\x1b[0m     \x1b[1;31m^\x1b[0m\n"
+        );
+    }
+}
@@ -1,8 +1,51 @@
-use std::{fmt, mem};
-
-use crate::arena::{ArenaVec, GIB};
+use std::{env, error::Error, fmt, mem, ops::Range, sync::OnceLock};
+
+use crate::{
+    arena::{probe_limits, ArenaAlloc, ArenaVec, VmAlloc, KIB},
+    source::FileId,
+};
+
+mod cache;
+mod check;
+mod cursor;
+mod delimiter;
+mod emit;
+pub mod escape;
+mod keyword;
+pub mod number;
+mod owned;
+mod parallel;
+mod relex;
+mod render;
+mod repl;
+mod stats;
+mod stream;
+mod token_cursor;
+pub mod trivia;
+
+use cursor::{Cursor, Mark};
+use escape::LiteralMode;
+use trivia::{add_trivia, TokenTrivia, Trivia, TriviaKind};
+
+pub use cache::{CACHE_MAGIC, CACHE_VERSION};
+pub(crate) use check::panic_message;
+pub use check::{lex_checked, InvariantViolation};
+pub use delimiter::Delimiter;
+pub(crate) use emit::{write_json_span, write_json_str};
+pub use emit::{EmitFormat, BINARY_MAGIC, BINARY_VERSION};
+pub use escape::{EscapeError, LiteralValue};
+pub use number::{NumError, NumSuffix, NumValue, NumValues};
+pub use owned::{OwnedLexError, OwnedSpan, OwnedTokens, OwnedTrivia};
+pub use parallel::lex_parallel;
+pub use relex::TextEdit;
+pub use render::{Highlight, RenderFormat, HTML_STYLE};
+pub use repl::{Feed, ReplLexer, Unfinished};
+pub use stats::{ArrayMemory, TokenStats, TokensMemory};
+pub use stream::StreamLexer;
+pub use token_cursor::{Checkpoint, Token, TokenCursor, UnexpectedToken};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
 pub enum TokenType {
     And, // and
     Or,  // or
@@ -16,8 +59,9 @@ pub enum TokenType {
     LessEqual,    // <=
     GreaterEqual, // >=
 
-    Feather, // >-
-    Arrow,   // ->
+    Feather,  // >-
+    Arrow,    // ->
+    FatArrow, // =>
 
     Ampersand, // &
     Pipe,      // |
@@ -36,8 +80,12 @@ pub enum TokenType {
     Modulo, // %
 
     Pub, // pub
+    Use, // use (soft keyword)
+
+    Const,  // const (soft keyword)
+    Static, // static (soft keyword)
 
-    Packed, // packed
+    Packed, // packed (soft keyword)
     Struct, // struct
     Enum,   // enum
     Union,  // union
@@ -45,59 +93,623 @@ pub enum TokenType {
     Fn,       // fn
     Defer,    // defer
     If,       // if
-    Then,     // then
+    Then,     // then (soft keyword)
     Else,     // else
     While,    // while
     Do,       // do
     Loop,     // loop
+    For,      // for (soft keyword)
+    In,       // in (soft keyword)
     Continue, // continue
     Break,    // break
-
-    Equal,    // =
-    Semi,     // ;
-    Colon,    // :
-    Comma,    // ,
-    Dot,      // .
-    LParens,  // (
-    RParens,  // )
-    LBracket, // [
-    RBracket, // ]
-    LBrace,   // {
-    RBrace,   // }
+    Match,    // match (soft keyword)
+    Return,   // return (soft keyword)
+
+    True,  // true (soft keyword)
+    False, // false (soft keyword)
+    None,  // none (soft keyword)
+
+    Equal,       // =
+    Semi,        // ;
+    Colon,       // :
+    DoubleColon, // ::
+    Comma,       // ,
+    Dot,         // .
+    Question,    // ?
+    Hash,        // #
+    At,          // @
+
+    AttrStart,      // #[
+    InnerAttrStart, // #![
+    /// Closing bracket of an attribute
+    AttrEnd, // ]
+    LParens,        // (
+    RParens,        // )
+    LBracket,       // [
+    RBracket,       // ]
+    LBrace,         // {
+    RBrace,         // }
 
     String,
+    ByteString, // b"..."
+    CString,    // c"..."
     StringInterpBeg,
     StringInterpMid,
     StringInterpEnd,
-    Char, // 'a'
+    /// Format spec of an interpolation, including its leading colon (`:08.3` in `{x:08.3}`)
+    FormatSpec,
+    Char,     // 'a'
+    ByteChar, // b'a'
     Ident,
     Num,
+
+    DocComment,       // /// doc
+    ModuleDocComment, // //! doc
+
+    /// Slice of code that could not be lexed, see [`Tokens::errors`]
+    Error,
+    /// Empty token at the end of the code, always the last one
+    Eof,
+}
+
+/// Byte range of a token, as stored in [`Tokens::spans`].
+///
+/// Its line and column are computed when needed, see [`Tokens::span`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: u32,
+    pub len: u32,
+}
+
+impl Span {
+    #[inline]
+    pub const fn end(self) -> usize {
+        self.start as usize + self.len as usize
+    }
+
+    #[inline]
+    pub const fn range(self) -> Range<usize> {
+        self.start as usize..self.end()
+    }
+}
+
+impl From<&TokenSpan<'_>> for Span {
+    #[inline]
+    fn from(span: &TokenSpan) -> Self {
+        Self {
+            start: span.start as u32,
+            len: span.slice.len() as u32,
+        }
+    }
+}
+
+impl From<TokenSpan<'_>> for Span {
+    #[inline]
+    fn from(span: TokenSpan) -> Self {
+        Self::from(&span)
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Span of code along with its position, resolved from a [`Span`] or stored as is for errors and trivia.
+#[derive(Debug, Clone, Copy)]
 pub struct TokenSpan<'a> {
     pub slice: &'a str,
+    /// Byte offset of the slice in the code
+    pub start: usize,
     pub line: usize,
     pub col: usize,
 }
 
 impl<'a> TokenSpan<'a> {
     #[inline]
-    pub const fn new(slice: &'a str, line: usize, col: usize) -> Self {
-        Self { slice, line, col }
+    pub const fn new(slice: &'a str, start: usize, line: usize, col: usize) -> Self {
+        Self {
+            slice,
+            start,
+            line,
+            col,
+        }
+    }
+
+    /// Byte offset of the end of the slice in the code.
+    #[inline]
+    pub const fn end(&self) -> usize {
+        self.start + self.slice.len()
+    }
+
+    /// Byte range of the slice in the code.
+    #[inline]
+    pub const fn range(&self) -> Range<usize> {
+        self.start..self.end()
+    }
+
+    /// Name of an identifier, without the `r#` prefix of raw identifiers.
+    #[inline]
+    pub fn ident_name(&self) -> &'a str {
+        self.slice.strip_prefix("r#").unwrap_or(self.slice)
     }
 }
 
 #[derive(Debug)]
 pub struct Tokens<'a> {
+    /// File of the code in its [`SourceMap`](crate::source::SourceMap)
+    pub file: FileId,
     /// The entire code file
     pub code: &'a str,
     /// Sorted list containing the position of all line breaks
+    /// (the position of the `\n` for `\r\n` line breaks)
     pub line_breaks: ArenaVec<usize>,
-    /// Token spans in the code
-    pub spans: ArenaVec<TokenSpan<'a>>,
-    /// Respective token types
+    /// Token spans in the code, see [`Tokens::span`] to get their slice, line and column
+    pub spans: ArenaVec<Span>,
+    /// Respective token types, the last one always being [`TokenType::Eof`]
     pub types: ArenaVec<TokenType>,
+    /// Errors encountered while lexing, in order of appearance
+    pub errors: ArenaVec<LexError<'a>>,
+    /// How the code was lexed
+    pub options: LexOptions,
+    /// Whitespace and comments in the code, only with [`LexMode::PreserveTrivia`]
+    pub trivia: ArenaVec<Trivia<'a>>,
+    /// Trivia attached to each token, only with [`LexMode::PreserveTrivia`]
+    pub token_trivia: ArenaVec<TokenTrivia>,
+    /// Number of interpolations and attributes the lexer is currently in
+    nesting_depth: usize,
+    /// Position on its line of the code before the first line break,
+    /// for batches and chunks that start in the middle of a file
+    origin: Mark,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LexMode {
+    /// Whitespace and regular comments are thrown away
+    #[default]
+    Normal,
+    /// Whitespace and regular comments are kept as trivia attached to tokens,
+    /// so that the code can be reconstructed exactly
+    PreserveTrivia,
+}
+
+/// Options to tune how code is lexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LexOptions {
+    pub mode: LexMode,
+    /// Width of tab stops for [`Tokens::display_col`], `1` counting tabs as single characters
+    pub tab_width: usize,
+    /// Maximum depth of interpolations and attributes nested inside each other
+    pub max_nesting_depth: usize,
+    /// Whether `b"..."`, `c"..."` and `b'...'` literals are allowed
+    /// (otherwise their prefix is lexed as an identifier)
+    pub allow_literal_prefixes: bool,
+    /// Maximum length of a token in bytes, longer ones being reported
+    pub max_token_len: usize,
+    /// Maximum length of the code in bytes, longer code being reported instead of lexed
+    /// (it can't be more than 4 GiB, as spans store 32-bit offsets)
+    pub max_code_len: usize,
+    /// Size of the address space initially reserved for each of the token arrays,
+    /// which grow past it when needed
+    pub reservation: Reservation,
+    /// Whether the token arrays are backed by transparent huge pages (only on Linux),
+    /// which makes going through the tokens of very large inputs cause fewer TLB misses.
+    /// It is on by default when the `CSUSSUS_HUGE_PAGES` environment variable is `1`.
+    pub huge_pages: bool,
+}
+
+/// How much address space the token arrays initially reserve, see [`LexOptions::reservation`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Reservation {
+    /// Enough for the worst case of the code being lexed: there are never more tokens
+    /// (nor line breaks) than bytes, so the arrays don't have to grow
+    #[default]
+    FromCodeLen,
+    /// The same number of bytes for every array, e.g. to keep the address space of
+    /// each file small in constrained environments
+    Fixed(usize),
+}
+
+/// Smallest address space that an array reserves, as reservations are made of whole pages.
+pub const MIN_RESERVATION: usize = 64 * KIB;
+
+impl Reservation {
+    /// Address space to reserve for an array that has at most `max_len` elements
+    /// when the reservation is derived from the code.
+    ///
+    /// Derived reservations are kept within a 16th of what can be reserved (see
+    /// [`probe_limits`]), as there are several arrays and the rest of the compiler.
+    pub fn size_for<T>(self, max_len: usize) -> usize {
+        let size = match self {
+            Reservation::FromCodeLen => (max_len.saturating_mul(mem::size_of::<T>()))
+                .min(probe_limits().max_reservation / 16),
+            Reservation::Fixed(size) => size,
+        };
+        size.max(MIN_RESERVATION)
+    }
+}
+
+impl LexOptions {
+    /// Checks that the options make sense, as they may come from users.
+    ///
+    /// Lexing doesn't require it: a fixed reservation that's out of range
+    /// is raised to [`MIN_RESERVATION`] or falls back to the heap.
+    pub fn validate(&self) -> Result<(), InvalidOptions> {
+        if let Reservation::Fixed(size) = self.reservation {
+            if size < MIN_RESERVATION {
+                return Err(InvalidOptions::ReservationTooSmall(size));
+            }
+            if size > isize::MAX as usize {
+                return Err(InvalidOptions::ReservationTooLarge(size));
+            }
+        }
+        if self.tab_width == 0 {
+            return Err(InvalidOptions::ZeroTabWidth);
+        }
+        Ok(())
+    }
+
+    /// Default allocation policy of the token arrays, with huge pages if enabled.
+    fn vm_alloc(&self) -> VmAlloc {
+        VmAlloc {
+            huge_pages: self.huge_pages,
+        }
+    }
+}
+
+/// Options that don't make sense, see [`LexOptions::validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvalidOptions {
+    /// A fixed reservation is smaller than [`MIN_RESERVATION`]
+    ReservationTooSmall(usize),
+    /// A fixed reservation is larger than the address space of the target
+    ReservationTooLarge(usize),
+    ZeroTabWidth,
+}
+
+impl fmt::Display for InvalidOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidOptions::ReservationTooSmall(size) => {
+                write!(
+                    f,
+                    "Reservation of {size} bytes is smaller than {MIN_RESERVATION}"
+                )
+            }
+            InvalidOptions::ReservationTooLarge(size) => {
+                write!(
+                    f,
+                    "Reservation of {size} bytes is larger than the address space"
+                )
+            }
+            InvalidOptions::ZeroTabWidth => write!(f, "Tab width cannot be 0"),
+        }
+    }
+}
+
+impl Error for InvalidOptions {}
+
+/// Whether huge pages are enabled by the environment, see [`LexOptions::huge_pages`].
+fn huge_pages_from_env() -> bool {
+    static HUGE_PAGES: OnceLock<bool> = OnceLock::new();
+    *HUGE_PAGES.get_or_init(|| env::var_os("CSUSSUS_HUGE_PAGES").is_some_and(|var| var == "1"))
+}
+
+impl Default for LexOptions {
+    fn default() -> Self {
+        Self {
+            mode: LexMode::Normal,
+            tab_width: 1,
+            max_nesting_depth: 64,
+            allow_literal_prefixes: true,
+            max_token_len: usize::MAX,
+            max_code_len: u32::MAX as usize,
+            reservation: Reservation::FromCodeLen,
+            huge_pages: huge_pages_from_env(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LexErrorKind {
+    UnfinishedInterpString,
+    UnfinishedString,
+    UnfinishedChar,
+    UnknownToken,
+    InvalidEscape(EscapeError),
+    InvalidNumSuffix,
+    UnexpectedEof,
+    NestingTooDeep,
+    TokenTooLong,
+    InputTooLarge,
+    MalformedNum,
+    NumOverflow,
+    EmptyChar,
+    MultipleCharsInChar,
+    NonAsciiByteChar,
+    NulInCString,
+    UnclosedAttribute,
+    EmptyAttribute,
+    MissingDigits,
+    UnclosedDelimiter(Delimiter),
+    UnexpectedDelimiter(Delimiter),
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LexErrorKind::UnfinishedInterpString => "Unfinished interpolated string",
+            LexErrorKind::UnfinishedString => "Unfinished string",
+            LexErrorKind::UnfinishedChar => "Unfinished char",
+            LexErrorKind::UnknownToken => "Cannot parse token",
+            LexErrorKind::InvalidNumSuffix => "Invalid number literal suffix",
+            LexErrorKind::UnexpectedEof => "Unexpected end of file",
+            LexErrorKind::NestingTooDeep => "Nested too deeply",
+            LexErrorKind::TokenTooLong => "Token is too long",
+            LexErrorKind::InputTooLarge => "Input is too large",
+            LexErrorKind::MalformedNum => "Malformed number literal",
+            LexErrorKind::NumOverflow => "Number literal out of range",
+            LexErrorKind::EmptyChar => "Empty char literal",
+            LexErrorKind::MultipleCharsInChar => "Char literal must contain exactly one character",
+            LexErrorKind::NonAsciiByteChar => "Byte char literal must be ASCII",
+            LexErrorKind::NulInCString => "C string literal cannot contain NUL bytes",
+            LexErrorKind::UnclosedAttribute => "Unclosed attribute",
+            LexErrorKind::EmptyAttribute => "Empty attribute",
+            LexErrorKind::MissingDigits => "Number literal is missing digits",
+            LexErrorKind::UnclosedDelimiter(delimiter) => {
+                return write!(f, "Unclosed `{}`", delimiter.open());
+            }
+            LexErrorKind::UnexpectedDelimiter(delimiter) => {
+                return write!(f, "Unexpected `{}`", delimiter.close());
+            }
+            LexErrorKind::InvalidEscape(error) => return write!(f, "{error}"),
+        })
+    }
+}
+
+/// A recoverable lexing error.
+///
+/// Its span is the same as the one of the respective [`TokenType::Error`] token,
+/// except for errors about the contents of a valid token (like invalid escapes),
+/// which point inside of that token instead.
+#[derive(Debug, Clone, Copy)]
+pub struct LexError<'a> {
+    pub file: FileId,
+    pub kind: LexErrorKind,
+    pub span: TokenSpan<'a>,
+    /// Other end of the error, like the opening delimiter of an unclosed one
+    pub related: Option<TokenSpan<'a>>,
+}
+
+impl<'a> fmt::Display for LexError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.col, self.kind)?;
+        if let Some(related) = &self.related {
+            write!(f, " opened at {}:{}", related.line, related.col)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Panics if the code is larger than 4 GiB, as spans store 32-bit offsets.
+    fn empty(file: FileId, code: &'a str, options: LexOptions) -> Self {
+        Self::empty_in(file, code, options, &options.vm_alloc())
+    }
+
+    fn empty_in(file: FileId, code: &'a str, options: LexOptions, alloc: &impl ArenaAlloc) -> Self {
+        assert!(code.len() <= u32::MAX as usize, "Code is too large");
+
+        // there is at most one token per byte, plus EOF
+        let max_tokens = code.len() + 1;
+        let max_trivia = match options.mode {
+            LexMode::Normal => 0,
+            LexMode::PreserveTrivia => max_tokens,
+        };
+        let reservation = options.reservation;
+        Tokens {
+            file,
+            code,
+            line_breaks: alloc.new_vec(reservation.size_for::<usize>(code.len())),
+            spans: alloc.new_vec(reservation.size_for::<Span>(max_tokens)),
+            types: alloc.new_vec(reservation.size_for::<TokenType>(max_tokens)),
+            errors: alloc.new_vec(reservation.size_for::<LexError>(max_tokens)),
+            options,
+            trivia: alloc.new_vec(reservation.size_for::<Trivia>(max_trivia)),
+            token_trivia: alloc.new_vec(reservation.size_for::<TokenTrivia>(max_trivia)),
+            nesting_depth: 0,
+            origin: Mark {
+                pos: 0,
+                line: 1,
+                col: 0,
+            },
+        }
+    }
+
+    /// Slice, line and column of the token at the given index.
+    ///
+    /// The line is looked up in [`Tokens::line_breaks`], prefer [`Tokens::iter_spans`]
+    /// to go through many tokens.
+    #[inline]
+    pub fn span(&self, idx: usize) -> TokenSpan<'a> {
+        self.resolve(self.spans[idx])
+    }
+
+    /// Slice, line and column of a span in the code.
+    pub fn resolve(&self, span: Span) -> TokenSpan<'a> {
+        let line_idx =
+            (self.line_breaks.as_slice()).partition_point(|&lb| lb < span.start as usize);
+        self.resolve_on_line(span, line_idx)
+    }
+
+    /// Slices, lines and columns of every token, in order.
+    pub fn iter_spans(&self) -> impl Iterator<Item = TokenSpan<'a>> + '_ {
+        let line_breaks = self.line_breaks.as_slice();
+        let mut line_idx = 0;
+        self.spans.iter().map(move |&span| {
+            let start = span.start as usize;
+            while line_idx < line_breaks.len() && line_breaks[line_idx] < start {
+                line_idx += 1;
+            }
+            self.resolve_on_line(span, line_idx)
+        })
+    }
+
+    /// Resolves a span knowing that it starts after `line_idx` line breaks.
+    #[inline]
+    fn resolve_on_line(&self, span: Span, line_idx: usize) -> TokenSpan<'a> {
+        let start = span.start as usize;
+        let (line, col) = match line_idx {
+            0 => (self.origin.line, self.origin.col + start - self.origin.pos),
+            _ => (
+                self.origin.line + line_idx,
+                start - self.line_breaks[line_idx - 1] - 1,
+            ),
+        };
+        TokenSpan::new(&self.code[span.range()], start, line, col)
+    }
+
+    /// Column of the span in characters rather than bytes, for diagnostics.
+    ///
+    /// [`TokenSpan::col`] counts bytes, so multi-byte characters earlier on the line
+    /// shift it. This counts the characters between the start of the line and the span
+    /// instead, looking the line up in [`Tokens::line_breaks`].
+    /// Tabs go to the next multiple of [`LexOptions::tab_width`].
+    pub fn display_col(&self, span: &TokenSpan) -> usize {
+        let offset = span.start;
+        let line_idx = self
+            .line_breaks
+            .as_slice()
+            .partition_point(|&lb| lb < offset);
+        let line_start = match line_idx {
+            0 => 0,
+            _ => self.line_breaks[line_idx - 1] + 1,
+        };
+
+        // the line may have started before the code (see `StreamLexer`)
+        let prefix = &self.code[line_start..offset];
+        let tab_width = self.options.tab_width.max(1);
+        let prefix_width = prefix.chars().fold(0, |col, ch| match ch {
+            '\t' => (col / tab_width + 1) * tab_width,
+            _ => col + 1,
+        });
+        span.col - prefix.len() + prefix_width
+    }
+
+    /// Line and column (in bytes) of a byte offset in the code.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let span = Span {
+            start: offset as u32,
+            len: 0,
+        };
+        let span = self.resolve(span);
+        (span.line, span.col)
+    }
+
+    /// Copy of the tokens in the given range, as a batch that only ends with the EOF token
+    /// if the range has it.
+    ///
+    /// Only the line breaks between the tokens are copied, which is enough for their lines
+    /// and columns to stay the same. Errors and trivia are left out. Tokens can't be shared
+    /// between threads, so this is how parts of them are handed to other threads.
+    pub fn batch(&self, range: Range<usize>) -> Tokens<'a> {
+        let spans = &self.spans.as_slice()[range.clone()];
+        let (start, end) = match (spans.first(), spans.last()) {
+            (Some(first), Some(last)) => (first.start as usize, last.end()),
+            _ => (0, 0),
+        };
+        let line_breaks = self.line_breaks.as_slice();
+        let line_breaks = &line_breaks[line_breaks.partition_point(|&lb| lb < start)..];
+        let line_breaks = &line_breaks[..line_breaks.partition_point(|&lb| lb < end)];
+
+        let options = LexOptions {
+            mode: LexMode::Normal,
+            ..self.options
+        };
+        let (reservation, alloc) = (options.reservation, options.vm_alloc());
+        let (line, col) = self.line_col(start);
+        let batch = Tokens {
+            file: self.file,
+            code: self.code,
+            line_breaks: alloc.new_vec(reservation.size_for::<usize>(line_breaks.len())),
+            spans: alloc.new_vec(reservation.size_for::<Span>(spans.len())),
+            types: alloc.new_vec(reservation.size_for::<TokenType>(spans.len())),
+            errors: alloc.new_vec(0),
+            options,
+            trivia: alloc.new_vec(0),
+            token_trivia: alloc.new_vec(0),
+            nesting_depth: 0,
+            origin: Mark {
+                pos: start,
+                line,
+                col,
+            },
+        };
+
+        line_breaks.iter().for_each(|&lb| batch.line_breaks.add(lb));
+        spans.iter().for_each(|&span| batch.spans.add(span));
+        (self.types.as_slice()[range])
+            .iter()
+            .for_each(|&ty| batch.types.add(ty));
+        batch
+    }
+
+    /// Computes the range of tokens that changed between two lexes of the same file.
+    ///
+    /// Tokens are compared by type and slice, so tokens that only moved
+    /// (e.g. after a line got inserted above them) are not considered changed.
+    pub fn diff(old: &Tokens, new: &Tokens) -> TokenEdits {
+        let old_len = old.types.len();
+        let new_len = new.types.len();
+
+        let same = |i: usize, j: usize| {
+            old.types[i] == new.types[j]
+                && old.code[old.spans[i].range()] == new.code[new.spans[j].range()]
+        };
+
+        let mut prefix = 0;
+        while prefix < old_len && prefix < new_len && same(prefix, prefix) {
+            prefix += 1;
+        }
+
+        let mut suffix = 0;
+        while suffix < old_len - prefix
+            && suffix < new_len - prefix
+            && same(old_len - suffix - 1, new_len - suffix - 1)
+        {
+            suffix += 1;
+        }
+
+        TokenEdits {
+            old: prefix..old_len - suffix,
+            new: prefix..new_len - suffix,
+        }
+    }
+
+    /// Gives the memory that the arrays don't use back, see [`ArenaVec::shrink_to_fit`].
+    ///
+    /// Tokens that are kept around after lexing, like open files of a language server,
+    /// then only take the memory they need.
+    pub fn shrink_to_fit(&mut self) {
+        self.line_breaks.shrink_to_fit();
+        self.spans.shrink_to_fit();
+        self.types.shrink_to_fit();
+        self.errors.shrink_to_fit();
+        self.trivia.shrink_to_fit();
+        self.token_trivia.shrink_to_fit();
+    }
+}
+
+/// Minimal range of tokens that differ between two lexes of the same file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenEdits {
+    /// Range of the replaced tokens in the old lex
+    pub old: Range<usize>,
+    /// Range of the replacing tokens in the new lex
+    pub new: Range<usize>,
+}
+
+impl TokenEdits {
+    /// Whether both lexes have exactly the same tokens.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.old.is_empty() && self.new.is_empty()
+    }
 }
 
 impl<'a> fmt::Display for Tokens<'a> {
@@ -110,12 +722,18 @@ impl<'a> fmt::Display for Tokens<'a> {
 
         let mut col_dwidth = 0;
         let mut type_dwidth = 0;
-        for (&ty, span) in self.types.iter().zip(self.spans.iter()) {
+        for (&ty, span) in self.types.iter().zip(self.iter_spans()) {
             col_dwidth = col_dwidth.max(log10(span.col));
             type_dwidth = type_dwidth.max(format!("{ty:?}").len());
         }
 
-        for (ty, TokenSpan { slice, line, col }) in self.types.iter().zip(self.spans.iter()) {
+        for (
+            ty,
+            TokenSpan {
+                slice, line, col, ..
+            },
+        ) in self.types.iter().zip(self.iter_spans())
+        {
             writeln!(
                 f,
                 "{line:>line_dwidth$}:{col:<col_dwidth$}   {:<type_dwidth$}   {slice}",
@@ -130,28 +748,6 @@ impl<'a> fmt::Display for Tokens<'a> {
     }
 }
 
-mod kw {
-    pub const CONTINUE: &[u8] = b"continue";
-    pub const PACKED: &[u8] = b"packed";
-    pub const STRUCT: &[u8] = b"struct";
-    pub const UNION: &[u8] = b"union";
-    pub const DEFER: &[u8] = b"defer";
-    pub const WHILE: &[u8] = b"while";
-    pub const BREAK: &[u8] = b"break";
-    pub const ENUM: &[u8] = b"enum";
-    pub const THEN: &[u8] = b"then";
-    pub const ELSE: &[u8] = b"else";
-    pub const LOOP: &[u8] = b"loop";
-    pub const AND: &[u8] = b"and";
-    pub const XOR: &[u8] = b"xor";
-    pub const NOT: &[u8] = b"not";
-    pub const PUB: &[u8] = b"pub";
-    pub const OR: &[u8] = b"or";
-    pub const FN: &[u8] = b"fn";
-    pub const IF: &[u8] = b"if";
-    pub const DO: &[u8] = b"do";
-}
-
 mod op {
     pub const EQUALS: &[u8] = b"==";
     pub const NOT_EQUALS: &[u8] = b"!=";
@@ -159,6 +755,8 @@ mod op {
     pub const GREATER_EQUAL: &[u8] = b">=";
     pub const FEATHER: &[u8] = b">-";
     pub const ARROW: &[u8] = b"->";
+    pub const FAT_ARROW: &[u8] = b"=>";
+    pub const DOUBLE_COLON: &[u8] = b"::";
     pub const L_SHIFT: &[u8] = b"<<";
     pub const R_SHIFT: &[u8] = b">>";
     pub const INCR: &[u8] = b"++";
@@ -180,6 +778,9 @@ mod op {
     pub const COLON: &[u8] = b":";
     pub const COMMA: &[u8] = b",";
     pub const DOT: &[u8] = b".";
+    pub const QUESTION: &[u8] = b"?";
+    pub const HASH: &[u8] = b"#";
+    pub const AT: &[u8] = b"@";
     pub const L_PARENS: &[u8] = b"(";
     pub const R_PARENS: &[u8] = b")";
     pub const L_BRACKET: &[u8] = b"[";
@@ -188,83 +789,484 @@ mod op {
     pub const R_BRACE: &[u8] = b"}";
 }
 
-pub fn lex<'a>(file_name: &str, code: &'a str) -> Tokens<'a> {
-    let mut line = 1;
-    let mut line_start = code.as_ptr() as usize;
-
-    let addr_space_size = 64 * GIB;
+pub fn lex(file: FileId, code: &str) -> Tokens<'_> {
+    lex_with_options(file, code, LexOptions::default())
+}
 
-    let mut tokens = Tokens {
-        code,
-        line_breaks: ArenaVec::new(addr_space_size / 8),
-        spans: ArenaVec::new(addr_space_size),
-        types: ArenaVec::new(addr_space_size / mem::size_of::<TokenSpan>()),
+pub fn lex_with_mode(file: FileId, code: &str, mode: LexMode) -> Tokens<'_> {
+    let options = LexOptions {
+        mode,
+        ..LexOptions::default()
     };
+    lex_with_options(file, code, options)
+}
 
-    let bcode = tokens.code.as_bytes();
-    let mut input = bcode;
-    while !input.is_empty() {
-        input = consume_token(file_name, input, &mut line, &mut line_start, &mut tokens);
+pub fn lex_with_options(file: FileId, code: &str, options: LexOptions) -> Tokens<'_> {
+    lex_in(file, code, options, &options.vm_alloc())
+}
+
+/// Lexes the code like [`lex_with_options`], with the token arrays allocated by `alloc`
+/// ([`LexOptions::huge_pages`] only applies to [`VmAlloc`]).
+pub fn lex_in<'a>(
+    file: FileId,
+    code: &'a str,
+    options: LexOptions,
+    alloc: &impl ArenaAlloc,
+) -> Tokens<'a> {
+    if let Some(tokens) = reject_too_large(file, code, options, alloc) {
+        return tokens;
+    }
+
+    let mut tokens = Tokens::empty_in(file, code, options, alloc);
+
+    let mut cursor = Cursor::new(code);
+    while !cursor.is_eof() {
+        lex_token(&mut cursor, &mut tokens);
+    }
+    add_eof(&mut tokens);
+    tokens.match_delimiters();
+
+    if options.mode == LexMode::PreserveTrivia {
+        tokens.attach_trivia();
     }
 
     tokens
 }
 
+/// Tokens of code that is longer than [`LexOptions::max_code_len`], if it is.
+///
+/// Nothing is lexed: they are the tokens of empty code with a single error.
+fn reject_too_large<'a>(
+    file: FileId,
+    code: &'a str,
+    options: LexOptions,
+    alloc: &impl ArenaAlloc,
+) -> Option<Tokens<'a>> {
+    if code.len() <= options.max_code_len.min(u32::MAX as usize) {
+        return None;
+    }
+
+    let mut tokens = Tokens::empty_in(file, &code[..0], options, alloc);
+    let span = TokenSpan::new(&code[..0], 0, 1, 0);
+    add_error_at(&tokens, LexErrorKind::InputTooLarge, span);
+    add_eof(&mut tokens);
+    Some(tokens)
+}
+
+/// Whether the byte starts a line break (`\n`, `\r\n` or a lone `\r`).
+#[inline]
+fn is_line_break(byte: u8) -> bool {
+    matches!(byte, b'\n' | b'\r')
+}
+
+#[inline]
+fn is_ident_start(byte: u8) -> bool {
+    matches!(byte, b'_' | b'A'..=b'Z' | b'a'..=b'z')
+}
+
+#[inline]
+fn is_ident_continue(byte: u8) -> bool {
+    matches!(byte, b'_' | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9')
+}
+
+/// Consumes digits of the given radix and `_` separators, returning how many digits there were.
+fn eat_digits(cursor: &mut Cursor, radix: u32) -> usize {
+    let mut digits = 0;
+    cursor.eat_while(|b| match b {
+        b'_' => true,
+        _ if (b as char).is_digit(radix) => {
+            digits += 1;
+            true
+        }
+        _ => false,
+    });
+    digits
+}
+
+/// Whether the `.` at the cursor starts the fraction of a hex float,
+/// as opposed to something like a method call on a hex literal.
+///
+/// The digits are peeked with the cursor, as the literal depends on all of them.
+fn is_hex_fraction(cursor: &Cursor) -> bool {
+    let mut n = 1;
+    while cursor
+        .peek_nth(n)
+        .is_some_and(|b| b == b'_' || b.is_ascii_hexdigit())
+    {
+        n += 1;
+    }
+    matches!(cursor.peek_nth(n), Some(b'p' | b'P'))
+}
+
+/// Ignores whitespace, saving line breaks.
+fn skip_whitespace<'a>(cursor: &mut Cursor<'a>, tokens: &mut Tokens<'a>) {
+    loop {
+        let start = cursor.mark();
+        match cursor.peek() {
+            Some(b'\n' | b'\r') => {
+                tokens.line_breaks.add(cursor.bump_line_break());
+                add_trivia(tokens, TriviaKind::LineBreak, cursor.span_from(start));
+            }
+            Some(byte) if byte.is_ascii_whitespace() => {
+                cursor.eat_while(|b| !is_line_break(b) && b.is_ascii_whitespace());
+                add_trivia(tokens, TriviaKind::Whitespace, cursor.span_from(start));
+            }
+            _ => break,
+        }
+    }
+}
+
+/// Consumes the next token through [`consume_token`], reporting tokens that are too long.
+fn lex_token<'a>(cursor: &mut Cursor<'a>, tokens: &mut Tokens<'a>) {
+    let first_idx = tokens.spans.len();
+    consume_token(cursor, tokens);
+
+    let max_token_len = tokens.options.max_token_len;
+    for idx in first_idx..tokens.spans.len() {
+        if tokens.spans[idx].len as usize > max_token_len {
+            add_error_at(tokens, LexErrorKind::TokenTooLong, tokens.span(idx));
+        }
+    }
+}
+
+/// Interpolated string, interpolation or attribute that is being consumed, see [`consume_token`].
+#[derive(Debug, Clone, Copy)]
+enum Nesting {
+    /// Interpolated string, whose current segment starts at `segment_start`
+    String {
+        segment_start: Mark,
+        has_interpolation: bool,
+    },
+    /// Interpolated expression, with the number of braces and of any delimiters open inside of it
+    Interpolation { braces: usize, delimiters: usize },
+    /// Attribute starting at `start`, whose contents start at token `contents_idx`
+    Attribute {
+        start: Mark,
+        contents_idx: usize,
+        delimiters: usize,
+    },
+}
+
+/// Consumes the rest of an interpolated string segment, up to its closing quote or interpolation.
+fn consume_string_segment<'a>(
+    cursor: &mut Cursor<'a>,
+    tokens: &mut Tokens<'a>,
+    stack: &mut Vec<Nesting>,
+    mut segment_start: Mark,
+    mut has_interpolation: bool,
+) {
+    while let Some(byte) = cursor.peek() {
+        // skip escaped characters (except line breaks which need to be tracked)
+        if byte == b'\\' && cursor.peek_nth(1).is_some_and(|b| !is_line_break(b)) {
+            cursor.bump_n(2);
+            continue;
+        }
+
+        if byte == b'"' {
+            // end of string
+            cursor.bump();
+
+            tokens.types.add(match has_interpolation {
+                true => TokenType::StringInterpEnd,
+                false => TokenType::String,
+            });
+            let span = cursor.span_from(segment_start);
+            validate_escapes(tokens, &span);
+            tokens.spans.add(span.into());
+            return;
+        } else if byte == b'{' {
+            // inside interpolated expression
+            cursor.bump();
+
+            tokens.types.add(match has_interpolation {
+                true => TokenType::StringInterpMid,
+                false => TokenType::StringInterpBeg,
+            });
+            let span = cursor.span_from(segment_start);
+            validate_escapes(tokens, &span);
+            tokens.spans.add(span.into());
+
+            has_interpolation = true;
+
+            if tokens.nesting_depth < tokens.options.max_nesting_depth {
+                // the next segment starts once the interpolation is consumed
+                tokens.nesting_depth += 1;
+                stack.push(Nesting::String {
+                    segment_start,
+                    has_interpolation,
+                });
+                stack.push(Nesting::Interpolation {
+                    braces: 0,
+                    delimiters: 0,
+                });
+                return;
+            }
+
+            skip_interpolation(cursor, tokens);
+
+            // the previous segment is already a token, the error can't cover it again
+            segment_start = cursor.mark();
+            if cursor.is_eof() {
+                break;
+            }
+            cursor.bump();
+        } else if is_line_break(byte) {
+            // strings support line breaks
+            tokens.line_breaks.add(cursor.bump_line_break());
+        } else {
+            cursor.bump();
+        }
+    }
+
+    let span = cursor.span_from(segment_start);
+    add_error(tokens, LexErrorKind::UnfinishedInterpString, span);
+}
+
+/// Consumes the next token of an interpolated expression, or its closing brace.
+///
+/// Only a `}` that doesn't close a brace of the expression closes it, so that blocks
+/// and struct literals can be interpolated, and other delimiters that are left open
+/// are reported by [`Tokens::match_delimiters`] instead of swallowing the rest of the string.
+/// A single `:` outside of any delimiters starts a format spec, which runs up to the closing brace.
+fn consume_interpolation<'a>(
+    cursor: &mut Cursor<'a>,
+    tokens: &mut Tokens<'a>,
+    stack: &mut Vec<Nesting>,
+    mut braces: usize,
+    mut delimiters: usize,
+) {
+    skip_whitespace(cursor, tokens);
+
+    // braces still open at the end of the file are reported by `match_delimiters`
+    let is_closed = match cursor.peek() {
+        None => true,
+        Some(b'}') => braces == 0,
+        _ => false,
+    };
+    if is_closed {
+        tokens.nesting_depth -= 1;
+        if let Some(Nesting::String { segment_start, .. }) = stack.last_mut() {
+            *segment_start = cursor.mark();
+        }
+        return;
+    }
+
+    match cursor.peek() {
+        Some(b':') if delimiters == 0 && !cursor.starts_with(b"::") => {
+            let start = cursor.mark();
+            cursor.eat_while(|b| b != b'}' && !is_line_break(b));
+            tokens.types.add(TokenType::FormatSpec);
+            tokens.spans.add(cursor.span_from(start).into());
+            stack.push(Nesting::Interpolation { braces, delimiters });
+            return;
+        }
+        Some(b'{') => (braces, delimiters) = (braces + 1, delimiters + 1),
+        Some(b'}') => (braces, delimiters) = (braces - 1, delimiters.saturating_sub(1)),
+        Some(b'(' | b'[') => delimiters += 1,
+        Some(b')' | b']') => delimiters = delimiters.saturating_sub(1),
+        _ => {}
+    }
+
+    stack.push(Nesting::Interpolation { braces, delimiters });
+    consume_single_token(cursor, tokens, stack);
+}
+
+/// Consumes the next token of an attribute, or its closing bracket.
+///
+/// Brackets are matched so that attributes like `#[repr(packed)]` or `#[a[0]]` work.
+fn consume_attribute<'a>(
+    cursor: &mut Cursor<'a>,
+    tokens: &mut Tokens<'a>,
+    stack: &mut Vec<Nesting>,
+    start: Mark,
+    contents_idx: usize,
+    mut delimiters: usize,
+) {
+    skip_whitespace(cursor, tokens);
+    match cursor.peek() {
+        None => {
+            tokens.nesting_depth -= 1;
+            let span = tokens.span(contents_idx - 1);
+            add_error_at(tokens, LexErrorKind::UnclosedAttribute, span);
+            return;
+        }
+        Some(b']') if delimiters == 0 => {
+            tokens.nesting_depth -= 1;
+            let is_empty = tokens.types.len() == contents_idx;
+
+            let end = cursor.mark();
+            cursor.bump();
+            tokens.types.add(TokenType::AttrEnd);
+            tokens.spans.add(cursor.span_from(end).into());
+
+            if is_empty {
+                add_error_at(
+                    tokens,
+                    LexErrorKind::EmptyAttribute,
+                    cursor.span_from(start),
+                );
+            }
+            return;
+        }
+        Some(b'{' | b'(' | b'[') => delimiters += 1,
+        Some(b'}' | b')' | b']') => delimiters = delimiters.saturating_sub(1),
+        _ => {}
+    }
+
+    stack.push(Nesting::Attribute {
+        start,
+        contents_idx,
+        delimiters,
+    });
+    consume_single_token(cursor, tokens, stack);
+}
+
+/// Skips an interpolated expression that is nested too deeply, reporting it as a single error.
+///
+/// Braces are still matched, but without lexing anything inside them.
+fn skip_interpolation<'a>(cursor: &mut Cursor<'a>, tokens: &mut Tokens<'a>) {
+    let start = cursor.mark();
+    let mut brace_depth = 0usize;
+    while let Some(byte) = cursor.peek() {
+        match byte {
+            b'}' if brace_depth == 0 => break,
+            b'{' => brace_depth += 1,
+            b'}' => brace_depth -= 1,
+            _ if is_line_break(byte) => {
+                tokens.line_breaks.add(cursor.bump_line_break());
+                continue;
+            }
+            _ => {}
+        }
+        cursor.bump();
+    }
+
+    add_error(
+        tokens,
+        LexErrorKind::NestingTooDeep,
+        cursor.span_from(start),
+    );
+}
+
 /// Consume - in most cases - a single token.
 ///
 /// Exceptions are made for special nestings, like interpolated strings and
-/// pairs of tokens that indicate a beginning and an end like parentheses,
-/// in which case it will recurse.
-fn consume_token<'a>(
-    file_name: &str,
-    mut input: &'a [u8],
-    line: &mut usize,
-    line_start: &mut usize,
+/// attributes, which are consumed whole. What they are nested in is kept on an
+/// explicit stack rather than by recursing, so that deep nesting can't overflow the
+/// call stack. Other delimiters are single tokens, matched afterwards by [`Tokens::match_delimiters`].
+fn consume_token<'a>(cursor: &mut Cursor<'a>, tokens: &mut Tokens<'a>) {
+    let mut stack = Vec::new();
+    consume_single_token(cursor, tokens, &mut stack);
+
+    while let Some(nesting) = stack.pop() {
+        match nesting {
+            Nesting::String {
+                segment_start,
+                has_interpolation,
+            } => {
+                consume_string_segment(cursor, tokens, &mut stack, segment_start, has_interpolation)
+            }
+            Nesting::Interpolation { braces, delimiters } => {
+                consume_interpolation(cursor, tokens, &mut stack, braces, delimiters);
+            }
+            Nesting::Attribute {
+                start,
+                contents_idx,
+                delimiters,
+            } => consume_attribute(cursor, tokens, &mut stack, start, contents_idx, delimiters),
+        }
+    }
+}
+
+/// Consumes a single token, pushing what it opens onto the stack of [`consume_token`].
+fn consume_single_token<'a>(
+    cursor: &mut Cursor<'a>,
     tokens: &mut Tokens<'a>,
-) -> &'a [u8] {
-    let bcode = tokens.code.as_bytes();
-    let start_addr = bcode.as_ptr() as usize;
+    stack: &mut Vec<Nesting>,
+) {
+    skip_whitespace(cursor, tokens);
 
-    // save line breaks
-    while !input.is_empty() && input[0] == b'\n' {
-        let addr = input.as_ptr() as usize;
-        tokens.line_breaks.add(addr - start_addr);
-        input = &input[1..];
-        *line_start = input.as_ptr() as usize;
-        *line += 1;
+    if cursor.is_eof() {
+        return;
     }
 
-    // ignore whitespace
-    while !input.is_empty() && input[0].is_ascii_whitespace() {
-        input = &input[1..];
-    }
+    let start = cursor.mark();
+
+    // comments (doc comments are kept as tokens)
+    if cursor.starts_with(b"//") {
+        let doc_type = if cursor.starts_with(b"///") && !cursor.starts_with(b"////") {
+            Some(TokenType::DocComment)
+        } else if cursor.starts_with(b"//!") {
+            Some(TokenType::ModuleDocComment)
+        } else {
+            None
+        };
+
+        cursor.eat_while(|b| !is_line_break(b));
+
+        if let Some(doc_type) = doc_type {
+            tokens.types.add(doc_type);
+            tokens.spans.add(cursor.span_from(start).into());
+        } else {
+            add_trivia(tokens, TriviaKind::Comment, cursor.span_from(start));
+        }
 
-    if input.is_empty() {
-        return input;
+        return;
     }
 
-    // ignore comments
-    if input.starts_with(b"//") {
-        input = &input[2..];
-        while input[0] != b'\n' {
-            input = &input[1..];
+    // attributes, a lone `#` depending on the two bytes after it
+    // (looked at with the cursor, so that re-lexing and streaming know)
+    let attr_start = if cursor.starts_with(b"#[") {
+        Some((TokenType::AttrStart, 2))
+    } else if cursor.starts_with(b"#![") {
+        Some((TokenType::InnerAttrStart, 3))
+    } else {
+        None
+    };
+
+    if let Some((toktype, prefix_len)) = attr_start {
+        // too deeply nested attributes are lexed as a lone `#`
+        if tokens.nesting_depth < tokens.options.max_nesting_depth {
+            cursor.bump_n(prefix_len);
+            tokens.types.add(toktype);
+            tokens.spans.add(cursor.span_from(start).into());
+
+            tokens.nesting_depth += 1;
+            stack.push(Nesting::Attribute {
+                start,
+                contents_idx: tokens.types.len(),
+                delimiters: 0,
+            });
+            return;
         }
-        return input;
+
+        cursor.bump();
+        tokens.types.add(TokenType::Hash);
+        tokens.spans.add(cursor.span_from(start).into());
+        add_error_at(
+            tokens,
+            LexErrorKind::NestingTooDeep,
+            cursor.span_from(start),
+        );
+        return;
     }
 
     // operators
     {
+        let rest = cursor.rest();
         let mut op_len;
         let is_operator = 'op: {
             op_len = 2;
-            if input.len() >= op_len {
-                let toktype = match &input[..op_len] {
+            if rest.len() >= op_len {
+                let toktype = match &rest[..op_len] {
                     op::EQUALS => Some(TokenType::Equals),
                     op::NOT_EQUALS => Some(TokenType::NotEquals),
                     op::LESS_EQUAL => Some(TokenType::LessEqual),
                     op::GREATER_EQUAL => Some(TokenType::GreaterEqual),
                     op::FEATHER => Some(TokenType::Feather),
                     op::ARROW => Some(TokenType::Arrow),
+                    op::FAT_ARROW => Some(TokenType::FatArrow),
+                    op::DOUBLE_COLON => Some(TokenType::DoubleColon),
                     op::L_SHIFT => Some(TokenType::LShift),
                     op::R_SHIFT => Some(TokenType::RShift),
                     op::INCR => Some(TokenType::Incr),
@@ -280,8 +1282,8 @@ fn consume_token<'a>(
             }
 
             op_len = 1;
-            if input.len() >= op_len {
-                let toktype = match &input[..op_len] {
+            if rest.len() >= op_len {
+                let toktype = match &rest[..op_len] {
                     op::MODULO => Some(TokenType::Modulo),
                     op::LESS_THAN => Some(TokenType::LessThan),
                     op::GREATER_THAN => Some(TokenType::GreaterThan),
@@ -298,6 +1300,9 @@ fn consume_token<'a>(
                     op::COLON => Some(TokenType::Colon),
                     op::COMMA => Some(TokenType::Comma),
                     op::DOT => Some(TokenType::Dot),
+                    op::QUESTION => Some(TokenType::Question),
+                    op::HASH => Some(TokenType::Hash),
+                    op::AT => Some(TokenType::At),
                     op::L_PARENS => Some(TokenType::LParens),
                     op::R_PARENS => Some(TokenType::RParens),
                     op::L_BRACKET => Some(TokenType::LBracket),
@@ -317,426 +1322,1484 @@ fn consume_token<'a>(
         };
 
         if is_operator {
-            let col = input.as_ptr() as usize - *line_start;
-            let slice = unsafe { std::str::from_utf8_unchecked(&input[..op_len]) };
-            tokens.spans.add(TokenSpan::new(slice, *line, col));
-            input = &input[op_len..];
-            return input;
+            cursor.bump_n(op_len);
+            tokens.spans.add(cursor.span_from(start).into());
+            return;
         }
     }
 
     // interpolated strings
-    if input.starts_with(b"$\"") {
-        let mut is_valid = false;
+    if cursor.starts_with(b"$\"") {
+        cursor.bump_n(2);
+        stack.push(Nesting::String {
+            segment_start: start,
+            has_interpolation: false,
+        });
+        return;
+    }
 
-        let mut start_str_addr = input.as_ptr() as usize;
-        input = &input[2..];
+    let allow_prefixes = tokens.options.allow_literal_prefixes;
 
-        let mut has_interpolation = false;
-        while !input.is_empty() {
-            if input.starts_with(br#"\""#) || input.starts_with(br#"\{"#) {
-                input = &input[2..];
+    // strings
+    // todo: raw strings (like in Rust)
+    let string_type = if allow_prefixes && cursor.starts_with(b"b\"") {
+        Some((TokenType::ByteString, 2))
+    } else if allow_prefixes && cursor.starts_with(b"c\"") {
+        Some((TokenType::CString, 2))
+    } else if cursor.peek() == Some(b'"') {
+        Some((TokenType::String, 1))
+    } else {
+        None
+    };
+
+    if let Some((toktype, prefix_len)) = string_type {
+        let mut is_valid = false;
+
+        cursor.bump_n(prefix_len);
+        while let Some(byte) = cursor.peek() {
+            // skip escaped characters (except line breaks which need to be tracked)
+            if byte == b'\\' && cursor.peek_nth(1).is_some_and(|b| !is_line_break(b)) {
+                cursor.bump_n(2);
                 continue;
             }
 
-            if input[0] == b'"' {
-                // end of string
-
+            if byte == b'"' {
                 is_valid = true;
-                input = &input[1..];
-
-                let end_str_addr = input.as_ptr() as usize;
-                let start = start_str_addr - start_addr;
-                let end = end_str_addr - start_addr;
-
-                tokens.types.add(match has_interpolation {
-                    true => TokenType::StringInterpEnd,
-                    false => TokenType::String,
-                });
-                let col = bcode.as_ptr() as usize + start - *line_start;
-                let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-                tokens.spans.add(TokenSpan::new(slice, *line, col));
+                cursor.bump();
                 break;
-            } else if input[0] == b'{' {
-                // inside interpolated expression (we can consume tokens recursively)
-
-                input = &input[1..];
-
-                let end_str_addr = input.as_ptr() as usize;
-                let start = start_str_addr - start_addr;
-                let end = end_str_addr - start_addr;
-
-                tokens.types.add(match has_interpolation {
-                    true => TokenType::StringInterpMid,
-                    false => TokenType::StringInterpBeg,
-                });
-                let col = bcode.as_ptr() as usize + start - *line_start;
-                let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-                tokens.spans.add(TokenSpan::new(slice, *line, col));
-
-                has_interpolation = true;
-
-                while !input.is_empty() && input[0] != b'}' {
-                    input = consume_token(file_name, input, line, line_start, tokens);
-                }
-                if input.is_empty() {
-                    break;
-                }
-
-                start_str_addr = input.as_ptr() as usize;
-                input = &input[1..];
-            } else if input[0] == b'\n' {
-                // strings support line breaks
+            }
 
-                let addr = input.as_ptr() as usize;
-                tokens.line_breaks.add(addr - start_addr);
-                input = &input[1..];
-                *line_start = input.as_ptr() as usize;
-                *line += 1;
+            // strings support line breaks
+            if is_line_break(byte) {
+                tokens.line_breaks.add(cursor.bump_line_break());
             } else {
-                input = &input[1..];
+                cursor.bump();
             }
         }
 
+        let span = cursor.span_from(start);
         if is_valid {
-            return input;
+            tokens.types.add(toktype);
+            validate_escapes(tokens, &span);
+            if toktype == TokenType::CString {
+                validate_c_string(tokens, &span);
+            }
+            tokens.spans.add(span.into());
         } else {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unfinished interpolated string");
+            add_error(tokens, LexErrorKind::UnfinishedString, span);
         }
+
+        return;
     }
 
-    // strings
-    // todo: raw strings (like in Rust)
-    let (is_string, prefix): (bool, &[u8]) = if input.starts_with(b"b\"") {
-        (true, b"b\"")
-    } else if input.starts_with(b"c\"") {
-        (true, b"c\"")
-    } else if input[0] == b'"' {
-        (true, b"\"")
+    // chars
+    let char_type = if allow_prefixes && cursor.starts_with(b"b'") {
+        Some((TokenType::ByteChar, 2))
+    } else if cursor.peek() == Some(b'\'') {
+        Some((TokenType::Char, 1))
     } else {
-        (false, b"")
+        None
     };
 
-    if is_string {
+    if let Some((toktype, prefix_len)) = char_type {
         let mut is_valid = false;
 
-        let start_str_addr = input.as_ptr() as usize;
-        input = &input[prefix.len()..];
-        while !input.is_empty() {
-            if input.starts_with(br#"\""#) {
-                input = &input[2..];
+        cursor.bump_n(prefix_len);
+        while let Some(byte) = cursor.peek() {
+            // skip escaped characters (except line breaks which need to be tracked)
+            if byte == b'\\' && cursor.peek_nth(1).is_some_and(|b| !is_line_break(b)) {
+                cursor.bump_n(2);
                 continue;
             }
 
-            if input[0] == b'"' {
+            if byte == b'\'' {
                 is_valid = true;
-                input = &input[1..];
+                cursor.bump();
                 break;
             }
 
-            // strings support line breaks
-            if input[0] == b'\n' {
-                let addr = input.as_ptr() as usize;
-                tokens.line_breaks.add(addr - start_addr);
-                input = &input[1..];
-                *line_start = input.as_ptr() as usize;
-                *line += 1;
-            } else {
-                input = &input[1..];
+            // chars can't contain line breaks, leave them for the next token
+            if is_line_break(byte) {
+                break;
             }
+            cursor.bump();
         }
 
+        let span = cursor.span_from(start);
         if is_valid {
-            let end_str_addr = input.as_ptr() as usize;
-            let start = start_str_addr - start_addr;
-            let end = end_str_addr - start_addr;
-
-            tokens.types.add(TokenType::String);
-            let col = bcode.as_ptr() as usize + start - *line_start;
-            let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-            tokens.spans.add(TokenSpan::new(slice, *line, col));
-            return input;
+            tokens.types.add(toktype);
+            validate_escapes(tokens, &span);
+            validate_char(tokens, &span);
+            tokens.spans.add(span.into());
         } else {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unfinished string");
+            add_error(tokens, LexErrorKind::UnfinishedChar, span);
         }
+
+        return;
     }
 
-    // chars
-    let (is_char, prefix): (bool, &[u8]) = if input.starts_with(b"b'") {
-        (true, b"b'")
-    } else if input[0] == b'\'' {
-        (true, b"'")
-    } else {
-        (false, b"")
-    };
+    // raw identifiers (never keywords)
+    if cursor.starts_with(b"r#") && cursor.peek_nth(2).is_some_and(is_ident_start) {
+        cursor.bump_n(2);
+        cursor.eat_while(is_ident_continue);
 
-    if is_char {
-        let mut is_valid = false;
+        tokens.types.add(TokenType::Ident);
+        tokens.spans.add(cursor.span_from(start).into());
+        return;
+    }
 
-        let start_str_addr = input.as_ptr() as usize;
-        input = &input[prefix.len()..];
-        while !input.is_empty() {
-            if input.starts_with(br#"\'"#) {
-                input = &input[2..];
-                continue;
+    // identifiers
+    if cursor.peek_is(is_ident_start) {
+        cursor.eat_while(is_ident_continue);
+
+        let span = cursor.span_from(start);
+        let toktype = keyword::lookup(span.slice.as_bytes());
+        tokens.types.add(toktype.unwrap_or(TokenType::Ident));
+        tokens.spans.add(span.into());
+        return;
+    }
+
+    // numbers
+    if cursor.peek_is(|b| b.is_ascii_digit()) {
+        // whether the end of file was hit before the literal could be complete
+        let mut is_truncated = false;
+        // prefixes and exponents that aren't followed by any digit
+        let mut missing_digits = Vec::new();
+
+        let radix_prefix = match cursor.rest() {
+            [b'0', b'x', ..] => Some(16),
+            [b'0', b'o', ..] => Some(8),
+            [b'0', b'b', ..] => Some(2),
+            _ => None,
+        };
+
+        if let Some(radix) = radix_prefix {
+            // hex, octal and binary literals
+            cursor.bump_n(2);
+            let digits = eat_digits(cursor, radix);
+            if digits == 0 {
+                is_truncated = cursor.is_eof();
+                missing_digits.push(cursor.span_from(start));
             }
 
-            if input[0] == b'\'' {
-                is_valid = true;
-                input = &input[1..];
-                break;
+            // hex floats, the fraction only being one if there is a binary exponent (`0x1.8p3`)
+            if radix == 16 && cursor.peek() == Some(b'.') && is_hex_fraction(cursor) {
+                cursor.bump();
+                eat_digits(cursor, 16);
             }
+            if radix == 16 && cursor.peek_is(|b| matches!(b, b'p' | b'P')) {
+                let exponent_start = cursor.mark();
+                cursor.bump();
+                if cursor.peek_is(|b| matches!(b, b'+' | b'-')) {
+                    cursor.bump();
+                }
+                if eat_digits(cursor, 10) == 0 {
+                    is_truncated = cursor.is_eof();
+                    missing_digits.push(cursor.span_from(exponent_start));
+                }
+            }
+        } else {
+            // decimal and floating literals
 
-            // chars can handle line breaks (though they shouldn't be allowed)
-            if input[0] == b'\n' {
-                let addr = input.as_ptr() as usize;
-                tokens.line_breaks.add(addr - start_addr);
-                input = &input[1..];
-                *line_start = input.as_ptr() as usize;
-                *line += 1;
-            } else {
-                input = &input[1..];
+            // whole part
+            cursor.eat_while(|b| matches!(b, b'_' | b'0'..=b'9'));
+
+            // fractional part, which `..` doesn't start so that `0..10` is a range
+            // (peeked with the cursor, as it makes the number depend on the code after it)
+            if cursor.peek() == Some(b'.') && cursor.peek_nth(1) != Some(b'.') {
+                cursor.bump();
+                cursor.eat_while(|b| matches!(b, b'_' | b'0'..=b'9'));
+            }
+
+            // exponent
+            if cursor.peek_is(|b| matches!(b, b'e' | b'E')) {
+                let exponent_start = cursor.mark();
+                cursor.bump();
+                if cursor.peek_is(|b| matches!(b, b'+' | b'-')) {
+                    cursor.bump();
+                }
+                if eat_digits(cursor, 10) == 0 {
+                    is_truncated = cursor.is_eof();
+                    missing_digits.push(cursor.span_from(exponent_start));
+                }
             }
         }
 
-        if is_valid {
-            let end_str_addr = input.as_ptr() as usize;
-            let start = start_str_addr - start_addr;
-            let end = end_str_addr - start_addr;
-
-            tokens.types.add(TokenType::Char);
-            let col = bcode.as_ptr() as usize + start - *line_start;
-            let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-            tokens.spans.add(TokenSpan::new(slice, *line, col));
-            return input;
-        } else {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unfinished char");
+        if is_truncated {
+            add_error(tokens, LexErrorKind::UnexpectedEof, cursor.span_from(start));
+            return;
         }
-    }
 
-    // identifiers
-    if matches!(input[0], b'_' | b'A'..=b'Z' | b'a'..=b'z') {
-        let start_ident_addr = input.as_ptr() as usize;
+        // type suffix
+        let suffix_start = cursor.mark();
+        if cursor.peek_is(|b| b.is_ascii_alphabetic()) {
+            cursor.eat_while(is_ident_continue);
+        }
+
+        tokens.types.add(TokenType::Num);
+        tokens.spans.add(cursor.span_from(start).into());
 
-        input = &input[1..];
-        while matches!(input[0], b'_' | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9') {
-            input = &input[1..];
+        for span in missing_digits {
+            add_error_at(tokens, LexErrorKind::MissingDigits, span);
         }
 
-        let end_ident_addr = input.as_ptr() as usize;
-        let start = start_ident_addr - start_addr;
-        let end = end_ident_addr - start_addr;
+        let suffix = cursor.span_from(suffix_start);
+        if !suffix.slice.is_empty() && NumSuffix::parse(suffix.slice).is_none() {
+            add_error_at(tokens, LexErrorKind::InvalidNumSuffix, suffix);
+        }
 
-        let col = bcode.as_ptr() as usize + start - *line_start;
-        let ident_slice = &bcode[start..end];
+        return;
+    }
 
-        let mut token_len;
-        let is_keyword = 'kw: {
-            // keywords
+    // unknown token, skip a single character
+    cursor.bump_char();
+    add_error(tokens, LexErrorKind::UnknownToken, cursor.span_from(start));
+}
 
-            token_len = 8;
-            if ident_slice.len() >= token_len {
-                let toktype = if &ident_slice[..token_len] == kw::CONTINUE {
-                    Some(TokenType::Continue)
-                } else {
-                    None
-                };
+/// Records the invalid escape sequences of a finished literal as errors.
+fn validate_escapes<'a>(tokens: &mut Tokens<'a>, span: &TokenSpan<'a>) {
+    let Some((mode, body)) = escape::literal_body(span.slice) else {
+        return;
+    };
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+    let body_start = body.start;
+    let body = &span.slice[body];
+    if !body.contains('\\') {
+        return;
+    }
 
-            token_len = 6;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::PACKED => Some(TokenType::Packed),
-                    kw::STRUCT => Some(TokenType::Struct),
-                    _ => None,
-                };
+    escape::unescape(body, mode, None, |range, error| {
+        let range = body_start + range.start..body_start + range.end;
+        add_error_at(
+            tokens,
+            LexErrorKind::InvalidEscape(error),
+            sub_span(span, range),
+        );
+    });
+}
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+/// Reports the NUL bytes of a finished C string literal, raw or escaped.
+fn validate_c_string<'a>(tokens: &mut Tokens<'a>, span: &TokenSpan<'a>) {
+    let Some((_, body)) = escape::literal_body(span.slice) else {
+        return;
+    };
 
-            token_len = 5;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::UNION => Some(TokenType::Union),
-                    kw::DEFER => Some(TokenType::Defer),
-                    kw::WHILE => Some(TokenType::While),
-                    kw::BREAK => Some(TokenType::Break),
-                    _ => None,
-                };
+    let body_start = body.start;
+    for range in escape::nul_ranges(&span.slice[body]) {
+        let range = body_start + range.start..body_start + range.end;
+        add_error_at(tokens, LexErrorKind::NulInCString, sub_span(span, range));
+    }
+}
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+/// Reports char literals that don't contain exactly one character.
+fn validate_char<'a>(tokens: &mut Tokens<'a>, span: &TokenSpan<'a>) {
+    let Some((mode, body)) = escape::literal_body(span.slice) else {
+        return;
+    };
 
-            token_len = 4;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::ENUM => Some(TokenType::Enum),
-                    kw::THEN => Some(TokenType::Then),
-                    kw::ELSE => Some(TokenType::Else),
-                    kw::LOOP => Some(TokenType::Loop),
-                    _ => None,
-                };
+    let body = &span.slice[body];
+    let kind = if mode == LiteralMode::ByteChar && !body.is_ascii() {
+        LexErrorKind::NonAsciiByteChar
+    } else {
+        let mut has_invalid_escape = false;
+        let mut value = Vec::new();
+        escape::unescape(body, mode, Some(&mut value), |_, _| {
+            has_invalid_escape = true
+        });
+
+        // invalid escapes are already reported
+        if has_invalid_escape {
+            return;
+        }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+        let char_count = match mode {
+            LiteralMode::ByteChar => value.len(),
+            _ => String::from_utf8_lossy(&value).chars().count(),
+        };
+        match char_count {
+            0 => LexErrorKind::EmptyChar,
+            1 => return,
+            _ => LexErrorKind::MultipleCharsInChar,
+        }
+    };
 
-            token_len = 3;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::AND => Some(TokenType::And),
-                    kw::XOR => Some(TokenType::Xor),
-                    kw::NOT => Some(TokenType::Not),
-                    kw::PUB => Some(TokenType::Pub),
-                    _ => None,
-                };
+    add_error_at(tokens, kind, *span);
+}
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+/// Part of a span, given as a byte range inside of it.
+fn sub_span<'a>(span: &TokenSpan<'a>, range: Range<usize>) -> TokenSpan<'a> {
+    // literals can span multiple lines
+    let (line, col) = line_col_in(span, range.start);
+    TokenSpan::new(
+        &span.slice[range.clone()],
+        span.start + range.start,
+        line,
+        col,
+    )
+}
 
-            token_len = 2;
-            if ident_slice.len() >= token_len {
-                let toktype = match &ident_slice[..token_len] {
-                    kw::OR => Some(TokenType::Or),
-                    kw::FN => Some(TokenType::Fn),
-                    kw::IF => Some(TokenType::If),
-                    kw::DO => Some(TokenType::Do),
-                    _ => None,
-                };
+/// Line and column of a byte offset inside of a span.
+fn line_col_in(span: &TokenSpan, offset: usize) -> (usize, usize) {
+    let before = &span.slice.as_bytes()[..offset];
+
+    let mut line = span.line;
+    let mut line_start = None;
+    for (i, &byte) in before.iter().enumerate() {
+        // `\r\n` is counted once, on the `\n`
+        let is_break = byte == b'\n' || (byte == b'\r' && before.get(i + 1) != Some(&b'\n'));
+        if is_break {
+            line += 1;
+            line_start = Some(i + 1);
+        }
+    }
 
-                if let Some(toktype) = toktype {
-                    tokens.types.add(toktype);
-                    break 'kw true;
-                }
-            }
+    match line_start {
+        Some(line_start) => (line, offset - line_start),
+        None => (line, span.col + offset),
+    }
+}
 
-            false
-        };
+/// Records a lexing error about a token that is still valid, without any [`TokenType::Error`] token.
+#[inline]
+fn add_error_at<'a>(tokens: &Tokens<'a>, kind: LexErrorKind, span: TokenSpan<'a>) {
+    tokens.errors.add(LexError {
+        file: tokens.file,
+        kind,
+        span,
+        related: None,
+    });
+}
 
-        if !is_keyword {
-            tokens.types.add(TokenType::Ident);
-        }
+/// Records a lexing error along with its respective [`TokenType::Error`] token.
+fn add_error<'a>(tokens: &mut Tokens<'a>, kind: LexErrorKind, span: TokenSpan<'a>) {
+    tokens.types.add(TokenType::Error);
+    tokens.spans.add((&span).into());
+    add_error_at(tokens, kind, span);
+}
+
+/// Adds the [`TokenType::Eof`] token that ends the tokens.
+fn add_eof(tokens: &mut Tokens) {
+    tokens.types.add(TokenType::Eof);
+    tokens.spans.add(Span {
+        start: tokens.code.len() as u32,
+        len: 0,
+    });
+}
+
+#[cfg(test)]
+mod tests_diff {
+    use crate::{
+        lexer::{lex, TokenEdits, Tokens},
+        source::FileId,
+    };
 
-        let slice = unsafe { std::str::from_utf8_unchecked(ident_slice) };
-        tokens.spans.add(TokenSpan::new(slice, *line, col));
-        return input;
+    #[test]
+    fn identical() {
+        let old = lex(FileId::default(), "a := 1 + 2;");
+        let new = lex(FileId::default(), "a := 1 + 2;");
+        let edits = Tokens::diff(&old, &new);
+        assert!(edits.is_empty());
+        assert_eq!(edits.old, 8..8);
     }
 
-    // numbers
-    if input[0].is_ascii_digit() {
-        let start_ident_addr = input.as_ptr() as usize;
-
-        if input.starts_with(b"0x") {
-            // hex literals
-            input = &input[2..];
-            while matches!(input[0], b'_' | b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F') {
-                input = &input[1..];
-            }
-        } else if input.starts_with(b"0o") {
-            // octal literals
-            input = &input[2..];
-            while matches!(input[0], b'_' | b'0'..=b'7') {
-                input = &input[1..];
-            }
-        } else if input.starts_with(b"0b") {
-            // binary literals
-            input = &input[2..];
-            while matches!(input[0], b'_' | b'0'..=b'1') {
-                input = &input[1..];
+    #[test]
+    fn replaced_token() {
+        let old = lex(FileId::default(), "a := 1 + 2;");
+        let new = lex(FileId::default(), "a := 1 * 2;");
+        let edits = Tokens::diff(&old, &new);
+        assert_eq!(
+            edits,
+            TokenEdits {
+                old: 4..5,
+                new: 4..5
             }
-        } else {
-            // decimal and floating literals
+        );
+    }
 
-            // whole part
-            input = &input[1..];
-            while matches!(input[0], b'_' | b'0'..=b'9') {
-                input = &input[1..];
+    #[test]
+    fn inserted_tokens() {
+        let old = lex(FileId::default(), "a := 1;");
+        let new = lex(FileId::default(), "a := 1 + 2;");
+        let edits = Tokens::diff(&old, &new);
+        assert_eq!(
+            edits,
+            TokenEdits {
+                old: 4..4,
+                new: 4..6
             }
+        );
+    }
 
-            // fractional part
-            if input[0] == b'.' {
-                input = &input[1..];
-                while matches!(input[0], b'_' | b'0'..=b'9') {
-                    input = &input[1..];
-                }
+    #[test]
+    fn removed_tokens() {
+        let old = lex(FileId::default(), "a := b c c d;");
+        let new = lex(FileId::default(), "a := b c d;");
+        let edits = Tokens::diff(&old, &new);
+        assert_eq!(
+            edits,
+            TokenEdits {
+                old: 5..6,
+                new: 5..5
             }
+        );
+    }
 
-            // exponent
-            if matches!(input[0], b'e' | b'E') {
-                input = &input[1..];
-                if matches!(input[0], b'+' | b'-') {
-                    input = &input[1..];
-                }
-                while matches!(input[0], b'_' | b'0'..=b'9') {
-                    input = &input[1..];
-                }
-            }
+    #[test]
+    fn moved_lines() {
+        let old = lex(FileId::default(), "a;\nb;");
+        let new = lex(FileId::default(), "a;\n\n\nb;");
+        assert!(Tokens::diff(&old, &new).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests_errors {
+    use crate::{
+        lexer::{lex, LexErrorKind, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn unfinished_string() {
+        let tokens = lex(FileId::default(), "a := \"hello");
+        assert_eq!(tokens.errors.len(), 1);
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::UnfinishedString);
+        assert_eq!(tokens.errors[0].span.slice, "\"hello");
+        assert_eq!(tokens.errors[0].span.col, 5);
+        assert_eq!(tokens.types[tokens.types.len() - 2], TokenType::Error);
+        assert_eq!(tokens.types[tokens.types.len() - 1], TokenType::Eof);
+    }
+
+    #[test]
+    fn unfinished_char() {
+        let tokens = lex(FileId::default(), "\n  'a");
+        assert_eq!(tokens.errors.len(), 1);
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::UnfinishedChar);
+        assert_eq!(tokens.errors[0].span.line, 2);
+        assert_eq!(tokens.errors[0].span.col, 2);
+    }
+
+    #[test]
+    fn unfinished_interpolated_string() {
+        let tokens = lex(FileId::default(), "$\"hello {a} world");
+        assert_eq!(tokens.errors.len(), 1);
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::UnfinishedInterpString);
+        assert_eq!(tokens.errors[0].span.slice, "} world");
+    }
+
+    #[test]
+    fn multiple_unknown_tokens() {
+        let tokens = lex(FileId::default(), "a $ b ඞ c;");
+        let types: Vec<_> = tokens.types.iter().copied().collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Ident,
+                TokenType::Error,
+                TokenType::Ident,
+                TokenType::Error,
+                TokenType::Ident,
+                TokenType::Semi,
+                TokenType::Eof,
+            ]
+        );
+        assert_eq!(tokens.errors.len(), 2);
+        assert_eq!(tokens.errors[1].span.slice, "ඞ");
+        assert_eq!(tokens.errors[1].kind, LexErrorKind::UnknownToken);
+    }
+}
+
+#[cfg(test)]
+mod tests_comments {
+    use crate::{
+        lexer::{lex, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn regular_comments_are_ignored() {
+        let tokens = lex(FileId::default(), "// hello\na //// not doc\n//");
+        assert_eq!(tokens.types.as_slice(), [TokenType::Ident, TokenType::Eof]);
+    }
+
+    #[test]
+    fn doc_comments() {
+        let tokens = lex(
+            FileId::default(),
+            "//! module doc\n\n  /// item doc\nfoo :: fn() {}",
+        );
+        assert_eq!(tokens.types[0], TokenType::ModuleDocComment);
+        assert_eq!(tokens.span(0).slice, "//! module doc");
+        assert_eq!(tokens.types[1], TokenType::DocComment);
+        assert_eq!(tokens.span(1).slice, "/// item doc");
+        assert_eq!(tokens.span(1).line, 3);
+        assert_eq!(tokens.span(1).col, 2);
+        assert_eq!(tokens.types[2], TokenType::Ident);
+    }
+
+    #[test]
+    fn doc_comment_at_end_of_file() {
+        let tokens = lex(FileId::default(), "a\n/// trailing");
+        assert_eq!(tokens.types[1], TokenType::DocComment);
+        assert_eq!(tokens.span(1).slice, "/// trailing");
+    }
+}
+
+#[cfg(test)]
+mod tests_escapes {
+    use crate::{
+        lexer::{lex, EscapeError, LexErrorKind, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn escaped_backslash_ends_string() {
+        let tokens = lex(FileId::default(), r#"a := "\\"; b;"#);
+        assert!(tokens.errors.is_empty());
+        assert_eq!(tokens.types[3], TokenType::String);
+        assert_eq!(tokens.span(3).slice, r#""\\""#);
+        assert_eq!(tokens.types[5], TokenType::Ident);
+    }
+
+    #[test]
+    fn invalid_escape_spans() {
+        let tokens = lex(FileId::default(), "x := \"ok \\q\n  \\xZZ\";");
+        assert_eq!(tokens.errors.len(), 2);
+
+        let error = &tokens.errors[0];
+        assert_eq!(
+            error.kind,
+            LexErrorKind::InvalidEscape(EscapeError::UnknownEscape)
+        );
+        assert_eq!(error.span.slice, "\\q");
+        assert_eq!((error.span.line, error.span.col), (1, 9));
+
+        let error = &tokens.errors[1];
+        assert_eq!(
+            error.kind,
+            LexErrorKind::InvalidEscape(EscapeError::InvalidHexEscape)
+        );
+        assert_eq!(error.span.slice, "\\x");
+        assert_eq!((error.span.line, error.span.col), (2, 2));
+
+        // the string itself is still a valid token
+        assert!(tokens.types.iter().all(|&ty| ty != TokenType::Error));
+    }
+
+    #[test]
+    fn prefixed_literals() {
+        let tokens = lex(FileId::default(), "b\"a\" c\"b\" b'c' \"d\"");
+        assert!(tokens.errors.is_empty());
+        assert_eq!(
+            tokens.types.as_slice(),
+            [
+                TokenType::ByteString,
+                TokenType::CString,
+                TokenType::ByteChar,
+                TokenType::String,
+                TokenType::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn nul_in_c_strings() {
+        let tokens = lex(FileId::default(), "c\"a\\0\n\\x00\\\\0\" b\"\\0\" c\"\0\"");
+        let errors: Vec<_> = (tokens.errors.iter())
+            .map(|e| (e.kind, e.span.slice, e.span.line, e.span.col))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::NulInCString, "\\0", 1, 3),
+                (LexErrorKind::NulInCString, "\\x00", 2, 0),
+                (LexErrorKind::NulInCString, "\0", 2, 17),
+            ]
+        );
+        assert_eq!(tokens.types[0], TokenType::CString);
+    }
+}
+
+#[cfg(test)]
+mod tests_numbers {
+    use crate::{
+        lexer::{lex, LexErrorKind, NumSuffix, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn suffixes() {
+        let tokens = lex(
+            FileId::default(),
+            "42u32 255u8 3.5f64 1_000i64 0xFFu8 0x1f32 7;",
+        );
+        let suffixes: Vec<_> = tokens.iter_spans().map(|span| span.num_suffix()).collect();
+        assert_eq!(
+            suffixes,
+            [
+                Some(NumSuffix::U32),
+                Some(NumSuffix::U8),
+                Some(NumSuffix::F64),
+                Some(NumSuffix::I64),
+                Some(NumSuffix::U8),
+                None,
+                None,
+                None,
+                None,
+            ]
+        );
+        assert_eq!(tokens.span(2).num_digits(), "3.5");
+        assert_eq!(tokens.span(5).num_digits(), "0x1f32");
+        assert!(tokens.errors.is_empty());
+    }
+
+    #[test]
+    fn invalid_suffix() {
+        let tokens = lex(FileId::default(), "a := 12abc;");
+        assert_eq!(tokens.types[3], TokenType::Num);
+        assert_eq!(tokens.span(3).slice, "12abc");
+        assert_eq!(tokens.errors.len(), 1);
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::InvalidNumSuffix);
+        assert_eq!(tokens.errors[0].span.slice, "abc");
+        assert_eq!(tokens.errors[0].span.col, 7);
+    }
+
+    #[test]
+    fn hex_floats() {
+        let tokens = lex(FileId::default(), "0x1.8p3 0x1p-2f32 0xA.max 0x1e3;");
+        let slices: Vec<_> = tokens.iter_spans().map(|span| span.slice).collect();
+        assert_eq!(
+            slices,
+            ["0x1.8p3", "0x1p-2f32", "0xA", ".", "max", "0x1e3", ";", ""]
+        );
+        assert_eq!(tokens.span(1).num_suffix(), Some(NumSuffix::F32));
+        assert!(tokens.errors.is_empty());
+    }
+
+    #[test]
+    fn ranges() {
+        let tokens = lex(FileId::default(), "0..10 1...2 3.5..4.");
+        let slices: Vec<_> = tokens.iter_spans().map(|span| span.slice).collect();
+        assert_eq!(
+            slices,
+            ["0", ".", ".", "10", "1", ".", ".", ".", "2", "3.5", ".", ".", "4.", ""]
+        );
+        assert!(tokens.errors.is_empty());
+    }
+
+    #[test]
+    fn missing_digits() {
+        let tokens = lex(FileId::default(), "0x; 0b_u8 0o 1e+; 0x1p;");
+        let errors: Vec<_> = (tokens.errors.iter())
+            .map(|e| (e.kind, e.span.slice, e.span.col))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::MissingDigits, "0x", 0),
+                (LexErrorKind::MissingDigits, "0b_", 4),
+                (LexErrorKind::MissingDigits, "0o", 10),
+                (LexErrorKind::MissingDigits, "e+", 14),
+                (LexErrorKind::MissingDigits, "p", 21),
+            ]
+        );
+        assert_eq!(tokens.span(2).slice, "0b_u8");
+        assert_eq!(tokens.span(2).num_suffix(), Some(NumSuffix::U8));
+    }
+
+    #[test]
+    fn suffix_at_end_of_file() {
+        let tokens = lex(FileId::default(), "1usize");
+        assert_eq!(tokens.span(0).num_suffix(), Some(NumSuffix::Usize));
+    }
+}
+
+#[cfg(test)]
+mod tests_eof {
+    use crate::{
+        lexer::{lex, LexErrorKind, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn tokens_at_end_of_file() {
+        for code in ["abc", "12", "0x1F", "1.5e3", "// comment", "a   "] {
+            let tokens = lex(FileId::default(), code);
+            assert!(tokens.errors.is_empty(), "{code:?}");
         }
+    }
 
-        let end_ident_addr = input.as_ptr() as usize;
-        let start = start_ident_addr - start_addr;
-        let end = end_ident_addr - start_addr;
+    #[test]
+    fn truncated_numbers() {
+        for code in ["0x", "0o", "0b", "1e", "2.5e-"] {
+            let tokens = lex(FileId::default(), code);
+            let types = tokens.types.as_slice();
+            assert_eq!(types, [TokenType::Error, TokenType::Eof], "{code:?}");
+            assert_eq!(tokens.errors[0].kind, LexErrorKind::UnexpectedEof);
+            assert_eq!(tokens.errors[0].span.slice, code);
+        }
+    }
 
-        tokens.types.add(TokenType::Num);
-        let col = bcode.as_ptr() as usize + start - *line_start;
-        let slice = unsafe { std::str::from_utf8_unchecked(&bcode[start..end]) };
-        tokens.spans.add(TokenSpan::new(slice, *line, col));
-        return input;
+    #[test]
+    fn eof_token() {
+        for code in ["", "a", "a  \n", "\"unfinished", "(\n"] {
+            let tokens = lex(FileId::default(), code);
+            let eof = tokens.span(tokens.types.len() - 1);
+            assert_eq!(tokens.types.as_slice().last(), Some(&TokenType::Eof));
+            assert_eq!((eof.start, eof.slice), (code.len(), ""), "{code:?}");
+        }
+
+        let tokens = lex(FileId::default(), "a\n  ");
+        let eof = tokens.span(1);
+        assert_eq!((eof.line, eof.col), (2, 2));
     }
 
-    // Special recursions (parentheses, etc.)
-    if input[0] == b'(' {
-        let start_str_addr = input.as_ptr() as usize;
+    #[test]
+    fn line_breaks_after_trailing_whitespace() {
+        let tokens = lex(FileId::default(), "a;  \n  b;\t\n\nc;");
+        assert_eq!(tokens.line_breaks.as_slice(), [4, 10, 11]);
+        assert_eq!((tokens.span(2).line, tokens.span(2).col), (2, 2));
+        assert_eq!((tokens.span(4).line, tokens.span(4).col), (4, 0));
+    }
+}
 
-        while !input.is_empty() && input[0] != b')' {
-            input = consume_token(file_name, input, line, line_start, tokens);
+#[cfg(test)]
+mod tests_line_endings {
+    use crate::{
+        lexer::{lex, lex_with_mode, LexMode, StreamLexer},
+        source::FileId,
+    };
+
+    const MIXED: &str = "a;\r\n  b; // b\r\nc;\rd;\n\"x\r\ny\" e; 'r' \"\\q\";\r\n";
+
+    fn positions(code: &str) -> Vec<(String, usize, usize)> {
+        let tokens = lex(FileId::default(), code);
+        let positions = tokens
+            .iter_spans()
+            .map(|s| (s.slice.to_string(), s.line, s.col));
+        positions.collect()
+    }
+
+    #[test]
+    fn lines_and_columns() {
+        let positions = positions(MIXED);
+        let expected = [
+            ("a", 1, 0),
+            (";", 1, 1),
+            ("b", 2, 2),
+            (";", 2, 3),
+            ("c", 3, 0),
+            (";", 3, 1),
+            ("d", 4, 0),
+            (";", 4, 1),
+            ("\"x\r\ny\"", 5, 0),
+            ("e", 6, 3),
+            (";", 6, 4),
+            ("'r'", 6, 6),
+            ("\"\\q\"", 6, 10),
+            (";", 6, 14),
+            ("", 7, 0),
+        ];
+
+        let expected: Vec<_> = expected.map(|(s, l, c)| (s.to_string(), l, c)).into();
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn line_break_table() {
+        let tokens = lex(FileId::default(), MIXED);
+        // `\r\n` line breaks are recorded on the `\n`
+        assert_eq!(tokens.line_breaks.as_slice(), [3, 14, 17, 20, 24, 41]);
+    }
+
+    #[test]
+    fn comments_exclude_carriage_returns() {
+        let tokens = lex_with_mode(
+            FileId::default(),
+            "/// doc\r\na // c\r\n",
+            LexMode::PreserveTrivia,
+        );
+        assert_eq!(tokens.span(0).slice, "/// doc");
+        assert_eq!(tokens.trivia[2].span.slice, "// c");
+        assert_eq!(tokens.trivia[3].span.slice, "\r\n");
+    }
+
+    #[test]
+    fn escape_errors_after_line_breaks() {
+        let tokens = lex(FileId::default(), "\"a\r\n \\q\r \\w\"");
+        let errors: Vec<_> = tokens
+            .errors
+            .iter()
+            .map(|e| (e.span.line, e.span.col))
+            .collect();
+        assert_eq!(errors, [(2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn streamed_line_breaks() {
+        let expected = positions(MIXED);
+        let mut lexer = StreamLexer::with_chunk_size(FileId::default(), MIXED.as_bytes(), 1);
+
+        let mut streamed = Vec::new();
+        while let Some(batch) = lexer.next_batch().unwrap() {
+            let positions = batch
+                .iter_spans()
+                .map(|s| (s.slice.to_string(), s.line, s.col));
+            streamed.extend(positions);
         }
-        if input.is_empty() {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unclosed parenthesis");
+        assert_eq!(streamed, expected);
+    }
+}
+
+#[cfg(test)]
+mod tests_display_col {
+    use crate::{lexer::lex, source::FileId};
+
+    #[test]
+    fn counts_characters() {
+        let tokens = lex(FileId::default(), "\"ඞඞ\" x;\nà := 'é' + y;");
+        let cols: Vec<_> = tokens
+            .iter_spans()
+            .map(|s| (s.col, tokens.display_col(&s)))
+            .collect();
+        assert_eq!(
+            cols,
+            [
+                (0, 0),
+                (9, 5),
+                (10, 6),
+                (0, 0),
+                (3, 2),
+                (4, 3),
+                (6, 5),
+                (11, 9),
+                (13, 11),
+                (14, 12),
+                (15, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn after_line_breaks() {
+        let tokens = lex(FileId::default(), "ඞ\r\nඞ ඞ\rඞ §");
+        let error = &tokens.errors[tokens.errors.len() - 1];
+        assert_eq!((error.span.line, error.span.col), (3, 4));
+        assert_eq!(tokens.display_col(&error.span), 2);
+    }
+}
+
+#[cfg(test)]
+mod tests_interpolation {
+    use crate::{
+        lexer::{lex, lex_with_options, LexErrorKind, LexOptions, TokenType},
+        source::FileId,
+    };
+
+    fn types_and_slices(code: &str) -> Vec<(TokenType, String)> {
+        let tokens = lex(FileId::default(), code);
+        let types_and_slices = (tokens.types.iter().zip(tokens.iter_spans()))
+            .map(|(&ty, span)| (ty, span.slice.to_string()));
+        types_and_slices.collect()
+    }
+
+    #[test]
+    fn nested_interpolated_strings() {
+        let expected = [
+            (TokenType::StringInterpBeg, "$\"outer {"),
+            (TokenType::StringInterpBeg, "$\"inner {"),
+            (TokenType::Ident, "x"),
+            (TokenType::StringInterpEnd, "}\""),
+            (TokenType::StringInterpEnd, "} end\""),
+            (TokenType::Eof, ""),
+        ];
+
+        let expected: Vec<_> = expected.map(|(ty, s)| (ty, s.to_string())).into();
+        assert_eq!(
+            types_and_slices("$\"outer {$\"inner {x}\"} end\""),
+            expected
+        );
+    }
+
+    #[test]
+    fn braces_inside_interpolations() {
+        let expected = [
+            (TokenType::StringInterpBeg, "$\"a {"),
+            (TokenType::LBrace, "{"),
+            (TokenType::String, "\"}\""),
+            (TokenType::RBrace, "}"),
+            (TokenType::StringInterpMid, "} b {"),
+            (TokenType::Ident, "y"),
+            (TokenType::StringInterpEnd, "}\""),
+            (TokenType::Eof, ""),
+        ];
+
+        let expected: Vec<_> = expected.map(|(ty, s)| (ty, s.to_string())).into();
+        assert_eq!(types_and_slices("$\"a { {\"}\"} } b {y}\""), expected);
+    }
+
+    #[test]
+    fn unbalanced_delimiters() {
+        // the brace closes the interpolation, the parenthesis is left open
+        let tokens = lex(FileId::default(), "$\"{ f(a } b\" c");
+        assert_eq!(tokens.types[4], TokenType::StringInterpEnd);
+        assert_eq!(tokens.types[5], TokenType::Ident);
+        assert_eq!(
+            (tokens.errors.iter().map(|e| e.to_string())).collect::<Vec<_>>(),
+            ["1:8: Unclosed `(` opened at 1:5"]
+        );
+
+        // so does the only brace that isn't closed inside of it
+        let tokens = lex(FileId::default(), "$\"{ {a} ] }\"");
+        assert_eq!(tokens.types[4], TokenType::RBracket);
+        assert_eq!(tokens.types[5], TokenType::StringInterpEnd);
+        assert_eq!(tokens.errors[0].to_string(), "1:8: Unexpected `]`");
+
+        // braces left open at the end of the file
+        let tokens = lex(FileId::default(), "$\"{ {a");
+        let errors: Vec<_> = (tokens.errors.iter())
+            .map(|e| (e.to_string(), e.related.map(|r| r.start)))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                ("1:6: Unfinished interpolated string".to_string(), None),
+                ("1:6: Unclosed `{` opened at 1:4".to_string(), Some(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // nesting is only limited by the options, not by the call stack
+        let options = LexOptions {
+            max_nesting_depth: usize::MAX,
+            ..LexOptions::default()
+        };
+        let depth = 100_000;
+        let code = "$\"{#[".repeat(depth) + "a" + &"]}\"".repeat(depth);
+        let tokens = lex_with_options(FileId::default(), &code, options);
+        assert!(tokens.errors.is_empty());
+        assert_eq!(tokens.types.len(), depth * 4 + 2);
+
+        let code = "$\"{".repeat(depth);
+        let tokens = lex(FileId::default(), &code);
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::NestingTooDeep);
+    }
+}
+
+#[cfg(test)]
+mod tests_format_spec {
+    use crate::{
+        lexer::{lex, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn format_specs() {
+        let tokens = lex(FileId::default(), "$\"{x:08.3} {f(a: 1)} {a::b:>5}\"");
+        let specs: Vec<_> = (tokens.types.iter().zip(tokens.iter_spans()))
+            .filter(|(&ty, _)| ty == TokenType::FormatSpec)
+            .map(|(_, span)| (span.slice, span.col))
+            .collect();
+        assert_eq!(specs, [(":08.3", 4), (":>5", 26)]);
+
+        let colons = tokens.types.iter().filter(|&&ty| ty == TokenType::Colon);
+        assert_eq!(colons.count(), 1);
+        assert!(tokens.types.iter().any(|&ty| ty == TokenType::DoubleColon));
+    }
+
+    #[test]
+    fn empty_spec() {
+        let tokens = lex(FileId::default(), "$\"{x:}\"");
+        assert_eq!(tokens.types[2], TokenType::FormatSpec);
+        assert_eq!(tokens.span(2).slice, ":");
+        assert_eq!(tokens.types[3], TokenType::StringInterpEnd);
+    }
+}
+
+#[cfg(test)]
+mod tests_chars {
+    use crate::{
+        lexer::{lex, EscapeError, LexErrorKind, TokenType},
+        source::FileId,
+    };
+
+    fn error_kinds(code: &str) -> Vec<LexErrorKind> {
+        lex(FileId::default(), code)
+            .errors
+            .iter()
+            .map(|e| e.kind)
+            .collect()
+    }
+
+    #[test]
+    fn single_characters() {
+        assert_eq!(error_kinds("'a' 'ඞ' '\\n' '\\u{1F4A9}' b'a' b'\\xFF'"), []);
+    }
+
+    #[test]
+    fn wrong_character_counts() {
+        let tokens = lex(FileId::default(), "'' 'abc' b'é'");
+        let errors: Vec<_> = tokens
+            .errors
+            .iter()
+            .map(|e| (e.kind, e.span.slice))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::EmptyChar, "''"),
+                (LexErrorKind::MultipleCharsInChar, "'abc'"),
+                (LexErrorKind::NonAsciiByteChar, "b'é'"),
+            ]
+        );
+
+        // they are still char tokens
+        assert_eq!(
+            tokens.types.as_slice(),
+            [
+                TokenType::Char,
+                TokenType::Char,
+                TokenType::ByteChar,
+                TokenType::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_escapes_are_reported_once() {
+        assert_eq!(
+            error_kinds("'\\q'"),
+            [LexErrorKind::InvalidEscape(EscapeError::UnknownEscape)]
+        );
+    }
+
+    #[test]
+    fn line_breaks_end_chars() {
+        let tokens = lex(FileId::default(), "'a\nb;");
+        assert_eq!(tokens.types[0], TokenType::Error);
+        assert_eq!(tokens.span(0).slice, "'a");
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::UnfinishedChar);
+        assert_eq!((tokens.span(1).slice, tokens.span(1).line), ("b", 2));
+    }
+}
+
+#[cfg(test)]
+mod tests_options {
+    use crate::{
+        arena::{HeapAlloc, TestAlloc, VmAlloc, MIB, RESERVES_ADDR_SPACE},
+        lexer::{
+            lex, lex_in, lex_parallel, lex_with_options, InvalidOptions, LexErrorKind, LexOptions,
+            Reservation, Span, TokenType,
+        },
+        source::FileId,
+    };
+
+    fn types(code: &str, options: LexOptions) -> Vec<TokenType> {
+        let tokens = lex_with_options(FileId::default(), code, options);
+        let types = tokens.types.iter().copied().collect();
+        types
+    }
+
+    #[test]
+    fn tab_width() {
+        let options = LexOptions {
+            tab_width: 4,
+            ..LexOptions::default()
+        };
+        let tokens = lex_with_options(FileId::default(), "\ta\t\tb  \tc", options);
+        let cols: Vec<_> = tokens
+            .iter_spans()
+            .map(|s| tokens.display_col(&s))
+            .collect();
+        assert_eq!(cols, [4, 12, 16, 17]);
+    }
+
+    #[test]
+    fn max_nesting_depth() {
+        let options = LexOptions {
+            max_nesting_depth: 1,
+            ..LexOptions::default()
+        };
+        let code = "$\"a {$\"b {\n{c}}\"} d {e}\"";
+        let tokens = lex_with_options(FileId::default(), code, options);
+
+        assert_eq!(tokens.errors.len(), 1);
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::NestingTooDeep);
+        assert_eq!(tokens.errors[0].span.slice, "\n{c}");
+        assert_eq!(tokens.line_breaks.len(), 1);
+
+        let expected = [
+            TokenType::StringInterpBeg,
+            TokenType::StringInterpBeg,
+            TokenType::Error,
+            TokenType::StringInterpEnd,
+            TokenType::StringInterpMid,
+            TokenType::Ident,
+            TokenType::StringInterpEnd,
+            TokenType::Eof,
+        ];
+        assert_eq!(types(code, options), expected);
+    }
+
+    #[test]
+    fn literal_prefixes() {
+        let options = LexOptions {
+            allow_literal_prefixes: false,
+            ..LexOptions::default()
+        };
+        assert_eq!(
+            types("b\"a\" c\"b\" b'c'", options),
+            [
+                TokenType::Ident,
+                TokenType::String,
+                TokenType::Ident,
+                TokenType::String,
+                TokenType::Ident,
+                TokenType::Char,
+                TokenType::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn max_code_len() {
+        let options = LexOptions {
+            max_code_len: 3,
+            ..LexOptions::default()
+        };
+        assert!(lex_with_options(FileId::default(), "a b", options)
+            .errors
+            .is_empty());
+
+        let tokens = lex_parallel(FileId::default(), "a b\nc", options);
+        assert_eq!(tokens.types.as_slice(), [TokenType::Eof]);
+        assert_eq!(tokens.errors.len(), 1);
+        assert_eq!(tokens.errors[0].to_string(), "1:0: Input is too large");
+    }
+
+    #[test]
+    fn huge_pages() {
+        let code = include_str!("../Cඞඞ.sus");
+        let options = LexOptions {
+            huge_pages: true,
+            ..LexOptions::default()
+        };
+        let tokens = lex_with_options(FileId::default(), code, options);
+        assert_eq!(
+            tokens.types.as_slice(),
+            lex(FileId::default(), code).types.as_slice()
+        );
+    }
+
+    #[test]
+    fn allocators() {
+        let code = include_str!("../Cඞඞ.sus");
+        let options = LexOptions::default();
+        let expected = lex(FileId::default(), code);
+
+        let on_heap = lex_in(FileId::default(), code, options, &HeapAlloc);
+        let small = lex_in(FileId::default(), code, options, &TestAlloc);
+        for tokens in [&on_heap, &small] {
+            assert!(tokens.types.is_on_heap());
+            assert_eq!(tokens.types.as_slice(), expected.types.as_slice());
+            assert_eq!(tokens.spans.as_slice(), expected.spans.as_slice());
+            assert_eq!(tokens.errors.len(), expected.errors.len());
         }
-    } else if input[0] == b'[' {
-        let start_str_addr = input.as_ptr() as usize;
+        // unless arenas can't be reserved
+        let tokens = lex_in(FileId::default(), code, options, &VmAlloc::default());
+        assert_eq!(tokens.types.is_on_heap(), !RESERVES_ADDR_SPACE);
+    }
+
+    #[test]
+    fn max_token_len() {
+        let options = LexOptions {
+            max_token_len: 4,
+            reservation: Reservation::Fixed(MIB),
+            ..LexOptions::default()
+        };
+        let tokens = lex_with_options(FileId::default(), "abcd abcde $\"{x} long\"", options);
+        let errors: Vec<_> = tokens
+            .errors
+            .iter()
+            .map(|e| (e.kind, e.span.slice))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::TokenTooLong, "abcde"),
+                (LexErrorKind::TokenTooLong, "} long\""),
+            ]
+        );
+    }
 
-        while !input.is_empty() && input[0] != b']' {
-            input = consume_token(file_name, input, line, line_start, tokens);
+    #[test]
+    fn reservations() {
+        let code = "a b ".repeat(10_000);
+        let tokens = lex(FileId::default(), &code);
+        assert_eq!(tokens.types.len(), 20_001);
+        // the arrays were reserved for the worst case, so they never had to grow
+        let reserved = Reservation::FromCodeLen.size_for::<Span>(code.len() + 1);
+        assert!(tokens.spans.reserved_bytes() < 2 * reserved);
+        assert!(lex(FileId::default(), "a").spans.reserved_bytes() < reserved);
+
+        let options = LexOptions {
+            reservation: Reservation::Fixed(MIB),
+            ..LexOptions::default()
+        };
+        assert_eq!(options.validate(), Ok(()));
+        let tokens = lex_with_options(FileId::default(), &code, options);
+        assert_eq!(tokens.types.len(), 20_001);
+        // out of range reservations are rejected, but still lex
+        for (reservation, error) in [
+            (
+                Reservation::Fixed(0),
+                InvalidOptions::ReservationTooSmall(0),
+            ),
+            (
+                Reservation::Fixed(usize::MAX),
+                InvalidOptions::ReservationTooLarge(usize::MAX),
+            ),
+        ] {
+            let options = LexOptions {
+                reservation,
+                ..LexOptions::default()
+            };
+            assert_eq!(options.validate(), Err(error));
+            let tokens = lex_with_options(FileId::default(), "a b", options);
+            assert_eq!(tokens.types.len(), 3);
         }
-        if input.is_empty() {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unclosed bracket");
+    }
+}
+
+#[cfg(test)]
+mod tests_punctuation {
+    use crate::{
+        lexer::{lex, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn punctuation() {
+        let tokens = lex(
+            FileId::default(),
+            "a::b => c? #d @ e :: f := g => h == i >= j",
+        );
+        let types: Vec<_> = tokens.types.iter().copied().collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Ident,
+                TokenType::DoubleColon,
+                TokenType::Ident,
+                TokenType::FatArrow,
+                TokenType::Ident,
+                TokenType::Question,
+                TokenType::Hash,
+                TokenType::Ident,
+                TokenType::At,
+                TokenType::Ident,
+                TokenType::DoubleColon,
+                TokenType::Ident,
+                TokenType::Colon,
+                TokenType::Equal,
+                TokenType::Ident,
+                TokenType::FatArrow,
+                TokenType::Ident,
+                TokenType::Equals,
+                TokenType::Ident,
+                TokenType::GreaterEqual,
+                TokenType::Ident,
+                TokenType::Eof,
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_attributes {
+    use crate::{
+        lexer::{lex, lex_with_options, Delimiter, LexErrorKind, LexOptions, TokenType},
+        source::FileId,
+    };
+
+    fn types_and_slices(code: &str) -> Vec<(TokenType, String)> {
+        let tokens = lex(FileId::default(), code);
+        let types_and_slices = (tokens.types.iter().zip(tokens.iter_spans()))
+            .map(|(&ty, span)| (ty, span.slice.to_string()));
+        types_and_slices.collect()
+    }
+
+    #[test]
+    fn attributes() {
+        let expected = [
+            (TokenType::InnerAttrStart, "#!["),
+            (TokenType::Ident, "no_std"),
+            (TokenType::AttrEnd, "]"),
+            (TokenType::AttrStart, "#["),
+            (TokenType::Ident, "repr"),
+            (TokenType::LParens, "("),
+            (TokenType::Ident, "packed"),
+            (TokenType::RParens, ")"),
+            (TokenType::AttrEnd, "]"),
+            (TokenType::AttrStart, "#["),
+            (TokenType::Ident, "a"),
+            (TokenType::LBracket, "["),
+            (TokenType::Num, "0"),
+            (TokenType::RBracket, "]"),
+            (TokenType::AttrEnd, "]"),
+            (TokenType::Ident, "S"),
+            (TokenType::Eof, ""),
+        ];
+
+        let expected: Vec<_> = expected.map(|(ty, s)| (ty, s.to_string())).into();
+        let code = "#![no_std]\n#[repr(packed)] #[ a[0] ]\nS";
+        assert_eq!(types_and_slices(code), expected);
+        assert!(lex(FileId::default(), code).errors.is_empty());
+    }
+
+    #[test]
+    fn invalid_attributes() {
+        let tokens = lex(FileId::default(), "#[] a # b #[c(d]");
+        let errors: Vec<_> = tokens
+            .errors
+            .iter()
+            .map(|e| (e.kind, e.span.slice))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::EmptyAttribute, "#[]"),
+                (LexErrorKind::UnclosedAttribute, "#["),
+                (LexErrorKind::UnexpectedDelimiter(Delimiter::Bracket), "]"),
+                (LexErrorKind::UnclosedDelimiter(Delimiter::Parens), ""),
+            ]
+        );
+        assert_eq!(tokens.types[3], TokenType::Hash);
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let options = LexOptions {
+            max_nesting_depth: 1,
+            ..LexOptions::default()
+        };
+        let tokens = lex_with_options(FileId::default(), "#[a(#[b])]", options);
+        assert_eq!(tokens.errors.len(), 1);
+        assert_eq!(tokens.errors[0].kind, LexErrorKind::NestingTooDeep);
+        assert_eq!(tokens.types[3], TokenType::Hash);
+        assert_eq!(tokens.types[8], TokenType::AttrEnd);
+    }
+}
+
+#[cfg(test)]
+mod tests_offsets {
+    use std::mem;
+
+    use crate::{
+        lexer::{lex, Span, TokenType},
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../Cඞඞ.sus");
+
+    #[test]
+    fn offsets_match_slices() {
+        let tokens = lex(FileId::default(), SAMPLE);
+        let spans = (tokens.iter_spans()).chain(tokens.errors.iter().map(|e| e.span));
+        for span in spans {
+            assert_eq!(&SAMPLE[span.range()], span.slice);
+            assert_eq!(span.end() - span.start, span.slice.len());
         }
-    } else if input[0] == b'{' {
-        let start_str_addr = input.as_ptr() as usize;
+    }
 
-        while !input.is_empty() && input[0] != b'}' {
-            input = consume_token(file_name, input, line, line_start, tokens);
+    #[test]
+    fn line_col_of_offsets() {
+        let tokens = lex(FileId::default(), SAMPLE);
+        for span in tokens.iter_spans() {
+            assert_eq!(tokens.line_col(span.start), (span.line, span.col));
         }
-        if input.is_empty() {
-            let col = start_str_addr + 1 - *line_start;
-            panic!("{file_name}:{line}:{col}: Unclosed brace");
+
+        let tokens = lex(FileId::default(), "a\r\nb\nc");
+        assert_eq!(tokens.line_col(0), (1, 0));
+        assert_eq!(tokens.line_col(1), (1, 1));
+        assert_eq!(tokens.line_col(3), (2, 0));
+        assert_eq!(tokens.line_col(5), (3, 0));
+    }
+
+    #[test]
+    fn dense_spans() {
+        assert_eq!(mem::size_of::<Span>(), 8);
+        assert_eq!(mem::size_of::<TokenType>(), 1);
+
+        // resolving spans one by one gives the same as iterating over them
+        let tokens = lex(FileId::default(), SAMPLE);
+        for (i, span) in tokens.iter_spans().enumerate() {
+            let resolved = tokens.span(i);
+            assert_eq!((resolved.line, resolved.col), (span.line, span.col));
+            assert_eq!(resolved.slice, span.slice);
         }
+
+        // the error token of an unfinished string comes after the ones inside of it
+        let tokens = lex(FileId::default(), "$\"{a}\nb {c}\n");
+        let positions: Vec<_> = (tokens.iter_spans()).map(|s| (s.line, s.col)).collect();
+        let expected: Vec<_> = (0..tokens.spans.len())
+            .map(|i| (tokens.span(i).line, tokens.span(i).col))
+            .collect();
+        assert_eq!(positions, expected);
     }
+}
 
-    let start_str_addr = input.as_ptr() as usize;
-    let col = start_str_addr + 1 - *line_start;
-    panic!("{file_name}:{line}:{col}: Cannot parse token");
+#[cfg(test)]
+mod tests_raw_idents {
+    use crate::{
+        lexer::{lex, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn raw_keywords_are_idents() {
+        let tokens = lex(FileId::default(), "r#enum r#loop r#x enum r # y");
+        let types: Vec<_> = tokens.types.iter().copied().collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Ident,
+                TokenType::Enum,
+                TokenType::Ident,
+                TokenType::Hash,
+                TokenType::Ident,
+                TokenType::Eof,
+            ]
+        );
+
+        let names: Vec<_> = (tokens.iter_spans())
+            .map(|s| (s.slice, s.ident_name()))
+            .collect();
+        assert_eq!(names[0], ("r#enum", "enum"));
+        assert_eq!(names[1], ("r#loop", "loop"));
+        assert_eq!(names[2], ("r#x", "x"));
+        assert_eq!(names[4], ("r", "r"));
+        assert!(tokens.errors.is_empty());
+    }
+
+    #[test]
+    fn lone_raw_prefix() {
+        let tokens = lex(FileId::default(), "r#1 r#");
+        let types: Vec<_> = tokens.types.iter().copied().collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Ident,
+                TokenType::Hash,
+                TokenType::Num,
+                TokenType::Ident,
+                TokenType::Hash,
+                TokenType::Eof,
+            ]
+        );
+    }
 }
@@ -0,0 +1,402 @@
+//! On-disk cache of tokens, to skip lexing files that didn't change since the last run.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    mem,
+};
+
+use super::{LexMode, LexOptions, Span, TokenType, Tokens};
+use crate::{
+    arena::{ArenaVec, Snapshot, SNAPSHOT_ALIGN, SNAPSHOT_MAP_THRESHOLD},
+    source::FileId,
+};
+
+/// Magic bytes at the start of a cache.
+pub const CACHE_MAGIC: &[u8; 4] = b"SUSC";
+
+/// Version of the cache format, bumped whenever its layout or the lexer output changes.
+pub const CACHE_VERSION: u32 = 7;
+
+/// Written in the native byte order, to tell caches from machines with another one.
+const BYTE_ORDER_MARK: u32 = 0x0102_0304;
+
+/// Magic, version, byte order mark and size of `usize`, then the [`CacheKey`]
+/// and the offset and length of each array as `u64`s.
+const HEADER_LEN: usize = 16 + (7 + 3 * 2) * 8;
+
+// SAFETY: two `u32`s, without padding
+unsafe impl Snapshot for Span {}
+
+// SAFETY: a `u8` discriminant that is checked
+unsafe impl Snapshot for TokenType {
+    #[inline]
+    fn is_valid(bytes: &[u8]) -> bool {
+        bytes[0] <= TokenType::Eof as u8
+    }
+}
+
+/// What the cached tokens depend on, besides the code.
+#[derive(PartialEq, Eq)]
+struct CacheKey {
+    mode: u64,
+    allow_literal_prefixes: u64,
+    max_nesting_depth: u64,
+    max_token_len: u64,
+    max_code_len: u64,
+    code_len: u64,
+    code_hash: u64,
+}
+
+impl CacheKey {
+    fn new(code: &str, options: &LexOptions) -> Self {
+        Self {
+            mode: options.mode as u64,
+            allow_literal_prefixes: options.allow_literal_prefixes as u64,
+            max_nesting_depth: options.max_nesting_depth as u64,
+            max_token_len: options.max_token_len as u64,
+            max_code_len: options.max_code_len as u64,
+            code_len: code.len() as u64,
+            code_hash: hash_code(code.as_bytes()),
+        }
+    }
+
+    fn fields(&self) -> [u64; 7] {
+        [
+            self.mode,
+            self.allow_literal_prefixes,
+            self.max_nesting_depth,
+            self.max_token_len,
+            self.max_code_len,
+            self.code_len,
+            self.code_hash,
+        ]
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Whether the tokens can be cached: when they have errors or trivia, lexing the code
+    /// again is what reports the errors, and trivia isn't worth it.
+    pub fn is_cacheable(&self) -> bool {
+        self.errors.is_empty() && self.options.mode == LexMode::Normal
+    }
+
+    /// Writes the line breaks, spans and types to a cache that [`Tokens::load_cache`] loads.
+    ///
+    /// Arrays are written as their bytes, large ones at offsets aligned to [`SNAPSHOT_ALIGN`]
+    /// so that they can be mapped back. The cache is thus only valid on machines with
+    /// the same byte order and size of `usize`.
+    ///
+    /// Returns an [`io::ErrorKind::InvalidInput`] error if the tokens aren't cacheable.
+    pub fn write_cache(&self, out: &mut impl Write) -> io::Result<()> {
+        if !self.is_cacheable() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Tokens with errors or trivia can't be cached",
+            ));
+        }
+
+        let arrays = [
+            self.line_breaks.used_bytes(),
+            self.spans.used_bytes(),
+            self.types.used_bytes(),
+        ];
+        let mut offsets = [0; 3];
+        let mut end = HEADER_LEN;
+        for (offset, size) in offsets.iter_mut().zip(arrays) {
+            *offset = end.next_multiple_of(array_align(size));
+            end = *offset + size;
+        }
+
+        out.write_all(CACHE_MAGIC)?;
+        out.write_all(&CACHE_VERSION.to_ne_bytes())?;
+        out.write_all(&BYTE_ORDER_MARK.to_ne_bytes())?;
+        out.write_all(&(mem::size_of::<usize>() as u32).to_ne_bytes())?;
+        for n in CacheKey::new(self.code, &self.options).fields() {
+            out.write_all(&n.to_ne_bytes())?;
+        }
+        let lengths = [self.line_breaks.len(), self.spans.len(), self.types.len()];
+        for (offset, len) in offsets.into_iter().zip(lengths) {
+            out.write_all(&(offset as u64).to_ne_bytes())?;
+            out.write_all(&(len as u64).to_ne_bytes())?;
+        }
+
+        let mut pos = HEADER_LEN;
+        for (i, (offset, size)) in offsets.into_iter().zip(arrays).enumerate() {
+            out.write_all(&vec![0; offset - pos])?;
+            match i {
+                0 => self.line_breaks.write_snapshot(out)?,
+                1 => self.spans.write_snapshot(out)?,
+                _ => self.types.write_snapshot(out)?,
+            }
+            pos = offset + size;
+        }
+        Ok(())
+    }
+
+    /// Loads the tokens of the code from a cache written by [`Tokens::write_cache`],
+    /// as if the code was lexed with the given options.
+    ///
+    /// Returns `None` if the cache is for other code or options, or from another version,
+    /// and an [`io::ErrorKind::InvalidData`] error if a token doesn't fit in the code.
+    pub fn load_cache(
+        file: FileId,
+        code: &'a str,
+        options: LexOptions,
+        cache: &File,
+    ) -> io::Result<Option<Tokens<'a>>> {
+        // SAFETY: nothing is mapped
+        unsafe { Self::load_cache_impl(file, code, options, cache, false) }
+    }
+
+    /// Loads tokens like [`Tokens::load_cache`], mapping large arrays from the cache file
+    /// instead of reading them, see [`ArenaVec::map_snapshot`].
+    ///
+    /// # Safety
+    ///
+    /// The cache file must not be written to nor truncated while the tokens are around,
+    /// by this process or any other.
+    pub unsafe fn map_cache(
+        file: FileId,
+        code: &'a str,
+        options: LexOptions,
+        cache: &File,
+    ) -> io::Result<Option<Tokens<'a>>> {
+        Self::load_cache_impl(file, code, options, cache, true)
+    }
+
+    /// See [`Tokens::map_cache`] for the safety contract if `map` is true.
+    unsafe fn load_cache_impl(
+        file: FileId,
+        code: &'a str,
+        options: LexOptions,
+        cache: &File,
+        map: bool,
+    ) -> io::Result<Option<Tokens<'a>>> {
+        let mut header = [0; HEADER_LEN];
+        (&*cache).read_exact(&mut header)?;
+        if &header[..4] != CACHE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a token cache",
+            ));
+        }
+
+        let u32_at = |i: usize| u32::from_ne_bytes(header[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_ne_bytes(header[i..i + 8].try_into().unwrap());
+        let layout = (u32_at(4), u32_at(8), u32_at(12));
+        let native_layout = (
+            CACHE_VERSION,
+            BYTE_ORDER_MARK,
+            mem::size_of::<usize>() as u32,
+        );
+        if layout != native_layout {
+            return Ok(None);
+        }
+
+        let key = CacheKey::new(code, &options);
+        if (0..7).map(|i| u64_at(16 + i * 8)).ne(key.fields()) {
+            return Ok(None);
+        }
+
+        let array = |i: usize| {
+            let (offset, len) = (u64_at(72 + i * 16), u64_at(80 + i * 16));
+            let len = usize::try_from(len).map_err(|_| io::ErrorKind::OutOfMemory)?;
+            io::Result::Ok((offset, len))
+        };
+        let (offset, len) = array(0)?;
+        let line_breaks = ArenaVec::load_snapshot(cache, offset, len, map)?;
+        let (offset, len) = array(1)?;
+        let spans = ArenaVec::<Span>::load_snapshot(cache, offset, len, map)?;
+        let (offset, len) = array(2)?;
+        let types = ArenaVec::<TokenType>::load_snapshot(cache, offset, len, map)?;
+
+        let mut prev_end = 0;
+        let spans_in_code = spans.iter().all(|span| {
+            let (start, end) = (span.start as usize, span.end());
+            let in_code = prev_end <= start && end <= code.len();
+            prev_end = end;
+            in_code && code.is_char_boundary(start) && code.is_char_boundary(end)
+        });
+        if types.len() != spans.len() || types.last() != Some(&TokenType::Eof) || !spans_in_code {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Corrupted token cache",
+            ));
+        }
+
+        let mut tokens = Tokens::empty(file, code, options);
+        tokens.line_breaks = line_breaks;
+        tokens.spans = spans;
+        tokens.types = types;
+        Ok(Some(tokens))
+    }
+}
+
+/// Alignment of an array of the given size in a cache.
+fn array_align(size: usize) -> usize {
+    match size >= SNAPSHOT_MAP_THRESHOLD {
+        true => SNAPSHOT_ALIGN,
+        false => mem::align_of::<u64>(),
+    }
+}
+
+/// Hash of the code, to tell whether a cache is for the same code.
+///
+/// It goes through 8 bytes at a time, so that it takes little time compared to lexing.
+/// It isn't meant to resist collisions on purpose, only to catch edited files.
+fn hash_code(code: &[u8]) -> u64 {
+    const K: u64 = 0x517c_c1b7_2722_0a95;
+
+    let mut words = code.chunks_exact(8);
+    let mut hash = code.len() as u64;
+    for word in &mut words {
+        let word = u64::from_le_bytes(word.try_into().unwrap());
+        hash = (hash.rotate_left(5) ^ word).wrapping_mul(K);
+    }
+    for &byte in words.remainder() {
+        hash = (hash.rotate_left(5) ^ byte as u64).wrapping_mul(K);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests_cache {
+    use std::{
+        env,
+        fs::{self, File},
+        io::{self, Write},
+        path::PathBuf,
+        process,
+    };
+
+    use crate::{
+        lexer::{
+            cache::{CACHE_MAGIC, HEADER_LEN},
+            lex, lex_with_options, LexMode, LexOptions, TokenType, Tokens,
+        },
+        source::FileId,
+    };
+
+    fn cache_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("csussus-cache-{}-{name}", process::id()))
+    }
+
+    fn write_cache(name: &str, tokens: &Tokens) -> PathBuf {
+        let path = cache_path(name);
+        let mut out = Vec::new();
+        tokens.write_cache(&mut out).unwrap();
+        fs::write(&path, out).unwrap();
+        path
+    }
+
+    fn load(path: &PathBuf, code: &str, options: LexOptions) -> io::Result<Option<Vec<TokenType>>> {
+        let cache = File::open(path)?;
+        let tokens = Tokens::load_cache(FileId::default(), code, options, &cache)?;
+        Ok(tokens.map(|tokens| tokens.types.as_slice().to_vec()))
+    }
+
+    #[test]
+    fn round_trip() {
+        // large enough for the spans and types to be mapped by `map_cache`
+        let small = "main :: fn() {\n    s := $\"{a:x}\";\r\n}\n";
+        let large = small.repeat(100_000);
+
+        for (name, code) in [("small", small), ("large", &large[..])] {
+            let tokens = lex(FileId::default(), code);
+            let path = write_cache(name, &tokens);
+
+            let options = LexOptions::default();
+            let read = load(&path, code, options).unwrap().unwrap();
+            assert_eq!(read, tokens.types.as_slice());
+
+            let cache = File::open(&path).unwrap();
+            // SAFETY: the cache is only removed once the tokens are dropped
+            let cached = unsafe { Tokens::map_cache(FileId::default(), code, options, &cache) };
+            let cached = cached.unwrap().unwrap();
+            assert_eq!(cached.line_breaks.as_slice(), tokens.line_breaks.as_slice());
+            assert_eq!(cached.spans.as_slice(), tokens.spans.as_slice());
+            assert_eq!(cached.types.as_slice(), tokens.types.as_slice());
+            assert!(cached.check_invariants().is_empty());
+
+            // mapped tokens are copied on write, the cache stays the same
+            let mut cached = cached;
+            cached.types[0] = TokenType::Error;
+            cached.types.add(TokenType::Eof);
+            drop(cached);
+            assert!(load(&path, code, options).unwrap().is_some());
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn stale() {
+        let tokens = lex(FileId::default(), "a := b'c';");
+        let path = write_cache("stale", &tokens);
+        let options = LexOptions::default();
+
+        assert_eq!(load(&path, "a := b'd';", options).unwrap(), None);
+        let no_prefixes = LexOptions {
+            allow_literal_prefixes: false,
+            ..options
+        };
+        assert_eq!(load(&path, "a := b'c';", no_prefixes).unwrap(), None);
+        assert!(load(&path, "a := b'c';", options).unwrap().is_some());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn not_cacheable() {
+        let tokens = lex(FileId::default(), "a $ b");
+        let error = tokens.write_cache(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let tokens = lex_with_options(
+            FileId::default(),
+            "a b",
+            LexOptions {
+                mode: LexMode::PreserveTrivia,
+                ..LexOptions::default()
+            },
+        );
+        assert!(!tokens.is_cacheable());
+    }
+
+    #[test]
+    fn corrupted() {
+        let code = "a b c";
+        let mut out = Vec::new();
+        lex(FileId::default(), code).write_cache(&mut out).unwrap();
+        let options = LexOptions::default();
+        let path = cache_path("corrupted");
+        assert_eq!(&out[..4], CACHE_MAGIC);
+
+        // the last byte is the type of the EOF token
+        let mut bytes = out.clone();
+        *bytes.last_mut().unwrap() = 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let error = load(&path, code, options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        fs::write(&path, &out[..out.len() - 1]).unwrap();
+        let error = load(&path, code, options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        // the span of the string ends in the middle of `ඞ`
+        let code = "a \"ඞ\"";
+        let mut out = Vec::new();
+        lex(FileId::default(), code).write_cache(&mut out).unwrap();
+        let span = [2u32.to_ne_bytes(), 5u32.to_ne_bytes()].concat();
+        let str_len = out.windows(8).position(|bytes| bytes == span).unwrap() + 4;
+        out[str_len..str_len + 4].copy_from_slice(&2u32.to_ne_bytes());
+        fs::write(&path, &out).unwrap();
+        let error = load(&path, code, options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[0; HEADER_LEN]).unwrap();
+        let error = load(&path, code, options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
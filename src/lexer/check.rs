@@ -0,0 +1,221 @@
+//! Validation of the lexer output, for fuzzing.
+
+use std::{any::Any, fmt, panic};
+
+use super::{lex_with_options, LexMode, LexOptions, TokenType, Tokens};
+use crate::source::FileId;
+
+/// Internal invariant of [`Tokens`] that doesn't hold, see [`Tokens::check_invariants`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InvariantViolation {
+    /// The lexer panicked, with its panic message
+    Panic(String),
+    /// `types` and `spans` don't have the same length
+    LengthMismatch { types: usize, spans: usize },
+    /// A token span goes past the end of the code or cuts a character in half
+    SpanOutOfBounds { idx: usize },
+    /// A token span starts before the end of the previous one
+    OverlappingSpans { idx: usize },
+    /// The last token isn't [`TokenType::Eof`]
+    MissingEof,
+    /// The line break table differs from the line breaks of the code, starting at this index
+    WrongLineBreaks { idx: usize },
+    /// The span of an error isn't the slice of code it claims to be
+    InvalidErrorSpan { idx: usize },
+    /// Trivia is preserved but not attached to every token
+    TriviaMismatch { token_trivia: usize, types: usize },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::Panic(message) => write!(f, "Lexer panicked: {message}"),
+            InvariantViolation::LengthMismatch { types, spans } => {
+                write!(f, "{types} token types for {spans} spans")
+            }
+            InvariantViolation::SpanOutOfBounds { idx } => {
+                write!(f, "Span of token {idx} is out of bounds")
+            }
+            InvariantViolation::OverlappingSpans { idx } => {
+                write!(f, "Span of token {idx} overlaps the previous one")
+            }
+            InvariantViolation::MissingEof => write!(f, "Last token is not EOF"),
+            InvariantViolation::WrongLineBreaks { idx } => {
+                write!(f, "Line breaks differ from the code at line break {idx}")
+            }
+            InvariantViolation::InvalidErrorSpan { idx } => {
+                write!(f, "Span of error {idx} doesn't match the code")
+            }
+            InvariantViolation::TriviaMismatch {
+                token_trivia,
+                types,
+            } => write!(f, "Trivia attached to {token_trivia} of {types} tokens"),
+        }
+    }
+}
+
+/// Lexes the code like [`lex_with_options`], then checks the invariants of the tokens.
+///
+/// This never panics: a panic of the lexer is caught and returned as a violation,
+/// so that it can be called on arbitrary input by fuzzers (see the `fuzz` directory).
+pub fn lex_checked(
+    file: FileId,
+    code: &str,
+    options: LexOptions,
+) -> Result<Tokens<'_>, Vec<InvariantViolation>> {
+    let result = panic::catch_unwind(|| {
+        let tokens = lex_with_options(file, code, options);
+        let violations = tokens.check_invariants();
+        (tokens, violations)
+    });
+
+    match result {
+        Ok((tokens, violations)) if violations.is_empty() => Ok(tokens),
+        Ok((_, violations)) => Err(violations),
+        Err(payload) => Err(vec![InvariantViolation::Panic(panic_message(&*payload))]),
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_string(),
+        },
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Checks the internal invariants of the tokens, returning the ones that don't hold.
+    ///
+    /// Spans are checked as raw offsets, so that this doesn't panic on broken tokens.
+    pub fn check_invariants(&self) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        let code = self.code;
+
+        let (types, spans) = (self.types.as_slice(), self.spans.as_slice());
+        if types.len() != spans.len() {
+            violations.push(InvariantViolation::LengthMismatch {
+                types: types.len(),
+                spans: spans.len(),
+            });
+        }
+
+        let mut prev_end = 0;
+        for (idx, span) in spans.iter().enumerate() {
+            let (start, end) = (span.start as usize, span.end());
+            if end > code.len() || !code.is_char_boundary(start) || !code.is_char_boundary(end) {
+                violations.push(InvariantViolation::SpanOutOfBounds { idx });
+            } else if start < prev_end {
+                violations.push(InvariantViolation::OverlappingSpans { idx });
+            }
+            prev_end = prev_end.max(end);
+        }
+
+        if types.last() != Some(&TokenType::Eof) {
+            violations.push(InvariantViolation::MissingEof);
+        }
+
+        let expected_line_breaks = line_breaks_of(code);
+        let line_breaks = self.line_breaks.as_slice();
+        if line_breaks != expected_line_breaks {
+            let idx = (line_breaks.iter().zip(&expected_line_breaks))
+                .position(|(lb, expected)| lb != expected)
+                .unwrap_or(line_breaks.len().min(expected_line_breaks.len()));
+            violations.push(InvariantViolation::WrongLineBreaks { idx });
+        }
+
+        for (idx, error) in self.errors.iter().enumerate() {
+            let span = &error.span;
+            if code.get(span.range()) != Some(span.slice) {
+                violations.push(InvariantViolation::InvalidErrorSpan { idx });
+            }
+        }
+
+        if self.options.mode == LexMode::PreserveTrivia && self.token_trivia.len() != types.len() {
+            violations.push(InvariantViolation::TriviaMismatch {
+                token_trivia: self.token_trivia.len(),
+                types: types.len(),
+            });
+        }
+
+        violations
+    }
+}
+
+/// Offsets of the line breaks of the code, as recorded in [`Tokens::line_breaks`].
+fn line_breaks_of(code: &str) -> Vec<usize> {
+    let bytes = code.as_bytes();
+    (0..bytes.len())
+        .filter(|&i| match bytes[i] {
+            b'\n' => true,
+            b'\r' => bytes.get(i + 1) != Some(&b'\n'),
+            _ => false,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests_check {
+    use crate::{
+        lexer::{check::InvariantViolation, lex, lex_checked, LexMode, LexOptions, Span},
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+    #[test]
+    fn valid_tokens() {
+        for mode in [LexMode::Normal, LexMode::PreserveTrivia] {
+            let options = LexOptions {
+                mode,
+                ..LexOptions::default()
+            };
+            assert!(lex_checked(FileId::default(), SAMPLE, options).is_ok());
+        }
+    }
+
+    #[test]
+    fn truncated_code() {
+        // every prefix of the sample stops in the middle of something
+        let boundaries = (0..SAMPLE.len()).filter(|&i| SAMPLE.is_char_boundary(i));
+        for end in boundaries.step_by(7) {
+            let code = &SAMPLE[..end];
+            let result = lex_checked(FileId::default(), code, LexOptions::default());
+            assert_eq!(result.err(), None, "{code:?}");
+        }
+    }
+
+    #[test]
+    fn broken_tokens() {
+        let mut tokens = lex(FileId::default(), "a\nb");
+        tokens.spans.add(Span { start: 1, len: 5 });
+        tokens.line_breaks.truncate(0);
+
+        assert_eq!(
+            tokens.check_invariants(),
+            [
+                InvariantViolation::LengthMismatch { types: 3, spans: 4 },
+                InvariantViolation::SpanOutOfBounds { idx: 3 },
+                InvariantViolation::WrongLineBreaks { idx: 0 },
+            ]
+        );
+
+        let tokens = lex(FileId::default(), "a b");
+        tokens.spans.add(Span { start: 0, len: 1 });
+        tokens.types.add(tokens.types[0]);
+        let violations = tokens.check_invariants();
+        assert_eq!(
+            violations,
+            [
+                InvariantViolation::OverlappingSpans { idx: 3 },
+                InvariantViolation::MissingEof,
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "Span of token 3 overlaps the previous one"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Bounds-safe cursor over the code being lexed.
+
+use std::cell::Cell;
+
+use super::TokenSpan;
+
+/// Position of the cursor in the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    /// Byte offset in the code
+    pub pos: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Cursor over the bytes of the code being lexed.
+///
+/// Peeking past the end of the code yields `None` instead of panicking,
+/// so scanners can treat the end of file as just another terminator.
+///
+/// It keeps track of how far the code was looked at, see [`Cursor::lookahead_end`].
+#[derive(Debug, Clone)]
+pub struct Cursor<'a> {
+    code: &'a str,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+    /// End of the code that [`Cursor::peek_nth`] and [`Cursor::starts_with`] looked at
+    lookahead_end: Cell<usize>,
+}
+
+impl<'a> Cursor<'a> {
+    #[inline]
+    pub fn new(code: &'a str) -> Self {
+        Self {
+            code,
+            pos: 0,
+            line: 1,
+            line_start: 0,
+            lookahead_end: Cell::new(0),
+        }
+    }
+
+    /// Creates a cursor that starts at the given position in the code.
+    #[inline]
+    pub fn at(code: &'a str, mark: Mark) -> Self {
+        Self {
+            code,
+            pos: mark.pos,
+            line: mark.line,
+            line_start: mark.pos - mark.col,
+            lookahead_end: Cell::new(mark.pos),
+        }
+    }
+
+    #[inline]
+    pub fn is_eof(&self) -> bool {
+        self.pos >= self.code.len()
+    }
+
+    #[inline]
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    #[inline]
+    pub fn col(&self) -> usize {
+        self.pos - self.line_start
+    }
+
+    #[inline]
+    pub fn mark(&self) -> Mark {
+        Mark {
+            pos: self.pos,
+            line: self.line,
+            col: self.col(),
+        }
+    }
+
+    /// The code that has yet to be consumed.
+    #[inline]
+    pub fn rest(&self) -> &'a [u8] {
+        &self.code.as_bytes()[self.pos..]
+    }
+
+    #[inline]
+    pub fn peek(&self) -> Option<u8> {
+        self.code.as_bytes().get(self.pos).copied()
+    }
+
+    #[inline]
+    pub fn peek_nth(&self, n: usize) -> Option<u8> {
+        self.look_at(self.pos + n + 1);
+        self.code.as_bytes().get(self.pos + n).copied()
+    }
+
+    #[inline]
+    pub fn peek_is(&self, pred: impl FnOnce(u8) -> bool) -> bool {
+        self.peek().is_some_and(pred)
+    }
+
+    /// Whether the code at the cursor starts with the prefix, only looking at the bytes
+    /// up to the first one that differs.
+    #[inline]
+    pub fn starts_with(&self, prefix: &[u8]) -> bool {
+        let rest = self.rest();
+        let looked_at = match rest.iter().zip(prefix).position(|(a, b)| a != b) {
+            Some(idx) => idx + 1,
+            None => prefix.len().min(rest.len() + 1),
+        };
+        self.look_at(self.pos + looked_at);
+        rest.starts_with(prefix)
+    }
+
+    /// End of the code that was looked at so far: the byte at the cursor, which decides
+    /// where the current token ends, and any byte after it that was peeked at.
+    ///
+    /// Tokens only depend on the code before this, so code that gets cut or edited
+    /// past it is lexed the same. It can be past the end of the code, when the end
+    /// of file was looked at.
+    #[inline]
+    pub fn lookahead_end(&self) -> usize {
+        self.lookahead_end.get().max(self.pos + 1)
+    }
+
+    #[inline]
+    fn look_at(&self, end: usize) {
+        if end > self.lookahead_end.get() {
+            self.lookahead_end.set(end);
+        }
+    }
+
+    /// Consumes a single byte.
+    ///
+    /// Line breaks must be consumed with [`Cursor::bump_line_break`] instead.
+    #[inline]
+    pub fn bump(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    /// Consumes `n` bytes, stopping at the end of file.
+    #[inline]
+    pub fn bump_n(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.code.len());
+    }
+
+    /// Consumes a single (possibly multi-byte) character.
+    #[inline]
+    pub fn bump_char(&mut self) -> Option<char> {
+        let ch = self.code[self.pos..].chars().next()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    /// Consumes a line break (`\n`, `\r\n` or a lone `\r`),
+    /// returning the offset of its last byte so that it can be recorded.
+    #[inline]
+    pub fn bump_line_break(&mut self) -> usize {
+        debug_assert!(matches!(self.peek(), Some(b'\n' | b'\r')));
+        if self.starts_with(b"\r\n") {
+            self.pos += 1;
+        }
+
+        let offset = self.pos;
+        self.pos += 1;
+        self.line += 1;
+        self.line_start = self.pos;
+        offset
+    }
+
+    /// Consumes bytes while they match the predicate, returning how many were consumed.
+    #[inline]
+    pub fn eat_while(&mut self, mut pred: impl FnMut(u8) -> bool) -> usize {
+        let start = self.pos;
+        while self.peek_is(&mut pred) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    /// Span of code from the given mark to the cursor.
+    #[inline]
+    pub fn span_from(&self, start: Mark) -> TokenSpan<'a> {
+        TokenSpan::new(self.slice_from(start.pos), start.pos, start.line, start.col)
+    }
+
+    /// Slice of code from the given offset to the cursor.
+    #[inline]
+    pub fn slice_from(&self, start: usize) -> &'a str {
+        &self.code[start..self.pos]
+    }
+}
@@ -0,0 +1,272 @@
+//! Matching of parentheses, brackets and braces.
+
+use super::{LexError, LexErrorKind, TokenSpan, TokenType, Tokens};
+
+/// A pair of delimiters that has to be matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Delimiter {
+    /// `(` and `)`
+    Parens,
+    /// `[` and `]`
+    Bracket,
+    /// `{` and `}`
+    Brace,
+}
+
+impl Delimiter {
+    pub const fn open(self) -> char {
+        match self {
+            Delimiter::Parens => '(',
+            Delimiter::Bracket => '[',
+            Delimiter::Brace => '{',
+        }
+    }
+
+    pub const fn close(self) -> char {
+        match self {
+            Delimiter::Parens => ')',
+            Delimiter::Bracket => ']',
+            Delimiter::Brace => '}',
+        }
+    }
+}
+
+/// Something that was opened and has yet to be closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opener {
+    Delimiter(Delimiter),
+    /// Interpolation in a string, closed by the next string segment
+    Interpolation,
+    /// Attribute, closed by its `]`
+    Attribute,
+}
+
+impl TokenType {
+    /// Delimiter opened by the token, if any.
+    #[inline]
+    pub const fn opened_delimiter(self) -> Option<Delimiter> {
+        match self {
+            TokenType::LParens => Some(Delimiter::Parens),
+            TokenType::LBracket => Some(Delimiter::Bracket),
+            TokenType::LBrace => Some(Delimiter::Brace),
+            _ => None,
+        }
+    }
+
+    /// Delimiter closed by the token, if any.
+    #[inline]
+    pub const fn closed_delimiter(self) -> Option<Delimiter> {
+        match self {
+            TokenType::RParens => Some(Delimiter::Parens),
+            TokenType::RBracket => Some(Delimiter::Bracket),
+            TokenType::RBrace => Some(Delimiter::Brace),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Matches delimiters with an explicit stack, recording errors for the ones that don't match.
+    ///
+    /// Interpolations and attributes are groups of their own, delimiters inside of them
+    /// can't be closed from outside. Previous delimiter errors are replaced, so that
+    /// this can be done again after the tokens change.
+    pub(super) fn match_delimiters(&mut self) {
+        let mut stack: Vec<(Opener, usize)> = Vec::new();
+        let mut delimiter_errors = Vec::new();
+
+        // pops the openers from the given index, reporting unclosed delimiters
+        let close_from = |stack: &mut Vec<(Opener, usize)>,
+                          errors: &mut Vec<LexError<'a>>,
+                          stack_idx: usize,
+                          at: TokenSpan<'a>| {
+            for (opener, idx) in stack.drain(stack_idx..) {
+                if let Opener::Delimiter(delimiter) = opener {
+                    errors.push(LexError {
+                        file: self.file,
+                        kind: LexErrorKind::UnclosedDelimiter(delimiter),
+                        span: at,
+                        related: Some(self.span(idx)),
+                    });
+                }
+            }
+        };
+
+        for (i, &ty) in self.types.iter().enumerate() {
+            let group = match ty {
+                TokenType::StringInterpMid | TokenType::StringInterpEnd => {
+                    Some(Opener::Interpolation)
+                }
+                TokenType::AttrEnd => Some(Opener::Attribute),
+                _ => None,
+            };
+            if let Some(group) = group {
+                if let Some(stack_idx) = stack.iter().rposition(|&(o, _)| o == group) {
+                    close_from(&mut stack, &mut delimiter_errors, stack_idx, self.span(i));
+                }
+            }
+
+            let opener = match ty {
+                TokenType::StringInterpBeg | TokenType::StringInterpMid => {
+                    Some(Opener::Interpolation)
+                }
+                TokenType::AttrStart | TokenType::InnerAttrStart => Some(Opener::Attribute),
+                _ => ty.opened_delimiter().map(Opener::Delimiter),
+            };
+            if let Some(opener) = opener {
+                stack.push((opener, i));
+            }
+
+            let Some(delimiter) = ty.closed_delimiter() else {
+                continue;
+            };
+
+            // the closing delimiter closes the ones opened after its own, but not outside its group
+            let group_start = (stack.iter())
+                .rposition(|&(o, _)| !matches!(o, Opener::Delimiter(_)))
+                .map_or(0, |stack_idx| stack_idx + 1);
+            let matching = stack[group_start..]
+                .iter()
+                .rposition(|&(o, _)| o == Opener::Delimiter(delimiter));
+
+            match matching {
+                Some(stack_idx) => {
+                    let at = self.span(i);
+                    close_from(
+                        &mut stack,
+                        &mut delimiter_errors,
+                        group_start + stack_idx + 1,
+                        at,
+                    );
+                    stack.pop();
+                }
+                None => delimiter_errors.push(LexError {
+                    file: self.file,
+                    kind: LexErrorKind::UnexpectedDelimiter(delimiter),
+                    span: self.span(i),
+                    related: None,
+                }),
+            }
+        }
+
+        // unfinished strings and attributes already have their own errors
+        let (line, col) = self.line_col(self.code.len());
+        let eof = TokenSpan::new(&self.code[self.code.len()..], self.code.len(), line, col);
+        close_from(&mut stack, &mut delimiter_errors, 0, eof);
+
+        // merge both lists of errors, each of them being in order of appearance
+        let old_errors: Vec<_> = (self.errors.iter())
+            .filter(|e| !e.kind.is_delimiter_error())
+            .cloned()
+            .collect();
+        self.errors.truncate(0);
+
+        let mut delimiter_errors = delimiter_errors.into_iter().peekable();
+        for error in old_errors {
+            while let Some(delimiter_error) =
+                (delimiter_errors).next_if(|d| d.span.start < error.span.start)
+            {
+                self.errors.add(delimiter_error);
+            }
+            self.errors.add(error);
+        }
+        delimiter_errors.for_each(|error| self.errors.add(error));
+    }
+}
+
+impl LexErrorKind {
+    #[inline]
+    const fn is_delimiter_error(self) -> bool {
+        matches!(
+            self,
+            LexErrorKind::UnclosedDelimiter(_) | LexErrorKind::UnexpectedDelimiter(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests_delimiter {
+    use crate::{
+        lexer::{lex, Delimiter, LexErrorKind},
+        source::FileId,
+    };
+
+    fn delimiter_errors(code: &str) -> Vec<String> {
+        let tokens = lex(FileId::default(), code);
+        tokens.errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn balanced() {
+        assert!(delimiter_errors("f(a[0], { b })").is_empty());
+        assert!(delimiter_errors("$\"{ (a) } and {[b]}\" #[repr(C)]").is_empty());
+    }
+
+    #[test]
+    fn unclosed() {
+        let tokens = lex(FileId::default(), "f(a[0]\n{ b");
+        let errors: Vec<_> = (tokens.errors.iter())
+            .map(|e| (e.kind, e.span.line, e.span.col, e.related.unwrap().col))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (LexErrorKind::UnclosedDelimiter(Delimiter::Parens), 2, 3, 1),
+                (LexErrorKind::UnclosedDelimiter(Delimiter::Brace), 2, 3, 0),
+            ]
+        );
+        assert_eq!(
+            tokens.errors[0].to_string(),
+            "2:3: Unclosed `(` opened at 1:1"
+        );
+    }
+
+    #[test]
+    fn unexpected() {
+        assert_eq!(
+            delimiter_errors("a) ]"),
+            ["1:1: Unexpected `)`", "1:3: Unexpected `]`"]
+        );
+    }
+
+    #[test]
+    fn mismatched() {
+        // the brace closes the parenthesis opened inside of it
+        assert_eq!(
+            delimiter_errors("{ (a }"),
+            ["1:5: Unclosed `(` opened at 1:2"]
+        );
+        // the bracket doesn't match anything
+        assert_eq!(
+            delimiter_errors("(a]\nb"),
+            ["1:2: Unexpected `]`", "2:1: Unclosed `(` opened at 1:0"]
+        );
+    }
+
+    #[test]
+    fn groups() {
+        // delimiters can't be closed across interpolations and attributes
+        assert_eq!(delimiter_errors("$\"{ a) }\""), ["1:5: Unexpected `)`"]);
+        assert_eq!(
+            delimiter_errors("( $\"{ ) }\""),
+            ["1:6: Unexpected `)`", "1:10: Unclosed `(` opened at 1:0"]
+        );
+        assert_eq!(
+            delimiter_errors("( #[a)]"),
+            ["1:5: Unexpected `)`", "1:7: Unclosed `(` opened at 1:0"]
+        );
+    }
+
+    #[test]
+    fn in_order_of_appearance() {
+        let errors = delimiter_errors("(\n$ )\n]\n'");
+        assert_eq!(
+            errors,
+            [
+                "2:0: Cannot parse token",
+                "3:0: Unexpected `]`",
+                "4:0: Unfinished char",
+            ]
+        );
+    }
+}
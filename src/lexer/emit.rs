@@ -0,0 +1,229 @@
+//! Machine-readable dumps of the lexer output, for editor plugins and test harnesses.
+
+use std::io::{self, Write};
+
+use super::{TokenSpan, Tokens};
+
+/// Magic bytes at the start of the binary format.
+pub const BINARY_MAGIC: &[u8; 4] = b"SUST";
+
+/// Version of the binary format, bumped whenever its layout changes.
+pub const BINARY_VERSION: u32 = 8;
+
+/// Format in which tokens can be emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmitFormat {
+    /// Human-oriented listing, as produced by the `Display` impl of [`Tokens`]
+    Text,
+    /// One JSON object with the tokens and errors
+    Json,
+    /// Compact little-endian binary format, see [`Tokens::write_binary`]
+    Binary,
+}
+
+impl Tokens<'_> {
+    /// Writes the tokens in the given format.
+    pub fn emit(&self, format: EmitFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            EmitFormat::Text => writeln!(out, "{self}"),
+            EmitFormat::Json => self.write_json(out),
+            EmitFormat::Binary => self.write_binary(out),
+        }
+    }
+
+    /// Writes the tokens and errors as a single JSON object:
+    ///
+    /// ```json
+    /// {"file":0,"tokens":[{"type":"Ident","start":0,"end":3,"line":1,"col":0,"slice":"abc"}],
+    ///  "errors":[{"kind":"UnknownToken","message":"Cannot parse token","start":4,...}]}
+    /// ```
+    ///
+    /// Errors with a related span (like unclosed delimiters) also have a `"related"` span object.
+    pub fn write_json(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "{{\"file\":{},\"tokens\":[", self.file.index())?;
+        for (i, (ty, span)) in self.types.iter().zip(self.iter_spans()).enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write!(out, "{{\"type\":\"{ty:?}\",")?;
+            write_json_span(out, &span)?;
+            out.write_all(b"}")?;
+        }
+
+        out.write_all(b"],\"errors\":[")?;
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write!(out, "{{\"kind\":\"{:?}\",\"message\":", error.kind)?;
+            write_json_str(out, &error.kind.to_string())?;
+            out.write_all(b",")?;
+            write_json_span(out, &error.span)?;
+            if let Some(related) = &error.related {
+                out.write_all(b",\"related\":{")?;
+                write_json_span(out, related)?;
+                out.write_all(b"}")?;
+            }
+            out.write_all(b"}")?;
+        }
+        out.write_all(b"]}\n")
+    }
+
+    /// Writes the tokens and errors in a compact binary format.
+    ///
+    /// Every integer is a little-endian `u32`, and every span is written
+    /// as `start, len, line, col`, its slice being `code[start..start + len]`.
+    ///
+    /// ```text
+    /// magic: "SUST", version, file
+    /// code_len, code: [u8; code_len]
+    /// token_count, tokens: [type: u8, span; token_count]
+    /// error_count, errors: [message_len, message: [u8; message_len], span, related; error_count]
+    /// ```
+    ///
+    /// The related span of an error is a `u8` that is `1` if it is followed by a span, `0` otherwise.
+    ///
+    /// Types are the discriminants of [`TokenType`](super::TokenType).
+    ///
+    /// Panics if the code is larger than 4 GiB.
+    pub fn write_binary(&self, out: &mut impl Write) -> io::Result<()> {
+        assert!(self.code.len() <= u32::MAX as usize, "Code is too large");
+
+        out.write_all(BINARY_MAGIC)?;
+        write_u32(out, BINARY_VERSION)?;
+        write_u32(out, self.file.index() as u32)?;
+
+        write_u32(out, self.code.len() as u32)?;
+        out.write_all(self.code.as_bytes())?;
+
+        write_u32(out, self.types.len() as u32)?;
+        for (&ty, span) in self.types.iter().zip(self.iter_spans()) {
+            out.write_all(&[ty as u8])?;
+            write_binary_span(out, &span)?;
+        }
+
+        write_u32(out, self.errors.len() as u32)?;
+        for error in self.errors.iter() {
+            let message = error.kind.to_string();
+            write_u32(out, message.len() as u32)?;
+            out.write_all(message.as_bytes())?;
+            write_binary_span(out, &error.span)?;
+            match &error.related {
+                Some(related) => {
+                    out.write_all(&[1])?;
+                    write_binary_span(out, related)?;
+                }
+                None => out.write_all(&[0])?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn write_json_span(out: &mut impl Write, span: &TokenSpan) -> io::Result<()> {
+    write!(
+        out,
+        "\"start\":{},\"end\":{},\"line\":{},\"col\":{},\"slice\":",
+        span.start,
+        span.end(),
+        span.line,
+        span.col
+    )?;
+    write_json_str(out, span.slice)
+}
+
+pub(crate) fn write_json_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            ch if ch.is_control() => write!(out, "\\u{:04x}", ch as u32)?,
+            ch => write!(out, "{ch}")?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn write_binary_span(out: &mut impl Write, span: &TokenSpan) -> io::Result<()> {
+    write_u32(out, span.start as u32)?;
+    write_u32(out, span.slice.len() as u32)?;
+    write_u32(out, span.line as u32)?;
+    write_u32(out, span.col as u32)
+}
+
+#[inline]
+fn write_u32(out: &mut impl Write, n: u32) -> io::Result<()> {
+    out.write_all(&n.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests_emit {
+    use crate::{
+        lexer::{emit::BINARY_MAGIC, lex, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn json() {
+        let tokens = lex(FileId::default(), "a := \"\\\"\t\";\n$");
+        let mut out = Vec::new();
+        tokens.write_json(&mut out).unwrap();
+
+        let expected = concat!(
+            r#"{"file":0,"tokens":["#,
+            r#"{"type":"Ident","start":0,"end":1,"line":1,"col":0,"slice":"a"},"#,
+            r#"{"type":"Colon","start":2,"end":3,"line":1,"col":2,"slice":":"},"#,
+            r#"{"type":"Equal","start":3,"end":4,"line":1,"col":3,"slice":"="},"#,
+            r#"{"type":"String","start":5,"end":10,"line":1,"col":5,"slice":"\"\\\"\t\""},"#,
+            r#"{"type":"Semi","start":10,"end":11,"line":1,"col":10,"slice":";"},"#,
+            r#"{"type":"Error","start":12,"end":13,"line":2,"col":0,"slice":"$"},"#,
+            r#"{"type":"Eof","start":13,"end":13,"line":2,"col":1,"slice":""}"#,
+            r#"],"errors":["#,
+            r#"{"kind":"UnknownToken","message":"Cannot parse token","#,
+            r#""start":12,"end":13,"line":2,"col":0,"slice":"$"}"#,
+            "]}\n",
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn binary() {
+        let tokens = lex(FileId::default(), "a\n$");
+        let mut out = Vec::new();
+        tokens.write_binary(&mut out).unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(BINARY_MAGIC);
+        for n in [8, 0, 3] {
+            expected.extend_from_slice(&u32::to_le_bytes(n));
+        }
+        expected.extend_from_slice(b"a\n$");
+
+        expected.extend_from_slice(&u32::to_le_bytes(3));
+        expected.push(TokenType::Ident as u8);
+        for n in [0, 1, 1, 0] {
+            expected.extend_from_slice(&u32::to_le_bytes(n));
+        }
+        expected.push(TokenType::Error as u8);
+        for n in [2, 1, 2, 0] {
+            expected.extend_from_slice(&u32::to_le_bytes(n));
+        }
+        expected.push(TokenType::Eof as u8);
+        for n in [3, 0, 2, 1] {
+            expected.extend_from_slice(&u32::to_le_bytes(n));
+        }
+
+        expected.extend_from_slice(&u32::to_le_bytes(1));
+        expected.extend_from_slice(&u32::to_le_bytes(18));
+        expected.extend_from_slice(b"Cannot parse token");
+        for n in [2, 1, 2, 0] {
+            expected.extend_from_slice(&u32::to_le_bytes(n));
+        }
+        expected.push(0);
+        assert_eq!(out, expected);
+    }
+}
@@ -0,0 +1,390 @@
+//! Validation and decoding of escape sequences in string and char literals.
+
+use std::{borrow::Cow, fmt, ops::Range};
+
+use super::TokenSpan;
+
+/// The kind of literal whose body is being unescaped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LiteralMode {
+    Str,       // "..."
+    InterpStr, // $"..."
+    ByteStr,   // b"..."
+    CStr,      // c"..."
+    Char,      // '.'
+    ByteChar,  // b'.'
+}
+
+impl LiteralMode {
+    #[inline]
+    pub fn is_byte(self) -> bool {
+        matches!(self, Self::ByteStr | Self::CStr | Self::ByteChar)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EscapeError {
+    /// `\` at the very end of the literal
+    LoneBackslash,
+    /// `\` followed by a character that isn't a known escape
+    UnknownEscape,
+    /// `\x` not followed by 2 hex digits
+    InvalidHexEscape,
+    /// `\x` above `\x7F` in a non-byte literal
+    HexEscapeOutOfRange,
+    /// `\u` not followed by `{` 1 to 6 hex digits `}`
+    InvalidUnicodeEscape,
+    /// `\u{...}` that isn't a valid unicode scalar value
+    UnicodeEscapeOutOfRange,
+    /// `\u{...}` in a byte literal
+    UnicodeEscapeInByteLiteral,
+}
+
+impl fmt::Display for EscapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EscapeError::LoneBackslash => "Lone backslash at the end of literal",
+            EscapeError::UnknownEscape => "Unknown escape sequence",
+            EscapeError::InvalidHexEscape => "Hex escape must have exactly 2 hex digits",
+            EscapeError::HexEscapeOutOfRange => "Hex escape must be at most \\x7F",
+            EscapeError::InvalidUnicodeEscape => {
+                "Unicode escape must be \\u{...} with 1 to 6 hex digits"
+            }
+            EscapeError::UnicodeEscapeOutOfRange => {
+                "Unicode escape is not a valid unicode scalar value"
+            }
+            EscapeError::UnicodeEscapeInByteLiteral => {
+                "Unicode escapes are not allowed in byte literals"
+            }
+        })
+    }
+}
+
+/// Cooked contents of a literal, with its escapes decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiteralValue<'a> {
+    Str(Cow<'a, str>),
+    /// Contents of a byte string or C string (without the zero terminator)
+    Bytes(Cow<'a, [u8]>),
+    Char(char),
+    Byte(u8),
+}
+
+/// Finds the literal mode and the range of the body (between the quotes) of a literal slice.
+///
+/// Interpolated string segments like `} world {` are recognized as well.
+pub fn literal_body(slice: &str) -> Option<(LiteralMode, Range<usize>)> {
+    let bytes = slice.as_bytes();
+    let len = bytes.len();
+
+    let (mode, prefix_len) = match bytes {
+        [b'b', b'"', ..] => (LiteralMode::ByteStr, 2),
+        [b'c', b'"', ..] => (LiteralMode::CStr, 2),
+        [b'$', b'"', ..] => (LiteralMode::InterpStr, 2),
+        [b'}', ..] => (LiteralMode::InterpStr, 1),
+        [b'"', ..] => (LiteralMode::Str, 1),
+        [b'b', b'\'', ..] => (LiteralMode::ByteChar, 2),
+        [b'\'', ..] => (LiteralMode::Char, 1),
+        _ => return None,
+    };
+
+    let is_closed = match mode {
+        LiteralMode::InterpStr => matches!(bytes[len - 1], b'"' | b'{'),
+        LiteralMode::Char | LiteralMode::ByteChar => bytes[len - 1] == b'\'',
+        _ => bytes[len - 1] == b'"',
+    };
+
+    if len < prefix_len + 1 || !is_closed {
+        return None;
+    }
+
+    Some((mode, prefix_len..len - 1))
+}
+
+/// Decodes the escape sequences of a literal body, appending the result to `out` if provided.
+///
+/// Invalid escapes are reported to `on_error` with their byte range in the body, and skipped.
+/// In non-byte modes, what gets appended to `out` is always valid UTF-8.
+pub fn unescape(
+    body: &str,
+    mode: LiteralMode,
+    mut out: Option<&mut Vec<u8>>,
+    mut on_error: impl FnMut(Range<usize>, EscapeError),
+) {
+    let bytes = body.as_bytes();
+    let len = bytes.len();
+
+    let mut i = 0;
+    while i < len {
+        if bytes[i] != b'\\' {
+            let start = i;
+            while i < len && bytes[i] != b'\\' {
+                i += 1;
+            }
+            if let Some(out) = out.as_mut() {
+                out.extend_from_slice(&bytes[start..i]);
+            }
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+        if i >= len {
+            on_error(start..i, EscapeError::LoneBackslash);
+            break;
+        }
+
+        let c = bytes[i];
+        i += 1;
+
+        let simple = match c {
+            b'n' => Some(b'\n'),
+            b'r' => Some(b'\r'),
+            b't' => Some(b'\t'),
+            b'0' => Some(b'\0'),
+            b'\\' | b'\'' | b'"' => Some(c),
+            b'{' | b'}' if mode == LiteralMode::InterpStr => Some(c),
+            _ => None,
+        };
+
+        if let Some(byte) = simple {
+            if let Some(out) = out.as_mut() {
+                out.push(byte);
+            }
+            continue;
+        }
+
+        match c {
+            b'x' => {
+                let digits = bytes
+                    .get(i..i + 2)
+                    .filter(|d| d.iter().all(u8::is_ascii_hexdigit));
+                let Some(digits) = digits else {
+                    on_error(start..i, EscapeError::InvalidHexEscape);
+                    continue;
+                };
+
+                i += 2;
+                let digits = unsafe { std::str::from_utf8_unchecked(digits) };
+                let value = u8::from_str_radix(digits, 16).unwrap();
+                if !mode.is_byte() && value > 0x7F {
+                    on_error(start..i, EscapeError::HexEscapeOutOfRange);
+                } else if let Some(out) = out.as_mut() {
+                    out.push(value);
+                }
+            }
+            b'u' => {
+                if bytes.get(i) != Some(&b'{') {
+                    on_error(start..i, EscapeError::InvalidUnicodeEscape);
+                    continue;
+                }
+
+                let digits_start = i + 1;
+                let mut j = digits_start;
+                while j < len && bytes[j].is_ascii_hexdigit() {
+                    j += 1;
+                }
+
+                let digit_count = j - digits_start;
+                if j >= len || bytes[j] != b'}' || digit_count == 0 || digit_count > 6 {
+                    i = j;
+                    on_error(start..i, EscapeError::InvalidUnicodeEscape);
+                    continue;
+                }
+
+                i = j + 1;
+                if mode.is_byte() {
+                    on_error(start..i, EscapeError::UnicodeEscapeInByteLiteral);
+                    continue;
+                }
+
+                let value = u32::from_str_radix(&body[digits_start..j], 16).unwrap();
+                match char::from_u32(value) {
+                    Some(ch) => {
+                        if let Some(out) = out.as_mut() {
+                            let mut buf = [0; 4];
+                            out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                        }
+                    }
+                    None => on_error(start..i, EscapeError::UnicodeEscapeOutOfRange),
+                }
+            }
+            _ => {
+                // the escaped character might be multi-byte
+                while i < len && !body.is_char_boundary(i) {
+                    i += 1;
+                }
+                on_error(start..i, EscapeError::UnknownEscape);
+            }
+        }
+    }
+}
+
+/// Finds the byte ranges of a byte literal body that decode to a NUL byte.
+///
+/// Those are raw NUL characters, `\0` and `\x00`, as byte literals can't have unicode escapes.
+pub fn nul_ranges(body: &str) -> Vec<Range<usize>> {
+    let bytes = body.as_bytes();
+    let mut ranges = Vec::new();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let len = match &bytes[i..] {
+            [0, ..] => 1,
+            [b'\\', b'0', ..] => 2,
+            [b'\\', b'x', b'0', b'0', ..] => 4,
+            // other escapes can't be NUL, but they could escape a backslash
+            [b'\\', ..] => {
+                i += 2;
+                continue;
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        ranges.push(i..i + len);
+        i += len;
+    }
+
+    ranges
+}
+
+impl<'a> TokenSpan<'a> {
+    /// Cooked contents of a string or char literal token, with its escapes decoded.
+    ///
+    /// Invalid escapes are skipped (they are already reported while lexing).
+    /// Returns `None` if the span isn't a finished literal.
+    pub fn decoded_value(&self) -> Option<LiteralValue<'a>> {
+        let (mode, body) = literal_body(self.slice)?;
+        let body = &self.slice[body];
+
+        if !body.contains('\\') {
+            return Some(match mode {
+                LiteralMode::Str | LiteralMode::InterpStr => LiteralValue::Str(Cow::Borrowed(body)),
+                LiteralMode::ByteStr | LiteralMode::CStr => {
+                    LiteralValue::Bytes(Cow::Borrowed(body.as_bytes()))
+                }
+                LiteralMode::Char => LiteralValue::Char(body.chars().next()?),
+                LiteralMode::ByteChar => LiteralValue::Byte(*body.as_bytes().first()?),
+            });
+        }
+
+        let mut out = Vec::with_capacity(body.len());
+        unescape(body, mode, Some(&mut out), |_, _| {});
+
+        Some(match mode {
+            LiteralMode::Str | LiteralMode::InterpStr => {
+                // SAFETY: unescaping in non-byte modes always produces valid UTF-8
+                LiteralValue::Str(Cow::Owned(unsafe { String::from_utf8_unchecked(out) }))
+            }
+            LiteralMode::ByteStr | LiteralMode::CStr => LiteralValue::Bytes(Cow::Owned(out)),
+            LiteralMode::Char => {
+                let decoded = unsafe { std::str::from_utf8_unchecked(&out) };
+                LiteralValue::Char(decoded.chars().next()?)
+            }
+            LiteralMode::ByteChar => LiteralValue::Byte(*out.first()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests_unescape {
+    use std::borrow::Cow;
+
+    use crate::lexer::{
+        escape::{nul_ranges, unescape, EscapeError, LiteralMode, LiteralValue},
+        TokenSpan,
+    };
+
+    fn errors(body: &str, mode: LiteralMode) -> Vec<(std::ops::Range<usize>, EscapeError)> {
+        let mut errors = Vec::new();
+        unescape(body, mode, None, |range, error| errors.push((range, error)));
+        errors
+    }
+
+    fn decoded(slice: &str) -> Option<LiteralValue<'_>> {
+        TokenSpan::new(slice, 0, 1, 0).decoded_value()
+    }
+
+    #[test]
+    fn valid_escapes() {
+        let body = r#"\n\r\t\0\\\'\"\x41\u{1F600}"#;
+        assert!(errors(body, LiteralMode::Str).is_empty());
+
+        let value = decoded(r#""a\tb\x41\u{e9}""#).unwrap();
+        assert_eq!(value, LiteralValue::Str(Cow::Owned("a\tbAé".to_string())));
+    }
+
+    #[test]
+    fn borrowed_without_escapes() {
+        let value = decoded(r#""hello""#).unwrap();
+        assert!(matches!(value, LiteralValue::Str(Cow::Borrowed("hello"))));
+    }
+
+    #[test]
+    fn byte_literals() {
+        assert_eq!(
+            decoded(r#"b"\xFF\n""#),
+            Some(LiteralValue::Bytes(Cow::Owned(vec![0xFF, b'\n'])))
+        );
+        assert_eq!(decoded(r"b'\x7F'"), Some(LiteralValue::Byte(0x7F)));
+        assert_eq!(decoded(r"'\u{0D9E}'"), Some(LiteralValue::Char('ඞ')));
+        assert_eq!(
+            errors(r"\u{41}", LiteralMode::ByteStr),
+            [(0..6, EscapeError::UnicodeEscapeInByteLiteral)]
+        );
+    }
+
+    #[test]
+    fn interpolated_segments() {
+        assert!(errors(r"\{\}", LiteralMode::InterpStr).is_empty());
+        assert_eq!(
+            errors(r"\{", LiteralMode::Str),
+            [(0..2, EscapeError::UnknownEscape)]
+        );
+        assert_eq!(
+            decoded(r#"} \{ {"#),
+            Some(LiteralValue::Str(Cow::Owned(" { ".to_string())))
+        );
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        assert_eq!(
+            errors(r"\q", LiteralMode::Str),
+            [(0..2, EscapeError::UnknownEscape)]
+        );
+        assert_eq!(
+            errors(r"\ඞ", LiteralMode::Str),
+            [(0..4, EscapeError::UnknownEscape)]
+        );
+        assert_eq!(
+            errors(r"\x4", LiteralMode::Str),
+            [(0..2, EscapeError::InvalidHexEscape)]
+        );
+        assert_eq!(
+            errors(r"\xFF", LiteralMode::Str),
+            [(0..4, EscapeError::HexEscapeOutOfRange)]
+        );
+        assert_eq!(
+            errors(r"\u{}", LiteralMode::Str),
+            [(0..3, EscapeError::InvalidUnicodeEscape)]
+        );
+        assert_eq!(
+            errors(r"\u{D800}", LiteralMode::Str),
+            [(0..8, EscapeError::UnicodeEscapeOutOfRange)]
+        );
+        assert_eq!(
+            errors(r"a\", LiteralMode::Str),
+            [(1..2, EscapeError::LoneBackslash)]
+        );
+    }
+
+    #[test]
+    fn nul_bytes() {
+        assert_eq!(nul_ranges("a\\0b\0\\x00\\\\0\\x01"), [1..3, 4..5, 5..9]);
+        assert_eq!(nul_ranges("\\u{0}"), []);
+    }
+}
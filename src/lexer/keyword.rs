@@ -0,0 +1,154 @@
+//! Keyword recognition through a static hash table.
+
+use super::TokenType;
+
+/// Every keyword of the language, adding one only takes a new entry here.
+///
+/// New keywords should rather be soft keywords, so that they don't break existing identifiers.
+const KEYWORDS: &[(&[u8], TokenType)] = &[
+    (b"continue", TokenType::Continue),
+    (b"struct", TokenType::Struct),
+    (b"union", TokenType::Union),
+    (b"defer", TokenType::Defer),
+    (b"while", TokenType::While),
+    (b"break", TokenType::Break),
+    (b"enum", TokenType::Enum),
+    (b"else", TokenType::Else),
+    (b"loop", TokenType::Loop),
+    (b"and", TokenType::And),
+    (b"xor", TokenType::Xor),
+    (b"not", TokenType::Not),
+    (b"pub", TokenType::Pub),
+    (b"or", TokenType::Or),
+    (b"fn", TokenType::Fn),
+    (b"if", TokenType::If),
+    (b"do", TokenType::Do),
+];
+
+/// Keywords that are only keywords in specific contexts, lexed as identifiers.
+///
+/// Parsers reinterpret them where they expect them, see [`Token::as_soft_keyword`].
+///
+/// [`Token::as_soft_keyword`]: super::Token::as_soft_keyword
+const SOFT_KEYWORDS: &[(&[u8], TokenType)] = &[
+    (b"packed", TokenType::Packed),
+    (b"then", TokenType::Then),
+    (b"match", TokenType::Match),
+    (b"use", TokenType::Use),
+    (b"const", TokenType::Const),
+    (b"static", TokenType::Static),
+    (b"true", TokenType::True),
+    (b"false", TokenType::False),
+    (b"none", TokenType::None),
+    (b"return", TokenType::Return),
+    (b"for", TokenType::For),
+    (b"in", TokenType::In),
+];
+
+/// Number of slots in the table, kept at least twice the number of keywords
+/// so that probe sequences stay short.
+const TABLE_SIZE: usize = (KEYWORDS.len() * 2).next_power_of_two();
+
+/// Open-addressing table of indices into [`KEYWORDS`] plus one, `0` being an empty slot.
+static TABLE: [u8; TABLE_SIZE] = build_table();
+
+/// FNV-1a hash of the identifier.
+const fn hash(ident: &[u8]) -> usize {
+    let mut hash: u32 = 0x811c9dc5;
+    let mut i = 0;
+    while i < ident.len() {
+        hash ^= ident[i] as u32;
+        hash = hash.wrapping_mul(0x01000193);
+        i += 1;
+    }
+    hash as usize
+}
+
+const fn build_table() -> [u8; TABLE_SIZE] {
+    assert!(KEYWORDS.len() < u8::MAX as usize);
+
+    let mut table = [0; TABLE_SIZE];
+    let mut kw_idx = 0;
+    while kw_idx < KEYWORDS.len() {
+        let mut slot = hash(KEYWORDS[kw_idx].0) % TABLE_SIZE;
+        while table[slot] != 0 {
+            slot = (slot + 1) % TABLE_SIZE;
+        }
+
+        table[slot] = kw_idx as u8 + 1;
+        kw_idx += 1;
+    }
+    table
+}
+
+/// Token type of the keyword that is exactly this identifier, if any.
+pub(super) fn lookup(ident: &[u8]) -> Option<TokenType> {
+    let mut slot = hash(ident) % TABLE_SIZE;
+    loop {
+        let (keyword, toktype) = match TABLE[slot] {
+            0 => return None,
+            entry => KEYWORDS[entry as usize - 1],
+        };
+
+        if keyword == ident {
+            return Some(toktype);
+        }
+        slot = (slot + 1) % TABLE_SIZE;
+    }
+}
+
+/// Token type of the soft keyword that is exactly this identifier, if any.
+pub(super) fn lookup_soft(ident: &[u8]) -> Option<TokenType> {
+    (SOFT_KEYWORDS.iter())
+        .find(|&&(keyword, _)| keyword == ident)
+        .map(|&(_, toktype)| toktype)
+}
+
+impl TokenType {
+    /// Whether the token type is one of a soft keyword, which is never lexed as such.
+    #[inline]
+    pub fn is_soft_keyword(self) -> bool {
+        SOFT_KEYWORDS.iter().any(|&(_, toktype)| toktype == self)
+    }
+}
+
+#[cfg(test)]
+mod tests_keyword {
+    use crate::{
+        lexer::{
+            keyword::{lookup, lookup_soft, KEYWORDS, SOFT_KEYWORDS},
+            lex, TokenType,
+        },
+        source::FileId,
+    };
+
+    #[test]
+    fn every_keyword_is_found() {
+        for &(keyword, toktype) in KEYWORDS {
+            assert_eq!(lookup(keyword), Some(toktype));
+        }
+    }
+
+    #[test]
+    fn soft_keywords_are_idents() {
+        for &(keyword, toktype) in SOFT_KEYWORDS {
+            assert_eq!(lookup(keyword), None);
+            assert_eq!(lookup_soft(keyword), Some(toktype));
+            assert!(toktype.is_soft_keyword());
+
+            let tokens = lex(FileId::default(), std::str::from_utf8(keyword).unwrap());
+            assert_eq!(tokens.types.as_slice(), [TokenType::Ident, TokenType::Eof]);
+        }
+        assert!(!TokenType::Struct.is_soft_keyword());
+    }
+
+    #[test]
+    fn whole_identifiers_only() {
+        let tokens = lex(FileId::default(), "andy fnord if_ do2 continued str or");
+        let types: Vec<_> = tokens.types.iter().copied().collect();
+        let expected = [TokenType::Ident; 6]
+            .into_iter()
+            .chain([TokenType::Or, TokenType::Eof]);
+        assert_eq!(types, expected.collect::<Vec<_>>());
+    }
+}
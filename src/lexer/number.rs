@@ -0,0 +1,566 @@
+//! Number literal helpers.
+
+use std::{fmt, ops::Range};
+
+use super::{LexError, LexErrorKind, TokenSpan, TokenType, Tokens};
+use crate::arena::ArenaVec;
+
+/// Type suffix of a number literal, like the `u32` in `42u32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NumSuffix {
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Isize,
+    F16,
+    F32,
+    F64,
+}
+
+impl NumSuffix {
+    /// All suffixes, longest first so that they can be matched against the end of a literal.
+    pub const ALL: [NumSuffix; 15] = [
+        NumSuffix::Usize,
+        NumSuffix::Isize,
+        NumSuffix::U128,
+        NumSuffix::I128,
+        NumSuffix::U16,
+        NumSuffix::U32,
+        NumSuffix::U64,
+        NumSuffix::I16,
+        NumSuffix::I32,
+        NumSuffix::I64,
+        NumSuffix::F16,
+        NumSuffix::F32,
+        NumSuffix::F64,
+        NumSuffix::U8,
+        NumSuffix::I8,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|suffix| suffix.as_str() == s)
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            NumSuffix::U8 => "u8",
+            NumSuffix::U16 => "u16",
+            NumSuffix::U32 => "u32",
+            NumSuffix::U64 => "u64",
+            NumSuffix::U128 => "u128",
+            NumSuffix::Usize => "usize",
+            NumSuffix::I8 => "i8",
+            NumSuffix::I16 => "i16",
+            NumSuffix::I32 => "i32",
+            NumSuffix::I64 => "i64",
+            NumSuffix::I128 => "i128",
+            NumSuffix::Isize => "isize",
+            NumSuffix::F16 => "f16",
+            NumSuffix::F32 => "f32",
+            NumSuffix::F64 => "f64",
+        }
+    }
+
+    #[inline]
+    pub const fn is_float(self) -> bool {
+        matches!(self, NumSuffix::F16 | NumSuffix::F32 | NumSuffix::F64)
+    }
+
+    /// Number of bits of integer types, treating `usize` and `isize` as 64-bit.
+    pub const fn int_bits(self) -> Option<u32> {
+        match self {
+            NumSuffix::U8 | NumSuffix::I8 => Some(8),
+            NumSuffix::U16 | NumSuffix::I16 => Some(16),
+            NumSuffix::U32 | NumSuffix::I32 => Some(32),
+            NumSuffix::U64 | NumSuffix::I64 | NumSuffix::Usize | NumSuffix::Isize => Some(64),
+            NumSuffix::U128 | NumSuffix::I128 => Some(128),
+            NumSuffix::F16 | NumSuffix::F32 | NumSuffix::F64 => None,
+        }
+    }
+
+    #[inline]
+    pub const fn is_signed(self) -> bool {
+        !matches!(
+            self,
+            NumSuffix::U8
+                | NumSuffix::U16
+                | NumSuffix::U32
+                | NumSuffix::U64
+                | NumSuffix::U128
+                | NumSuffix::Usize
+        )
+    }
+}
+
+impl fmt::Display for NumSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Splits a number literal into its digits and its type suffix.
+///
+/// Float suffixes are never recognized on hex integers, as `f` is a hex digit.
+/// They are on hex floats, whose exponent is decimal.
+pub fn split_suffix(slice: &str) -> (&str, Option<NumSuffix>) {
+    let is_hex_int = slice.starts_with("0x") && !slice.contains(['p', 'P']);
+
+    for suffix in NumSuffix::ALL {
+        if is_hex_int && suffix.is_float() {
+            continue;
+        }
+
+        if let Some(digits) = slice.strip_suffix(suffix.as_str()) {
+            return (digits, Some(suffix));
+        }
+    }
+
+    (slice, None)
+}
+
+impl<'a> TokenSpan<'a> {
+    /// Type suffix of a `Num` token.
+    #[inline]
+    pub fn num_suffix(&self) -> Option<NumSuffix> {
+        split_suffix(self.slice).1
+    }
+
+    /// Slice of a `Num` token without its type suffix.
+    #[inline]
+    pub fn num_digits(&self) -> &'a str {
+        split_suffix(self.slice).0
+    }
+}
+
+/// Value of a number literal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumValue {
+    Int(u128),
+    Float(f64),
+}
+
+/// Why a number literal couldn't be parsed into a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumError {
+    /// The literal has no digits, or has an integer suffix on a float
+    Malformed,
+    /// The value doesn't fit in the literal's type
+    Overflow,
+    /// The literal has an unknown suffix (already reported while lexing)
+    InvalidSuffix,
+}
+
+/// Parses a number literal with its optional `0x`/`0o`/`0b` prefix, `_` separators and suffix.
+///
+/// Signed integers can go up to the absolute value of their minimum,
+/// as negative literals are the negation of a positive one.
+/// Floats without a suffix are checked against the range of `f64`.
+pub fn parse_num(slice: &str) -> Result<NumValue, NumError> {
+    let (body, suffix) = split_suffix(slice);
+    let (radix, digits) = match body.get(..2) {
+        Some("0x") => (16, &body[2..]),
+        Some("0o") => (8, &body[2..]),
+        Some("0b") => (2, &body[2..]),
+        _ => (10, body),
+    };
+
+    let numeric_len = numeric_len(digits, radix);
+    if numeric_len < digits.len() {
+        return Err(NumError::InvalidSuffix);
+    }
+
+    if radix == 16 && digits.contains(['p', 'P']) {
+        if suffix.is_some_and(|suffix| !suffix.is_float()) {
+            return Err(NumError::Malformed);
+        }
+        return check_float(parse_hex_float(digits)?, suffix);
+    }
+
+    let is_float = radix == 10 && digits.contains(['.', 'e', 'E']);
+    if is_float {
+        if suffix.is_some_and(|suffix| !suffix.is_float()) {
+            return Err(NumError::Malformed);
+        }
+
+        let cleaned: String = digits.chars().filter(|&ch| ch != '_').collect();
+        let has_exponent_digits = match cleaned.find(['e', 'E']) {
+            Some(e) => cleaned[e + 1..].bytes().any(|b| b.is_ascii_digit()),
+            None => true,
+        };
+        if !has_exponent_digits {
+            return Err(NumError::Malformed);
+        }
+
+        let value: f64 = cleaned.parse().map_err(|_| NumError::Malformed)?;
+        return check_float(value, suffix);
+    }
+
+    let mut has_digits = false;
+    let mut value: u128 = 0;
+    for ch in digits.chars().filter(|&ch| ch != '_') {
+        let digit = ch.to_digit(radix).ok_or(NumError::Malformed)?;
+        value = (value.checked_mul(radix as u128))
+            .and_then(|value| value.checked_add(digit as u128))
+            .ok_or(NumError::Overflow)?;
+        has_digits = true;
+    }
+
+    if !has_digits {
+        return Err(NumError::Malformed);
+    }
+
+    match suffix {
+        Some(suffix) if suffix.is_float() => check_float(value as f64, Some(suffix)),
+        Some(suffix) => {
+            let bits = suffix.int_bits().unwrap();
+            let max = match suffix.is_signed() {
+                true => 1 << (bits - 1),
+                false => u128::MAX >> (128 - bits),
+            };
+
+            match value <= max {
+                true => Ok(NumValue::Int(value)),
+                false => Err(NumError::Overflow),
+            }
+        }
+        None => Ok(NumValue::Int(value)),
+    }
+}
+
+/// Parses the digits of a hex float like `1.8p3`, which is `0x1.8 * 2^3`.
+///
+/// The mantissa is kept exact in a `u64`, digits that don't fit only being remembered
+/// as a sticky bit, so that the value is correctly rounded (to nearest, ties to even).
+fn parse_hex_float(digits: &str) -> Result<f64, NumError> {
+    let (mantissa, exponent) = digits.split_once(['p', 'P']).ok_or(NumError::Malformed)?;
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    // the value is `(mantissa | sticky) * 2^shift`
+    let (mut mantissa, mut sticky, mut shift) = (0u64, false, 0i64);
+    let mut has_digits = false;
+    let whole = whole.chars().map(|ch| (ch, 0));
+    let fraction = fraction.chars().map(|ch| (ch, -4));
+    for (ch, weight) in whole.chain(fraction).filter(|&(ch, _)| ch != '_') {
+        let digit = ch.to_digit(16).ok_or(NumError::Malformed)? as u64;
+        match mantissa >> 60 {
+            0 => {
+                mantissa = mantissa << 4 | digit;
+                shift += weight;
+            }
+            _ => {
+                sticky |= digit != 0;
+                shift += weight + 4;
+            }
+        }
+        has_digits = true;
+    }
+
+    let exponent: String = exponent.chars().filter(|&ch| ch != '_').collect();
+    let exponent: i32 = match exponent.parse() {
+        Ok(exponent) => exponent,
+        // too many digits to be anything else than infinity or zero
+        Err(_) if exponent.bytes().any(|b| b.is_ascii_digit()) => match exponent.starts_with('-') {
+            true => i32::MIN,
+            false => i32::MAX,
+        },
+        Err(_) => return Err(NumError::Malformed),
+    };
+
+    if !has_digits {
+        return Err(NumError::Malformed);
+    }
+    if mantissa == 0 {
+        return Ok(0.0);
+    }
+
+    // the value is in `[2^top, 2^(top + 1))`
+    let shift = shift + exponent as i64;
+    let bits = 64 - mantissa.leading_zeros() as i64;
+    let top = shift + bits - 1;
+    if top > f64::MAX_EXP as i64 - 1 {
+        return Ok(f64::INFINITY);
+    }
+
+    // bits that fit in the result, fewer than 53 for subnormals
+    let precision = (top + 1075).min(f64::MANTISSA_DIGITS as i64);
+    if precision < 0 {
+        return Ok(0.0);
+    }
+    let dropped = (bits - precision).max(0);
+    let mantissa = mantissa as u128;
+    let mut kept = mantissa >> dropped;
+    if dropped > 0 {
+        let half = 1 << (dropped - 1);
+        let rest = mantissa & ((half << 1) - 1);
+        if rest > half || (rest == half && (sticky || kept & 1 == 1)) {
+            kept += 1;
+        }
+    }
+
+    // exact, as `kept` has at most 54 bits and the scale is at least the smallest subnormal
+    let scale = shift + dropped;
+    let scale = match scale >= f64::MIN_EXP as i64 - 1 {
+        true => f64::from_bits(((scale + 1023) as u64) << 52),
+        false => f64::from_bits(1 << (scale + 1074)),
+    };
+    Ok(kept as f64 * scale)
+}
+
+/// Length of the part of the digits that the lexer considers numeric, before any suffix.
+fn numeric_len(digits: &str, radix: u32) -> usize {
+    let bytes = digits.as_bytes();
+    let digits_len = |from: usize| {
+        let digit_count = bytes[from..]
+            .iter()
+            .take_while(|&&b| b == b'_' || (b as char).is_digit(radix))
+            .count();
+        from + digit_count
+    };
+
+    let mut len = digits_len(0);
+    if radix == 16 {
+        if bytes.get(len) == Some(&b'.') {
+            len = digits_len(len + 1);
+        }
+        if matches!(bytes.get(len), Some(b'p' | b'P')) {
+            len += 1;
+            if matches!(bytes.get(len), Some(b'+' | b'-')) {
+                len += 1;
+            }
+            len += (bytes[len..].iter())
+                .take_while(|&&b| b == b'_' || b.is_ascii_digit())
+                .count();
+        }
+        return len;
+    }
+    if radix != 10 {
+        return len;
+    }
+
+    if bytes.get(len) == Some(&b'.') {
+        len = digits_len(len + 1);
+    }
+    if matches!(bytes.get(len), Some(b'e' | b'E')) {
+        len += 1;
+        if matches!(bytes.get(len), Some(b'+' | b'-')) {
+            len += 1;
+        }
+        len = digits_len(len);
+    }
+    len
+}
+
+fn check_float(value: f64, suffix: Option<NumSuffix>) -> Result<NumValue, NumError> {
+    let max = match suffix {
+        Some(NumSuffix::F16) => 65504.0,
+        Some(NumSuffix::F32) => f32::MAX as f64,
+        _ => f64::MAX,
+    };
+
+    match value <= max {
+        true => Ok(NumValue::Float(value)),
+        false => Err(NumError::Overflow),
+    }
+}
+
+impl<'a> TokenSpan<'a> {
+    /// Value of a `Num` token.
+    #[inline]
+    pub fn num_value(&self) -> Result<NumValue, NumError> {
+        parse_num(self.slice)
+    }
+}
+
+/// Values of the number literals of some [`Tokens`], see [`Tokens::num_values`].
+#[derive(Debug)]
+pub struct NumValues<'a> {
+    /// Sorted indices of the `Num` tokens that have a value
+    pub token_idxs: ArenaVec<usize>,
+    /// Respective values
+    pub values: ArenaVec<NumValue>,
+    /// Literals that are malformed or out of range
+    pub errors: ArenaVec<LexError<'a>>,
+}
+
+impl<'a> NumValues<'a> {
+    /// Value of the `Num` token at the given index.
+    pub fn get(&self, token_idx: usize) -> Option<NumValue> {
+        let idx = self.token_idxs.as_slice().binary_search(&token_idx).ok()?;
+        Some(self.values[idx])
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Parses every `Num` token into its value.
+    pub fn num_values(&self) -> NumValues<'a> {
+        let max_len = self.types.len();
+        let reservation = self.options.reservation;
+        let num_values = NumValues {
+            token_idxs: ArenaVec::new(reservation.size_for::<usize>(max_len)),
+            values: ArenaVec::new(reservation.size_for::<NumValue>(max_len)),
+            errors: ArenaVec::new(reservation.size_for::<LexError>(max_len)),
+        };
+
+        let nums = (self.types.iter().enumerate()).filter(|&(_, &ty)| ty == TokenType::Num);
+
+        for (token_idx, _) in nums {
+            let range = self.spans[token_idx].range();
+            let kind = match parse_num(&self.code[range.clone()]) {
+                Ok(value) => {
+                    num_values.token_idxs.add(token_idx);
+                    num_values.values.add(value);
+                    continue;
+                }
+                Err(NumError::InvalidSuffix) => continue,
+                // already reported by the lexer
+                Err(NumError::Malformed) if self.has_missing_digits(range) => continue,
+                Err(NumError::Malformed) => LexErrorKind::MalformedNum,
+                Err(NumError::Overflow) => LexErrorKind::NumOverflow,
+            };
+
+            num_values.errors.add(LexError {
+                file: self.file,
+                kind,
+                span: self.span(token_idx),
+                related: None,
+            });
+        }
+
+        num_values
+    }
+
+    fn has_missing_digits(&self, range: Range<usize>) -> bool {
+        (self.errors.iter()).any(|error| {
+            error.kind == LexErrorKind::MissingDigits && range.contains(&error.span.start)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests_num_values {
+    use crate::{
+        lexer::{
+            lex,
+            number::{parse_num, NumError, NumValue},
+            LexErrorKind,
+        },
+        source::FileId,
+    };
+
+    #[test]
+    fn integers() {
+        assert_eq!(parse_num("1_000"), Ok(NumValue::Int(1000)));
+        assert_eq!(parse_num("0xFF_u8"), Ok(NumValue::Int(255)));
+        assert_eq!(parse_num("0o17"), Ok(NumValue::Int(15)));
+        assert_eq!(parse_num("0b1010_1010"), Ok(NumValue::Int(170)));
+        assert_eq!(parse_num("0x1f32"), Ok(NumValue::Int(0x1f32)));
+        assert_eq!(parse_num("128i8"), Ok(NumValue::Int(128)));
+        assert_eq!(parse_num("3f32"), Ok(NumValue::Float(3.0)));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(parse_num("3.5"), Ok(NumValue::Float(3.5)));
+        assert_eq!(parse_num("1_0.2_5e-1"), Ok(NumValue::Float(1.025)));
+        assert_eq!(parse_num("2E3f64"), Ok(NumValue::Float(2000.0)));
+        assert_eq!(parse_num("1."), Ok(NumValue::Float(1.0)));
+    }
+
+    #[test]
+    fn hex_floats() {
+        assert_eq!(parse_num("0x1.8p3"), Ok(NumValue::Float(12.0)));
+        assert_eq!(parse_num("0x1p-2"), Ok(NumValue::Float(0.25)));
+        assert_eq!(parse_num("0xA_0.0_4P+1"), Ok(NumValue::Float(320.03125)));
+        assert_eq!(parse_num("0x1p3f32"), Ok(NumValue::Float(8.0)));
+        assert_eq!(parse_num("0x1p99999"), Err(NumError::Overflow));
+        assert_eq!(parse_num("0x1p3u8"), Err(NumError::Malformed));
+        assert_eq!(parse_num("0x1p"), Err(NumError::Malformed));
+        assert_eq!(parse_num("0x.p1"), Err(NumError::Malformed));
+    }
+
+    #[test]
+    fn hex_float_rounding() {
+        let float = |value| Ok(NumValue::Float(value));
+        let one_plus = |ulps| float(f64::from_bits(1.0f64.to_bits() + ulps));
+        let subnormal = |bits| float(f64::from_bits(bits));
+
+        // ties round to even, digits past the tie round up
+        assert_eq!(parse_num("0x1.00000000000008p0"), one_plus(0));
+        assert_eq!(parse_num("0x1.00000000000018p0"), one_plus(2));
+        assert_eq!(parse_num("0x1.00000000000008000000001p0"), one_plus(1));
+        assert_eq!(parse_num("0x1.00000000000007ffffffffffp0"), one_plus(0));
+
+        assert_eq!(parse_num("0x10p-1078"), subnormal(1));
+        assert_eq!(parse_num("0x1.8p-1075"), subnormal(1));
+        assert_eq!(parse_num("0x1p-1075"), float(0.0));
+        assert_eq!(parse_num("0x1.000001p-1075"), subnormal(1));
+        assert_eq!(parse_num("0x3p-1075"), subnormal(2));
+        assert_eq!(
+            parse_num("0x1.fffffffffffffp-1023"),
+            float(f64::MIN_POSITIVE)
+        );
+        assert_eq!(parse_num("0x1p-99999"), float(0.0));
+        assert_eq!(parse_num("0x0.000p5"), float(0.0));
+
+        assert_eq!(parse_num("0x1.fffffffffffff7ffp1023"), float(f64::MAX));
+        assert_eq!(
+            parse_num("0x1.fffffffffffff8p1023"),
+            Err(NumError::Overflow)
+        );
+
+        // the mantissa alone doesn't fit in a float
+        let long = format!("0x1{}p-1200", "0".repeat(300));
+        assert_eq!(parse_num(&long), float(1.0));
+        let long = format!("0x0.{}1p1200", "0".repeat(299));
+        assert_eq!(parse_num(&long), float(1.0));
+    }
+
+    #[test]
+    fn invalid_literals() {
+        assert_eq!(parse_num("256u8"), Err(NumError::Overflow));
+        assert_eq!(parse_num("129i8"), Err(NumError::Overflow));
+        assert_eq!(
+            parse_num("340282366920938463463374607431768211456"),
+            Err(NumError::Overflow)
+        );
+        assert_eq!(parse_num("1e39f32"), Err(NumError::Overflow));
+        assert_eq!(parse_num("1e400"), Err(NumError::Overflow));
+        assert_eq!(parse_num("0x"), Err(NumError::Malformed));
+        assert_eq!(parse_num("0b__"), Err(NumError::Malformed));
+        assert_eq!(parse_num("1e"), Err(NumError::Malformed));
+        assert_eq!(parse_num("1.5u8"), Err(NumError::Malformed));
+        assert_eq!(parse_num("12abc"), Err(NumError::InvalidSuffix));
+    }
+
+    #[test]
+    fn side_table() {
+        let tokens = lex(FileId::default(), "a := 0x10 + 300u8 * 2.5 - 7abc;");
+        let num_values = tokens.num_values();
+
+        assert_eq!(num_values.get(3), Some(NumValue::Int(16)));
+        assert_eq!(num_values.get(5), None);
+        assert_eq!(num_values.get(7), Some(NumValue::Float(2.5)));
+        assert_eq!(num_values.get(9), None);
+        assert_eq!(num_values.values.len(), 2);
+
+        // invalid suffixes are only reported by the lexer
+        assert_eq!(num_values.errors.len(), 1);
+        assert_eq!(num_values.errors[0].kind, LexErrorKind::NumOverflow);
+        assert_eq!(num_values.errors[0].span.slice, "300u8");
+
+        // and so are missing digits
+        let tokens = lex(FileId::default(), "a := 0x + 1.5u8;");
+        let errors = tokens.num_values().errors;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::MalformedNum);
+        assert_eq!(errors[0].span.slice, "1.5u8");
+    }
+}
@@ -0,0 +1,194 @@
+//! Tokens that own their code, to be cached or sent to other threads.
+
+use super::{
+    trivia::{TokenTrivia, Trivia, TriviaKind},
+    LexError, LexErrorKind, LexOptions, Span, TokenSpan, TokenType, Tokens,
+};
+use crate::source::FileId;
+
+/// Span of code as byte offsets along with its position, resolved against the code of [`OwnedTokens`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OwnedSpan {
+    pub start: u32,
+    pub len: u32,
+    pub line: u32,
+    pub col: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedLexError {
+    pub kind: LexErrorKind,
+    pub span: OwnedSpan,
+    pub related: Option<OwnedSpan>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedTrivia {
+    pub kind: TriviaKind,
+    pub span: OwnedSpan,
+}
+
+/// Owned counterpart of [`Tokens`], with the spans of errors and trivia stored as offsets instead of slices.
+///
+/// Unlike [`Tokens`], it doesn't borrow the code and is backed by regular vectors,
+/// so it can be cached for as long as needed and sent to other threads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedTokens {
+    pub file: FileId,
+    pub code: String,
+    pub line_breaks: Vec<usize>,
+    pub spans: Vec<Span>,
+    pub types: Vec<TokenType>,
+    pub errors: Vec<OwnedLexError>,
+    pub options: LexOptions,
+    pub trivia: Vec<OwnedTrivia>,
+    pub token_trivia: Vec<TokenTrivia>,
+}
+
+impl OwnedTokens {
+    /// Copies the tokens along with their code.
+    pub fn from_tokens(tokens: &Tokens) -> Self {
+        let to_owned = |span: &TokenSpan| OwnedSpan {
+            start: span.start as u32,
+            len: span.slice.len() as u32,
+            line: span.line as u32,
+            col: span.col as u32,
+        };
+
+        Self {
+            file: tokens.file,
+            code: tokens.code.to_string(),
+            line_breaks: tokens.line_breaks.as_slice().to_vec(),
+            spans: tokens.spans.as_slice().to_vec(),
+            types: tokens.types.as_slice().to_vec(),
+            errors: (tokens.errors.iter())
+                .map(|error| OwnedLexError {
+                    kind: error.kind,
+                    span: to_owned(&error.span),
+                    related: error.related.as_ref().map(to_owned),
+                })
+                .collect(),
+            options: tokens.options,
+            trivia: (tokens.trivia.iter())
+                .map(|trivia| OwnedTrivia {
+                    kind: trivia.kind,
+                    span: to_owned(&trivia.span),
+                })
+                .collect(),
+            token_trivia: tokens.token_trivia.as_slice().to_vec(),
+        }
+    }
+
+    /// Slice of the code covered by a span.
+    #[inline]
+    pub fn slice(&self, span: &OwnedSpan) -> &str {
+        let start = span.start as usize;
+        &self.code[start..start + span.len as usize]
+    }
+
+    /// Borrowed span of the code covered by a span.
+    #[inline]
+    pub fn span(&self, span: &OwnedSpan) -> TokenSpan<'_> {
+        let (start, line, col) = (span.start as usize, span.line as usize, span.col as usize);
+        TokenSpan::new(self.slice(span), start, line, col)
+    }
+
+    /// Borrowed [`Tokens`] over the owned code, to use the APIs that work on them.
+    pub fn tokens(&self) -> Tokens<'_> {
+        let mut tokens = Tokens::empty(self.file, &self.code, self.options);
+
+        for &line_break in &self.line_breaks {
+            tokens.line_breaks.add(line_break);
+        }
+        for (&ty, &span) in self.types.iter().zip(&self.spans) {
+            tokens.types.add(ty);
+            tokens.spans.add(span);
+        }
+        for error in &self.errors {
+            tokens.errors.add(LexError {
+                file: self.file,
+                kind: error.kind,
+                span: self.span(&error.span),
+                related: error.related.as_ref().map(|span| self.span(span)),
+            });
+        }
+        for trivia in &self.trivia {
+            tokens.trivia.add(Trivia {
+                kind: trivia.kind,
+                span: self.span(&trivia.span),
+            });
+        }
+        for token_trivia in &self.token_trivia {
+            tokens.token_trivia.push(token_trivia.clone());
+        }
+
+        tokens
+    }
+}
+
+impl From<&Tokens<'_>> for OwnedTokens {
+    #[inline]
+    fn from(tokens: &Tokens<'_>) -> Self {
+        Self::from_tokens(tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests_owned {
+    use std::thread;
+
+    use crate::{
+        lexer::{lex, lex_with_mode, owned::OwnedTokens, LexMode, Tokens},
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+    fn assert_same_tokens(a: &Tokens, b: &Tokens) {
+        assert_eq!(a.types.as_slice(), b.types.as_slice());
+        let spans = |tokens: &Tokens| -> Vec<_> {
+            let spans = tokens.iter_spans();
+            spans
+                .map(|s| (s.slice.to_string(), s.line, s.col))
+                .collect()
+        };
+        assert_eq!(spans(a), spans(b));
+        assert_eq!(a.line_breaks.as_slice(), b.line_breaks.as_slice());
+
+        let errors =
+            |tokens: &Tokens| -> Vec<_> { tokens.errors.iter().map(|e| e.to_string()).collect() };
+        assert_eq!(errors(a), errors(b));
+    }
+
+    #[test]
+    fn outlives_the_code() {
+        let owned = {
+            let code = String::from("a := \"b\" + 'cd';\n");
+            let tokens = lex(FileId::default(), &code);
+            OwnedTokens::from(&tokens)
+        };
+
+        let owned = thread::spawn(move || owned).join().unwrap();
+        assert_eq!(&owned.code[owned.spans[3].range()], "\"b\"");
+        assert_eq!(owned.errors.len(), 1);
+        assert_same_tokens(
+            &owned.tokens(),
+            &lex(FileId::default(), "a := \"b\" + 'cd';\n"),
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let tokens = lex_with_mode(FileId::default(), SAMPLE, LexMode::PreserveTrivia);
+        let owned = OwnedTokens::from_tokens(&tokens);
+        let borrowed = owned.tokens();
+
+        assert_same_tokens(&borrowed, &tokens);
+        assert_eq!(borrowed.trivia.len(), tokens.trivia.len());
+        assert_eq!(
+            borrowed.leading_trivia(5).len(),
+            tokens.leading_trivia(5).len()
+        );
+        assert_eq!(OwnedTokens::from_tokens(&borrowed), owned);
+    }
+}
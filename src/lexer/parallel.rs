@@ -0,0 +1,251 @@
+//! Lexing of large files on multiple threads.
+
+use std::thread;
+
+use super::{
+    add_eof,
+    cursor::{Cursor, Mark},
+    lex_token, lex_with_options, reject_too_large,
+    relex::mark_at,
+    skip_whitespace, LexMode, LexOptions, Tokens,
+};
+use crate::source::FileId;
+
+/// Files smaller than this many bytes per thread are not worth splitting.
+const MIN_CHUNK_LEN: usize = 64 * 1024;
+
+/// Lexes the code on as many threads as available, giving the same result as [`lex_with_options`].
+///
+/// The code is split in chunks at line starts, which are lexed independently and stitched
+/// back together. Chunks that turn out not to start outside of a token (like in the middle of
+/// a multi-line string) are lexed again from where the previous chunk actually ended.
+pub fn lex_parallel(file: FileId, code: &str, options: LexOptions) -> Tokens<'_> {
+    let thread_count = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_count = thread_count.min(code.len() / MIN_CHUNK_LEN);
+    lex_in_chunks(file, code, options, chunk_count)
+}
+
+/// Lexed chunk of the code, with lines counted from the start of the chunk.
+struct Chunk<'a> {
+    start: usize,
+    end: usize,
+    tokens: Tokens<'a>,
+    /// Position after the whitespace at the start of the chunk
+    first_token_pos: usize,
+    /// Number of line breaks and trivia in the whitespace at the start of the chunk
+    leading_lens: (usize, usize),
+    /// Position where lexing stopped, at or after the end of the chunk
+    end_pos: usize,
+}
+
+fn lex_in_chunks(file: FileId, code: &str, options: LexOptions, chunk_count: usize) -> Tokens<'_> {
+    if let Some(tokens) = reject_too_large(file, code, options, &options.vm_alloc()) {
+        return tokens;
+    }
+
+    let bounds = chunk_bounds(code, chunk_count);
+    if bounds.len() <= 1 {
+        return lex_with_options(file, code, options);
+    }
+
+    let mut chunks: Vec<Chunk> = thread::scope(|scope| {
+        let handles: Vec<_> = (bounds.iter())
+            .map(|&(start, end)| scope.spawn(move || lex_chunk(file, code, options, start, end)))
+            .collect();
+
+        let chunks = handles.into_iter().map(|handle| handle.join().unwrap());
+        chunks.collect()
+    });
+
+    // the first chunk starts at the start of the code, so it is always valid
+    let first = chunks.remove(0);
+    let mut tokens = first.tokens;
+    let mut pos = first.end_pos;
+
+    for chunk in chunks {
+        if pos == chunk.start || pos == chunk.first_token_pos {
+            pos = append_chunk(&tokens, chunk, pos);
+        } else {
+            // the previous chunk spilled over this one, lex it again from where it stopped
+            let mut cursor = Cursor::at(code, mark_at(tokens.line_breaks.as_slice(), pos));
+            pos = lex_until(&mut cursor, &mut tokens, chunk.end);
+        }
+    }
+    add_eof(&mut tokens);
+    tokens.match_delimiters();
+
+    if options.mode == LexMode::PreserveTrivia {
+        tokens.attach_trivia();
+    }
+
+    tokens
+}
+
+/// Splits the code into ranges that start right after a `\n`.
+fn chunk_bounds(code: &str, chunk_count: usize) -> Vec<(usize, usize)> {
+    let bytes = code.as_bytes();
+    let target_len = code.len() / chunk_count.max(1);
+
+    let mut bounds = Vec::with_capacity(chunk_count);
+    let mut start = 0;
+    while start < code.len() {
+        let target = (start + target_len).min(code.len());
+        let end = match bytes[target..].iter().position(|&b| b == b'\n') {
+            Some(lf) if bounds.len() + 1 < chunk_count => target + lf + 1,
+            _ => code.len(),
+        };
+
+        bounds.push((start, end));
+        start = end;
+    }
+    bounds
+}
+
+fn lex_chunk(file: FileId, code: &str, options: LexOptions, start: usize, end: usize) -> Chunk<'_> {
+    let mut tokens = Tokens::empty(file, code, options);
+    tokens.origin = Mark {
+        pos: start,
+        line: 1,
+        col: 0,
+    };
+    let mut cursor = Cursor::at(code, tokens.origin);
+
+    skip_whitespace(&mut cursor, &mut tokens);
+    let first_token_pos = cursor.pos();
+    let leading_lens = (tokens.line_breaks.len(), tokens.trivia.len());
+
+    let end_pos = lex_until(&mut cursor, &mut tokens, end);
+    Chunk {
+        start,
+        end,
+        tokens,
+        first_token_pos,
+        leading_lens,
+        end_pos,
+    }
+}
+
+/// Lexes tokens until the cursor is at or after `end`, returning where it stopped.
+///
+/// Whitespace is skipped before checking, so that the stopping position
+/// is the same no matter where the lexing started.
+fn lex_until<'a>(cursor: &mut Cursor<'a>, tokens: &mut Tokens<'a>, end: usize) -> usize {
+    loop {
+        skip_whitespace(cursor, tokens);
+        if cursor.pos() >= end || cursor.is_eof() {
+            return cursor.pos();
+        }
+        lex_token(cursor, tokens);
+    }
+}
+
+/// Appends a chunk, rebasing its lines, and returns where it stopped.
+///
+/// If the previous chunk already consumed the whitespace at the start of this one,
+/// its line breaks and trivia are skipped.
+fn append_chunk<'a>(tokens: &Tokens<'a>, mut chunk: Chunk<'a>, pos: usize) -> usize {
+    let line_offset = (tokens.line_breaks.as_slice()).partition_point(|&lb| lb < chunk.start);
+    let (line_breaks_skip, trivia_skip) = match pos == chunk.start {
+        true => (0, 0),
+        false => chunk.leading_lens,
+    };
+
+    for &line_break in &chunk.tokens.line_breaks.as_slice()[line_breaks_skip..] {
+        tokens.line_breaks.add(line_break);
+    }
+    // token spans are only offsets, their lines are found from the merged line breaks
+    for (&ty, &span) in chunk.tokens.types.iter().zip(chunk.tokens.spans.iter()) {
+        tokens.types.add(ty);
+        tokens.spans.add(span);
+    }
+    for error in chunk.tokens.errors.iter_mut() {
+        error.span.line += line_offset;
+        tokens.errors.add(*error);
+    }
+    for trivia in &mut chunk.tokens.trivia.as_mut_slice()[trivia_skip..] {
+        trivia.span.line += line_offset;
+        tokens.trivia.add(*trivia);
+    }
+
+    chunk.end_pos
+}
+
+#[cfg(test)]
+mod tests_parallel {
+    use crate::{
+        lexer::{
+            lex_parallel, lex_with_options,
+            parallel::{chunk_bounds, lex_in_chunks},
+            LexMode, LexOptions, Tokens,
+        },
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+    fn summary(tokens: &Tokens) -> Vec<String> {
+        let tokens_summary = (tokens.types.iter().zip(tokens.iter_spans()))
+            .map(|(ty, s)| format!("{ty:?} {:?} {}:{}", s.slice, s.line, s.col));
+        let errors = tokens.errors.iter().map(|e| e.to_string());
+        let trivia = (tokens.trivia.iter()).map(|t| {
+            format!(
+                "{:?} {:?} {}:{}",
+                t.kind, t.span.slice, t.span.line, t.span.col
+            )
+        });
+        let line_breaks = tokens.line_breaks.iter().map(|lb| lb.to_string());
+        let token_trivia = tokens.token_trivia.iter().map(|t| format!("{t:?}"));
+
+        (tokens_summary.chain(errors).chain(trivia))
+            .chain(line_breaks)
+            .chain(token_trivia)
+            .collect()
+    }
+
+    fn check_chunks(code: &str, mode: LexMode) {
+        let options = LexOptions {
+            mode,
+            ..LexOptions::default()
+        };
+        let expected = summary(&lex_with_options(FileId::default(), code, options));
+
+        for chunk_count in 1..=17 {
+            let tokens = lex_in_chunks(FileId::default(), code, options, chunk_count);
+            assert_eq!(summary(&tokens), expected, "{chunk_count} chunks");
+        }
+    }
+
+    #[test]
+    fn same_as_lexing_everything() {
+        check_chunks(SAMPLE, LexMode::Normal);
+        check_chunks(SAMPLE, LexMode::PreserveTrivia);
+    }
+
+    #[test]
+    fn chunks_inside_tokens() {
+        let code = "a := \"\nb\n\nc\n\";\n  $\"x {\n y\n}\"\r\n// \"\n\n   d /// e\n\n'f\ng';\n";
+        check_chunks(code, LexMode::Normal);
+        check_chunks(code, LexMode::PreserveTrivia);
+    }
+
+    #[test]
+    fn bounds_start_at_lines() {
+        let bounds = chunk_bounds(SAMPLE, 8);
+        assert_eq!(bounds.len(), 8);
+        assert_eq!(bounds[0].0, 0);
+        assert_eq!(bounds[7].1, SAMPLE.len());
+        for window in bounds.windows(2) {
+            assert_eq!(window[0].1, window[1].0);
+            assert_eq!(SAMPLE.as_bytes()[window[1].0 - 1], b'\n');
+        }
+    }
+
+    #[test]
+    fn large_file() {
+        let code = SAMPLE.repeat(200);
+        let options = LexOptions::default();
+        let tokens = lex_parallel(FileId::default(), &code, options);
+        let expected = lex_with_options(FileId::default(), &code, options);
+        assert_eq!(summary(&tokens), summary(&expected));
+    }
+}
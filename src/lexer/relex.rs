@@ -0,0 +1,477 @@
+//! Incremental re-lexing of edited code.
+
+use std::ops::Range;
+
+use super::{
+    add_eof,
+    cursor::{Cursor, Mark},
+    lex_token, LexMode, Span, TokenEdits, TokenSpan, TokenType, Tokens,
+};
+
+/// An edit of the code, replacing a range of it with new text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// Byte range of the old code that got replaced
+    pub range: Range<usize>,
+    /// Length in bytes of the text that replaced it
+    pub new_len: usize,
+}
+
+/// How much a token changes the nesting depth of interpolated strings and attributes.
+#[inline]
+fn nesting_depth_delta(ty: TokenType) -> isize {
+    match ty {
+        TokenType::StringInterpBeg | TokenType::AttrStart | TokenType::InnerAttrStart => 1,
+        TokenType::StringInterpEnd | TokenType::AttrEnd => -1,
+        _ => 0,
+    }
+}
+
+/// Previous index before `idx` where re-lexing can restart, outside of any nesting
+/// like `idx`. The tokens between them are lexed by a single [`lex_token`].
+fn previous_top_level(types: &[TokenType], idx: usize) -> usize {
+    let mut prev = idx - 1;
+    let mut depth = -nesting_depth_delta(types[prev]);
+    while prev > 0 && depth != 0 {
+        prev -= 1;
+        depth -= nesting_depth_delta(types[prev]);
+    }
+    prev
+}
+
+/// Finds the line and column of a byte offset using a sorted line break table.
+pub(super) fn mark_at(line_breaks: &[usize], pos: usize) -> Mark {
+    let idx = line_breaks.partition_point(|&lb| lb < pos);
+    let line_start = if idx == 0 {
+        0
+    } else {
+        line_breaks[idx - 1] + 1
+    };
+
+    Mark {
+        pos,
+        line: idx + 1,
+        col: pos - line_start,
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Re-lexes only the region of the code affected by an edit.
+    ///
+    /// `code` is the entire code after the edit. Tokens before the edit are kept
+    /// unless they looked at the edited code, and lexing stops as soon as it gets back in sync with the old tokens after the edit,
+    /// which are kept as well. All spans are moved to point into the new code.
+    ///
+    /// Returns which tokens got replaced.
+    pub fn relex_range(&mut self, code: &'a str, edit: TextEdit) -> TokenEdits {
+        let old_code = self.code;
+        let delta = code.len() as isize - old_code.len() as isize;
+        debug_assert_eq!(delta, edit.new_len as isize - edit.range.len() as isize);
+
+        let offset_of = |span: &TokenSpan| span.start;
+        let end_of = |span: &Span| span.end();
+
+        let old_types = self.types.as_slice();
+        let old_spans = self.spans.as_slice();
+        let old_line_breaks = self.line_breaks.as_slice();
+
+        // restart right after the last top-level token that ends before the edit
+        let mut restart = old_spans.partition_point(|span| end_of(span) < edit.range.start);
+        let mut depth: isize = old_types[..restart]
+            .iter()
+            .copied()
+            .map(nesting_depth_delta)
+            .sum();
+        while restart > 0 && depth > 0 {
+            restart -= 1;
+            depth -= nesting_depth_delta(old_types[restart]);
+        }
+
+        // tokens can depend on code past their end (like `0` in `0..`, `#` in `#![`, or `0x1`
+        // in `0x1.8p3`), so the ones before that looked at the edit are lexed again. Lexing
+        // never looks past whitespace, so the old tokens are lexed again from whitespace on
+        // to see how far they looked
+        let pos_of = |idx: usize| match idx {
+            0 => 0,
+            _ => end_of(&old_spans[idx - 1]),
+        };
+        let is_followed_by_whitespace =
+            |idx| (old_code.as_bytes().get(pos_of(idx))).is_some_and(u8::is_ascii_whitespace);
+        let mut anchor = restart;
+        while anchor > 0 && !is_followed_by_whitespace(anchor) {
+            anchor = previous_top_level(old_types, anchor);
+        }
+
+        let mut old_tokens = Tokens::empty(self.file, old_code, self.options);
+        let mut cursor = Cursor::at(old_code, mark_at(old_line_breaks, pos_of(anchor)));
+        let max_restart = restart;
+        restart = anchor;
+        while restart < max_restart {
+            lex_token(&mut cursor, &mut old_tokens);
+            if cursor.lookahead_end() > edit.range.start {
+                break;
+            }
+            restart = anchor + old_tokens.types.len();
+        }
+        let restart_pos = pos_of(restart);
+
+        // lex until the end of a new token lines up with the end of an old top-level token
+        let mut new_tokens = Tokens::empty(self.file, code, self.options);
+        // spans are resolved while lexing (for errors), so the lines before have to be known
+        let kept_line_breaks = old_line_breaks.partition_point(|&lb| lb < restart_pos);
+        for &lb in &old_line_breaks[..kept_line_breaks] {
+            new_tokens.line_breaks.add(lb);
+        }
+        let mut cursor = Cursor::at(code, mark_at(old_line_breaks, restart_pos));
+
+        let edit_end = edit.range.start + edit.new_len;
+        let mut old_idx = restart;
+        let mut old_depth = 0;
+        let mut sync = None;
+        while !cursor.is_eof() {
+            lex_token(&mut cursor, &mut new_tokens);
+
+            let new_end = cursor.pos();
+            if new_end < edit_end {
+                continue;
+            }
+
+            let old_end = (new_end as isize - delta) as usize;
+            while old_idx < old_spans.len() && end_of(&old_spans[old_idx]) < old_end {
+                old_depth += nesting_depth_delta(old_types[old_idx]);
+                old_idx += 1;
+            }
+
+            if old_idx < old_spans.len()
+                && end_of(&old_spans[old_idx]) == old_end
+                && old_depth + nesting_depth_delta(old_types[old_idx]) == 0
+            {
+                sync = Some((
+                    old_idx + 1,
+                    mark_at(old_line_breaks, old_end),
+                    cursor.mark(),
+                ));
+                break;
+            }
+        }
+
+        // move the remaining old tokens after the edit
+        let tail_start = sync.map_or(old_spans.len(), |(tail_start, _, _)| tail_start);
+        let rebase_span = |span: &Span| Span {
+            start: (span.start as isize + delta) as u32,
+            len: span.len,
+        };
+        let rebase = |span: &TokenSpan<'a>| -> TokenSpan<'a> {
+            let (_, old_sync, new_sync) = sync.unwrap();
+            let pos = (offset_of(span) as isize + delta) as usize;
+            let col = match span.line == old_sync.line {
+                true => span.col - old_sync.col + new_sync.col,
+                false => span.col,
+            };
+            let line = span.line - old_sync.line + new_sync.line;
+            TokenSpan::new(&code[pos..pos + span.slice.len()], pos, line, col)
+        };
+
+        let tail_types = old_types[tail_start..].to_vec();
+        let tail_spans: Vec<_> = old_spans[tail_start..].iter().map(rebase_span).collect();
+
+        let (tail_line_breaks, tail_errors, tail_trivia) = match sync {
+            Some((_, old_sync, _)) => {
+                let lb_idx = old_line_breaks.partition_point(|&lb| lb < old_sync.pos);
+                let tail_line_breaks: Vec<_> = old_line_breaks[lb_idx..]
+                    .iter()
+                    .map(|&lb| (lb as isize + delta) as usize)
+                    .collect();
+
+                let errors = self.errors.as_slice();
+                let err_idx = errors.partition_point(|err| offset_of(&err.span) < old_sync.pos);
+                let mut tail_errors = errors[err_idx..].to_vec();
+                for error in &mut tail_errors {
+                    error.span = rebase(&error.span);
+                }
+
+                let trivia = self.trivia.as_slice();
+                let trivia_idx = trivia.partition_point(|t| offset_of(&t.span) < old_sync.pos);
+                let mut tail_trivia = trivia[trivia_idx..].to_vec();
+                for trivia in &mut tail_trivia {
+                    trivia.span = rebase(&trivia.span);
+                }
+
+                (tail_line_breaks, tail_errors, tail_trivia)
+            }
+            None => (Vec::new(), Vec::new(), Vec::new()),
+        };
+
+        // splice everything together
+        let err_idx = self
+            .errors
+            .as_slice()
+            .partition_point(|err| offset_of(&err.span) < restart_pos);
+        let trivia_idx = self
+            .trivia
+            .as_slice()
+            .partition_point(|t| offset_of(&t.span) < restart_pos);
+
+        self.code = code;
+        self.types.truncate(restart);
+        self.spans.truncate(restart);
+        self.line_breaks.truncate(kept_line_breaks);
+        self.errors.truncate(err_idx);
+        self.trivia.truncate(trivia_idx);
+
+        // the code before the edit hasn't changed, but it's in another buffer now
+        let move_to_new_code = |span: &mut TokenSpan<'a>| {
+            let pos = offset_of(span);
+            span.slice = &code[pos..pos + span.slice.len()];
+        };
+        self.errors
+            .iter_mut()
+            .for_each(|err| move_to_new_code(&mut err.span));
+        self.trivia
+            .iter_mut()
+            .for_each(|t| move_to_new_code(&mut t.span));
+
+        // the old end of file token is kept with the tail, if there is one
+        if tail_types.is_empty() {
+            add_eof(&mut new_tokens);
+        }
+
+        let new_len = new_tokens.types.len();
+        for &ty in new_tokens.types.iter().chain(&tail_types) {
+            self.types.add(ty);
+        }
+        for &span in new_tokens.spans.iter().chain(&tail_spans) {
+            self.spans.add(span);
+        }
+        let new_line_breaks = &new_tokens.line_breaks.as_slice()[kept_line_breaks..];
+        for &lb in new_line_breaks.iter().chain(&tail_line_breaks) {
+            self.line_breaks.add(lb);
+        }
+        for error in new_tokens.errors.iter().chain(&tail_errors) {
+            self.errors.add(*error);
+        }
+        for trivia in new_tokens.trivia.iter().chain(&tail_trivia) {
+            self.trivia.add(*trivia);
+        }
+
+        // an edit can (un)balance delimiters anywhere in the code
+        self.match_delimiters();
+
+        if self.options.mode == LexMode::PreserveTrivia {
+            self.attach_trivia();
+        }
+
+        TokenEdits {
+            old: restart..tail_start,
+            new: restart..restart + new_len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_relex {
+    use crate::{
+        lexer::{lex, lex_with_mode, LexMode, TextEdit, Tokens},
+        source::FileId,
+    };
+
+    fn assert_same_tokens(relexed: &Tokens, lexed: &Tokens) {
+        let relexed_spans: Vec<_> = relexed
+            .iter_spans()
+            .map(|s| (s.slice, s.line, s.col))
+            .collect();
+        let lexed_spans: Vec<_> = lexed
+            .iter_spans()
+            .map(|s| (s.slice, s.line, s.col))
+            .collect();
+        assert_eq!(relexed_spans, lexed_spans);
+        assert_eq!(relexed.types.as_slice(), lexed.types.as_slice());
+        assert_eq!(relexed.line_breaks.as_slice(), lexed.line_breaks.as_slice());
+
+        let relexed_errors: Vec<_> = relexed.errors.iter().map(|e| e.to_string()).collect();
+        let lexed_errors: Vec<_> = lexed.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(relexed_errors, lexed_errors);
+
+        for span in relexed
+            .iter_spans()
+            .chain(relexed.errors.iter().map(|e| e.span))
+        {
+            assert_eq!(&relexed.code[span.range()], span.slice);
+        }
+    }
+
+    /// Replaces `old_text` (at its first occurrence) with `new_text` and checks
+    /// that re-lexing gives the same result as lexing from scratch.
+    fn check_edit(code: &str, old_text: &str, new_text: &str) -> (usize, usize) {
+        let start = code.find(old_text).unwrap();
+        let new_code = format!(
+            "{}{new_text}{}",
+            &code[..start],
+            &code[start + old_text.len()..]
+        );
+
+        let mut tokens = lex(FileId::default(), code);
+        let edits = tokens.relex_range(
+            &new_code,
+            TextEdit {
+                range: start..start + old_text.len(),
+                new_len: new_text.len(),
+            },
+        );
+
+        assert_same_tokens(&tokens, &lex(FileId::default(), &new_code));
+        (edits.old.len(), edits.new.len())
+    }
+
+    const CODE: &str = "main :: fn()\n{\n    a := 1 + 2;\n    s := $\"x {a} y {b}\";\n    // note\n    c := 'c';\n}\n";
+
+    #[test]
+    fn local_edits_stay_local() {
+        assert_eq!(check_edit(CODE, "1 + 2", "1 * 2"), (3, 3));
+        assert_eq!(check_edit(CODE, "c := ", "c := 3 + "), (4, 6));
+    }
+
+    #[test]
+    fn line_changes() {
+        check_edit(CODE, "a := 1", "a\n\n:= 1");
+        check_edit(CODE, ";\n    s", "; s");
+        check_edit(CODE, "\n}", "}");
+    }
+
+    #[test]
+    fn merged_tokens() {
+        check_edit(CODE, "main ::", "main:");
+        check_edit(CODE, "a := 1", "a := 12");
+        check_edit(CODE, "a ", "ab ");
+    }
+
+    #[test]
+    fn edits_in_strings_and_comments() {
+        check_edit(CODE, "{a}", "{a + x}");
+        check_edit(CODE, " y {b}", "");
+        check_edit(CODE, "x {", "\" x {");
+        check_edit(CODE, "// note", "note");
+        check_edit(CODE, "    c", "    // c");
+    }
+
+    #[test]
+    fn edits_at_boundaries() {
+        check_edit(CODE, "main", "");
+        check_edit(CODE, "main", "pub main");
+        check_edit(CODE, "}\n", "} extra;");
+        check_edit(CODE, "'c'", "'c");
+    }
+
+    #[test]
+    fn edits_in_attributes() {
+        let code = "#[repr(packed)]\nS :: struct {}\n#[a] b;\n";
+        check_edit(code, "packed", "C");
+        check_edit(code, ")]", ")");
+        check_edit(code, "#[a]", "#[a");
+        check_edit(code, "[a]", "a]");
+    }
+
+    #[test]
+    fn edits_unbalancing_delimiters() {
+        check_edit(CODE, "fn()", "fn(");
+        check_edit(CODE, "\n}", "\n");
+        check_edit(CODE, "{\n", "{\n)");
+        check_edit("a(b]\n", "]", ")");
+    }
+
+    #[test]
+    fn trivia_is_spliced() {
+        let code = "a := 1; // one\nb := 2;\n";
+        let new_code = "a := 1; // one\nb := 3;\n";
+
+        let mut tokens = lex_with_mode(FileId::default(), code, LexMode::PreserveTrivia);
+        let start = code.find('2').unwrap();
+        let edit = TextEdit {
+            range: start..start + 1,
+            new_len: 1,
+        };
+        tokens.relex_range(new_code, edit);
+
+        let lexed = lex_with_mode(FileId::default(), new_code, LexMode::PreserveTrivia);
+        assert_same_tokens(&tokens, &lexed);
+
+        let trivia = |tokens: &Tokens| -> Vec<_> {
+            let trivia = tokens.trivia.iter();
+            trivia
+                .map(|t| (t.kind, t.span.slice.to_string(), t.span.line))
+                .collect()
+        };
+        assert_eq!(trivia(&tokens), trivia(&lexed));
+        assert_eq!(
+            tokens.token_trivia.as_slice(),
+            lexed.token_trivia.as_slice()
+        );
+    }
+
+    #[test]
+    fn edits_past_lookahead() {
+        // `#` looks two bytes further for `#![`
+        assert_eq!(check_edit("#!a", "a", "[a"), (3, 2));
+        check_edit("#![a]", "!", "");
+        check_edit("#![a]", "[", " [");
+        check_edit("# [a]", " ", "!");
+        check_edit("b #!\n[a]", "\n", "");
+        check_edit("a #", "#", "#![b]");
+        check_edit("r a", " ", "#");
+        check_edit("x := r#a;", "#", "");
+        // `0` looks two bytes further for `..`
+        check_edit("0..", "..", "..if");
+        check_edit("a := 0..b;", ".b", "b");
+        check_edit("1.", "", "1.");
+        // `0x1` looks at all the hex digits after a `.` for a `p`
+        check_edit("0x1.8", "8", "8p3");
+        check_edit("0x1.f_ffp3", "p", "");
+        check_edit("x := 0x1.abs();", "abs()", "a_bp-1");
+    }
+
+    /// Checks inserting and deleting every snippet at every position of every snippet.
+    fn check_all_edits(snippets: &[&str]) {
+        for code in snippets {
+            for start in (0..=code.len()).filter(|&i| code.is_char_boundary(i)) {
+                for new_text in snippets.iter().copied().chain([""]) {
+                    for end in (start..=code.len()).filter(|&i| code.is_char_boundary(i)) {
+                        let new_code = format!("{}{new_text}{}", &code[..start], &code[end..]);
+                        let mut tokens = lex(FileId::default(), code);
+                        let edit = TextEdit {
+                            range: start..end,
+                            new_len: new_text.len(),
+                        };
+                        tokens.relex_range(&new_code, edit);
+                        assert_same_tokens(&tokens, &lex(FileId::default(), &new_code));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn all_small_edits() {
+        check_all_edits(&["#!a]", "r#b", "a:b", "//!c\n", "$\"{d}\"", "[", "#", " "]);
+        check_all_edits(&["0..", "1.e", "x", ".", "2e-3"]);
+        check_all_edits(&["0x1.8p3", "0xa.b", "p", "1"]);
+    }
+
+    #[test]
+    fn deletions_in_sample_file() {
+        const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+        let boundaries = (0..SAMPLE.len()).filter(|&i| SAMPLE.is_char_boundary(i));
+        for start in boundaries.step_by(13) {
+            let len = SAMPLE[start..].chars().next().unwrap().len_utf8();
+            let new_code = format!("{}{}", &SAMPLE[..start], &SAMPLE[start + len..]);
+
+            let mut tokens = lex(FileId::default(), SAMPLE);
+            let edit = TextEdit {
+                range: start..start + len,
+                new_len: 0,
+            };
+            tokens.relex_range(&new_code, edit);
+            assert_same_tokens(&tokens, &lex(FileId::default(), &new_code));
+        }
+    }
+}
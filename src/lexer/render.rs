@@ -0,0 +1,309 @@
+//! Syntax highlighting of lexed code, for terminals and web pages.
+
+use std::io::{self, Write};
+
+use super::{TokenType, Tokens};
+use crate::style::{Painter, Style};
+
+/// Format in which highlighted code can be rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RenderFormat {
+    /// Code colored with ANSI escape sequences, for terminals
+    Ansi,
+    /// Standalone HTML document, see [`Tokens::write_html`]
+    Html,
+}
+
+/// Category of tokens that are highlighted the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Highlight {
+    Keyword,
+    Operator,
+    Punctuation,
+    Attribute,
+    /// Strings and their interpolated segments
+    String,
+    Char,
+    Number,
+    Ident,
+    DocComment,
+    /// Regular comments, which are not tokens
+    Comment,
+    Error,
+}
+
+impl Highlight {
+    /// CSS class of the category in HTML output.
+    pub const fn css_class(self) -> &'static str {
+        match self {
+            Highlight::Keyword => "sus-keyword",
+            Highlight::Operator => "sus-operator",
+            Highlight::Punctuation => "sus-punctuation",
+            Highlight::Attribute => "sus-attribute",
+            Highlight::String => "sus-string",
+            Highlight::Char => "sus-char",
+            Highlight::Number => "sus-number",
+            Highlight::Ident => "sus-ident",
+            Highlight::DocComment => "sus-doc-comment",
+            Highlight::Comment => "sus-comment",
+            Highlight::Error => "sus-error",
+        }
+    }
+
+    /// SGR parameters of the category in ANSI output, `None` for uncolored text.
+    pub const fn ansi_style(self) -> Option<&'static str> {
+        match self {
+            Highlight::Keyword => Some("35"),
+            Highlight::Attribute => Some("36"),
+            Highlight::String | Highlight::Char => Some("32"),
+            Highlight::Number => Some("33"),
+            Highlight::DocComment => Some("3;32"),
+            Highlight::Comment => Some("90"),
+            Highlight::Error => Some("4;31"),
+            Highlight::Operator | Highlight::Punctuation | Highlight::Ident => None,
+        }
+    }
+}
+
+impl TokenType {
+    /// How the token is highlighted, `None` for [`TokenType::Eof`].
+    pub const fn highlight(self) -> Option<Highlight> {
+        use TokenType as T;
+
+        Some(match self {
+            T::And | T::Or | T::Xor | T::Not | T::Pub => Highlight::Keyword,
+            T::Packed | T::Struct | T::Enum | T::Union => Highlight::Keyword,
+            T::Fn | T::Defer | T::If | T::Then | T::Else => Highlight::Keyword,
+            T::While | T::Do | T::Loop | T::Continue | T::Break => Highlight::Keyword,
+            T::For | T::In => Highlight::Keyword,
+            T::Match | T::Return | T::Use | T::Const | T::Static => Highlight::Keyword,
+            T::True | T::False | T::None => Highlight::Keyword,
+
+            T::Equals | T::NotEquals | T::LessThan | T::GreaterThan => Highlight::Operator,
+            T::LessEqual | T::GreaterEqual | T::Feather | T::Arrow => Highlight::Operator,
+            T::FatArrow | T::Ampersand | T::Pipe | T::Caret | T::Tilde => Highlight::Operator,
+            T::LShift | T::RShift | T::Incr | T::Decr | T::Plus => Highlight::Operator,
+            T::Minus | T::Mul | T::Div | T::Pow | T::Modulo | T::Equal => Highlight::Operator,
+
+            T::Semi | T::Colon | T::DoubleColon | T::Comma | T::Dot => Highlight::Punctuation,
+            T::Question | T::Hash | T::At => Highlight::Punctuation,
+            T::LParens | T::RParens | T::LBracket | T::RBracket => Highlight::Punctuation,
+            T::LBrace | T::RBrace => Highlight::Punctuation,
+
+            T::AttrStart | T::InnerAttrStart | T::AttrEnd => Highlight::Attribute,
+
+            T::String | T::ByteString | T::CString => Highlight::String,
+            T::StringInterpBeg | T::StringInterpMid | T::StringInterpEnd => Highlight::String,
+            T::FormatSpec => Highlight::String,
+            T::Char | T::ByteChar => Highlight::Char,
+            T::Num => Highlight::Number,
+            T::Ident => Highlight::Ident,
+            T::DocComment | T::ModuleDocComment => Highlight::DocComment,
+            T::Error => Highlight::Error,
+            T::Eof => return None,
+        })
+    }
+
+    /// CSS class of the token type in HTML output, like `sus-string-interp-beg`.
+    pub fn css_class(self) -> String {
+        let mut class = String::from("sus");
+        for ch in format!("{self:?}").chars() {
+            if ch.is_ascii_uppercase() {
+                class.push('-');
+            }
+            class.push(ch.to_ascii_lowercase());
+        }
+        class
+    }
+}
+
+/// Style sheet of the categories in HTML output, embedded in [`Tokens::write_html`].
+pub const HTML_STYLE: &str = "\
+.sus { background: #1e1e2e; color: #cdd6f4; padding: 1em; }
+.sus-keyword { color: #cba6f7; }
+.sus-operator, .sus-punctuation { color: #9399b2; }
+.sus-attribute { color: #94e2d5; }
+.sus-string, .sus-char { color: #a6e3a1; }
+.sus-number { color: #fab387; }
+.sus-doc-comment { color: #a6e3a1; font-style: italic; }
+.sus-comment { color: #6c7086; }
+.sus-error { color: #f38ba8; text-decoration: wavy underline; }
+";
+
+impl Tokens<'_> {
+    /// Renders the highlighted code in the given format.
+    pub fn render(&self, format: RenderFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            RenderFormat::Ansi => self.write_ansi(out),
+            RenderFormat::Html => self.write_html(out),
+        }
+    }
+
+    /// Writes the code colored with ANSI escape sequences.
+    pub fn write_ansi(&self, out: &mut impl Write) -> io::Result<()> {
+        self.for_each_piece(|(_, highlight, slice)| {
+            match highlight {
+                // styles are reset before line breaks, so that they don't leak into pagers
+                Some(highlight) => (slice.split_inclusive('\n')).try_for_each(|line| {
+                    let (text, line_break) = split_line_break(line);
+                    let text = Painter::COLORED.paint(Style::Code(highlight), text);
+                    write!(out, "{text}{line_break}")
+                }),
+                None => out.write_all(slice.as_bytes()),
+            }
+        })
+    }
+
+    /// Writes a standalone HTML document with the code in a `<pre class="sus">`.
+    ///
+    /// Every token is in a `<span>` with the class of its category and the one of its type,
+    /// like `<span class="sus-keyword sus-if">`, so that they can be styled either way.
+    /// Regular comments only have the `sus-comment` class, as they are not tokens.
+    pub fn write_html(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(b"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n")?;
+        write!(out, "<style>\n{HTML_STYLE}</style>\n</head>\n<body>\n")?;
+        self.write_html_pre(out)?;
+        out.write_all(b"\n</body>\n</html>\n")
+    }
+
+    /// Writes only the `<pre class="sus">` of [`Tokens::write_html`], to embed it in a page.
+    pub fn write_html_pre(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(b"<pre class=\"sus\">")?;
+        self.for_each_piece(|(ty, highlight, slice)| match (ty, highlight) {
+            (_, None) => write_html_escaped(out, slice),
+            (Some(ty), Some(highlight)) => {
+                write!(
+                    out,
+                    "<span class=\"{} {}\">",
+                    highlight.css_class(),
+                    ty.css_class()
+                )?;
+                write_html_escaped(out, slice)?;
+                out.write_all(b"</span>")
+            }
+            (None, Some(highlight)) => {
+                write!(out, "<span class=\"{}\">", highlight.css_class())?;
+                write_html_escaped(out, slice)?;
+                out.write_all(b"</span>")
+            }
+        })?;
+        out.write_all(b"</pre>")
+    }
+
+    /// Goes through the whole code in order, as tokens and the text between them.
+    fn for_each_piece(&self, mut f: impl FnMut(Piece) -> io::Result<()>) -> io::Result<()> {
+        let mut pos = 0;
+        for (&ty, span) in self.types.iter().zip(self.spans.iter()) {
+            for_each_gap_piece(&self.code[pos..span.start as usize], &mut f)?;
+            f((Some(ty), ty.highlight(), &self.code[span.range()]))?;
+            pos = span.end();
+        }
+        for_each_gap_piece(&self.code[pos..], &mut f)
+    }
+}
+
+/// Type (if it's a token), highlight and slice of a piece of code.
+type Piece<'s> = (Option<TokenType>, Option<Highlight>, &'s str);
+
+/// Goes through the text between two tokens.
+///
+/// It is made of whitespace and regular comments, which run to the end of their line.
+fn for_each_gap_piece(text: &str, f: &mut impl FnMut(Piece) -> io::Result<()>) -> io::Result<()> {
+    for line in text.split_inclusive('\n') {
+        let (line, line_break) = split_line_break(line);
+        let comment_start = line.find(|c: char| !c.is_ascii_whitespace());
+        let (whitespace, comment) = line.split_at(comment_start.unwrap_or(line.len()));
+
+        f((None, None, whitespace))?;
+        if !comment.is_empty() {
+            f((None, Some(Highlight::Comment), comment))?;
+        }
+        f((None, None, line_break))?;
+    }
+    Ok(())
+}
+
+/// Splits a line from its trailing line break (`\n`, `\r\n` or `\r`), if any.
+fn split_line_break(line: &str) -> (&str, &str) {
+    let text = line.trim_end_matches(['\n', '\r']);
+    line.split_at(text.len())
+}
+
+fn write_html_escaped(out: &mut impl Write, s: &str) -> io::Result<()> {
+    let bytes = s.as_bytes();
+    let mut last = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escaped: &[u8] = match byte {
+            b'&' => b"&amp;",
+            b'<' => b"&lt;",
+            b'>' => b"&gt;",
+            b'"' => b"&quot;",
+            _ => continue,
+        };
+        out.write_all(&bytes[last..i])?;
+        out.write_all(escaped)?;
+        last = i + 1;
+    }
+    out.write_all(&bytes[last..])
+}
+
+#[cfg(test)]
+mod tests_render {
+    use crate::{
+        lexer::{lex, render::RenderFormat, TokenType},
+        source::FileId,
+    };
+
+    fn render(code: &str, format: RenderFormat) -> String {
+        let mut out = Vec::new();
+        lex(FileId::default(), code)
+            .render(format, &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn ansi() {
+        assert_eq!(
+            render("if a < 1 // c\n  $", RenderFormat::Ansi),
+            concat!(
+                "\x1b[35mif\x1b[0m a < \x1b[33m1\x1b[0m \x1b[90m// c\x1b[0m\n",
+                "  \x1b[4;31m$\x1b[0m",
+            )
+        );
+        // styles don't span line breaks
+        assert_eq!(
+            render("\"a\r\nb\"", RenderFormat::Ansi),
+            "\x1b[32m\"a\x1b[0m\r\n\x1b[32mb\"\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn html() {
+        let html = render("s := \"<&>\"; // \"c\"", RenderFormat::Html);
+        let pre = concat!(
+            r#"<pre class="sus">"#,
+            r#"<span class="sus-ident sus-ident">s</span> "#,
+            r#"<span class="sus-punctuation sus-colon">:</span>"#,
+            r#"<span class="sus-operator sus-equal">=</span> "#,
+            r#"<span class="sus-string sus-string">&quot;&lt;&amp;&gt;&quot;</span>"#,
+            r#"<span class="sus-punctuation sus-semi">;</span> "#,
+            r#"<span class="sus-comment">// &quot;c&quot;</span>"#,
+            "</pre>",
+        );
+        assert!(html.starts_with("<!DOCTYPE html>\n"));
+        assert!(html.contains(".sus-keyword {"));
+        assert!(html.contains(pre), "{html}");
+    }
+
+    #[test]
+    fn css_classes() {
+        assert_eq!(TokenType::If.css_class(), "sus-if");
+        assert_eq!(
+            TokenType::StringInterpBeg.css_class(),
+            "sus-string-interp-beg"
+        );
+        assert_eq!(TokenType::CString.css_class(), "sus-c-string");
+        assert_eq!(TokenType::Eof.highlight(), None);
+    }
+}
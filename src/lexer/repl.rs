@@ -0,0 +1,173 @@
+//! Lexer for interactive input, telling whether more lines are needed.
+
+use super::{lex_with_options, Delimiter, LexErrorKind, LexOptions, Tokens};
+use crate::source::FileId;
+
+/// Construct that is still open at the end of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unfinished {
+    /// A string, or an interpolated string
+    String,
+    Attribute,
+    Delimiter(Delimiter),
+}
+
+/// Result of feeding a line to a [`ReplLexer`].
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)] // short-lived, once per line
+pub enum Feed<'a> {
+    /// The input stops inside of something, more lines are needed
+    NeedsMore(Unfinished),
+    /// The input is complete, the next line starts a new input
+    Complete(Tokens<'a>),
+}
+
+/// Lexer that accumulates lines of input until they form complete code, for REPLs.
+///
+/// Code is complete when it doesn't stop inside of a string, an attribute or delimiters.
+/// Any other error is part of the complete tokens, as more lines couldn't fix it.
+/// The whole input is lexed again on every line, which is fine at the scale of a prompt.
+#[derive(Debug, Clone)]
+pub struct ReplLexer {
+    file: FileId,
+    options: LexOptions,
+    input: String,
+    is_complete: bool,
+}
+
+impl ReplLexer {
+    pub fn new(file: FileId) -> Self {
+        Self::with_options(file, LexOptions::default())
+    }
+
+    pub fn with_options(file: FileId, options: LexOptions) -> Self {
+        Self {
+            file,
+            options,
+            input: String::new(),
+            is_complete: false,
+        }
+    }
+
+    /// Input accumulated so far.
+    #[inline]
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Forgets the accumulated input, like when a user cancels the current prompt.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.is_complete = false;
+    }
+
+    /// Adds a line (including its line break) to the input and lexes it.
+    pub fn feed(&mut self, line: &str) -> Feed<'_> {
+        if self.is_complete {
+            self.reset();
+        }
+        self.input.push_str(line);
+
+        let tokens = lex_with_options(self.file, &self.input, self.options);
+        match unfinished(&tokens) {
+            Some(unfinished) => Feed::NeedsMore(unfinished),
+            None => {
+                self.is_complete = true;
+                Feed::Complete(tokens)
+            }
+        }
+    }
+}
+
+/// What the tokens stop inside of, if anything.
+fn unfinished(tokens: &Tokens) -> Option<Unfinished> {
+    let eof = tokens.code.len();
+    (tokens.errors.iter()).find_map(|error| match error.kind {
+        LexErrorKind::UnfinishedString | LexErrorKind::UnfinishedInterpString => {
+            Some(Unfinished::String)
+        }
+        LexErrorKind::UnclosedAttribute => Some(Unfinished::Attribute),
+        // unclosed delimiters are reported where they should have been closed
+        LexErrorKind::UnclosedDelimiter(delimiter) if error.span.start == eof => {
+            Some(Unfinished::Delimiter(delimiter))
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests_repl {
+    use crate::{
+        lexer::{
+            repl::{Feed, ReplLexer, Unfinished},
+            Delimiter, TokenType,
+        },
+        source::FileId,
+    };
+
+    fn needs_more(feed: Feed) -> Option<Unfinished> {
+        match feed {
+            Feed::NeedsMore(unfinished) => Some(unfinished),
+            Feed::Complete(_) => None,
+        }
+    }
+
+    #[test]
+    fn complete_lines() {
+        let mut lexer = ReplLexer::new(FileId::default());
+        match lexer.feed("a := 1;\n") {
+            Feed::Complete(tokens) => assert_eq!(tokens.types.len(), 6),
+            Feed::NeedsMore(unfinished) => panic!("needs more: {unfinished:?}"),
+        }
+
+        // errors that more lines can't fix are complete
+        assert_eq!(needs_more(lexer.feed("b := 'ab';\n")), None);
+        assert_eq!(needs_more(lexer.feed("c := );\n")), None);
+        assert_eq!(lexer.input(), "c := );\n");
+    }
+
+    #[test]
+    fn continued_lines() {
+        let mut lexer = ReplLexer::new(FileId::default());
+        assert_eq!(
+            needs_more(lexer.feed("main :: fn() {\n")),
+            Some(Unfinished::Delimiter(Delimiter::Brace))
+        );
+        assert_eq!(
+            needs_more(lexer.feed("    s := $\"{a}\n")),
+            Some(Unfinished::String)
+        );
+        assert_eq!(
+            needs_more(lexer.feed("\";\n")),
+            Some(Unfinished::Delimiter(Delimiter::Brace))
+        );
+        assert_eq!(
+            needs_more(lexer.feed("    #[inline(\n")),
+            Some(Unfinished::Attribute)
+        );
+
+        match lexer.feed(")] f();\n}\n") {
+            Feed::Complete(tokens) => {
+                assert!(tokens.errors.is_empty());
+                let types = tokens.types.as_slice();
+                assert_eq!(
+                    types[types.len() - 2..],
+                    [TokenType::RBrace, TokenType::Eof]
+                );
+            }
+            Feed::NeedsMore(unfinished) => panic!("needs more: {unfinished:?}"),
+        }
+
+        // the next line starts over
+        assert!(needs_more(lexer.feed("x\n")).is_none());
+        assert_eq!(lexer.input(), "x\n");
+    }
+
+    #[test]
+    fn reset() {
+        let mut lexer = ReplLexer::new(FileId::default());
+        assert!(needs_more(lexer.feed("(\n")).is_some());
+        lexer.reset();
+        assert!(needs_more(lexer.feed("1\n")).is_none());
+    }
+}
@@ -0,0 +1,128 @@
+//! Metrics about lexed tokens, for profiling and tooling.
+
+use std::collections::BTreeMap;
+
+use super::{TokenType, Tokens};
+pub use crate::memory::ArrayMemory;
+
+/// Memory taken by each of the arrays of [`Tokens`], not counting the code itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TokensMemory {
+    pub line_breaks: ArrayMemory,
+    pub spans: ArrayMemory,
+    pub types: ArrayMemory,
+    pub errors: ArrayMemory,
+    pub trivia: ArrayMemory,
+    pub token_trivia: ArrayMemory,
+}
+
+impl TokensMemory {
+    /// Memory taken by all the arrays together.
+    pub fn total(&self) -> ArrayMemory {
+        let arrays = [
+            self.line_breaks,
+            self.spans,
+            self.types,
+            self.errors,
+            self.trivia,
+            self.token_trivia,
+        ];
+        arrays.into_iter().sum()
+    }
+}
+
+/// Statistics about some [`Tokens`], see [`Tokens::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenStats {
+    /// Number of tokens of each type, types that don't appear being left out
+    pub counts: BTreeMap<TokenType, usize>,
+    pub token_count: usize,
+    pub line_count: usize,
+    pub error_count: usize,
+    pub memory: TokensMemory,
+}
+
+impl TokenStats {
+    /// Number of tokens of the given type.
+    #[inline]
+    pub fn count(&self, ty: TokenType) -> usize {
+        self.counts.get(&ty).copied().unwrap_or(0)
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Counts the tokens of each type and measures the memory they take.
+    pub fn stats(&self) -> TokenStats {
+        let mut counts = BTreeMap::new();
+        for &ty in self.types.iter() {
+            *counts.entry(ty).or_insert(0) += 1;
+        }
+
+        TokenStats {
+            counts,
+            token_count: self.types.len(),
+            line_count: self.line_breaks.len() + 1,
+            error_count: self.errors.len(),
+            memory: TokensMemory {
+                line_breaks: ArrayMemory::of(&self.line_breaks),
+                spans: ArrayMemory::of(&self.spans),
+                types: ArrayMemory::of(&self.types),
+                errors: ArrayMemory::of(&self.errors),
+                trivia: ArrayMemory::of(&self.trivia),
+                token_trivia: ArrayMemory::of(&self.token_trivia),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_stats {
+    use std::mem;
+
+    use crate::{
+        arena::HeapAlloc,
+        lexer::{lex, lex_in, lex_with_mode, LexMode, LexOptions, Span, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn counts() {
+        let tokens = lex(FileId::default(), "a := b + c;\nd := $;\n");
+        let stats = tokens.stats();
+
+        assert_eq!(stats.token_count, 13);
+        assert_eq!(stats.count(TokenType::Ident), 4);
+        assert_eq!(stats.count(TokenType::Colon), 2);
+        assert_eq!(stats.count(TokenType::Error), 1);
+        assert_eq!(stats.count(TokenType::Eof), 1);
+        assert_eq!(stats.count(TokenType::Struct), 0);
+        assert_eq!(stats.counts.values().sum::<usize>(), stats.token_count);
+        assert_eq!(stats.line_count, 3);
+        assert_eq!(stats.error_count, 1);
+    }
+
+    #[test]
+    fn memory() {
+        let tokens = lex(FileId::default(), "a b c");
+        let memory = tokens.stats().memory;
+
+        // the EOF token is counted as well
+        assert_eq!(memory.spans.used, 4 * mem::size_of::<Span>());
+        assert_eq!(memory.types.used, 4 * mem::size_of::<TokenType>());
+        assert_eq!(memory.trivia.used, 0);
+        assert!(memory.spans.committed >= memory.spans.used);
+
+        let total = memory.total();
+        assert_eq!(total.used, memory.spans.used + memory.types.used);
+
+        let tokens = lex_with_mode(FileId::default(), "a b c", LexMode::PreserveTrivia);
+        assert!(tokens.stats().memory.trivia.used > 0);
+
+        // heap buffers are shrunk to their exact length
+        let code = "a b c ".repeat(1000);
+        let mut tokens = lex_in(FileId::default(), &code, LexOptions::default(), &HeapAlloc);
+        tokens.shrink_to_fit();
+        let total = tokens.stats().memory.total();
+        assert_eq!(total.committed, total.used);
+    }
+}
@@ -0,0 +1,294 @@
+//! Streaming lexer that doesn't need the entire code in memory.
+
+use std::{
+    io::{self, Read},
+    str,
+};
+
+use super::{
+    add_eof,
+    cursor::{Cursor, Mark},
+    lex_token, LexMode, LexOptions, Tokens,
+};
+use crate::source::FileId;
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Lexer that reads code in chunks, buffering only enough to finish the current token.
+///
+/// Tokens are handed out in batches, each one being a [`Tokens`] over the part of the code
+/// that was lexed since the previous batch. Lines and columns count from the start of the
+/// stream, while byte offsets (in spans and `line_breaks`) are relative to the batch's code.
+///
+/// Only the last batch ends with a [`TokenType::Eof`](super::TokenType::Eof) token.
+/// Delimiters are not matched, as they can be closed in any later batch.
+pub struct StreamLexer<R> {
+    file: FileId,
+    reader: R,
+    chunk_size: usize,
+    options: LexOptions,
+    /// Code that has yet to be handed out (may end with an incomplete UTF-8 character)
+    buffer: Vec<u8>,
+    /// Length of the buffer's prefix that was handed out with the last batch
+    consumed: usize,
+    is_eof: bool,
+    /// Position of the start of the buffer in the stream
+    line: usize,
+    col: usize,
+}
+
+impl<R: Read> StreamLexer<R> {
+    pub fn new(file: FileId, reader: R) -> Self {
+        Self::with_chunk_size(file, reader, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(file: FileId, reader: R, chunk_size: usize) -> Self {
+        Self::with_options(file, reader, chunk_size, LexOptions::default())
+    }
+
+    /// Creates a stream lexer with custom options.
+    ///
+    /// Trivia can't be preserved across batches, so the mode is always [`LexMode::Normal`].
+    pub fn with_options(file: FileId, reader: R, chunk_size: usize, options: LexOptions) -> Self {
+        Self {
+            file,
+            reader,
+            chunk_size: chunk_size.max(1),
+            options: LexOptions {
+                mode: LexMode::Normal,
+                ..options
+            },
+            buffer: Vec::new(),
+            consumed: 0,
+            is_eof: false,
+            line: 1,
+            col: 0,
+        }
+    }
+
+    /// Lexes the next batch of tokens, or returns `None` once the stream is over.
+    ///
+    /// Fails if reading fails or if the code isn't valid UTF-8.
+    pub fn next_batch(&mut self) -> io::Result<Option<Tokens<'_>>> {
+        self.buffer.drain(..self.consumed);
+        self.consumed = 0;
+
+        loop {
+            if self.is_eof && self.buffer.is_empty() {
+                return Ok(None);
+            }
+
+            let valid_len = match str::from_utf8(&self.buffer) {
+                Ok(code) => code.len(),
+                // the chunk might have ended in the middle of a character
+                Err(e) if e.error_len().is_none() && !self.is_eof => e.valid_up_to(),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+
+            if self.is_eof || self.has_complete_tokens(valid_len) {
+                break;
+            }
+
+            // not even a single complete token, read more (reading more at once for long tokens)
+            let read_len = self.chunk_size.max(self.buffer.len());
+            self.read_chunk(read_len)?;
+        }
+
+        let valid_len = match str::from_utf8(&self.buffer) {
+            Ok(code) => code.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        let code = unsafe { str::from_utf8_unchecked(&self.buffer[..valid_len]) };
+        let (tokens, end) = lex_batch(
+            self.file,
+            code,
+            self.options,
+            (self.line, self.col),
+            self.is_eof,
+        );
+
+        self.consumed = end.pos;
+        self.line = end.line;
+        self.col = end.col;
+
+        Ok(Some(tokens))
+    }
+
+    /// Whether the buffer starts with at least one complete token.
+    ///
+    /// The tokens get lexed again once this is true, as they can't be returned
+    /// from within the reading loop while borrowing the buffer.
+    fn has_complete_tokens(&self, valid_len: usize) -> bool {
+        let code = unsafe { str::from_utf8_unchecked(&self.buffer[..valid_len]) };
+        let (_, end) = lex_batch(self.file, code, self.options, (self.line, self.col), false);
+        end.pos > 0
+    }
+
+    fn read_chunk(&mut self, read_len: usize) -> io::Result<()> {
+        let old_len = self.buffer.len();
+        self.buffer.resize(old_len + read_len, 0);
+
+        let read = loop {
+            match self.reader.read(&mut self.buffer[old_len..]) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buffer.truncate(old_len);
+                    return Err(e);
+                }
+            }
+        };
+
+        self.buffer.truncate(old_len + read);
+        self.is_eof = read == 0;
+        Ok(())
+    }
+}
+
+/// Lexes the complete tokens at the start of the code.
+///
+/// A top-level token is complete if all the code that lexing it looked at is there
+/// (see [`Cursor::lookahead_end`]), which is always past its end: more code could
+/// have extended it, or changed it like `#` followed by `![`. When the end of the code
+/// is also the end of the stream, everything is complete.
+///
+/// Returns the tokens along with the position in the stream right after them.
+fn lex_batch<'a>(
+    file: FileId,
+    code: &'a str,
+    options: LexOptions,
+    (line, col): (usize, usize),
+    is_eof: bool,
+) -> (Tokens<'a>, Mark) {
+    let mut tokens = Tokens::empty(file, code, options);
+    tokens.origin = Mark {
+        pos: 0,
+        line,
+        col: 0,
+    };
+    let mut cursor = Cursor::at(code, tokens.origin);
+
+    let mut end = cursor.mark();
+    let mut end_lens = (0, 0, 0);
+    while !cursor.is_eof() {
+        lex_token(&mut cursor, &mut tokens);
+
+        if cursor.lookahead_end() <= code.len() || is_eof {
+            end = cursor.mark();
+            end_lens = (
+                tokens.types.len(),
+                tokens.errors.len(),
+                tokens.line_breaks.len(),
+            );
+        }
+    }
+
+    let (types_len, errors_len, line_breaks_len) = end_lens;
+    tokens.code = &code[..end.pos];
+    tokens.types.truncate(types_len);
+    tokens.spans.truncate(types_len);
+    tokens.errors.truncate(errors_len);
+    tokens.line_breaks.truncate(line_breaks_len);
+    if is_eof {
+        add_eof(&mut tokens);
+    }
+
+    // the batch started in the middle of a line
+    tokens.origin.col = col;
+    for error in tokens.errors.iter_mut() {
+        if error.span.line == line {
+            error.span.col += col;
+        }
+    }
+    if end.line == line {
+        end.col += col;
+    }
+
+    (tokens, end)
+}
+
+#[cfg(test)]
+mod tests_stream {
+    use std::io;
+
+    use crate::{
+        lexer::{lex, stream::StreamLexer},
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+    #[test]
+    fn same_as_lexing_everything() {
+        let tokens = lex(FileId::default(), SAMPLE);
+        let expected: Vec<_> = (tokens.types.iter().zip(tokens.iter_spans()))
+            .map(|(&ty, span)| (ty, span.slice.to_string(), span.line, span.col))
+            .collect();
+        let expected_errors: Vec<_> = tokens.errors.iter().map(|e| e.to_string()).collect();
+
+        for chunk_size in [1, 2, 7, 100, 1 << 20] {
+            let mut lexer =
+                StreamLexer::with_chunk_size(FileId::default(), SAMPLE.as_bytes(), chunk_size);
+            let mut streamed = Vec::new();
+            let mut streamed_errors = Vec::new();
+            let mut line_break_count = 0;
+
+            while let Some(batch) = lexer.next_batch().unwrap() {
+                for (&ty, span) in batch.types.iter().zip(batch.iter_spans()) {
+                    streamed.push((ty, span.slice.to_string(), span.line, span.col));
+                }
+                streamed_errors.extend(batch.errors.iter().map(|e| e.to_string()));
+                line_break_count += batch.line_breaks.len();
+            }
+
+            assert_eq!(streamed, expected, "chunk size {chunk_size}");
+            assert_eq!(streamed_errors, expected_errors, "chunk size {chunk_size}");
+            assert_eq!(line_break_count, tokens.line_breaks.len());
+        }
+    }
+
+    /// Checks that streaming the code in chunks of any small size gives the same tokens
+    /// as lexing it at once.
+    fn check_chunk_sizes(code: &str) {
+        let tokens = lex(FileId::default(), code);
+        let expected: Vec<_> = (tokens.types.iter().zip(tokens.iter_spans()))
+            .map(|(&ty, span)| (ty, span.slice.to_string(), span.line, span.col))
+            .collect();
+
+        for chunk_size in 1..=code.len() {
+            let mut lexer =
+                StreamLexer::with_chunk_size(FileId::default(), code.as_bytes(), chunk_size);
+            let mut streamed = Vec::new();
+            while let Some(batch) = lexer.next_batch().unwrap() {
+                for (&ty, span) in batch.types.iter().zip(batch.iter_spans()) {
+                    streamed.push((ty, span.slice.to_string(), span.line, span.col));
+                }
+            }
+            assert_eq!(streamed, expected, "{code:?} in chunks of {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn tokens_depending_on_later_code() {
+        check_chunk_sizes("#![a]\n#!b #[c] d");
+        check_chunk_sizes("a #![b] # ![c] #!\n[d]");
+        check_chunk_sizes("x := r#type + r#;");
+        check_chunk_sizes("a := 0..10 + 1.5 + 2..=3 + 4.;");
+        check_chunk_sizes("x := 0x1.8p3 + 0xA.max(0x1.f_ffP-1) + 0x2.abc;");
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let code: &[u8] = b"abc := \xFF;";
+        let mut lexer = StreamLexer::with_chunk_size(FileId::default(), code, 4);
+
+        let error = loop {
+            match lexer.next_batch() {
+                Ok(Some(_)) => continue,
+                Ok(None) => panic!("invalid UTF-8 wasn't detected"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
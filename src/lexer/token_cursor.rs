@@ -0,0 +1,252 @@
+//! Index-safe cursor to walk over lexed tokens.
+
+use std::fmt;
+
+use super::{keyword, TokenSpan, TokenType, Tokens};
+
+/// A token along with its span, resolved from [`Tokens`].
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    pub ty: TokenType,
+    pub span: TokenSpan<'a>,
+}
+
+impl<'a> Token<'a> {
+    /// Soft keyword that the token spells, if it's an identifier that is one.
+    ///
+    /// Raw identifiers like `r#then` are never soft keywords.
+    #[inline]
+    pub fn as_soft_keyword(&self) -> Option<TokenType> {
+        match self.ty {
+            TokenType::Ident => keyword::lookup_soft(self.span.slice.as_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Whether the token is of the given type, identifiers being soft keywords when asked for one.
+    #[inline]
+    pub fn is(&self, ty: TokenType) -> bool {
+        self.ty == ty || (ty.is_soft_keyword() && self.as_soft_keyword() == Some(ty))
+    }
+}
+
+/// Position of a [`TokenCursor`] that it can be rewound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Checkpoint(usize);
+
+/// The token found by [`TokenCursor::expect`] wasn't of the expected type.
+#[derive(Debug, Clone)]
+pub struct UnexpectedToken<'a> {
+    pub expected: TokenType,
+    /// The token that was found instead, `None` at the end of tokens without an EOF token
+    pub found: Option<Token<'a>>,
+}
+
+impl<'a> fmt::Display for UnexpectedToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.found {
+            Some(Token {
+                ty: TokenType::Eof,
+                span,
+            }) => write!(
+                f,
+                "{}:{}: Expected {:?}, found end of file",
+                span.line, span.col, self.expected
+            ),
+            Some(Token { ty, span }) => write!(
+                f,
+                "{}:{}: Expected {:?}, found {ty:?}",
+                span.line, span.col, self.expected
+            ),
+            None => write!(f, "Expected {:?}, found end of file", self.expected),
+        }
+    }
+}
+
+/// Cursor over [`Tokens`], for parsers and other consumers of the tokens.
+///
+/// Every method stops at the [`TokenType::Eof`] token instead of panicking:
+/// it is never consumed, and peeking past it gives it again.
+#[derive(Debug, Clone)]
+pub struct TokenCursor<'t, 'a> {
+    tokens: &'t Tokens<'a>,
+    idx: usize,
+}
+
+impl<'a> Tokens<'a> {
+    /// Cursor starting at the first token.
+    #[inline]
+    pub fn cursor(&self) -> TokenCursor<'_, 'a> {
+        TokenCursor {
+            tokens: self,
+            idx: 0,
+        }
+    }
+}
+
+impl<'t, 'a> TokenCursor<'t, 'a> {
+    /// Index of the next token in [`Tokens`].
+    #[inline]
+    pub fn idx(&self) -> usize {
+        self.idx
+    }
+
+    #[inline]
+    pub fn is_eof(&self) -> bool {
+        self.idx >= self.end()
+    }
+
+    /// Index of the EOF token (or the number of tokens, for a batch that doesn't have one).
+    #[inline]
+    fn end(&self) -> usize {
+        let types = self.tokens.types.as_slice();
+        match types.last() {
+            Some(TokenType::Eof) => types.len() - 1,
+            _ => types.len(),
+        }
+    }
+
+    /// The `n`th token after the cursor without consuming anything, `peek(0)` being the next one.
+    #[inline]
+    pub fn peek(&self, n: usize) -> Option<Token<'a>> {
+        let idx = (self.idx + n).min(self.end());
+        let ty = *self.tokens.types.get(idx)?;
+        Some(Token {
+            ty,
+            span: self.tokens.span(idx),
+        })
+    }
+
+    /// Whether the next token is of the given type (see [`Token::is`] for soft keywords).
+    #[inline]
+    pub fn peek_is(&self, ty: TokenType) -> bool {
+        self.peek(0).is_some_and(|token| token.is(ty))
+    }
+
+    /// Consumes the next token if it is of the given type.
+    ///
+    /// Asking for a soft keyword consumes an identifier that spells it,
+    /// returning it with the soft keyword's type.
+    pub fn eat(&mut self, ty: TokenType) -> Option<Token<'a>> {
+        let token = self.peek(0).filter(|token| token.is(ty))?;
+        self.idx = (self.idx + 1).min(self.end());
+        Some(Token { ty, ..token })
+    }
+
+    /// Consumes the next token, failing without consuming it if it's not of the given type.
+    pub fn expect(&mut self, ty: TokenType) -> Result<Token<'a>, UnexpectedToken<'a>> {
+        self.eat(ty).ok_or(UnexpectedToken {
+            expected: ty,
+            found: self.peek(0),
+        })
+    }
+
+    #[inline]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.idx)
+    }
+
+    /// Goes back (or forward) to a checkpoint of a cursor over the same tokens.
+    #[inline]
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.idx = checkpoint.0.min(self.end());
+    }
+}
+
+impl<'t, 'a> Iterator for TokenCursor<'t, 'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_eof() {
+            return None;
+        }
+        let token = self.peek(0)?;
+        self.idx += 1;
+        Some(token)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end() - self.idx;
+        (len, Some(len))
+    }
+}
+
+#[cfg(test)]
+mod tests_token_cursor {
+    use crate::{
+        lexer::{lex, TokenType},
+        source::FileId,
+    };
+
+    #[test]
+    fn walk_tokens() {
+        let tokens = lex(FileId::default(), "rw i : usize = 0;");
+        let mut cursor = tokens.cursor();
+
+        assert_eq!(cursor.peek(1).map(|t| t.span.slice), Some("i"));
+        let token = cursor.next().unwrap();
+        assert_eq!((token.ty, token.span.slice), (TokenType::Ident, "rw"));
+
+        assert!(cursor.eat(TokenType::Colon).is_none());
+        assert_eq!(cursor.expect(TokenType::Ident).unwrap().span.slice, "i");
+        assert!(cursor.expect(TokenType::Colon).is_ok());
+
+        let error = cursor.expect(TokenType::Colon).unwrap_err();
+        assert_eq!(error.found.as_ref().map(|t| t.ty), Some(TokenType::Ident));
+        assert_eq!(error.to_string(), "1:7: Expected Colon, found Ident");
+
+        assert_eq!(cursor.count(), 4);
+    }
+
+    #[test]
+    fn soft_keywords() {
+        let tokens = lex(FileId::default(), "if then then r#then else then");
+        let mut cursor = tokens.cursor();
+
+        assert!(cursor.eat(TokenType::If).is_some());
+        assert!(cursor.peek_is(TokenType::Ident));
+        assert_eq!(
+            cursor.peek(0).unwrap().as_soft_keyword(),
+            Some(TokenType::Then)
+        );
+
+        // a soft keyword in a context that expects it
+        let token = cursor.expect(TokenType::Then).unwrap();
+        assert_eq!((token.ty, token.span.slice), (TokenType::Then, "then"));
+
+        // the same identifier elsewhere
+        assert_eq!(cursor.expect(TokenType::Ident).unwrap().span.slice, "then");
+
+        assert!(cursor.peek(0).unwrap().as_soft_keyword().is_none());
+        assert!(cursor.eat(TokenType::Then).is_none());
+        assert!(cursor.eat(TokenType::Ident).is_some());
+
+        assert!(cursor.eat(TokenType::Else).is_some());
+        assert!(cursor.eat(TokenType::Then).is_some());
+    }
+
+    #[test]
+    fn rewind_to_checkpoint() {
+        let tokens = lex(FileId::default(), "a b c");
+        let mut cursor = tokens.cursor();
+
+        cursor.next();
+        let checkpoint = cursor.checkpoint();
+        cursor.by_ref().for_each(drop);
+        assert!(cursor.is_eof());
+
+        // the EOF token is never consumed
+        for n in [0, 1, 10] {
+            assert_eq!(cursor.peek(n).map(|t| t.ty), Some(TokenType::Eof));
+        }
+        assert!(cursor.eat(TokenType::Eof).is_some());
+        assert!(cursor.next().is_none());
+
+        let error = cursor.expect(TokenType::Ident).unwrap_err();
+        assert_eq!(error.to_string(), "1:5: Expected Ident, found end of file");
+
+        cursor.rewind(checkpoint);
+        assert_eq!(cursor.idx(), 1);
+        assert_eq!(cursor.next().map(|t| t.span.slice), Some("b"));
+    }
+}
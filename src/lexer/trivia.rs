@@ -0,0 +1,183 @@
+//! Trivia (whitespace and comments) preserved alongside tokens.
+
+use std::ops::Range;
+
+use super::{LexMode, Span, TokenSpan, TokenType, Tokens};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriviaKind {
+    /// Run of whitespace that doesn't contain line breaks
+    Whitespace,
+    /// A single line break (`\n`, `\r\n` or a lone `\r`)
+    LineBreak,
+    /// Regular comment, without its terminating line break
+    Comment,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Trivia<'a> {
+    pub kind: TriviaKind,
+    pub span: TokenSpan<'a>,
+}
+
+/// Trivia attached to a token, as ranges in [`Tokens::trivia`].
+///
+/// Trailing trivia is everything after the token up to the end of its line.
+/// Leading trivia is everything else before the token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenTrivia {
+    pub leading: Range<usize>,
+    pub trailing: Range<usize>,
+}
+
+/// Records a piece of trivia if trivia is being preserved.
+#[inline]
+pub(super) fn add_trivia<'a>(tokens: &Tokens<'a>, kind: TriviaKind, span: TokenSpan<'a>) {
+    if tokens.options.mode == LexMode::PreserveTrivia {
+        tokens.trivia.add(Trivia { kind, span });
+    }
+}
+
+impl<'a> Tokens<'a> {
+    /// Trivia right before the token at the given index.
+    pub fn leading_trivia(&self, idx: usize) -> &[Trivia<'a>] {
+        match self.token_trivia.get(idx) {
+            Some(trivia) => &self.trivia.as_slice()[trivia.leading.clone()],
+            None => &[],
+        }
+    }
+
+    /// Trivia right after the token at the given index, up to the end of its line.
+    pub fn trailing_trivia(&self, idx: usize) -> &[Trivia<'a>] {
+        match self.token_trivia.get(idx) {
+            Some(trivia) => &self.trivia.as_slice()[trivia.trailing.clone()],
+            None => &[],
+        }
+    }
+
+    /// Trivia after the last token, which is the leading trivia of the [`TokenType::Eof`] token.
+    pub fn eof_trivia(&self) -> &[Trivia<'a>] {
+        match self.types.as_slice().last() {
+            Some(TokenType::Eof) => self.leading_trivia(self.types.len() - 1),
+            _ => &[],
+        }
+    }
+
+    /// Attaches every piece of trivia to its token, from scratch.
+    pub(super) fn attach_trivia(&mut self) {
+        let offset_of = |span: &TokenSpan| span.start;
+        let token_offset_of = |span: &Span| span.start as usize;
+
+        self.token_trivia.truncate(0);
+
+        let trivia = self.trivia.as_slice();
+        let spans = self.spans.as_slice();
+
+        let mut t = 0;
+        for (i, span) in spans.iter().enumerate() {
+            let start = token_offset_of(span);
+            let next_start = spans.get(i + 1).map_or(usize::MAX, token_offset_of);
+
+            let leading_start = t;
+            while t < trivia.len() && offset_of(&trivia[t].span) < start {
+                t += 1;
+            }
+
+            let trailing_start = t;
+            while t < trivia.len()
+                && trivia[t].kind != TriviaKind::LineBreak
+                && offset_of(&trivia[t].span) < next_start
+            {
+                t += 1;
+            }
+
+            self.token_trivia.push(TokenTrivia {
+                leading: leading_start..trailing_start,
+                trailing: trailing_start..t,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_trivia {
+    use crate::{
+        lexer::{
+            lex_with_mode,
+            trivia::{Trivia, TriviaKind},
+            LexMode, Tokens,
+        },
+        source::FileId,
+    };
+
+    fn kinds(trivia: &[Trivia]) -> Vec<TriviaKind> {
+        trivia.iter().map(|t| t.kind).collect()
+    }
+
+    fn round_trip(tokens: &Tokens) -> String {
+        let mut code = String::new();
+        for (i, span) in tokens.iter_spans().enumerate() {
+            tokens
+                .leading_trivia(i)
+                .iter()
+                .for_each(|t| code += t.span.slice);
+            code += span.slice;
+            tokens
+                .trailing_trivia(i)
+                .iter()
+                .for_each(|t| code += t.span.slice);
+        }
+        // the trivia at the end of the file is the leading trivia of the EOF token
+        code
+    }
+
+    #[test]
+    fn round_trips_sample_file() {
+        const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+        let tokens = lex_with_mode(FileId::default(), SAMPLE, LexMode::PreserveTrivia);
+        assert_eq!(round_trip(&tokens), SAMPLE);
+    }
+
+    #[test]
+    fn leading_and_trailing() {
+        let code = "// header\n\n  a := 1; // one\n\t/// doc\nb;  \n// end";
+        let tokens = lex_with_mode(FileId::default(), code, LexMode::PreserveTrivia);
+        assert_eq!(round_trip(&tokens), code);
+
+        // `a`
+        assert_eq!(
+            kinds(tokens.leading_trivia(0)),
+            [
+                TriviaKind::Comment,
+                TriviaKind::LineBreak,
+                TriviaKind::LineBreak,
+                TriviaKind::Whitespace,
+            ]
+        );
+        assert_eq!(kinds(tokens.trailing_trivia(0)), [TriviaKind::Whitespace]);
+
+        // `;`
+        assert_eq!(
+            kinds(tokens.trailing_trivia(4)),
+            [TriviaKind::Whitespace, TriviaKind::Comment]
+        );
+
+        // `/// doc` is a token, not trivia
+        assert_eq!(
+            kinds(tokens.leading_trivia(5)),
+            [TriviaKind::LineBreak, TriviaKind::Whitespace]
+        );
+
+        assert_eq!(
+            kinds(tokens.eof_trivia()),
+            [TriviaKind::LineBreak, TriviaKind::Comment]
+        );
+    }
+
+    #[test]
+    fn no_trivia_by_default() {
+        let tokens = crate::lexer::lex(FileId::default(), "a // comment\n b");
+        assert!(tokens.trivia.is_empty());
+        assert!(tokens.leading_trivia(1).is_empty());
+    }
+}
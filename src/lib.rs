@@ -0,0 +1,10 @@
+pub mod arena;
+pub mod ast;
+pub mod cst;
+pub mod diag;
+pub mod lexer;
+pub mod memory;
+pub mod module;
+pub mod parser;
+pub mod source;
+pub mod style;
@@ -1,9 +1,189 @@
-mod arena;
-mod lexer;
+use std::{env, io, process};
+
+use csussus::{
+    arena::probe_limits,
+    ast::AstFormat,
+    diag::{explain, Diagnostic, DiagnosticSink, MessageFormat, Renderer},
+    lexer::{EmitFormat, RenderFormat},
+    memory::MemoryReport,
+    parser::{parse_file, write_ebnf},
+    source::{SourceMap, Utf8Mode},
+    style::ColorChoice,
+};
 
 const CODE: &str = include_str!("../Cඞඞ.sus");
 
+const USAGE: &str =
+    "Usage: csussus [--emit tokens|tokens-json|tokens-bin|ansi|html|ast|ast-json|grammar] [--color always|auto|never] [--message-format human|json] [--error-limit N] [--memory-stats] [--mmap] [FILE]\n       csussus --explain CODE";
+
+/// What gets written to stdout.
+enum Output {
+    Tokens(EmitFormat),
+    Highlighted(RenderFormat),
+    Ast(AstFormat),
+    /// The grammar as EBNF, whatever the file
+    Grammar,
+}
+
 fn main() {
-    let tokens = lexer::lex("Cඞඞ.sus", CODE);
-    println!("{}", &tokens);
+    let mut output = Output::Tokens(EmitFormat::Text);
+    let mut path = None;
+    let mut memory_stats = false;
+    let mut mmap = false;
+    let mut color = ColorChoice::Auto;
+    let mut message_format = MessageFormat::Human;
+    let mut error_limit = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--emit" => {
+                output = match args.next().as_deref() {
+                    Some("tokens") => Output::Tokens(EmitFormat::Text),
+                    Some("tokens-json") => Output::Tokens(EmitFormat::Json),
+                    Some("tokens-bin") => Output::Tokens(EmitFormat::Binary),
+                    Some("ansi") => Output::Highlighted(RenderFormat::Ansi),
+                    Some("html") => Output::Highlighted(RenderFormat::Html),
+                    Some("ast") => Output::Ast(AstFormat::Sexp),
+                    Some("ast-json") => Output::Ast(AstFormat::Json),
+                    Some("grammar") => Output::Grammar,
+                    _ => exit_with_usage(),
+                }
+            }
+            "--color" => match args.next().as_deref().and_then(ColorChoice::from_name) {
+                Some(choice) => color = choice,
+                None => exit_with_usage(),
+            },
+            _ if arg.starts_with("--color=") => match ColorChoice::from_name(&arg[8..]) {
+                Some(choice) => color = choice,
+                None => exit_with_usage(),
+            },
+            "--message-format" => match args.next().as_deref().and_then(MessageFormat::from_name) {
+                Some(format) => message_format = format,
+                None => exit_with_usage(),
+            },
+            _ if arg.starts_with("--message-format=") => {
+                match MessageFormat::from_name(&arg[17..]) {
+                    Some(format) => message_format = format,
+                    None => exit_with_usage(),
+                }
+            }
+            "--error-limit" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(limit) if limit > 0 => error_limit = Some(limit),
+                _ => exit_with_usage(),
+            },
+            "--explain" => match args.next() {
+                Some(code) => match explain(&code) {
+                    Some(explanation) => {
+                        print!("{explanation}");
+                        return;
+                    }
+                    None => {
+                        eprintln!("{code} is not an error code");
+                        process::exit(1);
+                    }
+                },
+                None => exit_with_usage(),
+            },
+            "--memory-stats" => memory_stats = true,
+            "--mmap" => mmap = true,
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => exit_with_usage(),
+        }
+    }
+
+    // before anything takes address space, so that it's all there is
+    let limits = probe_limits();
+
+    let source_map = SourceMap::new();
+    let file = match path {
+        Some(path) => {
+            let file = match mmap {
+                // SAFETY: with `--mmap`, the user promises not to modify the file meanwhile
+                true => unsafe { source_map.add_mmap(&path, Utf8Mode::Strict) },
+                false => source_map.add_file(&path, Utf8Mode::Strict),
+            };
+            file.unwrap_or_else(|e| {
+                eprintln!("Cannot read {path}: {e}");
+                process::exit(1);
+            })
+        }
+        None => source_map.add("Cඞඞ.sus", CODE),
+    };
+
+    let tokens = source_map.lex(file);
+    // spans are the largest token array, the first to fall back when reserving fails
+    if tokens.spans.is_on_heap() && limits.max_reservation > 0 {
+        eprintln!("Warning: Tokens fell back to the heap, which takes more memory");
+        if let Some(rlimit) = limits.addr_space_rlimit {
+            eprintln!(
+                "The address space is limited to {rlimit} bytes, raise the limit with `ulimit -v`"
+            );
+        }
+    }
+    let mut sink = match error_limit {
+        Some(limit) => DiagnosticSink::with_error_limit(limit),
+        None => DiagnosticSink::new(),
+    };
+    sink.extend(tokens.errors.iter().map(Diagnostic::from));
+
+    // parsed whatever the output, so that its errors are always reported
+    let (ast, diagnostics) = parse_file(&tokens);
+    sink.extend(tokens.num_values().errors.iter().map(Diagnostic::from));
+    sink.extend(diagnostics.iter().map(Diagnostic::from));
+    ast.report_misplaced_operands(file, &mut sink);
+
+    let stdout = &mut io::stdout().lock();
+    let result = match output {
+        Output::Tokens(format) => tokens.emit(format, stdout),
+        Output::Highlighted(format) => tokens.render(format, stdout),
+        Output::Ast(format) => ast.emit(format, &tokens, &diagnostics, stdout),
+        Output::Grammar => write_ebnf(stdout),
+    };
+    if let Err(e) = result {
+        eprintln!("Cannot write output: {e}");
+        process::exit(1);
+    }
+
+    let diagnostics = sink.finish();
+    match message_format {
+        MessageFormat::Human => {
+            let mut renderer = Renderer::new(&source_map);
+            renderer.painter = color.painter(&io::stderr());
+            for diagnostic in &diagnostics.diagnostics {
+                eprintln!("{}", renderer.display(diagnostic));
+            }
+            if let Some(summary) = renderer.display_summary(&diagnostics) {
+                eprintln!("{summary}");
+            }
+            if let Some(code) = diagnostics.diagnostics.iter().find_map(|d| d.code) {
+                eprintln!("For more information about an error, try `csussus --explain {code}`.");
+            }
+        }
+        MessageFormat::Json => {
+            let stderr = &mut io::stderr().lock();
+            for diagnostic in &diagnostics.diagnostics {
+                if let Err(e) = diagnostic.write_json(&source_map, stderr) {
+                    eprintln!("Cannot write diagnostics: {e}");
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    if memory_stats {
+        let mut report = MemoryReport::new();
+        report.add("source", source_map.memory());
+        report.add("lexing", tokens.stats().memory.total());
+        report.add("parsing", ast.memory());
+        eprint!("{report}");
+    }
+    if diagnostics.errors > 0 {
+        process::exit(1);
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(2);
 }
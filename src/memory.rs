@@ -0,0 +1,175 @@
+//! Memory taken by the data of a compilation, phase by phase.
+
+use std::{fmt, iter::Sum, mem, ops::Add};
+
+use crate::arena::{ArenaVec, KIB};
+
+/// Memory taken by an array, from the bytes of its elements to the address space behind them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ArrayMemory {
+    /// Bytes taken by the elements
+    pub used: usize,
+    /// Bytes of physical memory committed for the elements
+    pub committed: usize,
+    /// Bytes of address space reserved for the elements
+    pub reserved: usize,
+}
+
+impl ArrayMemory {
+    pub fn of<T>(array: &ArenaVec<T>) -> Self {
+        Self {
+            used: array.used_bytes(),
+            committed: array.committed_bytes(),
+            reserved: array.reserved_bytes(),
+        }
+    }
+
+    /// Memory of a heap allocation of `capacity` elements, the first `len` of them being used.
+    ///
+    /// The whole allocation is counted as committed, although the OS may not have
+    /// touched the pages that were never written to.
+    pub fn of_heap<T>(len: usize, capacity: usize) -> Self {
+        Self {
+            used: len * mem::size_of::<T>(),
+            committed: capacity * mem::size_of::<T>(),
+            reserved: capacity * mem::size_of::<T>(),
+        }
+    }
+}
+
+impl Add for ArrayMemory {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            used: self.used + other.used,
+            committed: self.committed + other.committed,
+            reserved: self.reserved + other.reserved,
+        }
+    }
+}
+
+impl Sum for ArrayMemory {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(ArrayMemory::default(), Add::add)
+    }
+}
+
+/// Memory taken by the data of each phase of a compilation, like the tokens for lexing.
+///
+/// Its `Display` impl is the table printed by `--memory-stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Phases in the order they were added, with the memory their data takes
+    pub phases: Vec<(&'static str, ArrayMemory)>,
+}
+
+impl MemoryReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, phase: &'static str, memory: ArrayMemory) {
+        self.phases.push((phase, memory));
+    }
+
+    /// Memory taken by all the phases together.
+    pub fn total(&self) -> ArrayMemory {
+        self.phases.iter().map(|&(_, memory)| memory).sum()
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<10} {:>10} {:>10} {:>10}",
+            "phase", "used", "committed", "reserved"
+        )?;
+        for (phase, memory) in self.phases.iter().chain([&("total", self.total())]) {
+            writeln!(
+                f,
+                "{phase:<10} {:>10} {:>10} {:>10}",
+                Bytes(memory.used),
+                Bytes(memory.committed),
+                Bytes(memory.reserved)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of bytes displayed in the largest binary unit that keeps it above 1.
+struct Bytes(usize);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+        if self.0 < KIB {
+            return f.pad(&format!("{} B", self.0));
+        }
+
+        let (mut size, mut unit) = (self.0 as f64 / KIB as f64, 0);
+        while size >= KIB as f64 && unit < UNITS.len() - 1 {
+            size /= KIB as f64;
+            unit += 1;
+        }
+        f.pad(&format!("{size:.1} {}", UNITS[unit]))
+    }
+}
+
+#[cfg(test)]
+mod tests_memory {
+    use crate::{
+        arena::{ArenaVec, KIB, MIB, RESERVES_ADDR_SPACE},
+        memory::{ArrayMemory, MemoryReport},
+    };
+
+    #[test]
+    fn array_memory() {
+        let vec = ArenaVec::new(MIB);
+        vec.add(0u64);
+        let memory = ArrayMemory::of(&vec);
+        assert_eq!(memory.used, 8);
+        assert!(memory.committed >= memory.used);
+        // heap buffers only reserve what they allocate
+        let reserved = match RESERVES_ADDR_SPACE {
+            true => MIB,
+            false => memory.committed,
+        };
+        assert_eq!(memory.reserved, reserved);
+
+        assert_eq!(
+            ArrayMemory::of_heap::<u32>(3, 4),
+            ArrayMemory {
+                used: 12,
+                committed: 16,
+                reserved: 16,
+            }
+        );
+    }
+
+    #[test]
+    fn report() {
+        let mut report = MemoryReport::new();
+        report.add("source", ArrayMemory::of_heap::<u8>(100, 128));
+        let lexing = ArrayMemory {
+            used: 1536,
+            committed: 64 * KIB,
+            reserved: 3 * (MIB << 10),
+        };
+        report.add("lexing", lexing);
+
+        assert_eq!(report.total().used, 1636);
+        assert_eq!(
+            report.to_string(),
+            concat!(
+                "phase            used  committed   reserved\n",
+                "source          100 B      128 B      128 B\n",
+                "lexing        1.5 KiB   64.0 KiB    3.0 GiB\n",
+                "total         1.6 KiB   64.1 KiB    3.0 GiB\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Module graph of a program spanning several files.
+//!
+//! Every file is a module, whose path is the path of the file from the root directory
+//! of the program with `::` between its segments, so that `use app::ui::Button;` refers to
+//! the item `Button` of `app/ui.sus`, or to the module `app/ui/Button.sus` itself.
+//! When both exist, the module is the longest path that is a file.
+//!
+//! Modules are loaded as their imports are found, starting from the entry file of the program.
+//! Imports can form cycles, every module being loaded once.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{Ast, ItemId, ItemKind},
+    lexer::{Span, TokenSpan, Tokens},
+    parser::{parse_file, ParseError},
+    source::{FileId, SourceMap, Utf8Mode},
+};
+
+/// Extension of the files of modules.
+pub const MODULE_EXTENSION: &str = "sus";
+
+/// A file of the program, with its parsed code.
+#[derive(Debug)]
+pub struct Module<'a> {
+    pub file: FileId,
+    pub tokens: Tokens<'a>,
+    pub ast: Ast,
+    pub diagnostics: Vec<ParseError<'a>>,
+    pub imports: Vec<Import>,
+}
+
+/// A `use` item resolved to the module it refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Import {
+    /// `use` item of the importing module
+    pub item: ItemId,
+    pub module: FileId,
+    /// Item of that module the path ends with, `None` when it imports the module itself
+    pub name: Option<Span>,
+}
+
+#[derive(Debug)]
+pub enum ModuleErrorKind {
+    /// No file matches the path, which was looked for at the given paths
+    NotFound(Vec<PathBuf>),
+    /// The file of the module was found but cannot be read
+    Io(PathBuf, io::Error),
+}
+
+/// An import that cannot be resolved.
+#[derive(Debug)]
+pub struct ModuleError<'a> {
+    /// File of the `use` item
+    pub file: FileId,
+    pub item: ItemId,
+    /// Span of the path of the import
+    pub span: TokenSpan<'a>,
+    pub kind: ModuleErrorKind,
+}
+
+impl fmt::Display for ModuleError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.span.line, self.span.col)?;
+        match &self.kind {
+            ModuleErrorKind::NotFound(tried) => {
+                write!(f, "Cannot find module `{}`, tried ", self.span.slice)?;
+                for (i, path) in tried.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", path.display())?;
+                }
+                Ok(())
+            }
+            ModuleErrorKind::Io(path, e) => write!(f, "Cannot read {}: {e}", path.display()),
+        }
+    }
+}
+
+impl Error for ModuleError<'_> {}
+
+/// Modules of a program and their imports.
+#[derive(Debug)]
+pub struct ModuleGraph<'a> {
+    /// Modules in the order they were loaded, starting with the entry file
+    pub modules: Vec<Module<'a>>,
+    pub errors: Vec<ModuleError<'a>>,
+    /// Index in `modules` of each loaded file
+    indices: HashMap<FileId, usize>,
+}
+
+impl<'a> ModuleGraph<'a> {
+    /// Loads the entry file and every module it imports, directly or not.
+    ///
+    /// Module paths are relative to the root directory, files being added to the source map
+    /// as they are imported.
+    pub fn build(source_map: &'a SourceMap, entry: FileId, root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        let mut graph = Self {
+            modules: Vec::new(),
+            errors: Vec::new(),
+            indices: HashMap::new(),
+        };
+
+        // files are told apart by their canonical path, which in-memory files don't have
+        let mut files = HashMap::new();
+        if let Ok(path) = fs::canonicalize(&source_map.file(entry).path) {
+            files.insert(path, entry);
+        }
+
+        let mut queue = VecDeque::from([entry]);
+        while let Some(file) = queue.pop_front() {
+            let tokens = source_map.lex(file);
+            let (ast, diagnostics) = parse_file(&tokens);
+
+            let mut imports = Vec::new();
+            for (item_id, item) in ast.items.iter() {
+                let ItemKind::Use { path } = item.kind else {
+                    continue;
+                };
+                let segments: Vec<_> = (ast.paths[path].iter())
+                    .map(|&segment| &tokens.code[segment.range()])
+                    .collect();
+
+                let error = |kind| {
+                    let (first, last) = (ast.paths[path][0], item.name);
+                    let span = Span {
+                        start: first.start,
+                        len: last.end() as u32 - first.start,
+                    };
+                    ModuleError {
+                        file,
+                        item: item_id,
+                        span: tokens.resolve(span),
+                        kind,
+                    }
+                };
+
+                let (module_path, len) = match resolve(root, &segments) {
+                    Ok(resolved) => resolved,
+                    Err(tried) => {
+                        graph.errors.push(error(ModuleErrorKind::NotFound(tried)));
+                        continue;
+                    }
+                };
+
+                let key = fs::canonicalize(&module_path).unwrap_or_else(|_| module_path.clone());
+                let module = match files.get(&key) {
+                    Some(&module) => module,
+                    None => {
+                        let path = module_path.to_string_lossy().into_owned();
+                        match source_map.add_file(path, Utf8Mode::Strict) {
+                            Ok(module) => {
+                                files.insert(key, module);
+                                queue.push_back(module);
+                                module
+                            }
+                            Err(e) => {
+                                let kind = ModuleErrorKind::Io(module_path, e);
+                                graph.errors.push(error(kind));
+                                continue;
+                            }
+                        }
+                    }
+                };
+
+                imports.push(Import {
+                    item: item_id,
+                    module,
+                    name: (len < segments.len()).then_some(item.name),
+                });
+            }
+
+            graph.indices.insert(file, graph.modules.len());
+            graph.modules.push(Module {
+                file,
+                tokens,
+                ast,
+                diagnostics,
+                imports,
+            });
+        }
+
+        graph
+    }
+
+    /// The module of the given file, if it was loaded.
+    pub fn module(&self, file: FileId) -> Option<&Module<'a>> {
+        self.indices.get(&file).map(|&idx| &self.modules[idx])
+    }
+
+    /// Modules that the module of the file imports, once each.
+    pub fn dependencies(&self, file: FileId) -> impl Iterator<Item = FileId> + '_ {
+        let imports = self.module(file).map(|module| module.imports.as_slice());
+        let imports = imports.unwrap_or_default().iter();
+        let mut modules = imports.map(|import| import.module).collect::<Vec<_>>();
+        modules.sort_unstable();
+        modules.dedup();
+        modules.into_iter()
+    }
+}
+
+/// Path of the module file that the segments lead to, along with the number of segments
+/// that it takes, or every path that was tried.
+///
+/// The last segment can be an item of the module rather than the module itself.
+fn resolve(root: &Path, segments: &[&str]) -> Result<(PathBuf, usize), Vec<PathBuf>> {
+    let mut tried = Vec::new();
+    let min_len = segments.len().saturating_sub(1).max(1);
+    for len in (min_len..=segments.len()).rev() {
+        let mut path = root.to_path_buf();
+        path.extend(&segments[..len]);
+        path.set_extension(MODULE_EXTENSION);
+        if path.is_file() {
+            return Ok((path, len));
+        }
+        tried.push(path);
+    }
+    Err(tried)
+}
+
+#[cfg(test)]
+mod tests_module {
+    use std::{env, fs, process};
+
+    use crate::{
+        module::{ModuleErrorKind, ModuleGraph},
+        source::SourceMap,
+    };
+
+    #[test]
+    fn imports() {
+        let dir = env::temp_dir().join(format!("csussus-modules-{}", process::id()));
+        fs::create_dir_all(dir.join("app/ui")).unwrap();
+        fs::write(
+            dir.join("app/ui.sus"),
+            "use app::ui::Button;\nButton :: struct {}",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("app/ui/Button.sus"),
+            "pub use main;\npub use app::ui;",
+        )
+        .unwrap();
+        fs::write(dir.join("main.sus"), "").unwrap();
+        fs::write(dir.join("bad.sus"), b"\xff").unwrap();
+
+        let source_map = SourceMap::new();
+        let main = source_map.add(
+            "main.sus",
+            "use app::ui::Button;\nuse app::ui;\nuse app::ui::Window;\nuse app::nope::x;\nuse bad;",
+        );
+        let graph = ModuleGraph::build(&source_map, main, &dir);
+
+        let paths: Vec<_> = (graph.modules.iter())
+            .map(|module| source_map.file(module.file).path.replace('\\', "/"))
+            .map(|path| {
+                path.strip_prefix(dir.to_str().unwrap())
+                    .unwrap_or(&path)
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            ["main.sus", "/app/ui/Button.sus", "/app/ui.sus", "/main.sus"]
+        );
+
+        let main_module = graph.module(main).unwrap();
+        let imports: Vec<_> = (main_module.imports.iter())
+            .map(|import| {
+                let name = import
+                    .name
+                    .map(|name| &main_module.tokens.code[name.range()]);
+                (import.module, name)
+            })
+            .collect();
+        let (button, ui) = (graph.modules[1].file, graph.modules[2].file);
+        assert_eq!(imports, [(button, None), (ui, None), (ui, Some("Window"))]);
+        assert_eq!(graph.dependencies(main).collect::<Vec<_>>(), [button, ui]);
+
+        // `app/ui.sus` and `app/ui/Button.sus` import each other
+        let ui_module = graph.module(ui).unwrap();
+        assert_eq!(ui_module.imports[0].module, button);
+        assert_eq!(
+            graph.dependencies(button).collect::<Vec<_>>(),
+            [ui, graph.modules[3].file]
+        );
+
+        let errors: Vec<_> = graph.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("4:4: Cannot find module `app::nope::x`, tried "));
+        assert!(
+            matches!(&graph.errors[0].kind, ModuleErrorKind::NotFound(tried) if tried.len() == 2)
+        );
+        assert!(matches!(graph.errors[1].kind, ModuleErrorKind::Io(..)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,3550 @@
+//! Recursive-descent parser building an [`Ast`] out of [`Tokens`].
+//!
+//! ```text
+//! file   = ("#![" attr,* "]")* item* EOF
+//! item   = attrs "pub"? IDENT "::" (fn | struct | enum | union)
+//!        | attrs "pub"? "use" IDENT ("::" IDENT)* ";"
+//!        | attrs "pub"? ("const" | "static") IDENT ":" type? "=" expr ";"
+//! attrs  = ("#[" attr,* "]")*
+//! attr   = IDENT ("::" IDENT)* ("(" TOKEN* ")")?
+//! fn     = "fn" generics? "(" (pattern ":" type),* ")" (">-" type)? ("->" expr ";" | block)
+//! struct = "packed"? "struct" generics? type? "{" field,* "}"
+//! enum   = "enum" generics? type? "{" (IDENT (":" type)? ("=" expr)?),* "}"
+//! union  = "union" generics? "{" field,* "}"
+//! field  = attrs "pub"? IDENT ":" type
+//! generics = "[" IDENT,* "]"
+//! type   = IDENT ("[" type,* "]")?
+//!        | ("*" | "&" | "?") type
+//!        | "fn" "(" type,* ")" (">-" type)?
+//!        | "[" type (";" expr)? "]"
+//!        | "(" (type ("," type)* ","?)? ")"
+//! block  = "{" stmt* "}"
+//! stmt   = attrs "defer" expr ";"?
+//!        | attrs ("r" | "w" | "rw")? pattern ":" type? "=" expr ";"
+//!        | attrs expr ("=" | OP "=") expr ";"
+//!        | attrs expr ";"?
+//! expr   = pipe
+//! pipe   = or (">-" stage)*
+//! or     = and (("or" | "xor") and)*
+//! and    = not ("and" not)*
+//! not    = "not" not
+//!        | compare
+//! compare = bit_or (("==" | "!=" | "<" | ">" | "<=" | ">=") bit_or)*
+//! bit_or = bit_xor ("|" bit_xor)*
+//! bit_xor = bit_and ("^" bit_and)*
+//! bit_and = shift ("&" shift)*
+//! shift  = sum (("<<" | ">>") sum)*
+//! sum    = product (("+" | "-") product)*
+//! product = prefix (("*" | "/" | "%") prefix)*
+//! prefix = ("-" | "~" | "++" | "--") prefix
+//!        | pow
+//! pow    = postfix ("**" pow)?
+//! postfix = primary ("++" | "--" | suffix)*
+//! suffix = "." (IDENT | DIGITS)
+//!        | ("." IDENT)? "(" (expr | "_"),* ")"
+//!        | "[" expr "]"
+//! stage  = primary ("." IDENT)* ("(" (expr | "_"),* ")")?
+//! primary = LITERAL
+//!         | "(" ")"
+//!         | IDENT
+//!         | string
+//!         | struct_lit
+//!         | "(" expr ")"
+//!         | tuple
+//!         | section
+//!         | array
+//!         | block
+//!         | if
+//!         | match
+//!         | loop
+//!         | break
+//!         | return
+//!         | closure
+//! if     = "if" expr "then" expr ("else" expr)?
+//! loop   = label? ("while" expr "do" expr | "do" expr "while" expr | "loop" expr | for)
+//! for    = "for" pattern ("," pattern)? "in" expr (".." expr)? ("do" expr | block)
+//! break  = "break" label? expr?
+//!        | "continue" label?
+//! return = "return" expr?
+//! label  = "@" IDENT
+//! string = STRING_INTERP_BEG hole (STRING_INTERP_MID hole)* STRING_INTERP_END
+//! hole   = expr FORMAT_SPEC?
+//! struct_lit = IDENT "{" (IDENT (":" expr)?),* ("," ".." expr)? "}"
+//! tuple  = "(" expr ("," expr)+ ","? ")"
+//!        | "(" expr "," ")"
+//! section = "(" OP expr ")"
+//! array  = "[" (expr ";" expr | expr,*) "]"
+//! closure = "|" (primary_pattern (":" type)?),* "|" expr
+//!         | "fn" ("[" "&"? IDENT,* "]")? "(" (pattern (":" type)?),* ")" (">-" type)? ("->" expr | block)
+//! match  = "match" expr "{" (pattern ("if" expr)? "=>" expr),* "}"
+//! pattern = primary_pattern ("|" primary_pattern)*
+//! primary_pattern = IDENT
+//!                 | "_"
+//!                 | "-"? LITERAL
+//!                 | IDENT? "." IDENT ("(" pattern ")")?
+//! ```
+//!
+//! `match` is a soft keyword, which starts a `match` when followed by an expression,
+//! and so are `use`, `const` and `static`, which start an item when followed by an identifier.
+//! `true`, `false` and `none` are soft keywords too, which are literals wherever an expression
+//! or a pattern starts, and `()` is the unit value. So is `return`, wherever an expression starts,
+//! and `for`, which starts a loop when followed by an identifier.
+//!
+//! A tuple of a single element has a trailing comma, like `(a,)`, as `(a)` is just `a`.
+//! The indices of tuple fields are numbers, `t.0.1` being lexed with the number `0.1`
+//! which is split in two indices.
+//!
+//! Generics are between square brackets rather than angle brackets, so that types
+//! never have to be told apart from comparisons like `a < b > c`.
+//! A bracket starts an array type where a type starts, and gives arguments after a type name.
+//!
+//! The scrutinee of a `match` is followed by a block, and so are the conditions of `if` and `while`
+//! when their `then` or `do` is forgotten. So an identifier followed by a brace doesn't start
+//! a struct literal there, unless it's within delimiters like `match (P {}) {}`.
+//!
+//! The arguments of attributes are kept as tokens, balanced within their parentheses,
+//! for the passes that look for them to parse, see [`Attr`].
+//! Attributes whose path is not in [`ATTRIBUTES`](crate::ast::ATTRIBUTES) are reported without stopping parsing.
+//!
+//! Expressions that end with a block don't need a `;` to be followed by other statements,
+//! and neither does the last expression of a block.
+//!
+//! Operators are parsed by precedence climbing, as defined by a single table of operators,
+//! which the rules from `expr` to `postfix` are generated from (see [`grammar`]).
+//! Wherever an operand starts, so can a prefix operator, like in `a * -b`, whose operand
+//! then extends as far as its precedence allows.
+//! Comparisons group from left to right like other operators, but `a < b < c` comparing
+//! `a < b` to `c` is hardly ever meant, so chaining them is reported with the suggestion
+//! of `a < b and b < c`, while the tree is still built.
+//!
+//! `>-` marks the return type of a function in its signature, and pipes a value in expressions:
+//! `x >- f(a) >- g` is `g(f(x, a))`, the value being the first argument of the call after it.
+//! Signatures have no expressions before their body, so the two never meet,
+//! and neither do they in closures, which have the signatures of functions.
+//! A closure as the last stage of a pipeline, like `x >- |a| a * 2`, is called with the value.
+//! So are partial applications, calls with `_` among their arguments that are closures
+//! with a parameter for each, and sections like `(+ 1)`, operators missing their left operand:
+//! `xs >- map(_, f)` calls `map(_, f)` with `xs`, and `x >- (* 2)` calls `(* 2)` with `x`.
+//! Operators that start expressions like `-` don't start sections, `(-1)` being a number.
+//! Function types mark their return type with `>-` too, as `->` after a signature starts its body.
+//! Method calls work the same way, `a.f(b)` being the call `f(a, b)`.
+//!
+//! `++` and `--` are prefix and postfix operators, postfix ones binding like fields and indices
+//! from left to right, so `a.b[0]++` increments `a.b[0]` and `a++.b` is a field of `a++`.
+//! Prefix ones take all of that as their operand, `++a.b++` being `++(a.b++)`.
+//! Their operands are written to, and the passes after the parser reject those
+//! that are not places, like in `5++` (see [`Ast::is_place`]).
+//!
+//! Errors are recovered from at the end of the statement or item they are in,
+//! which becomes an error node, so that parsing goes on and finds the errors after it.
+//! Items never start in blocks, so one that does is taken as the block missing its `}`,
+//! which is reported with where the block starts, and the item is recovered at.
+
+use std::{error::Error, fmt, mem};
+
+use crate::{
+    arena::{ArenaSlice, SliceArena},
+    ast::{
+        Access, Arm, Ast, Attr, AttrKind, BinaryOp, Block, Capture, ClosureParam, Expr, ExprId,
+        Field, FieldInit, ForIter, GenericParam, Hole, Item, ItemId, ItemKind, LitKind, Param,
+        Pattern, PatternId, Stmt, StmtId, TokenRange, Type, TypeId, UnaryOp, Variant,
+    },
+    lexer::{Span, Token, TokenCursor, TokenSpan, TokenType, Tokens},
+    source::FileId,
+};
+
+mod check;
+mod grammar;
+mod parallel;
+
+pub use check::{parse_checked, InvariantViolation};
+pub use grammar::{grammar, write_ebnf, Rule};
+pub use parallel::parse_parallel;
+
+/// Maximum number of expressions that can be nested in one another,
+/// so that deep nesting returns an error rather than overflowing the stack.
+pub const MAX_NESTING_DEPTH: usize = 64;
+
+/// Maximum number of calls, fields, indices and operators that can follow each other
+/// in an expression and the ones it's nested in, like in `a.b().c[0] + d`.
+///
+/// Chains are parsed in a loop, but every link wraps the previous ones in a node,
+/// so long ones would overflow the stack of the passes that go through the tree.
+pub const MAX_CHAIN_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseErrorKind {
+    /// A token of the given type was expected
+    ExpectedToken(TokenType),
+    ExpectedItem,
+    ExpectedExpr,
+    ExpectedType,
+    ExpectedPattern,
+    /// A hole of an interpolated string has more than an expression
+    ExpectedHoleEnd,
+    /// A label was not followed by a loop
+    ExpectedLoop,
+    /// A `.` was followed by a number that is not a decimal index, like `t.0x1`
+    ExpectedTupleIndex,
+    /// An attribute is not one of [`ATTRIBUTES`](crate::ast::ATTRIBUTES)
+    UnknownAttr,
+    NestingTooDeep,
+    /// An item starts in a block, which is likely missing its `}`,
+    /// with the span, line and column of the `{` of the innermost block
+    ItemInBlock {
+        brace: Span,
+        line: usize,
+        col: usize,
+    },
+    /// Comparisons chained like `a < b < c`, which compares `a < b` to `c`,
+    /// with the span of the operand in the middle to suggest `a < b and b < c`
+    ChainedComparison {
+        middle: Span,
+    },
+}
+
+/// A parsing error, found at a token that doesn't fit the grammar.
+#[derive(Debug, Clone)]
+pub struct ParseError<'a> {
+    pub file: FileId,
+    pub kind: ParseErrorKind,
+    /// Type of the token that was found
+    pub found: TokenType,
+    pub span: TokenSpan<'a>,
+}
+
+impl<'a> ParseError<'a> {
+    /// What the error is, without its position.
+    pub fn message(&self) -> String {
+        let message = self.to_string();
+        let position_len = message.find(": ").map_or(0, |i| i + 2);
+        message[position_len..].to_string()
+    }
+
+    /// For a chained comparison, the chain with its middle operand compared to both sides,
+    /// like `a < b and b < c` for `a < b < c`, along with that operand.
+    pub fn unchained(&self) -> Option<(String, &'a str)> {
+        let ParseErrorKind::ChainedComparison { middle } = self.kind else {
+            return None;
+        };
+        // the span is the whole chain, which the middle operand is within
+        let chain = self.span.slice;
+        let middle_end = middle.end() - self.span.start;
+        let middle = &chain[middle_end - middle.len as usize..middle_end];
+        let (compared, rest) = chain.split_at(middle_end);
+        Some((format!("{compared} and {middle}{rest}"), middle))
+    }
+}
+
+impl<'a> fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.span.line, self.span.col)?;
+        match self.kind {
+            ParseErrorKind::ExpectedToken(ty) => write!(f, "Expected {ty:?}")?,
+            ParseErrorKind::ExpectedItem => f.write_str("Expected item")?,
+            ParseErrorKind::ExpectedExpr => f.write_str("Expected expression")?,
+            ParseErrorKind::ExpectedType => f.write_str("Expected type")?,
+            ParseErrorKind::ExpectedPattern => f.write_str("Expected pattern")?,
+            ParseErrorKind::ExpectedHoleEnd => f.write_str("Expected end of hole")?,
+            ParseErrorKind::ExpectedLoop => f.write_str("Expected loop")?,
+            ParseErrorKind::ExpectedTupleIndex => f.write_str("Expected tuple index")?,
+            ParseErrorKind::UnknownAttr => {
+                return write!(f, "Unknown attribute `{}`", self.span.slice)
+            }
+            ParseErrorKind::NestingTooDeep => return f.write_str("Nested too deeply"),
+            ParseErrorKind::ChainedComparison { .. } => {
+                let (rewrite, middle) = self.unchained().unwrap();
+                return write!(
+                    f,
+                    "Comparisons cannot be chained, write `{rewrite}` \
+                     to compare `{middle}` to both sides"
+                );
+            }
+            ParseErrorKind::ItemInBlock { line, col, .. } => {
+                let message = "Items are not allowed in blocks, did you mean to close the block";
+                return write!(f, "{message} at {line}:{col}?");
+            }
+        }
+        match self.found {
+            TokenType::Eof => f.write_str(", found end of file"),
+            found => write!(f, ", found {found:?}"),
+        }
+    }
+}
+
+impl Error for ParseError<'_> {}
+
+type ParseResult<'a, T> = Result<T, ParseError<'a>>;
+
+/// Parses the tokens of a file, along with its errors in order of appearance.
+pub fn parse_file<'a>(tokens: &Tokens<'a>) -> (Ast, Vec<ParseError<'a>>) {
+    let mut ast = Ast::new(tokens);
+    let mut parser = Parser {
+        tokens,
+        cursor: tokens.cursor(),
+        ast: &ast,
+        depth: 0,
+        chain_depth: 0,
+        diagnostics: Vec::new(),
+        struct_literals: true,
+    };
+
+    let file_attrs = match parser.attrs(TokenType::InnerAttrStart) {
+        Ok(attrs) => attrs,
+        Err(e) => {
+            parser.diagnostics.push(e);
+            parser.skip_to_item(0);
+            ArenaSlice::EMPTY
+        }
+    };
+    while !parser.cursor.is_eof() {
+        let (idx, start) = (parser.cursor.idx(), parser.next_start());
+        if let Err(e) = parser.item() {
+            parser.diagnostics.push(e);
+            parser.skip_to_item(idx);
+
+            let item = Item {
+                attrs: ArenaSlice::EMPTY,
+                is_pub: false,
+                name: Span { start, len: 0 },
+                generics: ArenaSlice::EMPTY,
+                kind: ItemKind::Error,
+            };
+            ast.items.alloc(item, parser.span_from(start));
+        }
+    }
+
+    let diagnostics = parser.diagnostics;
+    ast.file_attrs = file_attrs;
+    (ast, diagnostics)
+}
+
+/// Precedence of operators, from the loosest to the tightest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Prec {
+    /// `>-`, looser than every other operator so that `a + b >- f` is `f(a + b)`
+    Pipe,
+    Or,
+    And,
+    /// `not` applies to comparisons as a whole, so that `not a == b` is `not (a == b)`
+    Not,
+    Compare,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Sum,
+    Product,
+    /// Prefix `-`, `~`, `++` and `--`, looser than `**` so that `-a ** b` is `-(a ** b)`
+    Prefix,
+    Pow,
+    /// Postfix `++`, `--` and calls
+    Postfix,
+}
+
+/// How operators of the same precedence group, `a • b • c` being `(a • b) • c` when left-associative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Fixity {
+    Prefix(UnaryOp),
+    Infix(BinaryOp, Assoc),
+    Postfix(UnaryOp),
+    /// Left-associative, turned into a call of its right operand
+    Pipe,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Operator {
+    token: TokenType,
+    fixity: Fixity,
+    prec: Prec,
+}
+
+const fn prefix(token: TokenType, op: UnaryOp, prec: Prec) -> Operator {
+    Operator {
+        token,
+        fixity: Fixity::Prefix(op),
+        prec,
+    }
+}
+
+const fn infix(token: TokenType, op: BinaryOp, prec: Prec, assoc: Assoc) -> Operator {
+    Operator {
+        token,
+        fixity: Fixity::Infix(op, assoc),
+        prec,
+    }
+}
+
+const fn postfix(token: TokenType, op: UnaryOp) -> Operator {
+    Operator {
+        token,
+        fixity: Fixity::Postfix(op),
+        prec: Prec::Postfix,
+    }
+}
+
+const fn pipe(token: TokenType) -> Operator {
+    Operator {
+        token,
+        fixity: Fixity::Pipe,
+        prec: Prec::Pipe,
+    }
+}
+
+/// Every operator of the grammar, the only place that defines how expressions group.
+const OPERATORS: &[Operator] = {
+    use Assoc::*;
+    use BinaryOp as B;
+    use Prec as P;
+    use TokenType as T;
+    use UnaryOp as U;
+
+    &[
+        pipe(T::Feather),
+        infix(T::Or, B::Or, P::Or, Left),
+        infix(T::Xor, B::Xor, P::Or, Left),
+        infix(T::And, B::And, P::And, Left),
+        prefix(T::Not, U::Not, P::Not),
+        infix(T::Equals, B::Eq, P::Compare, Left),
+        infix(T::NotEquals, B::Ne, P::Compare, Left),
+        infix(T::LessThan, B::Lt, P::Compare, Left),
+        infix(T::GreaterThan, B::Gt, P::Compare, Left),
+        infix(T::LessEqual, B::Le, P::Compare, Left),
+        infix(T::GreaterEqual, B::Ge, P::Compare, Left),
+        infix(T::Pipe, B::BitOr, P::BitOr, Left),
+        infix(T::Caret, B::BitXor, P::BitXor, Left),
+        infix(T::Ampersand, B::BitAnd, P::BitAnd, Left),
+        infix(T::LShift, B::Shl, P::Shift, Left),
+        infix(T::RShift, B::Shr, P::Shift, Left),
+        infix(T::Plus, B::Add, P::Sum, Left),
+        infix(T::Minus, B::Sub, P::Sum, Left),
+        infix(T::Mul, B::Mul, P::Product, Left),
+        infix(T::Div, B::Div, P::Product, Left),
+        infix(T::Modulo, B::Mod, P::Product, Left),
+        prefix(T::Minus, U::Neg, P::Prefix),
+        prefix(T::Tilde, U::BitNot, P::Prefix),
+        prefix(T::Incr, U::PreIncr, P::Prefix),
+        prefix(T::Decr, U::PreDecr, P::Prefix),
+        infix(T::Pow, B::Pow, P::Pow, Right),
+        postfix(T::Incr, U::PostIncr),
+        postfix(T::Decr, U::PostDecr),
+    ]
+};
+
+/// Operator of the table that this token is with the given fixity, if any.
+fn operator(ty: TokenType, is_fixity: fn(Fixity) -> bool) -> Option<Operator> {
+    (OPERATORS.iter())
+        .find(|op| op.token == ty && is_fixity(op.fixity))
+        .copied()
+}
+
+/// Binding powers of an infix operator, see [`binding_power`].
+pub(crate) fn infix_power(op: BinaryOp) -> (u8, u8) {
+    let infix = OPERATORS.iter().find_map(|operator| match operator.fixity {
+        Fixity::Infix(other, assoc) if other == op => Some(binding_power(operator.prec, assoc)),
+        _ => None,
+    });
+    infix.expect("every binary operator is in the table")
+}
+
+/// Binding power of the operand of a prefix operator, see [`binding_power`].
+pub(crate) fn prefix_power(op: UnaryOp) -> u8 {
+    let prefix = OPERATORS.iter().find_map(|operator| match operator.fixity {
+        Fixity::Prefix(other) if other == op => Some(binding_power(operator.prec, Assoc::Right).1),
+        _ => None,
+    });
+    prefix.expect("every prefix operator is in the table")
+}
+
+/// Binding powers of the operator, the operand on a side is parsed with the power of that side.
+///
+/// Operators bind their operands more tightly than the operators of lower powers.
+/// Associativity makes the two sides of an infix operator differ, so that
+/// the operator binds more tightly on its side that groups first.
+fn binding_power(prec: Prec, assoc: Assoc) -> (u8, u8) {
+    let power = prec as u8 * 2;
+    match assoc {
+        Assoc::Left => (power, power + 1),
+        Assoc::Right => (power + 1, power),
+    }
+}
+
+struct Parser<'t, 'a> {
+    tokens: &'t Tokens<'a>,
+    cursor: TokenCursor<'t, 'a>,
+    /// Borrowed rather than owned, so that lists can be built in its arenas
+    /// while their elements are parsed
+    ast: &'t Ast,
+    /// Number of expressions being parsed
+    depth: usize,
+    /// Number of links of the chains being parsed, see [`MAX_CHAIN_DEPTH`]
+    chain_depth: usize,
+    /// Errors that were recovered from
+    diagnostics: Vec<ParseError<'a>>,
+    /// Whether an identifier followed by a brace is a struct literal
+    struct_literals: bool,
+}
+
+impl<'t, 'a> Parser<'t, 'a> {
+    fn item(&mut self) -> ParseResult<'a, ItemId> {
+        let start = self.next_start();
+        let attrs = self.attrs(TokenType::AttrStart)?;
+        let is_pub = self.cursor.eat(TokenType::Pub).is_some();
+        if self.cursor.peek_is(TokenType::Use)
+            && (self.cursor.peek(1)).is_some_and(|t| t.ty == TokenType::Ident)
+        {
+            return self.import(start, attrs, is_pub);
+        }
+        if (self.cursor.peek_is(TokenType::Const) || self.cursor.peek_is(TokenType::Static))
+            && (self.cursor.peek(1)).is_some_and(|t| t.ty == TokenType::Ident)
+        {
+            return self.global(start, attrs, is_pub);
+        }
+
+        let name = self.ident()?;
+        self.expect(TokenType::DoubleColon)?;
+
+        let (generics, kind) = if self.cursor.peek_is(TokenType::Fn) {
+            self.function()?
+        } else if self.cursor.peek_is(TokenType::Struct) || self.cursor.peek_is(TokenType::Packed) {
+            self.structure()?
+        } else if self.cursor.eat(TokenType::Enum).is_some() {
+            let generics = self.generics()?;
+            let repr = self.repr()?;
+            let ast = self.ast;
+            let variants = self.list(&ast.variants, TokenType::LBrace, Self::variant)?;
+            (generics, ItemKind::Enum { repr, variants })
+        } else if self.cursor.eat(TokenType::Union).is_some() {
+            let generics = self.generics()?;
+            let ast = self.ast;
+            let fields = self.list(&ast.fields, TokenType::LBrace, Self::field)?;
+            (generics, ItemKind::Union { fields })
+        } else {
+            return Err(self.error(ParseErrorKind::ExpectedItem));
+        };
+
+        let item = Item {
+            attrs,
+            is_pub,
+            name,
+            generics,
+            kind,
+        };
+        Ok(self.ast.items.alloc(item, self.span_from(start)))
+    }
+
+    fn import(
+        &mut self,
+        start: u32,
+        attrs: ArenaSlice<Attr>,
+        is_pub: bool,
+    ) -> ParseResult<'a, ItemId> {
+        self.expect(TokenType::Use)?;
+        let mut path = self.ast.paths.small_vec();
+        loop {
+            path.push(self.ident()?);
+            if self.cursor.eat(TokenType::DoubleColon).is_none() {
+                break;
+            }
+        }
+        self.expect(TokenType::Semi)?;
+
+        let name = path.as_slice()[path.len() - 1];
+        let item = Item {
+            attrs,
+            is_pub,
+            name,
+            generics: ArenaSlice::EMPTY,
+            kind: ItemKind::Use {
+                path: path.freeze(),
+            },
+        };
+        Ok(self.ast.items.alloc(item, self.span_from(start)))
+    }
+
+    /// Parses a `const` or `static` item.
+    fn global(
+        &mut self,
+        start: u32,
+        attrs: ArenaSlice<Attr>,
+        is_pub: bool,
+    ) -> ParseResult<'a, ItemId> {
+        let is_static = self.cursor.peek_is(TokenType::Static);
+        self.cursor.next();
+        let name = self.ident()?;
+        self.expect(TokenType::Colon)?;
+        let ty = match self.cursor.peek_is(TokenType::Equal) {
+            true => None,
+            false => Some(self.ty()?),
+        };
+        self.expect(TokenType::Equal)?;
+        let value = self.expr()?;
+        self.expect(TokenType::Semi)?;
+
+        let kind = match is_static {
+            true => ItemKind::Static { ty, value },
+            false => ItemKind::Const { ty, value },
+        };
+        let item = Item {
+            attrs,
+            is_pub,
+            name,
+            generics: ArenaSlice::EMPTY,
+            kind,
+        };
+        Ok(self.ast.items.alloc(item, self.span_from(start)))
+    }
+
+    fn function(&mut self) -> ParseResult<'a, (ArenaSlice<GenericParam>, ItemKind)> {
+        self.expect(TokenType::Fn)?;
+        let generics = self.generics()?;
+        let ast = self.ast;
+        let params = self.list(&ast.params, TokenType::LParens, |parser| {
+            let pattern = parser.pattern()?;
+            parser.expect(TokenType::Colon)?;
+            let ty = parser.ty()?;
+            Ok(Param { pattern, ty })
+        })?;
+
+        let ret = match self.cursor.eat(TokenType::Feather) {
+            Some(_) => Some(self.ty()?),
+            None => None,
+        };
+
+        let body = if self.cursor.eat(TokenType::Arrow).is_some() {
+            let body = self.expr()?;
+            self.expect(TokenType::Semi)?;
+            body
+        } else {
+            self.block()?
+        };
+        Ok((generics, ItemKind::Fn { params, ret, body }))
+    }
+
+    fn structure(&mut self) -> ParseResult<'a, (ArenaSlice<GenericParam>, ItemKind)> {
+        let packed = self.cursor.eat(TokenType::Packed).is_some();
+        self.expect(TokenType::Struct)?;
+        let generics = self.generics()?;
+        let repr = self.repr()?;
+        let ast = self.ast;
+        let fields = self.list(&ast.fields, TokenType::LBrace, Self::field)?;
+        let kind = ItemKind::Struct {
+            packed,
+            repr,
+            fields,
+        };
+        Ok((generics, kind))
+    }
+
+    /// Generic parameters after the keyword of an item, if any.
+    fn generics(&mut self) -> ParseResult<'a, ArenaSlice<GenericParam>> {
+        if !self.cursor.peek_is(TokenType::LBracket) {
+            return Ok(ArenaSlice::EMPTY);
+        }
+        let ast = self.ast;
+        self.list(&ast.generics, TokenType::LBracket, |parser| {
+            let name = parser.ident()?;
+            Ok(GenericParam { name })
+        })
+    }
+
+    /// Type before the `{` of a struct or an enum, if any.
+    fn repr(&mut self) -> ParseResult<'a, Option<TypeId>> {
+        match self.cursor.peek_is(TokenType::LBrace) {
+            true => Ok(None),
+            false => self.ty().map(Some),
+        }
+    }
+
+    /// Parses the attributes at the cursor, which are between `#![` and `]` for the given `open`
+    /// rather than between `#[` and `]`.
+    fn attrs(&mut self, open: TokenType) -> ParseResult<'a, ArenaSlice<Attr>> {
+        let mut attrs = self.ast.attrs.small_vec();
+        while self.cursor.eat(open).is_some() {
+            while !self.cursor.peek_is(TokenType::AttrEnd) {
+                attrs.push(self.attr()?);
+                if self.cursor.eat(TokenType::Comma).is_none() {
+                    break;
+                }
+            }
+            self.expect(TokenType::AttrEnd)?;
+        }
+        Ok(attrs.freeze())
+    }
+
+    fn attr(&mut self) -> ParseResult<'a, Attr> {
+        let start = self.next_start();
+        let mut path = self.ast.paths.small_vec();
+        loop {
+            path.push(self.ident()?);
+            if self.cursor.eat(TokenType::DoubleColon).is_none() {
+                break;
+            }
+        }
+        let path_span = self.span_from(start);
+        let segments: Vec<_> = (path.as_slice().iter())
+            .map(|segment| &self.tokens.code[segment.range()])
+            .collect();
+        let kind = AttrKind::from_path(&segments);
+        if kind.is_none() {
+            self.diagnostics.push(ParseError {
+                file: self.tokens.file,
+                kind: ParseErrorKind::UnknownAttr,
+                found: TokenType::Ident,
+                span: self.tokens.resolve(path_span),
+            });
+        }
+
+        let args = match self.cursor.peek_is(TokenType::LParens) {
+            true => Some(self.token_tree()?),
+            false => None,
+        };
+        Ok(Attr {
+            kind,
+            path: path.freeze(),
+            args,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Skips the tokens between parentheses, which can have delimiters of their own,
+    /// returning those within them.
+    fn token_tree(&mut self) -> ParseResult<'a, TokenRange> {
+        use TokenType as T;
+
+        self.expect(T::LParens)?;
+        let start = self.cursor.idx();
+        let mut depth = 0usize;
+        loop {
+            match self.cursor.peek(0).map(|token| token.ty) {
+                Some(T::LParens | T::LBracket | T::LBrace) => depth += 1,
+                Some(T::RParens) if depth == 0 => break,
+                Some(T::RParens | T::RBracket | T::RBrace) => depth = depth.saturating_sub(1),
+                // attributes end at their `]` even when their parentheses don't match
+                Some(T::AttrEnd | T::Eof) | None => break,
+                Some(_) => {}
+            }
+            self.cursor.next();
+        }
+        let len = self.cursor.idx() - start;
+        self.expect(T::RParens)?;
+        Ok(TokenRange {
+            start: start as u32,
+            len: len as u32,
+        })
+    }
+
+    fn field(&mut self) -> ParseResult<'a, Field> {
+        let attrs = self.attrs(TokenType::AttrStart)?;
+        let is_pub = self.cursor.eat(TokenType::Pub).is_some();
+        let name = self.ident()?;
+        self.expect(TokenType::Colon)?;
+        let ty = self.ty()?;
+        Ok(Field {
+            attrs,
+            is_pub,
+            name,
+            ty,
+        })
+    }
+
+    fn variant(&mut self) -> ParseResult<'a, Variant> {
+        let name = self.ident()?;
+        let ty = match self.cursor.eat(TokenType::Colon) {
+            Some(_) => Some(self.ty()?),
+            None => None,
+        };
+        let discriminant = match self.cursor.eat(TokenType::Equal) {
+            Some(_) => Some(self.expr()?),
+            None => None,
+        };
+        Ok(Variant {
+            name,
+            ty,
+            discriminant,
+        })
+    }
+
+    fn pattern(&mut self) -> ParseResult<'a, PatternId> {
+        self.nested(|parser| {
+            let start = parser.next_start();
+            let first = parser.primary_pattern()?;
+            if !parser.cursor.peek_is(TokenType::Pipe) {
+                return Ok(first);
+            }
+
+            let mut alternatives = parser.ast.pattern_lists.small_vec();
+            alternatives.push(first);
+            while parser.cursor.eat(TokenType::Pipe).is_some() {
+                alternatives.push(parser.primary_pattern()?);
+            }
+            let pattern = Pattern::Or(alternatives.freeze());
+            Ok(parser.ast.patterns.alloc(pattern, parser.span_from(start)))
+        })
+    }
+
+    fn primary_pattern(&mut self) -> ParseResult<'a, PatternId> {
+        use TokenType as T;
+
+        let start = self.next_start();
+        let Some(token) = self.cursor.peek(0) else {
+            return Err(self.error(ParseErrorKind::ExpectedPattern));
+        };
+        let is_next = |ty| self.cursor.peek(1).is_some_and(|t| t.ty == ty);
+
+        if let Some(lit) = lit_kind(&token) {
+            self.cursor.next();
+            return Ok(self
+                .ast
+                .patterns
+                .alloc(Pattern::Lit(lit), token.span.into()));
+        }
+        let pattern = match token.ty {
+            T::Ident if token.span.slice == "_" => {
+                self.cursor.next();
+                Pattern::Wildcard
+            }
+            T::Ident if !is_next(T::Dot) => {
+                self.cursor.next();
+                Pattern::Ident
+            }
+            T::Ident | T::Dot => {
+                let ty = match token.ty {
+                    T::Ident => Some(self.ty()?),
+                    _ => None,
+                };
+                self.expect(T::Dot)?;
+                let name = self.ident()?;
+                let payload = match self.cursor.eat(T::LParens) {
+                    Some(_) => {
+                        let payload = self.pattern()?;
+                        self.expect(T::RParens)?;
+                        Some(payload)
+                    }
+                    None => None,
+                };
+                Pattern::Variant { ty, name, payload }
+            }
+            T::Minus if is_next(T::Num) => {
+                self.cursor.next();
+                self.cursor.next();
+                Pattern::Lit(LitKind::Num)
+            }
+            _ => return Err(self.error(ParseErrorKind::ExpectedPattern)),
+        };
+        Ok(self.ast.patterns.alloc(pattern, self.span_from(start)))
+    }
+
+    fn ty(&mut self) -> ParseResult<'a, TypeId> {
+        use TokenType as T;
+
+        self.nested(|parser| {
+            let start = parser.next_start();
+            let Some(token) = parser.cursor.peek(0) else {
+                return Err(parser.error(ParseErrorKind::ExpectedType));
+            };
+
+            let ty = match token.ty {
+                T::Ident => {
+                    parser.cursor.next();
+                    let base = parser.ast.types.alloc(Type::Named, token.span.into());
+                    if !parser.cursor.peek_is(T::LBracket) {
+                        return Ok(base);
+                    }
+                    let ast = parser.ast;
+                    let args = parser.list(&ast.type_lists, T::LBracket, Self::ty)?;
+                    Type::Generic { base, args }
+                }
+                T::Mul => {
+                    parser.cursor.next();
+                    Type::Pointer(parser.ty()?)
+                }
+                // `**T` is a pointer to a pointer
+                T::Pow => {
+                    parser.cursor.next();
+                    let pointee = parser.ty()?;
+                    let inner = Span {
+                        start: start + 1,
+                        len: parser.span_from(start).len - 1,
+                    };
+                    Type::Pointer(parser.ast.types.alloc(Type::Pointer(pointee), inner))
+                }
+                T::Ampersand => {
+                    parser.cursor.next();
+                    Type::Ref(parser.ty()?)
+                }
+                T::Question => {
+                    parser.cursor.next();
+                    Type::Optional(parser.ty()?)
+                }
+                T::Fn => {
+                    parser.cursor.next();
+                    let ast = parser.ast;
+                    let params = parser.list(&ast.type_lists, T::LParens, Self::ty)?;
+                    let ret = match parser.cursor.eat(T::Feather) {
+                        Some(_) => Some(parser.ty()?),
+                        None => None,
+                    };
+                    Type::Fn { params, ret }
+                }
+                T::LBracket => {
+                    parser.cursor.next();
+                    let elem = parser.ty()?;
+                    let ty = match parser.cursor.eat(T::Semi) {
+                        Some(_) => Type::Array {
+                            elem,
+                            len: parser.expr()?,
+                        },
+                        None => Type::Slice(elem),
+                    };
+                    parser.expect(T::RBracket)?;
+                    ty
+                }
+                T::LParens => {
+                    parser.cursor.next();
+                    if parser.cursor.eat(T::RParens).is_some() {
+                        Type::Tuple(ArenaSlice::EMPTY)
+                    } else {
+                        let first = parser.ty()?;
+                        // a single type without a comma is only between parentheses
+                        if parser.cursor.eat(T::RParens).is_some() {
+                            return Ok(first);
+                        }
+                        let mut elems = parser.ast.type_lists.small_vec();
+                        elems.push(first);
+                        while parser.cursor.eat(T::Comma).is_some()
+                            && !parser.cursor.peek_is(T::RParens)
+                        {
+                            elems.push(parser.ty()?);
+                        }
+                        parser.expect(T::RParens)?;
+                        Type::Tuple(elems.freeze())
+                    }
+                }
+                _ => return Err(parser.error(ParseErrorKind::ExpectedType)),
+            };
+            Ok(parser.ast.types.alloc(ty, parser.span_from(start)))
+        })
+    }
+
+    fn block(&mut self) -> ParseResult<'a, ExprId> {
+        let start = self.next_start();
+        let brace = self.expect(TokenType::LBrace)?.span;
+
+        let mut stmts = self.ast.stmt_lists.small_vec();
+        // the list of statements is still being built, so defers are kept aside
+        let mut defers = Vec::new();
+        while !self.cursor.peek_is(TokenType::RBrace) && !self.cursor.is_eof() {
+            // the item is recovered at, after the error of the item that the block is in
+            if self.is_item_start() {
+                let (line, col) = (brace.line, brace.col);
+                let brace = Span::from(&brace);
+                return Err(self.error(ParseErrorKind::ItemInBlock { brace, line, col }));
+            }
+
+            let (idx, start) = (self.cursor.idx(), self.next_start());
+            let stmt = match self.stmt() {
+                Ok(stmt) => stmt,
+                Err(e) => {
+                    let goes_on = self.skip_to_stmt_end(idx);
+                    // inner blocks already told which block is missing its `}`
+                    let is_item_in_block = matches!(e.kind, ParseErrorKind::ItemInBlock { .. });
+                    if !goes_on && (self.cursor.is_eof() || is_item_in_block) {
+                        return Err(e);
+                    }
+                    self.diagnostics.push(e);
+                    if !goes_on {
+                        continue;
+                    }
+                    self.ast.stmts.alloc(Stmt::Error, self.span_from(start))
+                }
+            };
+            if let Stmt::Defer(_) = self.ast.stmts[stmt] {
+                defers.push(stmt);
+            }
+            stmts.push(stmt);
+        }
+        self.expect(TokenType::RBrace)?;
+
+        let tail = match stmts.as_slice().last().map(|&stmt| self.ast.stmts[stmt]) {
+            Some(Stmt::Expr(expr)) => Some(expr),
+            _ => None,
+        };
+        let stmts = stmts.freeze();
+        defers.reverse();
+        let defers = self.ast.stmt_lists.alloc(&defers);
+        let block = Block {
+            stmts,
+            defers,
+            tail,
+        };
+        Ok(self.alloc_expr(Expr::Block(block), start))
+    }
+
+    fn stmt(&mut self) -> ParseResult<'a, StmtId> {
+        let start = self.next_start();
+        let attrs = self.attrs(TokenType::AttrStart)?;
+        let stmt = if self.cursor.eat(TokenType::Defer).is_some() {
+            let expr = self.expr()?;
+            self.stmt_end()?;
+            Stmt::Defer(expr)
+        } else {
+            match self.declaration_access() {
+                Some(access) => self.declaration(access)?,
+                None => self.expr_stmt()?,
+            }
+        };
+        let id = self.ast.stmts.alloc(stmt, self.span_from(start));
+        if !attrs.is_empty() {
+            self.ast.stmt_attrs.add((id, attrs));
+        }
+        Ok(id)
+    }
+
+    /// Access of the declaration at the cursor, if there is one.
+    ///
+    /// Declarations start with a name followed by a `:`, which can have `r`, `w` or `rw` before it.
+    fn declaration_access(&self) -> Option<Access> {
+        let is_ident = |n| {
+            self.cursor
+                .peek(n)
+                .is_some_and(|t| t.ty == TokenType::Ident)
+        };
+        let marker = (self.cursor.peek(0)).filter(|_| is_ident(0) && is_ident(1));
+        let (access, name) = match marker.as_ref().map(|t| t.span.slice) {
+            Some("r") => (Access::Read, 1),
+            Some("w") => (Access::Write, 1),
+            Some("rw") => (Access::ReadWrite, 1),
+            _ => (Access::Implicit, 0),
+        };
+
+        let is_colon = (self.cursor.peek(name + 1)).is_some_and(|t| t.ty == TokenType::Colon);
+        (is_ident(name) && is_colon).then_some(access)
+    }
+
+    fn declaration(&mut self, access: Access) -> ParseResult<'a, Stmt> {
+        if access != Access::Implicit {
+            self.cursor.next();
+        }
+        let pattern = self.pattern()?;
+        self.expect(TokenType::Colon)?;
+        let ty = match self.cursor.peek_is(TokenType::Equal) {
+            true => None,
+            false => Some(self.ty()?),
+        };
+        self.expect(TokenType::Equal)?;
+        let value = self.expr()?;
+        self.expect(TokenType::Semi)?;
+        Ok(Stmt::Let {
+            access,
+            pattern,
+            ty,
+            value,
+        })
+    }
+
+    /// Expression, or assignment to one.
+    fn expr_stmt(&mut self) -> ParseResult<'a, Stmt> {
+        let expr = self.expr()?;
+
+        let assign = if let Some(op) = self.compound_assign_op() {
+            self.cursor.next();
+            self.cursor.next();
+            Some(Some(op))
+        } else {
+            self.cursor.eat(TokenType::Equal).map(|_| None)
+        };
+        if let Some(op) = assign {
+            let value = self.expr()?;
+            self.expect(TokenType::Semi)?;
+            return Ok(Stmt::Assign {
+                target: expr,
+                op,
+                value,
+            });
+        }
+
+        match self.stmt_end()? {
+            true => Ok(Stmt::Semi(expr)),
+            false => Ok(Stmt::Expr(expr)),
+        }
+    }
+
+    /// Semicolon after an expression, returning whether there is one.
+    fn stmt_end(&mut self) -> ParseResult<'a, bool> {
+        if self.cursor.eat(TokenType::Semi).is_some() {
+            Ok(true)
+        } else if self.prev_is(TokenType::RBrace) || self.cursor.peek_is(TokenType::RBrace) {
+            Ok(false)
+        } else {
+            Err(self.error(ParseErrorKind::ExpectedToken(TokenType::Semi)))
+        }
+    }
+
+    /// Operator of the compound assignment at the cursor, like `+` for `+=`.
+    ///
+    /// There are no tokens for them, they are an operator directly followed by a `=`.
+    fn compound_assign_op(&self) -> Option<BinaryOp> {
+        use BinaryOp as B;
+
+        let (op, equal) = (self.cursor.peek(0)?, self.cursor.peek(1)?);
+        if equal.ty != TokenType::Equal || op.span.end() != equal.span.start {
+            return None;
+        }
+        match operator(op.ty, |f| matches!(f, Fixity::Infix(..)))?.fixity {
+            Fixity::Infix(
+                op @ (B::Add | B::Sub | B::Mul | B::Div | B::Mod | B::Pow | B::BitAnd | B::BitOr)
+                | op @ (B::BitXor | B::Shl | B::Shr),
+                _,
+            ) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> ParseResult<'a, ExprId> {
+        self.expr_bp(0)
+    }
+
+    /// Expression whose operators all have a left binding power of at least `min_power`,
+    /// see [`binding_power`].
+    fn expr_bp(&mut self, min_power: u8) -> ParseResult<'a, ExprId> {
+        self.nested(|parser| parser.operators(min_power))
+    }
+
+    /// Parses a node that can be nested in nodes of its kind, within [`MAX_NESTING_DEPTH`].
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> ParseResult<'a, T>,
+    ) -> ParseResult<'a, T> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(self.error(ParseErrorKind::NestingTooDeep));
+        }
+        self.depth += 1;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn operators(&mut self, min_power: u8) -> ParseResult<'a, ExprId> {
+        let chain_depth = self.chain_depth;
+        let expr = self.operator_chain(min_power);
+        self.chain_depth = chain_depth;
+        expr
+    }
+
+    /// Counts a link of a chain, see [`MAX_CHAIN_DEPTH`].
+    fn chain_link(&mut self) -> ParseResult<'a, ()> {
+        if self.chain_depth >= MAX_CHAIN_DEPTH {
+            return Err(self.error(ParseErrorKind::NestingTooDeep));
+        }
+        self.chain_depth += 1;
+        Ok(())
+    }
+
+    fn operator_chain(&mut self, min_power: u8) -> ParseResult<'a, ExprId> {
+        let start = self.next_start();
+        let prefix = (self.cursor.peek(0))
+            .and_then(|token| operator(token.ty, |f| matches!(f, Fixity::Prefix(_))));
+
+        let mut lhs = match prefix {
+            Some(Operator {
+                fixity: Fixity::Prefix(op),
+                prec,
+                ..
+            }) => {
+                self.cursor.next();
+                let (_, power) = binding_power(prec, Assoc::Right);
+                let expr = self.expr_bp(power)?;
+                self.alloc_expr(Expr::Unary { op, expr }, start)
+            }
+            _ => self.primary()?,
+        };
+
+        // the last comparison of the loop, which another one after it would be chained to
+        let (mut comparison, mut chain_reported) = (None, false);
+        while let Some(token) = self.cursor.peek(0) {
+            if token.ty == TokenType::LParens {
+                self.chain_link()?;
+                let args = self.args(&[])?;
+                lhs = self.alloc_expr(Expr::Call { callee: lhs, args }, start);
+                continue;
+            }
+            if self.cursor.eat(TokenType::LBracket).is_some() {
+                self.chain_link()?;
+                let index = self.with_struct_literals(true, Self::expr)?;
+                self.expect(TokenType::RBracket)?;
+                lhs = self.alloc_expr(Expr::Index { expr: lhs, index }, start);
+                continue;
+            }
+            // `..` ends the start of a range rather than accessing a field
+            if !self.is_dot_dot() && self.cursor.eat(TokenType::Dot).is_some() {
+                self.chain_link()?;
+                if self.cursor.peek_is(TokenType::Num) {
+                    lhs = self.tuple_fields(lhs, start)?;
+                    continue;
+                }
+                let name = self.ident()?;
+                let expr = match self.cursor.peek_is(TokenType::LParens) {
+                    true => Expr::MethodCall {
+                        method: name,
+                        args: self.args(&[lhs])?,
+                    },
+                    false => Expr::Field { expr: lhs, name },
+                };
+                lhs = self.alloc_expr(expr, start);
+                continue;
+            }
+
+            // the operator of a compound assignment ends the expression assigned to
+            if self.compound_assign_op().is_some() {
+                break;
+            }
+
+            let kind = match operator(token.ty, |f| !matches!(f, Fixity::Prefix(_))) {
+                Some(Operator {
+                    fixity: Fixity::Postfix(op),
+                    ..
+                }) => {
+                    self.cursor.next();
+                    Expr::Unary { op, expr: lhs }
+                }
+                Some(Operator {
+                    fixity: Fixity::Infix(op, assoc),
+                    prec,
+                    ..
+                }) => {
+                    let (left_power, right_power) = binding_power(prec, assoc);
+                    if left_power < min_power {
+                        break;
+                    }
+                    self.cursor.next();
+                    let rhs = self.expr_bp(right_power)?;
+                    let chained = comparison.filter(|&prev| prev == lhs && !chain_reported);
+                    if let (Prec::Compare, Some(prev)) = (prec, chained) {
+                        let Expr::Binary { rhs: middle, .. } = self.ast.exprs[prev] else {
+                            unreachable!();
+                        };
+                        self.diagnostics.push(ParseError {
+                            file: self.tokens.file,
+                            kind: ParseErrorKind::ChainedComparison {
+                                middle: self.ast.exprs.span(middle),
+                            },
+                            found: token.ty,
+                            span: self.tokens.resolve(self.span_from(start)),
+                        });
+                        chain_reported = true;
+                    }
+                    Expr::Binary { op, lhs, rhs }
+                }
+                Some(Operator {
+                    fixity: Fixity::Pipe,
+                    prec,
+                    ..
+                }) => {
+                    let (left_power, _) = binding_power(prec, Assoc::Left);
+                    if left_power < min_power {
+                        break;
+                    }
+                    self.cursor.next();
+                    self.pipe_call(lhs)?
+                }
+                _ => break,
+            };
+            self.chain_link()?;
+            let is_comparison = matches!(kind, Expr::Binary { op, .. } if op.is_comparison());
+            lhs = self.alloc_expr(kind, start);
+            if is_comparison {
+                comparison = Some(lhs);
+            }
+        }
+        Ok(lhs)
+    }
+
+    /// Parses the index of a tuple field after its `.`, and the one after it in `t.0.1`,
+    /// which is lexed as a single number.
+    fn tuple_fields(&mut self, expr: ExprId, start: u32) -> ParseResult<'a, ExprId> {
+        let Some(token) = self.cursor.peek(0) else {
+            return Err(self.error(ParseErrorKind::ExpectedTupleIndex));
+        };
+        let slice = token.span.slice;
+        let (first, second) = match slice.split_once('.') {
+            Some((first, second)) => (first, Some(second)),
+            None => (slice, None),
+        };
+        // indices are decimal, without leading zeros
+        let is_index = |digits: &str| {
+            digits.bytes().all(|b| b.is_ascii_digit())
+                && (digits == "0" || !digits.starts_with('0'))
+                && !digits.is_empty()
+        };
+        if !is_index(first) || !second.is_none_or(is_index) {
+            return Err(self.error(ParseErrorKind::ExpectedTupleIndex));
+        }
+        self.cursor.next();
+
+        let index = Span {
+            start: token.span.start as u32,
+            len: first.len() as u32,
+        };
+        let field = Expr::TupleField { expr, index };
+        let span = Span {
+            start,
+            len: index.end() as u32 - start,
+        };
+        let mut expr = self.ast.exprs.alloc(field, span);
+        if let Some(second) = second {
+            let index = Span {
+                start: index.end() as u32 + 1,
+                len: second.len() as u32,
+            };
+            expr = self.alloc_expr(Expr::TupleField { expr, index }, start);
+        }
+        Ok(expr)
+    }
+
+    /// Call that the value is piped into, as its first argument,
+    /// or as the first one after the receiver of a method.
+    fn pipe_call(&mut self, value: ExprId) -> ParseResult<'a, Expr> {
+        let start = self.next_start();
+        let mut callee = self.nested(Self::primary)?;
+        let mut method = None;
+        while self.cursor.eat(TokenType::Dot).is_some() {
+            let name = self.ident()?;
+            if let Some(field) = method.replace(name) {
+                self.chain_link()?;
+                callee = self.alloc_expr(
+                    Expr::Field {
+                        expr: callee,
+                        name: field,
+                    },
+                    start,
+                );
+            }
+        }
+
+        // a partial application is called with the value, like a closure
+        if self.cursor.peek_is(TokenType::LParens) && self.has_placeholder_arg() {
+            let partial = match method {
+                Some(method) => Expr::MethodCall {
+                    method,
+                    args: self.args(&[callee])?,
+                },
+                None => Expr::Call {
+                    callee,
+                    args: self.args(&[])?,
+                },
+            };
+            let callee = self.alloc_expr(partial, start);
+            let args = self.ast.expr_lists.alloc(&[value]);
+            return Ok(Expr::Call { callee, args });
+        }
+
+        let (leading, method) = match method {
+            Some(method) => (&[callee, value][..], Some(method)),
+            None => (&[value][..], None),
+        };
+        let args = match self.cursor.peek_is(TokenType::LParens) {
+            true => self.args(leading)?,
+            false => self.ast.expr_lists.alloc(leading),
+        };
+        Ok(match method {
+            Some(method) => Expr::MethodCall { method, args },
+            None => Expr::Call { callee, args },
+        })
+    }
+
+    /// Whether the arguments at the cursor have a placeholder, not counting the ones
+    /// of the calls nested in them.
+    fn has_placeholder_arg(&self) -> bool {
+        use TokenType as T;
+
+        let mut depth = 0_usize;
+        for n in 0.. {
+            let Some(token) = self.cursor.peek(n) else {
+                return false;
+            };
+            match token.ty {
+                T::Eof => return false,
+                T::LParens | T::LBracket | T::LBrace | T::StringInterpBeg => depth += 1,
+                T::RParens | T::RBracket | T::RBrace | T::StringInterpEnd => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return false;
+                    }
+                }
+                _ => {}
+            }
+            if depth == 1 && matches!(token.ty, T::LParens | T::Comma) && self.is_placeholder(n + 1)
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the `n`th token after the cursor is a whole argument that is `_`.
+    fn is_placeholder(&self, n: usize) -> bool {
+        let is = |n, ty| self.cursor.peek(n).is_some_and(|token| token.ty == ty);
+        (self.cursor.peek(n))
+            .is_some_and(|token| token.is(TokenType::Ident) && token.span.slice == "_")
+            && (is(n + 1, TokenType::Comma) || is(n + 1, TokenType::RParens))
+    }
+
+    /// Infix operator of the section that starts at the cursor, if one does.
+    ///
+    /// Operators that can start an expression don't start sections,
+    /// so that `(-1)` stays a negative number and `(|x| x)` a closure.
+    fn section_op(&self) -> Option<(BinaryOp, u8)> {
+        if !self.cursor.peek_is(TokenType::LParens) {
+            return None;
+        }
+        let token = self.cursor.peek(1)?;
+        if starts_expr(token.ty) {
+            return None;
+        }
+        match operator(token.ty, |f| matches!(f, Fixity::Infix(..)))? {
+            Operator {
+                fixity: Fixity::Infix(op, assoc),
+                prec,
+                ..
+            } => Some((op, binding_power(prec, assoc).1)),
+            _ => None,
+        }
+    }
+
+    /// Parses a section like `(+ 1)`, whose operand binds like the right operand of its operator.
+    fn section(&mut self, op: BinaryOp, power: u8) -> ParseResult<'a, Expr> {
+        self.expect(TokenType::LParens)?;
+        self.cursor.next();
+        let rhs = self.with_struct_literals(true, |parser| parser.expr_bp(power))?;
+        self.expect(TokenType::RParens)?;
+        Ok(Expr::Section { op, rhs })
+    }
+
+    /// Arguments of a call between parentheses, after the given ones.
+    ///
+    /// A missing comma between two arguments is recovered from.
+    fn args(&mut self, leading: &[ExprId]) -> ParseResult<'a, ArenaSlice<ExprId>> {
+        self.with_struct_literals(true, |parser| {
+            parser.expect(TokenType::LParens)?;
+            let mut args = parser.ast.expr_lists.small_vec();
+            args.extend_from_slice(leading);
+            while !parser.cursor.peek_is(TokenType::RParens) {
+                let arg = match parser.is_placeholder(0) {
+                    true => {
+                        let start = parser.next_start();
+                        parser.cursor.next();
+                        parser.alloc_expr(Expr::Placeholder, start)
+                    }
+                    false => parser.expr()?,
+                };
+                args.push(arg);
+                if parser.cursor.eat(TokenType::Comma).is_none() {
+                    match parser.cursor.peek(0) {
+                        Some(next) if next.ty != TokenType::RParens && starts_expr(next.ty) => {
+                            let e = parser.error(ParseErrorKind::ExpectedToken(TokenType::Comma));
+                            parser.diagnostics.push(e);
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            parser.expect(TokenType::RParens)?;
+            Ok(args.freeze())
+        })
+    }
+
+    fn array(&mut self) -> ParseResult<'a, ExprId> {
+        let start = self.next_start();
+        self.expect(TokenType::LBracket)?;
+        let array = if self.cursor.peek_is(TokenType::RBracket) {
+            Expr::Array(ArenaSlice::EMPTY)
+        } else {
+            let first = self.expr()?;
+            match self.cursor.eat(TokenType::Semi) {
+                Some(_) => Expr::ArrayRepeat {
+                    value: first,
+                    len: self.expr()?,
+                },
+                None => {
+                    let mut elements = self.ast.expr_lists.small_vec();
+                    elements.push(first);
+                    while self.cursor.eat(TokenType::Comma).is_some()
+                        && !self.cursor.peek_is(TokenType::RBracket)
+                    {
+                        elements.push(self.expr()?);
+                    }
+                    Expr::Array(elements.freeze())
+                }
+            }
+        };
+        self.expect(TokenType::RBracket)?;
+        Ok(self.alloc_expr(array, start))
+    }
+
+    /// Parses an expression between parentheses, which is a tuple if it has a comma.
+    fn parens(&mut self) -> ParseResult<'a, ExprId> {
+        let start = self.next_start();
+        self.expect(TokenType::LParens)?;
+        let first = self.expr()?;
+        if self.cursor.eat(TokenType::Comma).is_none() {
+            self.expect(TokenType::RParens)?;
+            return Ok(first);
+        }
+
+        let mut elements = self.ast.expr_lists.small_vec();
+        elements.push(first);
+        while !self.cursor.peek_is(TokenType::RParens) {
+            elements.push(self.expr()?);
+            if self.cursor.eat(TokenType::Comma).is_none() {
+                break;
+            }
+        }
+        self.expect(TokenType::RParens)?;
+        let tuple = Expr::Tuple(elements.freeze());
+        Ok(self.alloc_expr(tuple, start))
+    }
+
+    fn struct_literal(&mut self) -> ParseResult<'a, Expr> {
+        let ty = self.ty()?;
+        self.expect(TokenType::LBrace)?;
+
+        let mut fields = self.ast.field_inits.small_vec();
+        let mut base = None;
+        while !self.cursor.peek_is(TokenType::RBrace) {
+            if self.eat_dot_dot() {
+                base = Some(self.expr()?);
+                break;
+            }
+
+            let name = self.ident()?;
+            let value = match self.cursor.eat(TokenType::Colon) {
+                Some(_) => self.expr()?,
+                None => self.ast.exprs.alloc(Expr::Ident, name),
+            };
+            fields.push(FieldInit { name, value });
+            if self.cursor.eat(TokenType::Comma).is_none() {
+                break;
+            }
+        }
+        self.expect(TokenType::RBrace)?;
+
+        let fields = fields.freeze();
+        Ok(Expr::Struct { ty, fields, base })
+    }
+
+    /// Whether a `..` is at the cursor, which is two dots next to each other.
+    fn is_dot_dot(&self) -> bool {
+        match (self.cursor.peek(0), self.cursor.peek(1)) {
+            (Some(first), Some(second)) => {
+                first.ty == TokenType::Dot
+                    && second.ty == TokenType::Dot
+                    && first.span.end() == second.span.start
+            }
+            _ => false,
+        }
+    }
+
+    fn eat_dot_dot(&mut self) -> bool {
+        let is_dot_dot = self.is_dot_dot();
+        if is_dot_dot {
+            self.cursor.next();
+            self.cursor.next();
+        }
+        is_dot_dot
+    }
+
+    /// Parses with struct literals allowed or not, which they are again within delimiters.
+    fn with_struct_literals<T>(
+        &mut self,
+        allowed: bool,
+        parse: impl FnOnce(&mut Self) -> ParseResult<'a, T>,
+    ) -> ParseResult<'a, T> {
+        let outer = mem::replace(&mut self.struct_literals, allowed);
+        let node = parse(self);
+        self.struct_literals = outer;
+        node
+    }
+
+    fn primary(&mut self) -> ParseResult<'a, ExprId> {
+        use TokenType as T;
+
+        let start = self.next_start();
+        let Some(token) = self.cursor.peek(0) else {
+            return Err(self.error(ParseErrorKind::ExpectedExpr));
+        };
+
+        if let Some(lit) = lit_kind(&token) {
+            self.cursor.next();
+            return Ok(self.alloc_expr(Expr::Lit(lit), start));
+        }
+        if let Some((op, power)) = self.section_op() {
+            let section = self.section(op, power)?;
+            return Ok(self.alloc_expr(section, start));
+        }
+
+        let kind = match token.ty {
+            T::Ident
+                if token.is(T::Match)
+                    && (self.cursor.peek(1)).is_some_and(|t| starts_expr(t.ty)) =>
+            {
+                self.cursor.next();
+                let scrutinee = self.with_struct_literals(false, Self::expr)?;
+                let ast = self.ast;
+                let arms = self.list(&ast.arms, T::LBrace, Self::arm)?;
+                Expr::Match { scrutinee, arms }
+            }
+            T::Ident
+                if token.is(T::For) && (self.cursor.peek(1)).is_some_and(|t| t.ty == T::Ident) =>
+            {
+                self.loop_expr()?
+            }
+            T::Ident if token.is(T::Return) => {
+                self.cursor.next();
+                let value = match self.cursor.peek(0) {
+                    Some(token) if starts_expr(token.ty) => Some(self.expr()?),
+                    _ => None,
+                };
+                Expr::Return { value }
+            }
+            T::Ident
+                if self.struct_literals
+                    && self.cursor.peek(1).is_some_and(|t| t.ty == T::LBrace) =>
+            {
+                self.struct_literal()?
+            }
+            T::Ident => {
+                self.cursor.next();
+                Expr::Ident
+            }
+            T::LParens if self.cursor.peek(1).is_some_and(|t| t.ty == T::RParens) => {
+                self.cursor.next();
+                self.cursor.next();
+                Expr::Lit(LitKind::Unit)
+            }
+            T::LParens => return self.with_struct_literals(true, Self::parens),
+            T::LBrace => return self.with_struct_literals(true, Self::block),
+            T::LBracket => return self.with_struct_literals(true, Self::array),
+            T::Pipe | T::Fn => self.closure()?,
+            T::StringInterpBeg => {
+                self.cursor.next();
+                let ast = self.ast;
+                let mut parts = ast.string_parts.small_vec();
+                let mut holes = ast.holes.small_vec();
+                let mut segment = token;
+                loop {
+                    parts.push(string_part(&segment.span));
+                    if segment.ty == T::StringInterpEnd {
+                        break;
+                    }
+
+                    let expr = self.expr()?;
+                    let spec = (self.cursor.eat(T::FormatSpec)).map(|spec| spec.span.into());
+                    holes.push(Hole { expr, spec });
+
+                    segment = match self.cursor.peek(0) {
+                        Some(next)
+                            if matches!(next.ty, T::StringInterpMid | T::StringInterpEnd) =>
+                        {
+                            self.cursor.next();
+                            next
+                        }
+                        _ => return Err(self.error(ParseErrorKind::ExpectedHoleEnd)),
+                    };
+                }
+                Expr::InterpolatedString {
+                    parts: parts.freeze(),
+                    holes: holes.freeze(),
+                }
+            }
+            T::If => {
+                self.cursor.next();
+                let cond = self.with_struct_literals(false, Self::expr)?;
+                self.expect(T::Then)?;
+                let then = self.expr()?;
+                let otherwise = match self.cursor.eat(T::Else) {
+                    Some(_) => Some(self.expr()?),
+                    None => None,
+                };
+                Expr::If {
+                    cond,
+                    then,
+                    otherwise,
+                }
+            }
+            T::At | T::While | T::Do | T::Loop => self.loop_expr()?,
+            T::Break => {
+                self.cursor.next();
+                let label = self.label()?;
+                let value = match self.cursor.peek(0) {
+                    Some(token) if starts_expr(token.ty) => Some(self.expr()?),
+                    _ => None,
+                };
+                Expr::Break { label, value }
+            }
+            T::Continue => {
+                self.cursor.next();
+                let label = self.label()?;
+                Expr::Continue { label }
+            }
+            _ => return Err(self.error(ParseErrorKind::ExpectedExpr)),
+        };
+        Ok(self.alloc_expr(kind, start))
+    }
+
+    /// Parses a closure, either `|params| body` or `fn[captures](params) >- R -> body`.
+    fn closure(&mut self) -> ParseResult<'a, Expr> {
+        use TokenType as T;
+
+        let ast = self.ast;
+        if self.cursor.eat(T::Pipe).is_some() {
+            // `|` also separates the alternatives of patterns, so parameters have a single one
+            let mut params = ast.closure_params.small_vec();
+            while !self.cursor.peek_is(T::Pipe) {
+                let pattern = self.primary_pattern()?;
+                params.push(self.closure_param(pattern)?);
+                if self.cursor.eat(T::Comma).is_none() {
+                    break;
+                }
+            }
+            self.expect(T::Pipe)?;
+            let params = params.freeze();
+            return Ok(Expr::Closure {
+                captures: None,
+                params,
+                ret: None,
+                body: self.expr()?,
+            });
+        }
+
+        self.expect(T::Fn)?;
+        let captures = match self.cursor.peek_is(T::LBracket) {
+            true => Some(self.list(&ast.captures, T::LBracket, |parser| {
+                let by_ref = parser.cursor.eat(T::Ampersand).is_some();
+                let name = parser.ident()?;
+                Ok(Capture { name, by_ref })
+            })?),
+            false => None,
+        };
+        let params = self.list(&ast.closure_params, T::LParens, |parser| {
+            let pattern = parser.pattern()?;
+            parser.closure_param(pattern)
+        })?;
+        let ret = match self.cursor.eat(T::Feather) {
+            Some(_) => Some(self.ty()?),
+            None => None,
+        };
+        let body = match self.cursor.eat(T::Arrow) {
+            Some(_) => self.expr()?,
+            None => self.block()?,
+        };
+        Ok(Expr::Closure {
+            captures,
+            params,
+            ret,
+            body,
+        })
+    }
+
+    /// Type of a closure parameter after its pattern, if it has one.
+    fn closure_param(&mut self, pattern: PatternId) -> ParseResult<'a, ClosureParam> {
+        let ty = match self.cursor.eat(TokenType::Colon) {
+            Some(_) => Some(self.ty()?),
+            None => None,
+        };
+        Ok(ClosureParam { pattern, ty })
+    }
+
+    fn arm(&mut self) -> ParseResult<'a, Arm> {
+        let pattern = self.pattern()?;
+        let guard = match self.cursor.eat(TokenType::If) {
+            Some(_) => Some(self.expr()?),
+            None => None,
+        };
+        self.expect(TokenType::FatArrow)?;
+        let body = self.expr()?;
+        Ok(Arm {
+            pattern,
+            guard,
+            body,
+        })
+    }
+
+    /// Loop with an optional label before it.
+    fn loop_expr(&mut self) -> ParseResult<'a, Expr> {
+        use TokenType as T;
+
+        let label = self.label()?;
+        let ty = (self.cursor.peek(0)).map(|token| token.as_soft_keyword().unwrap_or(token.ty));
+        if !matches!(ty, Some(T::While | T::Do | T::Loop | T::For)) {
+            return Err(self.error(ParseErrorKind::ExpectedLoop));
+        }
+        self.cursor.next();
+        match ty {
+            Some(T::While) => {
+                let cond = self.with_struct_literals(false, Self::expr)?;
+                self.expect(T::Do)?;
+                let body = self.expr()?;
+                Ok(Expr::While { label, cond, body })
+            }
+            Some(T::Do) => {
+                let body = self.expr()?;
+                self.expect(T::While)?;
+                let cond = self.expr()?;
+                Ok(Expr::DoWhile { label, body, cond })
+            }
+            Some(T::For) => {
+                let pattern = self.pattern()?;
+                let index = match self.cursor.eat(T::Comma) {
+                    Some(_) => Some(self.pattern()?),
+                    None => None,
+                };
+                self.expect(T::In)?;
+                let iter = self.with_struct_literals(false, |parser| {
+                    let start = parser.expr()?;
+                    Ok(match parser.eat_dot_dot() {
+                        true => ForIter::Range {
+                            start,
+                            end: parser.expr()?,
+                        },
+                        false => ForIter::Iter(start),
+                    })
+                })?;
+                // `do` can be left out before a block, like in `for x in xs { ... }`
+                if self.cursor.eat(T::Do).is_none() && !self.cursor.peek_is(T::LBrace) {
+                    return Err(self.error(ParseErrorKind::ExpectedToken(T::Do)));
+                }
+                let body = self.expr()?;
+                Ok(Expr::For {
+                    label,
+                    pattern,
+                    index,
+                    iter,
+                    body,
+                })
+            }
+            _ => {
+                let body = self.expr()?;
+                Ok(Expr::Loop { label, body })
+            }
+        }
+    }
+
+    /// Name of the label at the cursor, like `outer` in `@outer`.
+    fn label(&mut self) -> ParseResult<'a, Option<Span>> {
+        match self.cursor.eat(TokenType::At) {
+            Some(_) => self.ident().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Skips the rest of a statement that has an error, which started at the given token.
+    ///
+    /// Returns whether its block goes on, which is when the statement ends with a `;` or a block,
+    /// or when its own block ends with a `}`, rather than at the start of an item or at the end of the file.
+    fn skip_to_stmt_end(&mut self, stmt_idx: usize) -> bool {
+        let is_brace = |ty: &&TokenType| matches!(ty, TokenType::LBrace | TokenType::RBrace);
+        let consumed = &self.tokens.types.as_slice()[stmt_idx..self.cursor.idx()];
+        let mut depth = (consumed.iter().filter(is_brace)).fold(0usize, |depth, ty| match ty {
+            TokenType::LBrace => depth + 1,
+            _ => depth.saturating_sub(1),
+        });
+
+        // statements don't start with a `}`, so this always skips at least a token
+        while !self.cursor.is_eof() && !self.is_item_start() {
+            let ty = self.cursor.peek(0).map(|token| token.ty);
+            match ty {
+                Some(TokenType::Semi) if depth == 0 => {
+                    self.cursor.next();
+                    return true;
+                }
+                Some(TokenType::RBrace) if depth == 0 => return true,
+                Some(TokenType::LBrace) => depth += 1,
+                Some(TokenType::RBrace) => {
+                    depth -= 1;
+                    // like statements that end with a block, unless it goes on with an `else`
+                    self.cursor.next();
+                    if depth == 0 && !self.cursor.peek_is(TokenType::Else) {
+                        self.cursor.eat(TokenType::Semi);
+                        return true;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            self.cursor.next();
+        }
+        false
+    }
+
+    /// Skips the rest of an item that has an error, which started at the given token.
+    fn skip_to_item(&mut self, item_idx: usize) {
+        if self.cursor.idx() == item_idx {
+            self.cursor.next();
+        }
+        while !self.cursor.is_eof() && !self.is_item_start() {
+            self.cursor.next();
+        }
+    }
+
+    fn is_item_start(&self) -> bool {
+        is_item_start(&self.cursor)
+    }
+
+    /// Parses a list of comma-separated elements between delimiters, with an optional trailing comma.
+    fn list<T: Copy>(
+        &mut self,
+        arena: &'t SliceArena<T>,
+        open: TokenType,
+        mut element: impl FnMut(&mut Self) -> ParseResult<'a, T>,
+    ) -> ParseResult<'a, ArenaSlice<T>> {
+        let close = match open {
+            TokenType::LParens => TokenType::RParens,
+            TokenType::LBracket => TokenType::RBracket,
+            _ => TokenType::RBrace,
+        };
+
+        self.with_struct_literals(true, |parser| {
+            parser.expect(open)?;
+            let mut elements = arena.small_vec();
+            while !parser.cursor.peek_is(close) {
+                elements.push(element(parser)?);
+                if parser.cursor.eat(TokenType::Comma).is_none() {
+                    break;
+                }
+            }
+            parser.expect(close)?;
+            Ok(elements.freeze())
+        })
+    }
+
+    fn ident(&mut self) -> ParseResult<'a, Span> {
+        Ok(self.expect(TokenType::Ident)?.span.into())
+    }
+
+    fn expect(&mut self, ty: TokenType) -> ParseResult<'a, Token<'a>> {
+        (self.cursor.eat(ty)).ok_or_else(|| self.error(ParseErrorKind::ExpectedToken(ty)))
+    }
+
+    /// Error at the next token.
+    fn error(&self, kind: ParseErrorKind) -> ParseError<'a> {
+        let (found, span) = match self.cursor.peek(0) {
+            Some(token) => (token.ty, token.span),
+            None => {
+                let end = self.tokens.code.len();
+                let (line, col) = self.tokens.line_col(end);
+                (TokenType::Eof, TokenSpan::new("", end, line, col))
+            }
+        };
+        ParseError {
+            file: self.tokens.file,
+            kind,
+            found,
+            span,
+        }
+    }
+
+    fn alloc_expr(&self, expr: Expr, start: u32) -> ExprId {
+        self.ast.exprs.alloc(expr, self.span_from(start))
+    }
+
+    /// Offset of the start of the next token.
+    fn next_start(&self) -> u32 {
+        match self.tokens.spans.get(self.cursor.idx()) {
+            Some(span) => span.start,
+            None => self.tokens.code.len() as u32,
+        }
+    }
+
+    /// Span from the given offset to the end of the last consumed token.
+    fn span_from(&self, start: u32) -> Span {
+        let end = match self.cursor.idx().checked_sub(1) {
+            Some(idx) => self.tokens.spans[idx].end() as u32,
+            None => start,
+        };
+        Span {
+            start,
+            len: end.saturating_sub(start),
+        }
+    }
+
+    /// Whether the last consumed token is of the given type.
+    fn prev_is(&self, ty: TokenType) -> bool {
+        let prev = self.cursor.idx().checked_sub(1);
+        prev.is_some_and(|idx| self.tokens.types.get(idx) == Some(&ty))
+    }
+}
+
+/// Whether an item starts at the cursor, which errors in items are recovered at.
+///
+/// Only items that start like no statement can are recognized, like `name :: fn`,
+/// whether they have attributes before them or not.
+fn is_item_start(cursor: &TokenCursor) -> bool {
+    use TokenType as T;
+
+    let is = |n, ty| cursor.peek(n).is_some_and(|token| token.is(ty));
+    let mut n = 0;
+    while is(n, T::AttrStart) {
+        n += 1;
+        while !is(n, T::AttrEnd) {
+            // peeking past the end gives the EOF again
+            if cursor.peek(n).is_none_or(|token| token.ty == T::Eof) {
+                return false;
+            }
+            n += 1;
+        }
+        n += 1;
+    }
+    let n = n + usize::from(is(n, T::Pub));
+    let keyword = [T::Fn, T::Packed, T::Struct, T::Enum, T::Union];
+    ([T::Use, T::Const, T::Static].iter().any(|&ty| is(n, ty)) && is(n + 1, T::Ident))
+        || (is(n, T::Ident) && is(n + 1, T::DoubleColon) && keyword.iter().any(|&ty| is(n + 2, ty)))
+}
+
+/// Kind of the literal that the token is, `true`, `false` and `none` being soft keywords.
+fn lit_kind(token: &Token) -> Option<LitKind> {
+    match token.ty {
+        TokenType::Num => Some(LitKind::Num),
+        TokenType::String => Some(LitKind::String),
+        TokenType::ByteString => Some(LitKind::ByteString),
+        TokenType::CString => Some(LitKind::CString),
+        TokenType::Char => Some(LitKind::Char),
+        TokenType::ByteChar => Some(LitKind::ByteChar),
+        TokenType::Ident => match token.as_soft_keyword() {
+            Some(TokenType::True) => Some(LitKind::True),
+            Some(TokenType::False) => Some(LitKind::False),
+            Some(TokenType::None) => Some(LitKind::None),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Text of a segment of an interpolated string, without the delimiters around it:
+/// the `$"` or `}` before it and the `{` or `"` after it.
+fn string_part(segment: &TokenSpan) -> Span {
+    let slice = segment.slice;
+    let prefix_len = if slice.starts_with('$') { 2 } else { 1 };
+    // an unterminated string ends without its quote
+    let suffix_len = usize::from(slice.len() > prefix_len && slice.ends_with(['{', '"']));
+    Span {
+        start: (segment.start + prefix_len) as u32,
+        len: (slice.len() - prefix_len - suffix_len) as u32,
+    }
+}
+
+/// Whether an expression can start with a token of this type,
+/// so that `break` knows whether it's followed by its value and `match` whether it is a keyword.
+fn starts_expr(ty: TokenType) -> bool {
+    use TokenType as T;
+
+    matches!(
+        ty,
+        T::Ident | T::Num | T::String | T::ByteString | T::CString | T::Char | T::ByteChar
+    ) || matches!(
+        ty,
+        T::StringInterpBeg | T::LParens | T::LBrace | T::LBracket | T::Pipe | T::Fn
+    ) || matches!(
+        ty,
+        T::If | T::At | T::While | T::Do | T::Loop | T::Break | T::Continue
+    ) || operator(ty, |f| matches!(f, Fixity::Prefix(_))).is_some()
+}
+
+#[cfg(test)]
+mod tests_parser {
+    use std::mem;
+
+    use crate::{
+        ast::{
+            Ast, Attr, Expr, ExprId, ForIter, Item, ItemKind, LitKind, Pattern, PatternId, Stmt,
+            StmtId, Type, TypeId,
+        },
+        lexer::Span,
+        lexer::{lex, Tokens},
+        parser::{parse_file, Fixity, Operator, ParseErrorKind, MAX_CHAIN_DEPTH, OPERATORS},
+        source::FileId,
+    };
+
+    /// Expression as an S-expression, to compare trees at a glance.
+    fn sexp(ast: &Ast, tokens: &Tokens, id: ExprId) -> String {
+        let join = |ids: &[ExprId]| {
+            let sexps = ids.iter().map(|&id| format!(" {}", sexp(ast, tokens, id)));
+            sexps.collect::<String>()
+        };
+
+        match ast.exprs[id] {
+            Expr::Ident | Expr::Lit(_) => tokens.code[ast.exprs.span(id).range()].to_string(),
+            Expr::InterpolatedString { parts, holes } => {
+                let parts = &ast.string_parts[parts];
+                let mut sexp = format!("(interp {:?}", &tokens.code[parts[0].range()]);
+                for (part, hole) in parts[1..].iter().zip(&ast.holes[holes]) {
+                    let spec = hole.spec.map_or("", |spec| &tokens.code[spec.range()]);
+                    let expr = self::sexp(ast, tokens, hole.expr);
+                    sexp += &format!(" {expr}{spec} {:?}", &tokens.code[part.range()]);
+                }
+                sexp + ")"
+            }
+            Expr::Block(block) => {
+                let stmts = ast.stmt_lists[block.stmts].iter();
+                let stmts = stmts.map(|&stmt| format!(" {}", stmt_sexp(ast, tokens, stmt)));
+                format!("{{{} }}", stmts.collect::<String>())
+            }
+            Expr::Call { callee, args } => {
+                let callee = sexp(ast, tokens, callee);
+                format!("(call {callee}{})", join(&ast.expr_lists[args]))
+            }
+            Expr::Struct { ty, fields, base } => {
+                let fields = ast.field_inits[fields].iter().map(|field| {
+                    let name = &tokens.code[field.name.range()];
+                    format!(" ({name} {})", sexp(ast, tokens, field.value))
+                });
+                let base = base.map(|base| format!(" ..{}", sexp(ast, tokens, base)));
+                format!(
+                    "(struct {}{}{})",
+                    type_sexp(ast, tokens, ty),
+                    fields.collect::<String>(),
+                    base.unwrap_or_default()
+                )
+            }
+            Expr::Field { expr, name } => {
+                format!(
+                    "(. {} {})",
+                    sexp(ast, tokens, expr),
+                    &tokens.code[name.range()]
+                )
+            }
+            Expr::TupleField { expr, index } => {
+                format!(
+                    "(. {} {})",
+                    sexp(ast, tokens, expr),
+                    &tokens.code[index.range()]
+                )
+            }
+            Expr::Closure {
+                captures,
+                params,
+                ret,
+                body,
+            } => {
+                let mut out = String::from("(closure");
+                if let Some(captures) = captures {
+                    let captures = ast.captures[captures].iter().map(|capture| {
+                        let by_ref = if capture.by_ref { "&" } else { "" };
+                        format!("{by_ref}{}", &tokens.code[capture.name.range()])
+                    });
+                    out += &format!(" [{}]", captures.collect::<Vec<_>>().join(" "));
+                }
+                let params = ast.closure_params[params].iter().map(|param| {
+                    let pattern = pattern_sexp(ast, tokens, param.pattern);
+                    match param.ty {
+                        Some(ty) => format!("{pattern}:{}", type_sexp(ast, tokens, ty)),
+                        None => pattern,
+                    }
+                });
+                out += &format!(" ({})", params.collect::<Vec<_>>().join(" "));
+                if let Some(ret) = ret {
+                    out += &format!(" >- {}", type_sexp(ast, tokens, ret));
+                }
+                format!("{out} {})", sexp(ast, tokens, body))
+            }
+            Expr::MethodCall { method, args } => {
+                let method = &tokens.code[method.range()];
+                format!("(method {method}{})", join(&ast.expr_lists[args]))
+            }
+            Expr::Array(elements) => format!("[{} ]", join(&ast.expr_lists[elements])),
+            Expr::Tuple(elements) => format!("(tuple{})", join(&ast.expr_lists[elements])),
+            Expr::ArrayRepeat { value, len } => format!("(repeat{})", join(&[value, len])),
+            Expr::Index { expr, index } => format!("(index{})", join(&[expr, index])),
+            Expr::Unary { op, expr } => format!("({op:?} {})", sexp(ast, tokens, expr)),
+            Expr::Binary { op, lhs, rhs } => {
+                let (lhs, rhs) = (sexp(ast, tokens, lhs), sexp(ast, tokens, rhs));
+                format!("({op:?} {lhs} {rhs})")
+            }
+            Expr::Placeholder => "_".to_string(),
+            Expr::Section { op, rhs } => format!("({op:?} _ {})", sexp(ast, tokens, rhs)),
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => format!(
+                "(if{})",
+                join(&[&[cond, then][..], otherwise.as_slice()].concat())
+            ),
+            Expr::While { label, cond, body } => {
+                format!(
+                    "(while{}{})",
+                    label_sexp(tokens, label),
+                    join(&[cond, body])
+                )
+            }
+            Expr::DoWhile { label, body, cond } => {
+                format!(
+                    "(do-while{}{})",
+                    label_sexp(tokens, label),
+                    join(&[body, cond])
+                )
+            }
+            Expr::Loop { label, body } => {
+                format!("(loop{}{})", label_sexp(tokens, label), join(&[body]))
+            }
+            Expr::For {
+                label,
+                pattern,
+                index,
+                iter,
+                body,
+            } => {
+                let mut patterns = pattern_sexp(ast, tokens, pattern);
+                if let Some(index) = index {
+                    patterns += &format!(",{}", pattern_sexp(ast, tokens, index));
+                }
+                let iter = match iter {
+                    ForIter::Range { start, end } => format!("(range{})", join(&[start, end])),
+                    ForIter::Iter(iter) => sexp(ast, tokens, iter),
+                };
+                format!(
+                    "(for{} {patterns} {iter}{})",
+                    label_sexp(tokens, label),
+                    join(&[body])
+                )
+            }
+            Expr::Break { label, value } => {
+                format!(
+                    "(break{}{})",
+                    label_sexp(tokens, label),
+                    join(value.as_slice())
+                )
+            }
+            Expr::Return { value } => format!("(return{})", join(value.as_slice())),
+            Expr::Match { scrutinee, arms } => {
+                let arms = ast.arms[arms].iter().map(|arm| {
+                    let pattern = pattern_sexp(ast, tokens, arm.pattern);
+                    let guard = arm
+                        .guard
+                        .map(|guard| format!(" (if {})", sexp(ast, tokens, guard)));
+                    let body = sexp(ast, tokens, arm.body);
+                    format!(" ({pattern}{} {body})", guard.unwrap_or_default())
+                });
+                let scrutinee = sexp(ast, tokens, scrutinee);
+                format!("(match {scrutinee}{})", arms.collect::<String>())
+            }
+            Expr::Continue { label: None } => "continue".to_string(),
+            Expr::Continue { label } => format!("(continue{})", label_sexp(tokens, label)),
+        }
+    }
+
+    fn pattern_sexp(ast: &Ast, tokens: &Tokens, id: PatternId) -> String {
+        match ast.patterns[id] {
+            Pattern::Ident | Pattern::Wildcard | Pattern::Lit(_) => {
+                tokens.code[ast.patterns.span(id).range()].to_string()
+            }
+            Pattern::Variant { ty, name, payload } => {
+                let ty = ty.map(|ty| &tokens.code[ast.types.span(ty).range()]);
+                let payload =
+                    payload.map(|payload| format!(" {}", pattern_sexp(ast, tokens, payload)));
+                let name = &tokens.code[name.range()];
+                format!(
+                    "({}.{name}{})",
+                    ty.unwrap_or_default(),
+                    payload.unwrap_or_default()
+                )
+            }
+            Pattern::Or(alternatives) => {
+                let alternatives = ast.pattern_lists[alternatives].iter();
+                let alternatives =
+                    alternatives.map(|&id| format!(" {}", pattern_sexp(ast, tokens, id)));
+                format!("(or{})", alternatives.collect::<String>())
+            }
+        }
+    }
+
+    fn type_sexp(ast: &Ast, tokens: &Tokens, id: TypeId) -> String {
+        match ast.types[id] {
+            Type::Named => tokens.code[ast.types.span(id).range()].to_string(),
+            Type::Generic { base, args } => {
+                let args = ast.type_lists[args].iter();
+                let args = args.map(|&arg| format!(" {}", type_sexp(ast, tokens, arg)));
+                let base = type_sexp(ast, tokens, base);
+                format!("({base}{})", args.collect::<String>())
+            }
+            Type::Array { elem, len } => {
+                let (elem, len) = (type_sexp(ast, tokens, elem), sexp(ast, tokens, len));
+                format!("(array {elem} {len})")
+            }
+            Type::Slice(elem) => format!("(slice {})", type_sexp(ast, tokens, elem)),
+            Type::Pointer(pointee) => format!("(* {})", type_sexp(ast, tokens, pointee)),
+            Type::Ref(referent) => format!("(& {})", type_sexp(ast, tokens, referent)),
+            Type::Optional(ty) => format!("(? {})", type_sexp(ast, tokens, ty)),
+            Type::Fn { params, ret } => {
+                let params = ast.type_lists[params].iter();
+                let params = params.map(|&param| format!(" {}", type_sexp(ast, tokens, param)));
+                let ret = ret.map(|ret| format!(" >- {}", type_sexp(ast, tokens, ret)));
+                format!(
+                    "(fn{}{})",
+                    params.collect::<String>(),
+                    ret.unwrap_or_default()
+                )
+            }
+            Type::Tuple(elems) => {
+                let elems = ast.type_lists[elems].iter();
+                let elems = elems.map(|&elem| format!(" {}", type_sexp(ast, tokens, elem)));
+                format!("(tuple{})", elems.collect::<String>())
+            }
+        }
+    }
+
+    fn label_sexp(tokens: &Tokens, label: Option<Span>) -> String {
+        let label = label.map(|label| format!(" @{}", &tokens.code[label.range()]));
+        label.unwrap_or_default()
+    }
+
+    fn stmt_sexp(ast: &Ast, tokens: &Tokens, id: StmtId) -> String {
+        match ast.stmts[id] {
+            Stmt::Let {
+                access,
+                pattern,
+                ty,
+                value,
+            } => {
+                let name = &tokens.code[ast.patterns.span(pattern).range()];
+                let ty = ty.map(|ty| format!(" {}", &tokens.code[ast.types.span(ty).range()]));
+                let value = sexp(ast, tokens, value);
+                format!("(let {access:?} {name}{} {value})", ty.unwrap_or_default())
+            }
+            Stmt::Assign { target, op, value } => {
+                let op = op.map(|op| format!("{op:?}")).unwrap_or_default();
+                let (target, value) = (sexp(ast, tokens, target), sexp(ast, tokens, value));
+                format!("({op}= {target} {value})")
+            }
+            Stmt::Expr(expr) => sexp(ast, tokens, expr),
+            Stmt::Semi(expr) => format!("{};", sexp(ast, tokens, expr)),
+            Stmt::Defer(expr) => format!("(defer {})", sexp(ast, tokens, expr)),
+            Stmt::Error => "error".to_string(),
+        }
+    }
+
+    /// Body of the only function of the code.
+    fn body_sexp(code: &str) -> String {
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        match ast.items.as_slice() {
+            [item] => match item.kind {
+                ItemKind::Fn { body, .. } => sexp(&ast, &tokens, body),
+                _ => panic!("{item:?}"),
+            },
+            items => panic!("{items:?}"),
+        }
+    }
+
+    fn parse_error(code: &str) -> String {
+        let tokens = lex(FileId::default(), code);
+        let (_, diagnostics) = parse_file(&tokens);
+        match diagnostics.as_slice() {
+            [diagnostic] => diagnostic.to_string(),
+            _ => panic!("{diagnostics:?}"),
+        }
+    }
+
+    #[test]
+    fn items() {
+        let code = concat!(
+            "WindowFlags :: packed struct u32\n{\n centered : bool,\n resizable : bool,\n}\n",
+            "KeyboardEvent :: enum u8 { KeyPress : KeyCode = 0, ImeCommit : String = 3 }\n",
+            "ScreenMode :: enum { Windowed, Borderless }\n",
+            "pub Value :: union { pub int : i64, float : f64 }\n",
+            "add :: fn(a : i32, b : i32,) >- i32 -> a + b;\n",
+        );
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let slice = |span: Span| &code[span.range()];
+        let items = ast.items.as_slice();
+        let names: Vec<_> = items.iter().map(|item| slice(item.name)).collect();
+        assert_eq!(
+            names,
+            ["WindowFlags", "KeyboardEvent", "ScreenMode", "Value", "add"]
+        );
+        assert_eq!(
+            items.iter().map(|item| item.is_pub).collect::<Vec<_>>(),
+            [false, false, false, true, false]
+        );
+
+        let ItemKind::Struct {
+            packed: true,
+            repr: Some(repr),
+            fields,
+        } = items[0].kind
+        else {
+            panic!("{:?}", items[0]);
+        };
+        assert_eq!(slice(ast.types.span(repr)), "u32");
+        let fields: Vec<_> = (ast.fields[fields].iter())
+            .map(|field| (slice(field.name), slice(ast.types.span(field.ty))))
+            .collect();
+        assert_eq!(fields, [("centered", "bool"), ("resizable", "bool")]);
+
+        let ItemKind::Enum { repr, variants } = items[1].kind else {
+            panic!("{:?}", items[1]);
+        };
+        assert!(repr.is_some());
+        let variants: Vec<_> = (ast.variants[variants].iter())
+            .map(|variant| {
+                let ty = variant.ty.map(|ty| slice(ast.types.span(ty)));
+                let discriminant = variant.discriminant.map(|id| sexp(&ast, &tokens, id));
+                (slice(variant.name), ty, discriminant)
+            })
+            .collect();
+        assert_eq!(
+            variants,
+            [
+                ("KeyPress", Some("KeyCode"), Some("0".to_string())),
+                ("ImeCommit", Some("String"), Some("3".to_string())),
+            ]
+        );
+        assert!(matches!(
+            items[2].kind,
+            ItemKind::Enum { repr: None, variants } if variants.len() == 2
+        ));
+        let ItemKind::Union { fields } = items[3].kind else {
+            panic!("{:?}", items[3]);
+        };
+        let fields: Vec<_> = (ast.fields[fields].iter())
+            .map(|field| (field.is_pub, slice(field.name)))
+            .collect();
+        assert_eq!(fields, [(true, "int"), (false, "float")]);
+
+        let ItemKind::Fn { params, ret, body } = items[4].kind else {
+            panic!("{:?}", items[4]);
+        };
+        assert_eq!(params.len(), 2);
+        assert_eq!(ret.map(|ty| slice(ast.types.span(ty))), Some("i32"));
+        assert_eq!(sexp(&ast, &tokens, body), "(Add a b)");
+        assert_eq!(
+            slice(ast.items.spans()[4]),
+            "add :: fn(a : i32, b : i32,) >- i32 -> a + b;"
+        );
+    }
+
+    #[test]
+    fn globals() {
+        let code = concat!(
+            "pub const SIZE : usize = 4 * 8;\n",
+            "static rw : [u8; SIZE] = [0; SIZE];\n",
+            "const const := static;\n",
+            "f :: fn() { static; const = 1; }\n",
+        );
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let items: Vec<_> = (ast.items.iter())
+            .map(|(_, item)| {
+                let value = item
+                    .kind
+                    .const_init()
+                    .map(|value| sexp(&ast, &tokens, value));
+                (item.is_pub, &code[item.name.range()], value)
+            })
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (true, "SIZE", Some("(Mul 4 8)".to_string())),
+                (false, "rw", Some("(repeat 0 SIZE)".to_string())),
+                (false, "const", Some("static".to_string())),
+                (false, "f", None),
+            ]
+        );
+        assert!(matches!(
+            ast.items.as_slice()[1].kind,
+            ItemKind::Static { ty: Some(_), .. }
+        ));
+        assert!(matches!(
+            ast.items.as_slice()[2].kind,
+            ItemKind::Const { ty: None, .. }
+        ));
+
+        // recovered from at the next item
+        let tokens = lex(FileId::default(), "const N : u8 = ;\nstatic M := 1;");
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "1:15: Expected expression, found Semi"
+        );
+        assert!(matches!(
+            ast.items.as_slice(),
+            [
+                Item {
+                    kind: ItemKind::Error,
+                    ..
+                },
+                Item {
+                    kind: ItemKind::Static { .. },
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn attributes() {
+        let code = concat!(
+            "#![doc(\"app\")]\n",
+            "#[inline(always), test]\n#[cold]\npub f :: fn() {\n #[cold] g();\n #[doc()] x := 1;\n}\n",
+            "#[repr(C)] P :: struct { #[deprecated] pub x : i32, y : i32 }\n",
+            "#[test] use a::b;\n",
+        );
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let names = |attrs| -> Vec<_> {
+            (ast.attrs[attrs].iter())
+                .map(|attr: &Attr| {
+                    let args = attr.args.map(|args| args.len);
+                    (attr.kind.unwrap().name(), args)
+                })
+                .collect()
+        };
+        assert_eq!(names(ast.file_attrs), [("doc", Some(1))]);
+
+        let items = ast.items.as_slice();
+        assert_eq!(
+            names(items[0].attrs),
+            [("inline", Some(1)), ("test", None), ("cold", None)]
+        );
+        assert_eq!(&code[ast.items.spans()[0].range()][..9], "#[inline(");
+        let stmts: Vec<_> = (ast.stmts.ids())
+            .map(|id| names(ast.stmt_attrs(id)))
+            .collect();
+        assert_eq!(stmts, [vec![("cold", None)], vec![("doc", Some(0))]]);
+
+        let ItemKind::Struct { fields, .. } = items[1].kind else {
+            panic!("{:?}", items[1]);
+        };
+        assert_eq!(names(items[1].attrs), [("repr", Some(1))]);
+        let fields = &ast.fields[fields];
+        assert_eq!(
+            (
+                names(fields[0].attrs),
+                fields[0].is_pub,
+                names(fields[1].attrs)
+            ),
+            (vec![("deprecated", None)], true, vec![])
+        );
+        assert_eq!(names(items[2].attrs), [("test", None)]);
+
+        // unknown attributes are reported, and kept
+        let tokens = lex(
+            FileId::default(),
+            "#[inline, tool::lint(a(b), c)] f :: fn() {}",
+        );
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert_eq!(
+            (diagnostics.iter().map(|d| d.to_string())).collect::<Vec<_>>(),
+            ["1:10: Unknown attribute `tool::lint`"]
+        );
+        let attrs = &ast.attrs[ast.items.as_slice()[0].attrs];
+        assert_eq!((attrs[1].kind, ast.paths[attrs[1].path].len()), (None, 2));
+        assert_eq!(attrs[1].args.map(|args| args.len), Some(6));
+
+        assert_eq!(
+            parse_error("#[inline f :: fn() {}"),
+            "1:9: Expected AttrEnd, found Ident"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { #[cold] }"),
+            "1:20: Expected expression, found RBrace"
+        );
+        // items with attributes are recovered at
+        let tokens = lex(FileId::default(), "f :: fn(\n#[cold] h :: fn() {}");
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(
+            ast.items.as_slice(),
+            [
+                Item {
+                    kind: ItemKind::Error,
+                    ..
+                },
+                Item {
+                    kind: ItemKind::Fn { .. },
+                    ..
+                }
+            ]
+        ));
+
+        // arguments left open until the end of the file
+        assert_eq!(
+            parse_error("#[inline("),
+            "1:9: Expected RParens, found end of file"
+        );
+        let tokens = lex(FileId::default(), "f :: fn() { #[a( }");
+        let (_, diagnostics) = parse_file(&tokens);
+        assert_eq!(
+            (diagnostics.iter().map(|d| d.to_string())).collect::<Vec<_>>(),
+            [
+                "1:14: Unknown attribute `a`",
+                "1:18: Expected RParens, found end of file"
+            ]
+        );
+    }
+
+    #[test]
+    fn imports() {
+        let code = "pub use std::io::Writer;\nuse math;\nuse :: fn() {}";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let items: Vec<_> = (ast.items.iter())
+            .map(|(id, item)| {
+                let path = match item.kind {
+                    ItemKind::Use { path } => ast.paths[path].iter(),
+                    _ => [].iter(),
+                };
+                let path: Vec<_> = path.map(|segment| &code[segment.range()]).collect();
+                let span = &code[ast.items.span(id).range()];
+                (item.is_pub, &code[item.name.range()], path, span)
+            })
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (
+                    true,
+                    "Writer",
+                    vec!["std", "io", "Writer"],
+                    "pub use std::io::Writer;"
+                ),
+                (false, "math", vec!["math"], "use math;"),
+                (false, "use", vec![], "use :: fn() {}"),
+            ]
+        );
+
+        assert_eq!(parse_error("use a::;"), "1:7: Expected Ident, found Semi");
+        assert_eq!(parse_error("use a b;"), "1:6: Expected Semi, found Ident");
+    }
+
+    #[test]
+    fn generics() {
+        let code = concat!(
+            "map :: fn[T, U,](xs : Slice[T], f : Fn[T, U]) >- Vec[U] { r : Map[K, Vec[V]] = a < b; }\n",
+            "Pair :: struct[T] { a : T, b : T }\n",
+            "Flags :: packed struct[T] u8 {}\n",
+            "Option :: enum[T] { Some : T, None }\n",
+            "Either :: union[L, R] { left : L, right : R }\n",
+            "plain :: fn() {}\n",
+        );
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let generics: Vec<Vec<_>> = (ast.items.iter())
+            .map(|(_, item)| {
+                let generics = ast.generics[item.generics].iter();
+                generics.map(|param| &code[param.name.range()]).collect()
+            })
+            .collect();
+        assert_eq!(
+            generics,
+            [
+                vec!["T", "U"],
+                vec!["T"],
+                vec!["T"],
+                vec!["T"],
+                vec!["L", "R"],
+                vec![],
+            ]
+        );
+
+        let generic_types: Vec<_> = (ast.types.iter())
+            .filter(|(_, ty)| matches!(ty, Type::Generic { .. }))
+            .map(|(id, _)| &code[ast.types.span(id).range()])
+            .collect();
+        assert_eq!(
+            generic_types,
+            ["Slice[T]", "Fn[T, U]", "Vec[U]", "Vec[V]", "Map[K, Vec[V]]"]
+        );
+        assert!(matches!(
+            ast.items.as_slice()[2].kind,
+            ItemKind::Struct {
+                packed: true,
+                repr: Some(_),
+                ..
+            }
+        ));
+
+        assert_eq!(
+            parse_error("f :: fn[T U]() {}"),
+            "1:10: Expected RBracket, found Ident"
+        );
+        assert_eq!(
+            parse_error("f :: fn(x : Vec[1]) {}"),
+            "1:16: Expected type, found Num"
+        );
+    }
+
+    #[test]
+    fn literals() {
+        let code = "f :: fn() { a := true; match p.none { true => none, false => () } }";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        let lits: Vec<_> = (ast.exprs.iter())
+            .filter_map(|(id, expr)| match expr {
+                Expr::Lit(lit) => Some((*lit, &code[ast.exprs.span(id).range()])),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            lits,
+            [
+                (LitKind::True, "true"),
+                (LitKind::None, "none"),
+                (LitKind::Unit, "()")
+            ]
+        );
+        assert_eq!(
+            ast.patterns.as_slice(),
+            [
+                Pattern::Ident,
+                Pattern::Lit(LitKind::True),
+                Pattern::Lit(LitKind::False)
+            ]
+        );
+
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+        assert_eq!(body("not false or x.true"), "(Or (Not false) (. x true))");
+        assert_eq!(body("f(() , ( ))"), "(call f () ( ))");
+    }
+
+    #[test]
+    fn operators() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(
+            body("a + b * c == d and not e or f"),
+            "(Or (And (Eq (Add a (Mul b c)) d) (Not e)) f)"
+        );
+        assert_eq!(body("not 1 + 1 == 3"), "(Not (Eq (Add 1 1) 3))");
+        assert_eq!(body("a - b - c"), "(Sub (Sub a b) c)");
+        assert_eq!(
+            body("x << 1 | y & 3 ^ -z"),
+            "(BitOr (Shl x 1) (BitXor (BitAnd y 3) (Neg z)))"
+        );
+        assert_eq!(body("(a xor b) % ~c"), "(Mod (Xor a b) (BitNot c))");
+
+        // `**` is right-associative and tighter than prefix operators
+        assert_eq!(body("a ** b ** c"), "(Pow a (Pow b c))");
+        assert_eq!(body("-a ** -b * c"), "(Mul (Neg (Pow a (Neg b))) c)");
+        assert_eq!(
+            body("i++ + --j - -f(x)--"),
+            "(Sub (Add (PostIncr i) (PreDecr j)) (Neg (PostDecr (call f x))))"
+        );
+        assert_eq!(body("a == not b and c"), "(And (Eq a (Not b)) c)");
+        assert_eq!(body("(a < b) < c"), "(Lt (Lt a b) c)");
+        assert_eq!(body("f(a, g(b))(c,)"), "(call (call f a (call g b)) c)");
+
+        // chained comparisons are parsed as they group, and reported once per chain
+        assert_eq!(
+            parse_error("f :: fn() -> a < b < c;"),
+            "1:13: Comparisons cannot be chained, write `a < b and b < c` to compare `b` to both sides"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { x := 1 + a != b.c >= d == e; }"),
+            "1:17: Comparisons cannot be chained, write `1 + a != b.c and b.c >= d` \
+             to compare `b.c` to both sides"
+        );
+
+        // the piped value is the first argument, `>-` taking every operator before it
+        // and only the call after it
+        assert_eq!(body("x >- f(a) >- g"), "(call g (call f x a))");
+        assert_eq!(
+            body("a + b >- f() == c >- g(-d)"),
+            "(call g (Eq (call f (Add a b)) c) (Neg d))"
+        );
+        assert_eq!(body("x >- f(y >- h)(z)"), "(call (call f x (call h y)) z)");
+
+        // partial applications and sections are called with the value
+        assert_eq!(body("xs >- map(_, f)"), "(call (call map _ f) xs)");
+        assert_eq!(
+            body("xs >- a.fold(0, _) >- g(h(_), y)"),
+            "(call g (call (method fold a 0 _) xs) (call h _) y)"
+        );
+        assert_eq!(
+            body("x >- (+ 1) >- (* a.b)"),
+            "(call (Mul _ (. a b)) (call (Add _ 1) x))"
+        );
+        assert_eq!(body("x >- (** 2 ** 3)"), "(call (Pow _ (Pow 2 3)) x)");
+        assert_eq!(
+            body("f(_, g(_ + 1), [_])"),
+            "(call f _ (call g (Add _ 1)) [ _ ])"
+        );
+        assert_eq!(
+            body("(-1) + (|x| x)(2)"),
+            "(Add (Neg 1) (call (closure (x) x) 2))"
+        );
+    }
+
+    #[test]
+    fn operator_table() {
+        for (i, a) in OPERATORS.iter().enumerate() {
+            // a token is at most one operator of each fixity
+            let same_fixity =
+                |b: &Operator| mem::discriminant(&a.fixity) == mem::discriminant(&b.fixity);
+            let mut others = OPERATORS[i + 1..].iter();
+            assert!(
+                others.all(|b| !same_fixity(b) || b.token != a.token),
+                "{a:?}"
+            );
+
+            // infix operators of the same precedence group the same way
+            if let Fixity::Infix(_, assoc) = a.fixity {
+                let mut same_prec = OPERATORS.iter().filter(|b| b.prec == a.prec);
+                let same_assoc =
+                    |b: &Operator| matches!(b.fixity, Fixity::Infix(_, other) if other == assoc);
+                assert!(same_prec.all(same_assoc), "{a:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(
+            body(r#"$"{a}\n{b + 1:>4} and {$"in {c}":?}""#),
+            r#"(interp "" a "\\n" (Add b 1):>4 " and " (interp "in " c ""):? "")"#
+        );
+        assert_eq!(body(r#"$"{x}""#), r#"(interp "" x "")"#);
+
+        // a hole has one expression
+        assert_eq!(
+            parse_error(r#"f :: fn() -> $"{a b}";"#),
+            "1:18: Expected end of hole, found Ident"
+        );
+        assert_eq!(
+            parse_error(r#"f :: fn() -> $"{}";"#),
+            "1:16: Expected expression, found StringInterpEnd"
+        );
+    }
+
+    #[test]
+    fn arrays() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(body("[1, 2, 3,]"), "[ 1 2 3 ]");
+        assert_eq!(body("[]"), "[ ]");
+        assert_eq!(body("[[0; 4]; n + 1]"), "(repeat (repeat 0 4) (Add n 1))");
+        assert_eq!(
+            body("-a[i][j + 1]++ + f(x)[0]"),
+            "(Add (Neg (PostIncr (index (index a i) (Add j 1)))) (index (call f x) 0))"
+        );
+
+        let code = "f :: fn(a : [u8; 4], b : [[T]], c : Vec[[u8; N * 2]]) {}";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let ItemKind::Fn { params, .. } = ast.items.as_slice()[0].kind else {
+            unreachable!();
+        };
+        let types: Vec<_> = (ast.params[params].iter())
+            .map(|param| type_sexp(&ast, &tokens, param.ty))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "(array u8 4)",
+                "(slice (slice T))",
+                "(Vec (array u8 (Mul N 2)))"
+            ]
+        );
+    }
+
+    #[test]
+    fn tuples() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(body("(a, b + 1, (c))"), "(tuple a (Add b 1) c)");
+        assert_eq!(body("((a,), ())"), "(tuple (tuple a) ())");
+        assert_eq!(body("(a)"), "a");
+        assert_eq!(body("(P {}, x)"), "(tuple (struct P) x)");
+        assert_eq!(body("t.0 + (a, b).1"), "(Add (. t 0) (. (tuple a b) 1))");
+        assert_eq!(body("t.0.12.a.3(x)"), "(call (. (. (. (. t 0) 12) a) 3) x)");
+
+        // `t.0.1` is lexed with the number `0.1`, whose indices get spans of their own
+        let code = "f :: fn() -> t.0.1;";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let spans: Vec<_> = (ast.exprs.iter())
+            .filter_map(|(id, expr)| match *expr {
+                Expr::TupleField { index, .. } => {
+                    Some((&code[ast.exprs.span(id).range()], &code[index.range()]))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(spans, [("t.0", "0"), ("t.0.1", "1")]);
+
+        assert_eq!(
+            parse_error("f :: fn() -> t.01;"),
+            "1:15: Expected tuple index, found Num"
+        );
+        assert_eq!(
+            parse_error("f :: fn() -> t.0x1;"),
+            "1:15: Expected tuple index, found Num"
+        );
+        assert_eq!(
+            parse_error("f :: fn() -> (a b);"),
+            "1:16: Expected RParens, found Ident"
+        );
+    }
+
+    #[test]
+    fn structs() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(
+            body("Point { x: 1, y: a.b }"),
+            "(struct Point (x 1) (y (. a b)))"
+        );
+        assert_eq!(
+            body("Point { x, ..origin }"),
+            "(struct Point (x x) ..origin)"
+        );
+        assert_eq!(body("Unit {}.a"), "(. (struct Unit) a)");
+        assert_eq!(
+            body("a.b.c(d)[0].e++"),
+            "(PostIncr (. (index (method c (. a b) d) 0) e))"
+        );
+        assert_eq!(body("++a.b[0]"), "(PreIncr (index (. a b) 0))");
+        assert_eq!(body("a++.b"), "(. (PostIncr a) b)");
+        assert_eq!(body("--a--"), "(PreDecr (PostDecr a))");
+
+        // the block of a `match` isn't the fields of a struct literal
+        assert_eq!(
+            body("match p { _ => P { x: 1 } }"),
+            "(match p (_ (struct P (x 1))))"
+        );
+        assert_eq!(body("match (P {}) { _ => 0 }"), "(match (struct P) (_ 0))");
+        assert_eq!(
+            body("match f(P { x }) { _ => 0 }"),
+            "(match (call f (struct P (x x))) (_ 0))"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { r : P = P { x: 1, . .y }; }"),
+            "1:30: Expected Ident, found Dot"
+        );
+    }
+
+    #[test]
+    fn calls() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(body("f(a, g(b, h()),)"), "(call f a (call g b (call h)))");
+        assert_eq!(body("v.push(x).len()"), "(method len (method push v x))");
+        assert_eq!(body("a.b.c(d)"), "(method c (. a b) d)");
+        assert_eq!(body("(a.f)(b)"), "(call (. a f) b)");
+        assert_eq!(
+            body("x >- a.b.f(y) >- g"),
+            "(call g (method f (. a b) x y))"
+        );
+        assert_eq!(body("x >- v.len"), "(method len v x)");
+
+        // a missing comma is reported, and the arguments go on
+        let code = "f :: fn() -> g(a b, c) + h(d e);";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        let errors: Vec<_> = diagnostics.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "1:17: Expected Comma, found Ident",
+                "1:29: Expected Comma, found Ident"
+            ]
+        );
+        let ItemKind::Fn { body, .. } = ast.items.as_slice()[0].kind else {
+            unreachable!();
+        };
+        assert_eq!(
+            sexp(&ast, &tokens, body),
+            "(Add (call g a b c) (call h d e))"
+        );
+
+        // every argument has its span
+        let Expr::Binary { lhs, .. } = ast.exprs[body] else {
+            unreachable!();
+        };
+        let Expr::Call { args, .. } = ast.exprs[lhs] else {
+            unreachable!();
+        };
+        let spans: Vec<_> = (ast.expr_lists[args].iter())
+            .map(|&arg| &code[ast.exprs.span(arg).range()])
+            .collect();
+        assert_eq!(spans, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn closures() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(body("|x, y| x + y"), "(closure (x y) (Add x y))");
+        assert_eq!(body("|| 0"), "(closure () 0)");
+        assert_eq!(body("|x : i32, _| { x }"), "(closure (x:i32 _) { x })");
+        assert_eq!(body("fn(x) -> x + 1"), "(closure (x) (Add x 1))");
+        assert_eq!(
+            body("fn[&total, n](x : u32) >- u32 { total + x }"),
+            "(closure [&total n] (x:u32) >- u32 { (Add total x) })"
+        );
+        assert_eq!(body("fn[]() -> 0"), "(closure [] () 0)");
+        assert_eq!(
+            body("map(xs, |x| x * 2)"),
+            "(call map xs (closure (x) (Mul x 2)))"
+        );
+        // a closure is called with the value piped into it, and takes the rest of the pipeline
+        assert_eq!(
+            body("xs >- |x| x >- f"),
+            "(call (closure (x) (call f x)) xs)"
+        );
+        assert_eq!(
+            body("xs >- map(fn(x) -> x * 2) >- sum"),
+            "(call sum (call map xs (closure (x) (Mul x 2))))"
+        );
+        assert_eq!(body("a | |b| b"), "(BitOr a (closure (b) b))");
+
+        assert_eq!(
+            parse_error("f :: fn() -> |x y| x;"),
+            "1:16: Expected Pipe, found Ident"
+        );
+        assert_eq!(
+            parse_error("f :: fn() -> fn(x) x;"),
+            "1:19: Expected LBrace, found Ident"
+        );
+    }
+
+    #[test]
+    fn types() {
+        let code = concat!(
+            "f :: fn(a : *u8, b : **T, c : &[?T], d : ?&Vec[*T], e : fn(T, U) >- R, f : fn()) ",
+            ">- fn(fn() >- T) >- (A, (B), (C,), ()) -> g;",
+        );
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let ItemKind::Fn { params, ret, .. } = ast.items.as_slice()[0].kind else {
+            unreachable!();
+        };
+        let mut types: Vec<_> = (ast.params[params].iter())
+            .map(|param| type_sexp(&ast, &tokens, param.ty))
+            .collect();
+        types.extend(ret.map(|ret| type_sexp(&ast, &tokens, ret)));
+        assert_eq!(
+            types,
+            [
+                "(* u8)",
+                "(* (* T))",
+                "(& (slice (? T)))",
+                "(? (& (Vec (* T))))",
+                "(fn T U >- R)",
+                "(fn)",
+                "(fn (fn >- T) >- (tuple A B (tuple C) (tuple)))",
+            ]
+        );
+
+        let spans: Vec<_> = (ast.types.iter())
+            .filter(|(_, ty)| matches!(ty, Type::Pointer(_) | Type::Tuple(_)))
+            .map(|(id, _)| &code[ast.types.span(id).range()])
+            .collect();
+        assert_eq!(
+            spans,
+            ["*u8", "*T", "**T", "*T", "(C,)", "()", "(A, (B), (C,), ())"]
+        );
+        assert_eq!(
+            parse_error("f :: fn(a : fn(T) -> R) {}"),
+            "1:18: Expected RParens, found Arrow"
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let code = r#"
+control :: fn() >- bool
+{
+    if 10 + 9 == 21 then { println("yes"); } else println($"no {a:?} {b}");
+    while i < 10 do
+    {
+        if i % 2 == 0 then continue;
+        step()
+    }
+    loop { break out; }
+    break
+}
+"#;
+        assert_eq!(
+            body_sexp(code),
+            concat!(
+                r#"{ (if (Eq (Add 10 9) 21) { (call println "yes"); } "#,
+                r#"(call println (interp "no " a:? " " b ""))); "#,
+                "(while (Lt i 10) { (if (Eq (Mod i 2) 0) continue); (call step) }) ",
+                "(loop { (break out); }) (break) }",
+            )
+        );
+    }
+
+    #[test]
+    fn returns() {
+        let code = "f :: fn(x : i32) >- i32 { if x < 0 then return -x; g(return); x }";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let ItemKind::Fn { body, .. } = ast.items.as_slice()[0].kind else {
+            unreachable!();
+        };
+        assert_eq!(
+            sexp(&ast, &tokens, body),
+            "{ (if (Lt x 0) (return (Neg x))); (call g (return)); x }"
+        );
+
+        // the last expression is the value of its block, unless it has a `;`
+        let tail = |code: &str| {
+            let tokens = lex(FileId::default(), code);
+            let (ast, diagnostics) = parse_file(&tokens);
+            assert!(diagnostics.is_empty(), "{diagnostics:?}");
+            let blocks: Vec<_> = (ast.exprs.iter())
+                .filter_map(|(_, expr)| match expr {
+                    Expr::Block(block) => Some(block.tail.map(|tail| sexp(&ast, &tokens, tail))),
+                    _ => None,
+                })
+                .collect();
+            blocks
+        };
+        let of = |s: &str| Some(s.to_string());
+        assert_eq!(tail(code), [of("x")]);
+        assert_eq!(tail("f :: fn() { a; b; }"), [None]);
+        assert_eq!(
+            tail("f :: fn() { loop {} if a then { b } else { c } }"),
+            [None, of("b"), of("c"), of("(if a { b } { c })")]
+        );
+        assert_eq!(tail("f :: fn() { return }"), [of("(return)")]);
+        assert_eq!(tail("f :: fn() {}"), [None]);
+    }
+
+    #[test]
+    fn conditionals() {
+        let code = r#"
+f :: fn()
+{
+    sign := if x < 0 then -1 else if x == 0 then 0 else 1;
+    if a then if b then c else d;
+    if a then
+    {
+        g();
+    }
+    else if b then
+    {
+        h()
+    }
+    else { 2 }
+    y := (if a then b else c) + 1;
+    z := 1 + if a then b else c + 1;
+}
+"#;
+        assert_eq!(
+            body_sexp(code),
+            concat!(
+                "{ (let Implicit sign (if (Lt x 0) (Neg 1) (if (Eq x 0) 0 1))) ",
+                "(if a (if b c d)); ",
+                "(if a { (call g); } (if b { (call h) } { 2 })) ",
+                "(let Implicit y (Add (if a b c) 1)) ",
+                "(let Implicit z (Add 1 (if a b (Add c 1)))) }",
+            )
+        );
+
+        assert_eq!(
+            parse_error("f :: fn() { if a { b } }"),
+            "1:17: Expected Then, found LBrace"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { if a then b else }"),
+            "1:29: Expected expression, found RBrace"
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let code = r#"
+f :: fn()
+{
+    do i++ while i < 10;
+    do { step(); } while not done;
+    found := @outer loop
+    {
+        @inner while j < n do
+        {
+            if x == y then break @outer j;
+            if x > y then continue @outer;
+            if x < y then break @inner;
+            continue;
+        }
+        break 0;
+    };
+    @retry do try() while failed()
+}
+"#;
+        assert_eq!(
+            body_sexp(code),
+            concat!(
+                "{ (do-while (PostIncr i) (Lt i 10)); (do-while { (call step); } (Not done)); ",
+                "(let Implicit found (loop @outer { ",
+                "(while @inner (Lt j n) { (if (Eq x y) (break @outer j)); ",
+                "(if (Gt x y) (continue @outer)); (if (Lt x y) (break @inner)); continue; }) ",
+                "(break 0); })) ",
+                "(do-while @retry (call try) (call failed)) }",
+            )
+        );
+
+        let tokens = lex(FileId::default(), "f :: fn() { @a loop {} }");
+        let (ast, _) = parse_file(&tokens);
+        let labels: Vec<_> = (ast.exprs.iter())
+            .filter(|(_, expr)| expr.is_loop())
+            .map(|(_, expr)| &tokens.code[expr.loop_label().unwrap().range()])
+            .collect();
+        assert_eq!(labels, ["a"]);
+
+        assert_eq!(
+            parse_error("f :: fn() { @a if x then y; }"),
+            "1:15: Expected loop, found If"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { do x; }"),
+            "1:16: Expected While, found Semi"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { break @ 1; }"),
+            "1:20: Expected Ident, found Num"
+        );
+    }
+
+    #[test]
+    fn for_loops() {
+        let body = |expr: &str| body_sexp(&format!("f :: fn() -> {expr};"));
+
+        assert_eq!(body("for x in xs do f(x)"), "(for x xs (call f x))");
+        assert_eq!(
+            body("for x, i in xs.items { total += x * i; }"),
+            "(for x,i (. xs items) { (Add= total (Mul x i)) })"
+        );
+        assert_eq!(
+            body("@rows for _ in 0..n + 1 do break @rows"),
+            "(for @rows _ (range 0 (Add n 1)) (break @rows))"
+        );
+        assert_eq!(
+            body("for y in a.b..len(c) {}"),
+            "(for y (range (. a b) (call len c)) { })"
+        );
+        // struct literals can't follow the iterable, whose block is the body
+        assert_eq!(body("for p in points { p }"), "(for p points { p })");
+        // `for` is still an identifier elsewhere
+        assert_eq!(body("for + in"), "(Add for in)");
+
+        assert_eq!(
+            parse_error("f :: fn() -> for x in xs f(x);"),
+            "1:25: Expected Do, found Ident"
+        );
+        assert_eq!(
+            parse_error("f :: fn() -> for x of xs {};"),
+            "1:19: Expected In, found Ident"
+        );
+    }
+
+    #[test]
+    fn defers() {
+        let code = r#"
+f :: fn()
+{
+    defer println("last");
+    defer { close(file); }
+    while i < 10 do
+    {
+        defer i++;
+        if done then break;
+    }
+    defer a
+}
+"#;
+        assert_eq!(
+            body_sexp(code),
+            concat!(
+                r#"{ (defer (call println "last")) (defer { (call close file); }) "#,
+                "(while (Lt i 10) { (defer (PostIncr i)) (if done (break)); }) (defer a) }",
+            )
+        );
+
+        let tokens = lex(FileId::default(), code);
+        let (ast, _) = parse_file(&tokens);
+        let blocks: Vec<_> = (ast.exprs.iter())
+            .filter_map(|(_, expr)| match expr {
+                Expr::Block(block) => {
+                    let defers = ast.stmt_lists[block.defers].iter();
+                    let spans = defers.map(|&stmt| &code[ast.stmts.span(stmt).range()]);
+                    Some(spans.collect::<Vec<_>>())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                vec![],
+                vec!["defer i++;"],
+                vec![
+                    "defer a",
+                    "defer { close(file); }",
+                    r#"defer println("last");"#
+                ],
+            ]
+        );
+
+        assert_eq!(
+            parse_error("f :: fn() { defer a b }"),
+            "1:20: Expected Semi, found Ident"
+        );
+    }
+
+    #[test]
+    fn matches() {
+        let code = r#"
+f :: fn()
+{
+    name := match event
+    {
+        KeyboardEvent.KeyPress(.Enter | .Space) => "confirm",
+        .KeyPress(code) if code == 0 => { unknown() },
+        .ImeCommit(_) | .KeyRepeat(-1) => 'c',
+        0 => match x { _ => "nested", },
+        other => other,
+    };
+    match := match + 1;
+    match { a } { b => c }
+}
+"#;
+        assert_eq!(
+            body_sexp(code),
+            concat!(
+                "{ (let Implicit name (match event ",
+                r#"((KeyboardEvent.KeyPress (or (.Enter) (.Space))) "confirm") "#,
+                "((.KeyPress code) (if (Eq code 0)) { (call unknown) }) ",
+                "((or (.ImeCommit _) (.KeyRepeat -1)) 'c') ",
+                r#"(0 (match x (_ "nested"))) (other other))) "#,
+                "(let Implicit match (Add match 1)) (match { a } (b c)) }",
+            )
+        );
+
+        assert_eq!(
+            parse_error("f :: fn() { match x { + => 1 } }"),
+            "1:22: Expected pattern, found Plus"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { match x { a b } }"),
+            "1:24: Expected FatArrow, found Ident"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { match x { a => 1 b => 2 } }"),
+            "1:29: Expected RBrace, found Ident"
+        );
+
+        let code = format!("f :: fn() -> match x {{ {} => 0 }};", ".A(".repeat(100));
+        let tokens = lex(FileId::default(), &code);
+        let (_, diagnostics) = parse_file(&tokens);
+        assert_eq!(diagnostics[0].kind, ParseErrorKind::NestingTooDeep);
+
+        // chains count as nesting, each link wrapping the ones before it
+        let chain = |links, link: &str| format!("f :: fn() -> g{};", link.repeat(links));
+        for link in [".a", "()", "[0]", "++", " >- h", " + 1"] {
+            let (longest, too_long) = (chain(MAX_CHAIN_DEPTH, link), chain(20_000, link));
+            let tokens = lex(FileId::default(), &longest);
+            assert!(parse_file(&tokens).1.is_empty(), "{link}");
+            let tokens = lex(FileId::default(), &too_long);
+            let (_, diagnostics) = parse_file(&tokens);
+            assert_eq!(
+                diagnostics[0].kind,
+                ParseErrorKind::NestingTooDeep,
+                "{link}"
+            );
+        }
+    }
+
+    #[test]
+    fn declarations() {
+        let code = r#"
+f :: fn()
+{
+    rw i : usize = 0;
+    out := i < 10;
+    w _ : u8 = g();
+    r : i32 = 1;
+    i = i + 1;
+    i += 2;
+    x <<= 1 + 1;
+    x **= 2;
+    z = while i do { i--; };
+}
+"#;
+        assert_eq!(
+            body_sexp(code),
+            concat!(
+                "{ (let ReadWrite i usize 0) (let Implicit out (Lt i 10)) ",
+                "(let Write _ u8 (call g)) (let Implicit r i32 1) (= i (Add i 1)) ",
+                "(Add= i 2) (Shl= x (Add 1 1)) (Pow= x 2) ",
+                "(= z (while i { (PostDecr i); })) }",
+            )
+        );
+
+        // operators are only compound assignments when directly followed by `=`
+        assert_eq!(
+            parse_error("f :: fn() { i + = 1; }"),
+            "1:16: Expected expression, found Equal"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { rw i = 0; }"),
+            "1:15: Expected Semi, found Ident"
+        );
+        assert_eq!(
+            parse_error("f :: fn() { i : u8; }"),
+            "1:18: Expected Equal, found Semi"
+        );
+    }
+
+    #[test]
+    fn recovery() {
+        let code = r#"
+a :: fn()
+{
+    x := 1 +;
+    if x then { y z; w } else { 1 }
+    match x { 1 => 2 3 }
+    ok();
+}
+b :: struct { c : 1, d : u8 }
+pub use e::;
+f :: fn() { g()
+h :: fn() {}
+i :: enum { J = ) }
+k :: fn() {}
+"#;
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        let diagnostics: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "4:12: Expected expression, found Semi",
+                "5:18: Expected Semi, found Ident",
+                "6:21: Expected RBrace, found Num",
+                "9:18: Expected type, found Num",
+                "10:11: Expected Ident, found Semi",
+                "12:0: Expected Semi, found Ident",
+                "12:0: Items are not allowed in blocks, did you mean to close the block at 11:10?",
+                "13:16: Expected expression, found RParens",
+            ]
+        );
+
+        let items: Vec<_> = (ast.items.iter())
+            .map(|(id, item)| {
+                let span = &code[ast.items.span(id).range()];
+                (
+                    matches!(item.kind, ItemKind::Error),
+                    span.lines().next().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (false, "a :: fn()"),
+                (true, "b :: struct { c : 1, d : u8 }"),
+                (true, "pub use e::;"),
+                (true, "f :: fn() { g()"),
+                (false, "h :: fn() {}"),
+                (true, "i :: enum { J = ) }"),
+                (false, "k :: fn() {}"),
+            ]
+        );
+
+        let ItemKind::Fn { body, .. } = ast.items.as_slice()[0].kind else {
+            unreachable!();
+        };
+        assert_eq!(
+            sexp(&ast, &tokens, body),
+            "{ error (if x { error w } { 1 }) error (call ok); }"
+        );
+        let errors: Vec<_> = (ast.stmts.iter())
+            .filter(|(_, stmt)| matches!(stmt, Stmt::Error))
+            .map(|(id, _)| &code[ast.stmts.span(id).range()])
+            .collect();
+        assert_eq!(errors, ["x := 1 +;", "y z;", "match x { 1 => 2 3 }"]);
+    }
+
+    #[test]
+    fn items_in_blocks() {
+        let code = "a :: fn() {\n    if x then {\n        y();\nb :: struct {}\nc :: fn() { d :: enum {} }";
+        let tokens = lex(FileId::default(), code);
+        let (ast, diagnostics) = parse_file(&tokens);
+        let diagnostics: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "4:0: Items are not allowed in blocks, did you mean to close the block at 2:14?",
+                "5:12: Items are not allowed in blocks, did you mean to close the block at 5:10?",
+                "5:25: Expected Ident, found RBrace",
+            ]
+        );
+
+        let kinds: Vec<_> = (ast.items.iter())
+            .map(|(_, item)| matches!(item.kind, ItemKind::Error))
+            .collect();
+        assert_eq!(kinds, [true, false, true, false, true]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_error("a :: fn() { b c }"),
+            "1:14: Expected Semi, found Ident"
+        );
+        assert_eq!(parse_error("a :: 1;"), "1:5: Expected item, found Num");
+        assert_eq!(
+            parse_error("a :: fn("),
+            "1:8: Expected pattern, found end of file"
+        );
+        assert_eq!(
+            parse_error("a :: fn() -> ;"),
+            "1:13: Expected expression, found Semi"
+        );
+        assert_eq!(
+            parse_error("a :: struct { b : 1 }"),
+            "1:18: Expected type, found Num"
+        );
+        assert_eq!(
+            parse_error("a :: packed enum {}"),
+            "1:12: Expected Struct, found Enum"
+        );
+
+        let code = format!("a :: fn() -> {};", "(".repeat(1000));
+        let tokens = lex(FileId::default(), &code);
+        let (_, diagnostics) = parse_file(&tokens);
+        assert_eq!(diagnostics[0].kind, ParseErrorKind::NestingTooDeep);
+    }
+}
@@ -0,0 +1,284 @@
+//! Validation of the parser output, for fuzzing.
+
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::{parse_file, ParseError};
+use crate::{
+    ast::{walk_ast, Ast, Visitor},
+    lexer::{lex, panic_message, Span, Tokens},
+};
+
+/// Invariant of the AST parsed from some tokens that doesn't hold, see [`parse_checked`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InvariantViolation {
+    /// The parser or the printer panicked, with its panic message
+    Panic(String),
+    /// The span of a node goes past the end of the code or cuts a character in half
+    SpanOutOfBounds {
+        /// Name of the table of the node in the [`Ast`], like `"exprs"`
+        table: &'static str,
+        idx: usize,
+    },
+    /// The printed code of an AST without errors doesn't parse without errors
+    ReparseError { printed: String, message: String },
+    /// Printing the AST of the printed code doesn't give the same code
+    NotFixpoint { printed: String, reprinted: String },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::Panic(message) => write!(f, "Parser panicked: {message}"),
+            InvariantViolation::SpanOutOfBounds { table, idx } => {
+                write!(f, "Span of node {idx} of {table} is out of bounds")
+            }
+            InvariantViolation::ReparseError { printed, message } => {
+                write!(f, "Printed code doesn't parse: {message}\n{printed}")
+            }
+            InvariantViolation::NotFixpoint { printed, reprinted } => {
+                write!(
+                    f,
+                    "Printed code prints differently:\n{printed}\n---\n{reprinted}"
+                )
+            }
+        }
+    }
+}
+
+/// Parses the tokens like [`parse_file`], then checks the invariants of the AST.
+///
+/// Every node must have a span within the code, and every handle must point to a node.
+/// If there are no errors, printing the AST and parsing the printed code must give
+/// no errors either and print the same code, so that the AST is all the code means.
+///
+/// A panic of the parser or the printer is caught and returned as a violation,
+/// so that fuzzers (see the `fuzz` directory) report it along with the others.
+/// Stack overflows and hangs can't be caught: the parser bounds how deep trees get
+/// with [`MAX_NESTING_DEPTH`](super::MAX_NESTING_DEPTH) and
+/// [`MAX_CHAIN_DEPTH`](super::MAX_CHAIN_DEPTH), and fuzzers time out on hangs.
+pub fn parse_checked<'a>(
+    tokens: &Tokens<'a>,
+) -> Result<(Ast, Vec<ParseError<'a>>), Vec<InvariantViolation>> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let (ast, diagnostics) = parse_file(tokens);
+        let mut violations = ast.check_spans(tokens.code);
+        if violations.is_empty() {
+            // walking the whole AST indexes every handle in it
+            struct Walk;
+            impl Visitor for Walk {}
+            walk_ast(&mut Walk, &ast);
+
+            if tokens.errors.is_empty() && diagnostics.is_empty() {
+                violations.extend(check_fixpoint(&ast, tokens));
+            }
+        }
+        (ast, diagnostics, violations)
+    }));
+
+    match result {
+        Ok((ast, diagnostics, violations)) if violations.is_empty() => Ok((ast, diagnostics)),
+        Ok((_, _, violations)) => Err(violations),
+        Err(payload) => Err(vec![InvariantViolation::Panic(panic_message(&*payload))]),
+    }
+}
+
+/// Checks that the printed code of the AST parses back to an AST that prints the same.
+fn check_fixpoint(ast: &Ast, tokens: &Tokens) -> Option<InvariantViolation> {
+    let printed = ast.print(tokens);
+    let reprinted = {
+        let tokens = lex(tokens.file, &printed);
+        let (ast, diagnostics) = parse_file(&tokens);
+        match (tokens.errors.as_slice().first(), diagnostics.first()) {
+            (Some(error), _) => Err(error.to_string()),
+            (None, Some(diagnostic)) => Err(diagnostic.to_string()),
+            (None, None) => Ok(ast.print(&tokens)),
+        }
+    };
+
+    match reprinted {
+        Err(message) => Some(InvariantViolation::ReparseError { printed, message }),
+        Ok(reprinted) => {
+            (reprinted != printed).then_some(InvariantViolation::NotFixpoint { printed, reprinted })
+        }
+    }
+}
+
+impl Ast {
+    /// Checks that the spans of the nodes are within the code, returning those that aren't.
+    pub fn check_spans(&self, code: &str) -> Vec<InvariantViolation> {
+        let attr_spans: Vec<Span> = self.attrs.as_slice().iter().map(|attr| attr.span).collect();
+        let tables = [
+            ("items", self.items.spans()),
+            ("stmts", self.stmts.spans()),
+            ("exprs", self.exprs.spans()),
+            ("types", self.types.spans()),
+            ("patterns", self.patterns.spans()),
+            ("attrs", &attr_spans),
+        ];
+
+        let mut violations = Vec::new();
+        for (table, spans) in tables {
+            for (idx, span) in spans.iter().enumerate() {
+                let (start, end) = (span.start as usize, span.end());
+                if end > code.len() || !code.is_char_boundary(start) || !code.is_char_boundary(end)
+                {
+                    violations.push(InvariantViolation::SpanOutOfBounds { table, idx });
+                }
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests_check {
+    use crate::{
+        lexer::lex,
+        parser::{check::InvariantViolation, parse_checked, MAX_CHAIN_DEPTH},
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+    /// Xorshift generator, so that the tests are random but the same every time.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick<'s>(&mut self, choices: &[&'s str]) -> &'s str {
+            choices[self.below(choices.len())]
+        }
+    }
+
+    fn check(code: &str) -> bool {
+        let tokens = lex(FileId::default(), code);
+        match parse_checked(&tokens) {
+            Ok((_, diagnostics)) => tokens.errors.is_empty() && diagnostics.is_empty(),
+            Err(violations) => panic!("{code:?}\n{}", violations[0]),
+        }
+    }
+
+    #[test]
+    fn valid_ast() {
+        // the sample has invalid characters on purpose
+        assert!(!check(SAMPLE));
+    }
+
+    #[test]
+    fn truncated_code() {
+        let boundaries = (0..SAMPLE.len()).filter(|&i| SAMPLE.is_char_boundary(i));
+        for end in boundaries.step_by(7) {
+            check(&SAMPLE[..end]);
+        }
+    }
+
+    #[test]
+    fn random_tokens() {
+        const VOCABULARY: &[&str] = &[
+            "a", "b", "_", "1", "2.5", "\"s\"", "'c'", "$\"a{", "}b{", "}\"", "(", ")", "{", "}",
+            "[", "]", ",", ";", ":", "::", "=", ":=", "+=", ".", "..", "+", "-", "*", "**", "<",
+            "==", "|", "&", "?", "++", ">-", "->", "=>", "@l", "#[", "#![", "not", "and", "if",
+            "then", "else", "while", "do", "for", "in", "loop", "match", "break", "continue",
+            "return", "defer", "fn", "struct", "enum", "union", "const", "use", "pub", "rw",
+        ];
+
+        let mut rng = Rng(0x5eed);
+        for _ in 0..3000 {
+            let len = rng.below(40);
+            let code: Vec<&str> = (0..len).map(|_| rng.pick(VOCABULARY)).collect();
+            check(&code.join(" "));
+        }
+    }
+
+    /// Random expression that is mostly valid, nested at most `depth` times.
+    fn expr(rng: &mut Rng, depth: usize) -> String {
+        const ATOMS: &[&str] = &["a", "b", "1", "2.5", "\"s\"", "()", "t.0", "$\"x{a}y\""];
+        const OPS: &[&str] = &[
+            "+", "-", "*", "/", "**", "<<", "|", "&", "==", "<", "and", "or", "xor",
+        ];
+
+        if depth == 0 {
+            return rng.pick(ATOMS).to_string();
+        }
+        let (choice, op) = (rng.below(24), rng.pick(OPS));
+        let mut e = || expr(rng, depth - 1);
+        match choice {
+            // chained comparisons are reported
+            0..=4 if matches!(op, "==" | "<") => format!("({} {op} {})", e(), e()),
+            0..=4 => format!("{} {op} {}", e(), e()),
+            5 => format!("- {}", e()),
+            6 => format!("not {}", e()),
+            7 => format!("({})++", e()),
+            8 => format!("({})", e()),
+            9 => format!("f({}, {})", e(), e()),
+            10 => format!("({}).m({})", e(), e()),
+            11 => format!("({}, {})", e(), e()),
+            12 => format!("{}[{}]", e(), e()),
+            13 => format!("if {} then {} else {}", e(), e(), e()),
+            14 => format!("if {} then {}", e(), e()),
+            15 => format!("|x| {}", e()),
+            16 => format!("{{ y := {}; {} }}", e(), e()),
+            17 => format!("(P {{ x: {}, y }}).x", e()),
+            18 => format!("{} >- g(_, {})", e(), e()),
+            19 => format!("while {} do {}", e(), e()),
+            20 => format!("for i in 0..{} do {}", e(), e()),
+            21 => format!("match {} {{ .A(x) | _ if {} => {}, }}", e(), e(), e()),
+            22 => format!("@l loop {{ break @l {} }}", e()),
+            _ => format!("(+ ({}))", e()),
+        }
+    }
+
+    #[test]
+    fn random_exprs() {
+        let mut rng = Rng(0xc0de);
+        for _ in 0..2000 {
+            let depth = rng.below(5);
+            let code = format!(
+                "f :: fn() {{ x := {}; return {} }}",
+                expr(&mut rng, depth),
+                expr(&mut rng, depth)
+            );
+            assert!(check(&code), "{code:?}");
+        }
+    }
+
+    #[test]
+    fn deep_trees() {
+        // long chains are errors rather than overflowing the stack of the printer
+        let chain = |links| format!("f :: fn() -> g{};", ".a".repeat(links));
+        assert!(check(&chain(MAX_CHAIN_DEPTH)));
+        assert!(!check(&chain(20_000)));
+        // and argument lists left open end with the file
+        assert!(!check("#[inline("));
+        assert!(!check("f :: fn() { #[a( }"));
+    }
+
+    #[test]
+    fn violations() {
+        let tokens = lex(FileId::default(), "f :: fn() -> 1;");
+        let (ast, _) = parse_checked(&tokens).unwrap();
+        assert_eq!(
+            ast.check_spans("f"),
+            [
+                InvariantViolation::SpanOutOfBounds {
+                    table: "items",
+                    idx: 0
+                },
+                InvariantViolation::SpanOutOfBounds {
+                    table: "exprs",
+                    idx: 0
+                },
+            ]
+        );
+        assert_eq!(ast.check_spans("f :: fn() -> 1;")[..], []);
+    }
+}
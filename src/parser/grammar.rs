@@ -0,0 +1,343 @@
+//! Grammar of the language as data, exported as EBNF for the documentation and for tools.
+//!
+//! The rules of expressions are generated from the table of operators that the parser
+//! climbs, so that they can't tell a different story, and the others are written out
+//! next to it. The grammar at the top of the [parser](super) is this export, which is tested.
+
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use super::{Assoc, Fixity, Prec, OPERATORS};
+use crate::lexer::TokenType;
+
+/// Production of the grammar, a nonterminal and the sequences of symbols it can be in EBNF.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rule {
+    pub name: &'static str,
+    pub alternatives: Vec<String>,
+}
+
+impl fmt::Display for Rule {
+    /// Writes the rule on a line per alternative, the `|` of each under the `=`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:<6}", self.name);
+        for (i, alternative) in self.alternatives.iter().enumerate() {
+            match i {
+                0 => write!(f, "{name} = {alternative}")?,
+                _ => write!(f, "\n{:width$} | {alternative}", "", width = name.len())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rules before those of expressions, from the file down to statements.
+const ITEM_RULES: &[(&str, &[&str])] = &[
+    ("file", &[r##"("#![" attr,* "]")* item* EOF"##]),
+    (
+        "item",
+        &[
+            r#"attrs "pub"? IDENT "::" (fn | struct | enum | union)"#,
+            r#"attrs "pub"? "use" IDENT ("::" IDENT)* ";""#,
+            r#"attrs "pub"? ("const" | "static") IDENT ":" type? "=" expr ";""#,
+        ],
+    ),
+    ("attrs", &[r##"("#[" attr,* "]")*"##]),
+    ("attr", &[r#"IDENT ("::" IDENT)* ("(" TOKEN* ")")?"#]),
+    (
+        "fn",
+        &[r#""fn" generics? "(" (pattern ":" type),* ")" (">-" type)? ("->" expr ";" | block)"#],
+    ),
+    (
+        "struct",
+        &[r#""packed"? "struct" generics? type? "{" field,* "}""#],
+    ),
+    (
+        "enum",
+        &[r#""enum" generics? type? "{" (IDENT (":" type)? ("=" expr)?),* "}""#],
+    ),
+    ("union", &[r#""union" generics? "{" field,* "}""#]),
+    ("field", &[r#"attrs "pub"? IDENT ":" type"#]),
+    ("generics", &[r#""[" IDENT,* "]""#]),
+    (
+        "type",
+        &[
+            r#"IDENT ("[" type,* "]")?"#,
+            r#"("*" | "&" | "?") type"#,
+            r#""fn" "(" type,* ")" (">-" type)?"#,
+            r#""[" type (";" expr)? "]""#,
+            r#""(" (type ("," type)* ","?)? ")""#,
+        ],
+    ),
+    ("block", &[r#""{" stmt* "}""#]),
+    (
+        "stmt",
+        &[
+            r#"attrs "defer" expr ";"?"#,
+            r#"attrs ("r" | "w" | "rw")? pattern ":" type? "=" expr ";""#,
+            r#"attrs expr ("=" | OP "=") expr ";""#,
+            r#"attrs expr ";"?"#,
+        ],
+    ),
+];
+
+/// Rules after those of expressions, from their operands down to patterns.
+const PRIMARY_RULES: &[(&str, &[&str])] = &[
+    (
+        "suffix",
+        &[
+            r#""." (IDENT | DIGITS)"#,
+            r#"("." IDENT)? "(" (expr | "_"),* ")""#,
+            r#""[" expr "]""#,
+        ],
+    ),
+    (
+        "stage",
+        &[r#"primary ("." IDENT)* ("(" (expr | "_"),* ")")?"#],
+    ),
+    (
+        "primary",
+        &[
+            "LITERAL",
+            r#""(" ")""#,
+            "IDENT",
+            "string",
+            "struct_lit",
+            r#""(" expr ")""#,
+            "tuple",
+            "section",
+            "array",
+            "block",
+            "if",
+            "match",
+            "loop",
+            "break",
+            "return",
+            "closure",
+        ],
+    ),
+    ("if", &[r#""if" expr "then" expr ("else" expr)?"#]),
+    (
+        "loop",
+        &[r#"label? ("while" expr "do" expr | "do" expr "while" expr | "loop" expr | for)"#],
+    ),
+    (
+        "for",
+        &[r#""for" pattern ("," pattern)? "in" expr (".." expr)? ("do" expr | block)"#],
+    ),
+    (
+        "break",
+        &[r#""break" label? expr?"#, r#""continue" label?"#],
+    ),
+    ("return", &[r#""return" expr?"#]),
+    ("label", &[r#""@" IDENT"#]),
+    (
+        "string",
+        &["STRING_INTERP_BEG hole (STRING_INTERP_MID hole)* STRING_INTERP_END"],
+    ),
+    ("hole", &["expr FORMAT_SPEC?"]),
+    (
+        "struct_lit",
+        &[r#"IDENT "{" (IDENT (":" expr)?),* ("," ".." expr)? "}""#],
+    ),
+    (
+        "tuple",
+        &[r#""(" expr ("," expr)+ ","? ")""#, r#""(" expr "," ")""#],
+    ),
+    ("section", &[r#""(" OP expr ")""#]),
+    ("array", &[r#""[" (expr ";" expr | expr,*) "]""#]),
+    (
+        "closure",
+        &[
+            r#""|" (primary_pattern (":" type)?),* "|" expr"#,
+            r#""fn" ("[" "&"? IDENT,* "]")? "(" (pattern (":" type)?),* ")" (">-" type)? ("->" expr | block)"#,
+        ],
+    ),
+    (
+        "match",
+        &[r#""match" expr "{" (pattern ("if" expr)? "=>" expr),* "}""#],
+    ),
+    ("pattern", &[r#"primary_pattern ("|" primary_pattern)*"#]),
+    (
+        "primary_pattern",
+        &[
+            "IDENT",
+            r#""_""#,
+            r#""-"? LITERAL"#,
+            r#"IDENT? "." IDENT ("(" pattern ")")?"#,
+        ],
+    ),
+];
+
+/// Every rule of the grammar, starting with `file`.
+pub fn grammar() -> Vec<Rule> {
+    let written = |rules: &[(&'static str, &[&str])]| {
+        (rules.iter().map(|&(name, alternatives)| Rule {
+            name,
+            alternatives: alternatives.iter().map(|a| a.to_string()).collect(),
+        }))
+        .collect::<Vec<_>>()
+    };
+
+    let mut rules = written(ITEM_RULES);
+    rules.extend(expr_rules());
+    rules.extend(written(PRIMARY_RULES));
+    rules
+}
+
+/// Writes the grammar as EBNF, a rule after the other.
+pub fn write_ebnf(out: &mut impl Write) -> io::Result<()> {
+    for rule in grammar() {
+        writeln!(out, "{rule}")?;
+    }
+    Ok(())
+}
+
+/// Rules of expressions, a rule for each precedence of the operators from the loosest,
+/// whose operands are of the next precedence.
+fn expr_rules() -> Vec<Rule> {
+    let mut levels: Vec<(Prec, Vec<_>)> = Vec::new();
+    for operator in OPERATORS {
+        match levels.last_mut() {
+            Some((prec, operators)) if *prec == operator.prec => operators.push(*operator),
+            _ => levels.push((operator.prec, vec![*operator])),
+        }
+    }
+
+    let mut rules = vec![Rule {
+        name: "expr",
+        alternatives: vec![rule_name(levels[0].0).to_string()],
+    }];
+    for (i, (prec, operators)) in levels.iter().enumerate() {
+        let (name, next) = (rule_name(*prec), levels.get(i + 1));
+        let next = next.map_or("primary", |&(prec, _)| rule_name(prec));
+
+        let mut terminals: Vec<_> = (operators.iter())
+            .map(|operator| format!("{:?}", terminal(operator.token)))
+            .collect();
+        let is_postfix = matches!(operators[0].fixity, Fixity::Postfix(_));
+        if is_postfix {
+            terminals.push("suffix".to_string());
+        }
+        let terminals = match terminals.len() {
+            1 => terminals.remove(0),
+            _ => format!("({})", terminals.join(" | ")),
+        };
+
+        let alternatives = match operators[0].fixity {
+            Fixity::Pipe => vec![format!("{next} ({terminals} stage)*")],
+            Fixity::Infix(_, Assoc::Left) => vec![format!("{next} ({terminals} {next})*")],
+            Fixity::Infix(_, Assoc::Right) => vec![format!("{next} ({terminals} {name})?")],
+            Fixity::Prefix(_) => vec![format!("{terminals} {name}"), next.to_string()],
+            Fixity::Postfix(_) => vec![format!("{next} {terminals}*")],
+        };
+        rules.push(Rule { name, alternatives });
+    }
+    rules
+}
+
+fn rule_name(prec: Prec) -> &'static str {
+    match prec {
+        Prec::Pipe => "pipe",
+        Prec::Or => "or",
+        Prec::And => "and",
+        Prec::Not => "not",
+        Prec::Compare => "compare",
+        Prec::BitOr => "bit_or",
+        Prec::BitXor => "bit_xor",
+        Prec::BitAnd => "bit_and",
+        Prec::Shift => "shift",
+        Prec::Sum => "sum",
+        Prec::Product => "product",
+        Prec::Prefix => "prefix",
+        Prec::Pow => "pow",
+        Prec::Postfix => "postfix",
+    }
+}
+
+/// Code of a token of the table of operators.
+fn terminal(token: TokenType) -> &'static str {
+    use TokenType as T;
+
+    match token {
+        T::Feather => ">-",
+        T::Or => "or",
+        T::Xor => "xor",
+        T::And => "and",
+        T::Not => "not",
+        T::Equals => "==",
+        T::NotEquals => "!=",
+        T::LessThan => "<",
+        T::GreaterThan => ">",
+        T::LessEqual => "<=",
+        T::GreaterEqual => ">=",
+        T::Pipe => "|",
+        T::Caret => "^",
+        T::Ampersand => "&",
+        T::LShift => "<<",
+        T::RShift => ">>",
+        T::Plus => "+",
+        T::Minus => "-",
+        T::Mul => "*",
+        T::Div => "/",
+        T::Modulo => "%",
+        T::Tilde => "~",
+        T::Incr => "++",
+        T::Decr => "--",
+        T::Pow => "**",
+        _ => unreachable!("{token:?} is not an operator"),
+    }
+}
+
+#[cfg(test)]
+mod tests_grammar {
+    use crate::{
+        lexer::{lex, TokenType},
+        parser::{
+            grammar::{grammar, terminal, write_ebnf},
+            OPERATORS,
+        },
+        source::FileId,
+    };
+
+    #[test]
+    fn terminals() {
+        for operator in OPERATORS {
+            let tokens = lex(FileId::default(), terminal(operator.token));
+            assert_eq!(tokens.types.as_slice(), [operator.token, TokenType::Eof]);
+        }
+    }
+
+    #[test]
+    fn defined_rules() {
+        let rules = grammar();
+        let names: Vec<_> = rules.iter().map(|rule| rule.name).collect();
+        for rule in &rules {
+            for alternative in &rule.alternatives {
+                // nonterminals are the lowercase words out of quotes
+                let unquoted = alternative.split('"').step_by(2).collect::<String>();
+                let words = unquoted.split(|c: char| !c.is_alphanumeric() && c != '_');
+                for word in words.filter(|w| w.starts_with(|c: char| c.is_lowercase())) {
+                    assert!(names.contains(&word), "{word} in {}", rule.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn documented_grammar() {
+        let parser = include_str!("../parser.rs");
+        let documented: String = (parser.lines())
+            .skip_while(|line| *line != "//! ```text")
+            .skip(1)
+            .take_while(|line| *line != "//! ```")
+            .map(|line| format!("{}\n", line.strip_prefix("//! ").unwrap_or(line)))
+            .collect();
+
+        let mut ebnf = Vec::new();
+        write_ebnf(&mut ebnf).unwrap();
+        assert_eq!(documented, String::from_utf8(ebnf).unwrap());
+    }
+}
@@ -0,0 +1,648 @@
+//! Parsing of large files on multiple threads.
+
+use std::thread;
+
+use super::{is_item_start, parse_file, ParseError};
+use crate::{
+    arena::SliceArena,
+    ast::{
+        Arm, Ast, Attr, Block, ClosureParam, Expr, ExprId, Field, FieldInit, ForIter, Hole, Item,
+        ItemKind, Param, Pattern, Stmt, TokenRange, Type, TypeId, Variant,
+    },
+    lexer::{TokenType, Tokens},
+};
+
+/// Files with fewer tokens than this per thread are not worth splitting.
+const MIN_CHUNK_TOKENS: usize = 16 * 1024;
+
+/// Parses the tokens on as many threads as available, giving the same result as [`parse_file`].
+///
+/// Top-level items don't depend on each other, so after a quick scan for where they start,
+/// the tokens are split in chunks of items that are parsed into ASTs of their own and
+/// merged in order. Recovering from an error can skip past the end of a chunk, so files
+/// that have errors are parsed again on one thread.
+pub fn parse_parallel<'a>(tokens: &Tokens<'a>) -> (Ast, Vec<ParseError<'a>>) {
+    let thread_count = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_count = thread_count.min(tokens.types.len() / MIN_CHUNK_TOKENS);
+    parse_in_chunks(tokens, chunk_count)
+}
+
+fn parse_in_chunks<'a>(tokens: &Tokens<'a>, chunk_count: usize) -> (Ast, Vec<ParseError<'a>>) {
+    let bounds = chunk_bounds(&item_starts(tokens), tokens.types.len(), chunk_count);
+    if bounds.len() <= 1 {
+        return parse_file(tokens);
+    }
+
+    // tokens can't be shared between threads, each one gets a copy of its chunk
+    let batches: Vec<_> = (bounds.iter())
+        .map(|&(start, end)| tokens.batch(start..end))
+        .collect();
+    let chunks: Option<Vec<Ast>> = thread::scope(|scope| {
+        let handles: Vec<_> = (batches.into_iter())
+            .map(|batch| {
+                scope.spawn(move || {
+                    let (ast, diagnostics) = parse_file(&batch);
+                    diagnostics.is_empty().then_some(ast)
+                })
+            })
+            .collect();
+
+        let chunks = handles.into_iter().map(|handle| handle.join().unwrap());
+        chunks.collect()
+    });
+
+    let Some(chunks) = chunks else {
+        return parse_file(tokens);
+    };
+    let mut ast = Ast::new(tokens);
+    for (chunk, &(start, _)) in chunks.into_iter().zip(&bounds) {
+        append_chunk(&mut ast, chunk, start);
+    }
+    (ast, Vec::new())
+}
+
+/// Indices of the tokens that top-level items start at, after the first item.
+///
+/// Only the items right after a `;` or a `}` outside of any delimiter are found, which is
+/// where items end. An item that isn't found just stays in the chunk of the previous one.
+fn item_starts(tokens: &Tokens) -> Vec<usize> {
+    use TokenType as T;
+
+    let mut starts = Vec::new();
+    let mut depth = 0_usize;
+    let mut cursor = tokens.cursor();
+    while let Some(token) = cursor.next() {
+        match token.ty {
+            T::LParens | T::LBracket | T::LBrace => depth += 1,
+            T::AttrStart | T::InnerAttrStart | T::StringInterpBeg => depth += 1,
+            T::RParens | T::RBracket | T::AttrEnd | T::StringInterpEnd => {
+                depth = depth.saturating_sub(1);
+            }
+            T::RBrace => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 && is_item_start(&cursor) {
+                    starts.push(cursor.idx());
+                }
+            }
+            T::Semi if depth == 0 && is_item_start(&cursor) => starts.push(cursor.idx()),
+            _ => {}
+        }
+    }
+    starts
+}
+
+/// Splits the tokens into ranges of about the same length that start at items.
+///
+/// The last range has the EOF token, so that its chunk is parsed just like the end of the file.
+fn chunk_bounds(item_starts: &[usize], len: usize, chunk_count: usize) -> Vec<(usize, usize)> {
+    let target_len = (len / chunk_count.max(1)).max(1);
+
+    let mut bounds = Vec::with_capacity(chunk_count);
+    let mut start = 0;
+    while start < len {
+        let target = start + target_len;
+        let next_start = item_starts[item_starts.partition_point(|&idx| idx < target)..].first();
+        let end = match next_start {
+            Some(&idx) if bounds.len() + 1 < chunk_count => idx,
+            _ => len,
+        };
+
+        bounds.push((start, end));
+        start = end;
+    }
+    bounds
+}
+
+/// Appends the nodes of a chunk that starts at the given token, pointing them to where
+/// their children end up.
+///
+/// Chunks are appended in order, so the nodes end up where parsing the whole file would
+/// have put them.
+fn append_chunk(ast: &mut Ast, chunk: Ast, token_start: usize) {
+    let o = Offsets::of(ast, token_start);
+
+    for (id, &item) in chunk.items.iter() {
+        ast.items.alloc(o.item(item), chunk.items.span(id));
+    }
+    for (id, &stmt) in chunk.stmts.iter() {
+        ast.stmts.alloc(o.stmt(stmt), chunk.stmts.span(id));
+    }
+    for (id, &expr) in chunk.exprs.iter() {
+        ast.exprs.alloc(o.expr(expr), chunk.exprs.span(id));
+    }
+    for (id, &ty) in chunk.types.iter() {
+        ast.types.alloc(o.ty(ty), chunk.types.span(id));
+    }
+    for (id, &pattern) in chunk.patterns.iter() {
+        ast.patterns
+            .alloc(o.pattern(pattern), chunk.patterns.span(id));
+    }
+
+    let stmt_lists = relocated(&chunk.stmt_lists, |id| id.offset(o.stmts));
+    ast.stmt_lists.alloc(&stmt_lists);
+    let expr_lists = relocated(&chunk.expr_lists, |id| id.offset(o.exprs));
+    ast.expr_lists.alloc(&expr_lists);
+    let type_lists = relocated(&chunk.type_lists, |id| id.offset(o.types));
+    ast.type_lists.alloc(&type_lists);
+    let pattern_lists = relocated(&chunk.pattern_lists, |id| id.offset(o.patterns));
+    ast.pattern_lists.alloc(&pattern_lists);
+
+    ast.params
+        .alloc(&relocated(&chunk.params, |param| o.param(param)));
+    let closure_params = relocated(&chunk.closure_params, |param| o.closure_param(param));
+    ast.closure_params.alloc(&closure_params);
+    ast.fields
+        .alloc(&relocated(&chunk.fields, |field| o.field(field)));
+    ast.field_inits
+        .alloc(&relocated(&chunk.field_inits, |init| o.field_init(init)));
+    ast.variants
+        .alloc(&relocated(&chunk.variants, |variant| o.variant(variant)));
+    ast.arms.alloc(&relocated(&chunk.arms, |arm| o.arm(arm)));
+    ast.holes
+        .alloc(&relocated(&chunk.holes, |hole| o.hole(hole)));
+    ast.attrs
+        .alloc(&relocated(&chunk.attrs, |attr| o.attr(attr)));
+
+    // spans are offsets in the code, which are the same for every chunk
+    ast.captures.alloc(chunk.captures.as_slice());
+    ast.generics.alloc(chunk.generics.as_slice());
+    ast.paths.alloc(chunk.paths.as_slice());
+    ast.string_parts.alloc(chunk.string_parts.as_slice());
+
+    for &(stmt, attrs) in chunk.stmt_attrs.iter() {
+        (ast.stmt_attrs).add((stmt.offset(o.stmts), attrs.offset(o.attrs)));
+    }
+    // only the first chunk starts at the start of the file, where file attributes are
+    if !chunk.file_attrs.is_empty() {
+        ast.file_attrs = chunk.file_attrs.offset(o.attrs);
+    }
+}
+
+/// Elements of every slice of an arena, with their handles pointing to where they end up.
+fn relocated<T: Copy>(arena: &SliceArena<T>, relocate: impl Fn(T) -> T) -> Vec<T> {
+    arena
+        .as_slice()
+        .iter()
+        .map(|&element| relocate(element))
+        .collect()
+}
+
+/// Numbers of nodes and elements that the AST has before a chunk is appended to it,
+/// by which the handles of the chunk are offset.
+struct Offsets {
+    stmts: usize,
+    exprs: usize,
+    types: usize,
+    patterns: usize,
+    stmt_lists: usize,
+    expr_lists: usize,
+    params: usize,
+    closure_params: usize,
+    captures: usize,
+    fields: usize,
+    field_inits: usize,
+    variants: usize,
+    arms: usize,
+    generics: usize,
+    type_lists: usize,
+    paths: usize,
+    pattern_lists: usize,
+    string_parts: usize,
+    holes: usize,
+    attrs: usize,
+    /// Index of the first token of the chunk, as attribute arguments are token indices
+    tokens: usize,
+}
+
+impl Offsets {
+    fn of(ast: &Ast, token_start: usize) -> Self {
+        Self {
+            stmts: ast.stmts.len(),
+            exprs: ast.exprs.len(),
+            types: ast.types.len(),
+            patterns: ast.patterns.len(),
+            stmt_lists: ast.stmt_lists.len(),
+            expr_lists: ast.expr_lists.len(),
+            params: ast.params.len(),
+            closure_params: ast.closure_params.len(),
+            captures: ast.captures.len(),
+            fields: ast.fields.len(),
+            field_inits: ast.field_inits.len(),
+            variants: ast.variants.len(),
+            arms: ast.arms.len(),
+            generics: ast.generics.len(),
+            type_lists: ast.type_lists.len(),
+            paths: ast.paths.len(),
+            pattern_lists: ast.pattern_lists.len(),
+            string_parts: ast.string_parts.len(),
+            holes: ast.holes.len(),
+            attrs: ast.attrs.len(),
+            tokens: token_start,
+        }
+    }
+
+    fn item(&self, item: Item) -> Item {
+        let kind = match item.kind {
+            ItemKind::Fn { params, ret, body } => ItemKind::Fn {
+                params: params.offset(self.params),
+                ret: ret.map(|ty| ty.offset(self.types)),
+                body: body.offset(self.exprs),
+            },
+            ItemKind::Struct {
+                packed,
+                repr,
+                fields,
+            } => ItemKind::Struct {
+                packed,
+                repr: repr.map(|ty| ty.offset(self.types)),
+                fields: fields.offset(self.fields),
+            },
+            ItemKind::Enum { repr, variants } => ItemKind::Enum {
+                repr: repr.map(|ty| ty.offset(self.types)),
+                variants: variants.offset(self.variants),
+            },
+            ItemKind::Union { fields } => ItemKind::Union {
+                fields: fields.offset(self.fields),
+            },
+            ItemKind::Const { ty, value } => ItemKind::Const {
+                ty: ty.map(|ty| ty.offset(self.types)),
+                value: value.offset(self.exprs),
+            },
+            ItemKind::Static { ty, value } => ItemKind::Static {
+                ty: ty.map(|ty| ty.offset(self.types)),
+                value: value.offset(self.exprs),
+            },
+            ItemKind::Use { path } => ItemKind::Use {
+                path: path.offset(self.paths),
+            },
+            ItemKind::Error => ItemKind::Error,
+        };
+        Item {
+            attrs: item.attrs.offset(self.attrs),
+            generics: item.generics.offset(self.generics),
+            kind,
+            ..item
+        }
+    }
+
+    fn stmt(&self, stmt: Stmt) -> Stmt {
+        match stmt {
+            Stmt::Let {
+                access,
+                pattern,
+                ty,
+                value,
+            } => Stmt::Let {
+                access,
+                pattern: pattern.offset(self.patterns),
+                ty: ty.map(|ty| ty.offset(self.types)),
+                value: value.offset(self.exprs),
+            },
+            Stmt::Assign { target, op, value } => Stmt::Assign {
+                target: target.offset(self.exprs),
+                op,
+                value: value.offset(self.exprs),
+            },
+            Stmt::Expr(expr) => Stmt::Expr(expr.offset(self.exprs)),
+            Stmt::Semi(expr) => Stmt::Semi(expr.offset(self.exprs)),
+            Stmt::Defer(expr) => Stmt::Defer(expr.offset(self.exprs)),
+            Stmt::Error => Stmt::Error,
+        }
+    }
+
+    fn expr(&self, expr: Expr) -> Expr {
+        let e = |id: ExprId| id.offset(self.exprs);
+        match expr {
+            Expr::Ident | Expr::Lit(_) | Expr::Placeholder | Expr::Continue { .. } => expr,
+            Expr::InterpolatedString { parts, holes } => Expr::InterpolatedString {
+                parts: parts.offset(self.string_parts),
+                holes: holes.offset(self.holes),
+            },
+            Expr::Block(block) => Expr::Block(self.block(block)),
+            Expr::Call { callee, args } => Expr::Call {
+                callee: e(callee),
+                args: args.offset(self.expr_lists),
+            },
+            Expr::Struct { ty, fields, base } => Expr::Struct {
+                ty: ty.offset(self.types),
+                fields: fields.offset(self.field_inits),
+                base: base.map(e),
+            },
+            Expr::Field { expr, name } => Expr::Field {
+                expr: e(expr),
+                name,
+            },
+            Expr::MethodCall { method, args } => Expr::MethodCall {
+                method,
+                args: args.offset(self.expr_lists),
+            },
+            Expr::Closure {
+                captures,
+                params,
+                ret,
+                body,
+            } => Expr::Closure {
+                captures: captures.map(|captures| captures.offset(self.captures)),
+                params: params.offset(self.closure_params),
+                ret: ret.map(|ty| ty.offset(self.types)),
+                body: e(body),
+            },
+            Expr::TupleField { expr, index } => Expr::TupleField {
+                expr: e(expr),
+                index,
+            },
+            Expr::Array(elements) => Expr::Array(elements.offset(self.expr_lists)),
+            Expr::Tuple(elements) => Expr::Tuple(elements.offset(self.expr_lists)),
+            Expr::ArrayRepeat { value, len } => Expr::ArrayRepeat {
+                value: e(value),
+                len: e(len),
+            },
+            Expr::Index { expr, index } => Expr::Index {
+                expr: e(expr),
+                index: e(index),
+            },
+            Expr::Unary { op, expr } => Expr::Unary { op, expr: e(expr) },
+            Expr::Binary { op, lhs, rhs } => Expr::Binary {
+                op,
+                lhs: e(lhs),
+                rhs: e(rhs),
+            },
+            Expr::Section { op, rhs } => Expr::Section { op, rhs: e(rhs) },
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => Expr::If {
+                cond: e(cond),
+                then: e(then),
+                otherwise: otherwise.map(e),
+            },
+            Expr::While { label, cond, body } => Expr::While {
+                label,
+                cond: e(cond),
+                body: e(body),
+            },
+            Expr::DoWhile { label, body, cond } => Expr::DoWhile {
+                label,
+                body: e(body),
+                cond: e(cond),
+            },
+            Expr::Loop { label, body } => Expr::Loop {
+                label,
+                body: e(body),
+            },
+            Expr::For {
+                label,
+                pattern,
+                index,
+                iter,
+                body,
+            } => Expr::For {
+                label,
+                pattern: pattern.offset(self.patterns),
+                index: index.map(|index| index.offset(self.patterns)),
+                iter: match iter {
+                    ForIter::Range { start, end } => ForIter::Range {
+                        start: e(start),
+                        end: e(end),
+                    },
+                    ForIter::Iter(iter) => ForIter::Iter(e(iter)),
+                },
+                body: e(body),
+            },
+            Expr::Match { scrutinee, arms } => Expr::Match {
+                scrutinee: e(scrutinee),
+                arms: arms.offset(self.arms),
+            },
+            Expr::Break { label, value } => Expr::Break {
+                label,
+                value: value.map(e),
+            },
+            Expr::Return { value } => Expr::Return {
+                value: value.map(e),
+            },
+        }
+    }
+
+    fn block(&self, block: Block) -> Block {
+        Block {
+            stmts: block.stmts.offset(self.stmt_lists),
+            defers: block.defers.offset(self.stmt_lists),
+            tail: block.tail.map(|tail| tail.offset(self.exprs)),
+        }
+    }
+
+    fn ty(&self, ty: Type) -> Type {
+        let t = |id: TypeId| id.offset(self.types);
+        match ty {
+            Type::Named => ty,
+            Type::Generic { base, args } => Type::Generic {
+                base: t(base),
+                args: args.offset(self.type_lists),
+            },
+            Type::Array { elem, len } => Type::Array {
+                elem: t(elem),
+                len: len.offset(self.exprs),
+            },
+            Type::Slice(elem) => Type::Slice(t(elem)),
+            Type::Pointer(pointee) => Type::Pointer(t(pointee)),
+            Type::Ref(referent) => Type::Ref(t(referent)),
+            Type::Optional(inner) => Type::Optional(t(inner)),
+            Type::Fn { params, ret } => Type::Fn {
+                params: params.offset(self.type_lists),
+                ret: ret.map(t),
+            },
+            Type::Tuple(elements) => Type::Tuple(elements.offset(self.type_lists)),
+        }
+    }
+
+    fn pattern(&self, pattern: Pattern) -> Pattern {
+        match pattern {
+            Pattern::Ident | Pattern::Wildcard | Pattern::Lit(_) => pattern,
+            Pattern::Variant { ty, name, payload } => Pattern::Variant {
+                ty: ty.map(|ty| ty.offset(self.types)),
+                name,
+                payload: payload.map(|payload| payload.offset(self.patterns)),
+            },
+            Pattern::Or(alternatives) => Pattern::Or(alternatives.offset(self.pattern_lists)),
+        }
+    }
+
+    fn param(&self, param: Param) -> Param {
+        Param {
+            pattern: param.pattern.offset(self.patterns),
+            ty: param.ty.offset(self.types),
+        }
+    }
+
+    fn closure_param(&self, param: ClosureParam) -> ClosureParam {
+        ClosureParam {
+            pattern: param.pattern.offset(self.patterns),
+            ty: param.ty.map(|ty| ty.offset(self.types)),
+        }
+    }
+
+    fn field(&self, field: Field) -> Field {
+        Field {
+            attrs: field.attrs.offset(self.attrs),
+            ty: field.ty.offset(self.types),
+            ..field
+        }
+    }
+
+    fn field_init(&self, init: FieldInit) -> FieldInit {
+        FieldInit {
+            value: init.value.offset(self.exprs),
+            ..init
+        }
+    }
+
+    fn variant(&self, variant: Variant) -> Variant {
+        Variant {
+            ty: variant.ty.map(|ty| ty.offset(self.types)),
+            discriminant: (variant.discriminant).map(|expr| expr.offset(self.exprs)),
+            ..variant
+        }
+    }
+
+    fn arm(&self, arm: Arm) -> Arm {
+        Arm {
+            pattern: arm.pattern.offset(self.patterns),
+            guard: arm.guard.map(|guard| guard.offset(self.exprs)),
+            body: arm.body.offset(self.exprs),
+        }
+    }
+
+    fn hole(&self, hole: Hole) -> Hole {
+        Hole {
+            expr: hole.expr.offset(self.exprs),
+            ..hole
+        }
+    }
+
+    fn attr(&self, attr: Attr) -> Attr {
+        let args = attr.args.map(|args| TokenRange {
+            start: args.start + self.tokens as u32,
+            ..args
+        });
+        Attr {
+            path: attr.path.offset(self.paths),
+            args,
+            ..attr
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_parallel {
+    use crate::{
+        ast::Ast,
+        lexer::lex,
+        parser::{
+            parallel::{chunk_bounds, item_starts, parse_in_chunks},
+            parse_file, parse_parallel,
+        },
+        source::FileId,
+    };
+
+    const SAMPLE: &str = include_str!("../../Cඞඞ.sus");
+
+    /// Everything in the AST, handles included.
+    fn summary(ast: &Ast) -> String {
+        let nodes = format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            ast.items, ast.stmts, ast.exprs, ast.types, ast.patterns,
+        );
+        let lists = format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            ast.stmt_lists.as_slice(),
+            ast.expr_lists.as_slice(),
+            ast.type_lists.as_slice(),
+            ast.pattern_lists.as_slice(),
+            ast.paths.as_slice(),
+            ast.string_parts.as_slice(),
+            ast.generics.as_slice(),
+        );
+        let elements = format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            ast.params.as_slice(),
+            ast.closure_params.as_slice(),
+            ast.captures.as_slice(),
+            ast.fields.as_slice(),
+            ast.field_inits.as_slice(),
+            ast.variants.as_slice(),
+            ast.arms.as_slice(),
+            ast.holes.as_slice(),
+        );
+        let attrs = format!(
+            "{:?}\n{:?}\n{:?}",
+            ast.attrs.as_slice(),
+            ast.file_attrs,
+            ast.stmt_attrs,
+        );
+        [nodes, lists, elements, attrs].join("\n")
+    }
+
+    fn check_chunks(code: &str) {
+        let tokens = lex(FileId::default(), code);
+        let (expected, expected_diagnostics) = parse_file(&tokens);
+        let (expected, expected_diagnostics) =
+            (summary(&expected), format!("{expected_diagnostics:?}"));
+
+        for chunk_count in 1..=9 {
+            let (ast, diagnostics) = parse_in_chunks(&tokens, chunk_count);
+            assert_eq!(summary(&ast), expected, "{chunk_count} chunks");
+            assert_eq!(
+                format!("{diagnostics:?}"),
+                expected_diagnostics,
+                "{chunk_count} chunks"
+            );
+        }
+    }
+
+    #[test]
+    fn same_as_parsing_everything() {
+        let items = "#[inline, repr(C)]\nf :: fn(x : i32) { #[cold] g(x); }\n\
+                     #[test] t :: fn() -> assert($\"{f(1)}\" == \"\");\n";
+        let code = format!(
+            "#![doc(\"file\")]\n{}",
+            (SAMPLE.to_owned() + items).repeat(4)
+        );
+        let (_, diagnostics) = parse_file(&lex(FileId::default(), &code));
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+
+        check_chunks(SAMPLE);
+        check_chunks(&code);
+    }
+
+    #[test]
+    fn errors_parse_on_one_thread() {
+        let code = "a :: fn() { b(; }\nc :: fn() {}\nd :: fn() -> e +;\nf :: fn() {}\n";
+        check_chunks(&code.repeat(3));
+    }
+
+    #[test]
+    fn bounds_start_at_items() {
+        let code =
+            "a :: fn() {}\nb :: fn() -> { c } + d;\n#[e] f :: struct { g : [u8; 2] }\nh :: fn() {}";
+        let tokens = lex(FileId::default(), code);
+        let starts = item_starts(&tokens);
+        let slices: Vec<_> = (starts.iter()).map(|&idx| tokens.span(idx).slice).collect();
+        assert_eq!(slices, ["b", "#[", "h"]);
+
+        let len = tokens.types.len();
+        assert_eq!(chunk_bounds(&starts, len, 1), [(0, len)]);
+        // the second half starts at the first item after the middle
+        let middle = *starts.iter().find(|&&idx| idx >= len / 2).unwrap();
+        assert_eq!(chunk_bounds(&starts, len, 2), [(0, middle), (middle, len)]);
+        assert_eq!(chunk_bounds(&starts, len, 9).len(), 4);
+    }
+
+    #[test]
+    fn large_file() {
+        let code = SAMPLE.repeat(200);
+        let tokens = lex(FileId::default(), &code);
+        let (ast, _) = parse_parallel(&tokens);
+        assert_eq!(summary(&ast), summary(&parse_file(&tokens).0));
+    }
+}
@@ -0,0 +1,432 @@
+//! Source files of a compilation, referred to by [`FileId`]s.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    ops::Deref,
+    path::Path,
+    str,
+};
+
+use crate::{
+    arena::{ArenaVec, FileMapping, GIB},
+    lexer::{self, LexError, Tokens},
+    memory::ArrayMemory,
+};
+
+/// Identifier of a file in a [`SourceMap`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(u32);
+
+impl FileId {
+    /// Index of the file in its source map.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A code file along with the data needed to report locations in it.
+#[derive(Debug)]
+pub struct SourceFile {
+    pub id: FileId,
+    pub path: String,
+    pub code: SourceCode,
+    /// Sorted list containing the position of all line breaks
+    /// (the position of the `\n` for `\r\n` line breaks)
+    pub line_breaks: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(id: FileId, path: String, code: SourceCode) -> Self {
+        let bytes = code.as_bytes();
+        let line_breaks = (bytes.iter().enumerate())
+            .filter(|&(i, &byte)| {
+                byte == b'\n' || (byte == b'\r' && bytes.get(i + 1) != Some(&b'\n'))
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        Self {
+            id,
+            path,
+            code,
+            line_breaks,
+        }
+    }
+
+    /// Line and column (in bytes) of a byte offset in the code.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let idx = self.line_breaks.partition_point(|&lb| lb < offset);
+        let line_start = match idx {
+            0 => 0,
+            _ => self.line_breaks[idx - 1] + 1,
+        };
+        (idx + 1, offset - line_start)
+    }
+
+    /// Code of a line (counted from 1), without its line break.
+    pub fn line(&self, line: usize) -> &str {
+        let start = match line - 1 {
+            0 => 0,
+            idx => self.line_breaks[idx - 1] + 1,
+        };
+        let end = self.line_breaks.get(line - 1).copied();
+        let code = &self.code[start..end.unwrap_or(self.code.len())];
+        code.strip_suffix('\r').unwrap_or(code)
+    }
+
+    /// Number of lines, an empty file having one.
+    pub fn line_count(&self) -> usize {
+        self.line_breaks.len() + 1
+    }
+}
+
+/// Code of a [`SourceFile`], either in a string or mapped from its file.
+pub enum SourceCode {
+    Owned(String),
+    /// Mapped code that is valid UTF-8, see [`SourceCode::mmap`]
+    Mapped(FileMapping),
+}
+
+/// How invalid UTF-8 is handled when loading code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Utf8Mode {
+    /// Invalid UTF-8 is an error
+    #[default]
+    Strict,
+    /// Invalid UTF-8 is replaced with U+FFFD, which copies the code into a string
+    Lossy,
+}
+
+impl SourceCode {
+    /// Reads the whole file into a string.
+    pub fn read(path: impl AsRef<Path>, utf8_mode: Utf8Mode) -> io::Result<Self> {
+        Self::read_file(&mut File::open(path)?, utf8_mode)
+    }
+
+    /// Maps the file in memory instead of reading it, so that even very large files
+    /// aren't copied. The code is still gone through once, to validate it as UTF-8.
+    ///
+    /// Files that can't be mapped are read instead: pipes and other files that aren't
+    /// regular files (like `/dev/stdin`), empty files, and any file on targets
+    /// that can't map files (like WebAssembly).
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to nor truncated while the code is around,
+    /// see [`FileMapping::new`].
+    pub unsafe fn mmap(path: impl AsRef<Path>, utf8_mode: Utf8Mode) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() == 0 {
+            return Self::read_file(&mut file, utf8_mode);
+        }
+
+        let mapping = match FileMapping::new(&file) {
+            Ok(mapping) => mapping,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                return Self::read_file(&mut file, utf8_mode);
+            }
+            Err(e) => return Err(e),
+        };
+
+        match (str::from_utf8(mapping.as_bytes()), utf8_mode) {
+            (Ok(_), _) => Ok(SourceCode::Mapped(mapping)),
+            (Err(_), Utf8Mode::Lossy) => {
+                let code = String::from_utf8_lossy(mapping.as_bytes());
+                Ok(SourceCode::Owned(code.into_owned()))
+            }
+            (Err(e), Utf8Mode::Strict) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    /// Reads the rest of the file into a string.
+    fn read_file(file: &mut File, utf8_mode: Utf8Mode) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        match (String::from_utf8(bytes), utf8_mode) {
+            (Ok(code), _) => Ok(SourceCode::Owned(code)),
+            (Err(e), Utf8Mode::Lossy) => {
+                let code = String::from_utf8_lossy(e.as_bytes());
+                Ok(SourceCode::Owned(code.into_owned()))
+            }
+            (Err(e), Utf8Mode::Strict) => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, e.utf8_error()))
+            }
+        }
+    }
+
+    /// Memory taken by the code. Mapped code isn't counted as committed,
+    /// as its pages belong to the page cache of the OS.
+    pub fn memory(&self) -> ArrayMemory {
+        match self {
+            SourceCode::Owned(code) => ArrayMemory::of_heap::<u8>(code.len(), code.capacity()),
+            SourceCode::Mapped(mapping) => ArrayMemory {
+                used: mapping.len(),
+                committed: 0,
+                reserved: mapping.len(),
+            },
+        }
+    }
+}
+
+impl Deref for SourceCode {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        match self {
+            SourceCode::Owned(code) => code,
+            // SAFETY: validated when mapped
+            SourceCode::Mapped(mapping) => unsafe { str::from_utf8_unchecked(mapping.as_bytes()) },
+        }
+    }
+}
+
+impl From<String> for SourceCode {
+    fn from(code: String) -> Self {
+        SourceCode::Owned(code)
+    }
+}
+
+impl From<&str> for SourceCode {
+    fn from(code: &str) -> Self {
+        SourceCode::Owned(code.to_string())
+    }
+}
+
+impl fmt::Debug for SourceCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceCode::Owned(code) => f.debug_tuple("Owned").field(code).finish(),
+            SourceCode::Mapped(mapping) => f.debug_tuple("Mapped").field(mapping).finish(),
+        }
+    }
+}
+
+/// Owner of every file of a compilation.
+///
+/// Files can be added while others are borrowed, so that tokens of
+/// different files can be around at the same time.
+pub struct SourceMap {
+    /// Files are boxed so that they never move, only their references being added
+    /// to the vector. They are freed when the source map is dropped.
+    files: ArenaVec<&'static SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self {
+            files: ArenaVec::new(GIB),
+        }
+    }
+
+    pub fn add(&self, path: impl Into<String>, code: impl Into<SourceCode>) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        let file = Box::new(SourceFile::new(id, path.into(), code.into()));
+        self.files.add(Box::leak(file));
+        id
+    }
+
+    /// Adds a file by reading it, see [`SourceCode::read`].
+    pub fn add_file(&self, path: impl Into<String>, utf8_mode: Utf8Mode) -> io::Result<FileId> {
+        let path = path.into();
+        let code = SourceCode::read(&path, utf8_mode)?;
+        Ok(self.add(path, code))
+    }
+
+    /// Adds a file by mapping it in memory, see [`SourceCode::mmap`].
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to nor truncated while the source map is around.
+    pub unsafe fn add_mmap(
+        &self,
+        path: impl Into<String>,
+        utf8_mode: Utf8Mode,
+    ) -> io::Result<FileId> {
+        let path = path.into();
+        let code = SourceCode::mmap(&path, utf8_mode)?;
+        Ok(self.add(path, code))
+    }
+
+    /// The file with the given ID, which must come from this source map.
+    #[inline]
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        self.files[id.index()]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &'_ SourceFile> {
+        self.files.iter().map(|file| -> &SourceFile { file })
+    }
+
+    pub fn lex(&self, id: FileId) -> Tokens<'_> {
+        lexer::lex(id, &self.file(id).code)
+    }
+
+    /// Memory taken by the files, including their code and line break tables.
+    pub fn memory(&self) -> ArrayMemory {
+        let files = self.files.iter().map(|file| {
+            let line_breaks = &file.line_breaks;
+            file.code.memory()
+                + ArrayMemory::of_heap::<usize>(line_breaks.len(), line_breaks.capacity())
+        });
+        let boxes = ArrayMemory::of_heap::<SourceFile>(self.files.len(), self.files.len());
+        ArrayMemory::of(&self.files) + boxes + files.sum()
+    }
+
+    /// Displays an error along with the path of its file and its character-based column.
+    pub fn display_error<'m>(
+        &'m self,
+        tokens: &'m Tokens,
+        error: &'m LexError,
+    ) -> impl fmt::Display + 'm {
+        DisplayError {
+            path: &self.file(error.file).path,
+            col: tokens.display_col(&error.span),
+            related: (error.related.as_ref()).map(|span| (span.line, tokens.display_col(span))),
+            error,
+        }
+    }
+}
+
+impl Drop for SourceMap {
+    fn drop(&mut self) {
+        for file in self.files.iter_mut() {
+            // SAFETY: files come from boxes, and nothing borrows the source map anymore
+            drop(unsafe { Box::from_raw(*file as *const SourceFile as *mut SourceFile) });
+        }
+    }
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct DisplayError<'m, 'a> {
+    path: &'m str,
+    col: usize,
+    /// Line and character-based column of the error's related span
+    related: Option<(usize, usize)>,
+    error: &'m LexError<'a>,
+}
+
+impl<'m, 'a> fmt::Display for DisplayError<'m, 'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (path, line, col) = (self.path, self.error.span.line, self.col);
+        write!(f, "{path}:{line}:{col}: {}", self.error.kind)?;
+        if let Some((line, col)) = self.related {
+            write!(f, " opened at {line}:{col}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests_source_map {
+    use std::{env, fs, io, process};
+
+    use crate::source::{SourceCode, SourceMap, Utf8Mode};
+
+    #[test]
+    fn multiple_files() {
+        let source_map = SourceMap::new();
+        let a = source_map.add("a.sus", "x := 'ab';");
+        let a_tokens = source_map.lex(a);
+
+        // adding files doesn't invalidate the tokens of other files
+        let b = source_map.add("dir/b.sus", "\n  ඞ y;\r\n§");
+        let b_tokens = source_map.lex(b);
+
+        assert_ne!(a, b);
+        assert_eq!((a_tokens.file, b_tokens.file), (a, b));
+        assert_eq!(source_map.files().count(), 2);
+        assert!(source_map.memory().used >= "x := 'ab';\n  ඞ y;\r\n§".len());
+
+        let errors: Vec<_> = (a_tokens.errors.iter().map(|e| (&a_tokens, e)))
+            .chain(b_tokens.errors.iter().map(|e| (&b_tokens, e)))
+            .map(|(tokens, error)| source_map.display_error(tokens, error).to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "a.sus:1:5: Char literal must contain exactly one character",
+                "dir/b.sus:2:2: Cannot parse token",
+                "dir/b.sus:3:0: Cannot parse token",
+            ]
+        );
+    }
+
+    #[test]
+    fn line_breaks_match_the_lexer() {
+        let source_map = SourceMap::new();
+        let id = source_map.add("test", "a\r\nb\rc\n\nd");
+        let tokens = source_map.lex(id);
+
+        let file = source_map.file(id);
+        assert_eq!(file.line_breaks, tokens.line_breaks.as_slice());
+        assert_eq!(file.line_col(8), (5, 0));
+        assert_eq!(file.line_col(5), (3, 0));
+
+        let lines: Vec<_> = (1..=file.line_count())
+            .map(|line| file.line(line))
+            .collect();
+        assert_eq!(lines, ["a", "b", "c", "", "d"]);
+    }
+
+    #[test]
+    fn mapped_files() {
+        let dir = env::temp_dir().join(format!("csussus-mmap-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (valid, invalid, empty) = (dir.join("a.sus"), dir.join("b.sus"), dir.join("c.sus"));
+        fs::write(&valid, "ඞ := 1;\n$").unwrap();
+        fs::write(&invalid, b"a \xff b").unwrap();
+        fs::write(&empty, "").unwrap();
+
+        // SAFETY: the files are only written before being mapped
+        let mmap = |path: &_, utf8_mode| unsafe { SourceCode::mmap(path, utf8_mode) };
+        let source_map = SourceMap::new();
+        let a = unsafe { source_map.add_mmap(valid.to_str().unwrap(), Utf8Mode::Strict) };
+        let a = a.unwrap();
+        assert!(matches!(source_map.file(a).code, SourceCode::Mapped(_)));
+        assert_eq!(&*source_map.file(a).code, "ඞ := 1;\n$");
+        assert_eq!(source_map.file(a).line_breaks, [9]);
+        let tokens = source_map.lex(a);
+        assert_eq!((tokens.types.len(), tokens.errors.len()), (7, 2));
+        assert_eq!(source_map.file(a).code.memory().committed, 0);
+
+        let error = mmap(&invalid, Utf8Mode::Strict).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let code = mmap(&invalid, Utf8Mode::Lossy).unwrap();
+        assert_eq!(&*code, "a \u{fffd} b");
+
+        assert_eq!(&*mmap(&empty, Utf8Mode::Strict).unwrap(), "");
+        assert!(mmap(&dir.join("none.sus"), Utf8Mode::Strict).is_err());
+
+        let b = source_map.add_file(invalid.to_str().unwrap(), Utf8Mode::Lossy);
+        let b = b.unwrap();
+        assert!(matches!(source_map.file(b).code, SourceCode::Owned(_)));
+        assert_eq!(&*source_map.file(b).code, "a \u{fffd} b");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn pipes() {
+        use std::{io::Write, os::fd::AsRawFd};
+
+        // pipes claim to be empty, so they are read instead of mapped
+        let (reader, mut writer) = io::pipe().unwrap();
+        writer.write_all("a := 1;".as_bytes()).unwrap();
+        drop(writer);
+        let path = format!("/proc/self/fd/{}", reader.as_raw_fd());
+        // SAFETY: pipes aren't mapped
+        let code = unsafe { SourceCode::mmap(&path, Utf8Mode::Strict) }.unwrap();
+        assert!(matches!(code, SourceCode::Owned(_)));
+        assert_eq!(&*code, "a := 1;");
+    }
+}
@@ -0,0 +1,151 @@
+//! Styling of terminal output with ANSI escape sequences, shared by everything that
+//! writes colored text, like [rendered diagnostics](crate::diag::Renderer)
+//! and [highlighted code](crate::lexer::Tokens::write_ansi).
+//!
+//! Whether to color is decided once, with a [`ColorChoice`], and then carried around
+//! in a [`Painter`], so that colored and plain output are written by the same code.
+
+use std::{
+    env,
+    fmt::{self, Display},
+    io::IsTerminal,
+};
+
+use crate::{diag::Severity, lexer::Highlight};
+
+/// Whether output is colored, as chosen with `--color`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorChoice {
+    Always,
+    /// Colored if written to a terminal, unless the `NO_COLOR` variable is set
+    #[default]
+    Auto,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "always" => Some(ColorChoice::Always),
+            "auto" => Some(ColorChoice::Auto),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    /// Painter of output written to the given stream.
+    pub fn painter(self, stream: &impl IsTerminal) -> Painter {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|var| !var.is_empty());
+        Painter {
+            enabled: self.colors(stream.is_terminal(), no_color),
+        }
+    }
+
+    /// Whether to color, given whether the output is a terminal
+    /// and whether `NO_COLOR` is set to something (see <https://no-color.org>).
+    pub fn colors(self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Auto => is_terminal && !no_color,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Role of a piece of text, which is styled the same way in all outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Style {
+    /// Severity of a diagnostic, along with its primary underline
+    Severity(Severity),
+    /// Main message of a diagnostic, and the name of its notes
+    Emphasis,
+    /// Underlines and messages of labels
+    Secondary,
+    /// Line numbers and the margin of quoted code
+    Gutter,
+    Help,
+    Code(Highlight),
+}
+
+impl Style {
+    /// SGR parameters of the style, `None` for plain text.
+    pub const fn ansi_style(self) -> Option<&'static str> {
+        match self {
+            Style::Severity(Severity::Error) => Some("1;31"),
+            Style::Severity(Severity::Warning) => Some("1;33"),
+            Style::Severity(Severity::Note) => Some("1;32"),
+            Style::Emphasis => Some("1"),
+            Style::Secondary | Style::Gutter => Some("1;34"),
+            Style::Help => Some("1;36"),
+            Style::Code(highlight) => highlight.ansi_style(),
+        }
+    }
+}
+
+/// Wraps text in ANSI escape sequences when colors are enabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Painter {
+    pub enabled: bool,
+}
+
+impl Painter {
+    pub const PLAIN: Self = Self { enabled: false };
+    pub const COLORED: Self = Self { enabled: true };
+
+    /// Displays the text in the style. Line breaks should be left out of it,
+    /// as the style would otherwise leak into pagers.
+    pub fn paint<T: Display>(self, style: Style, text: T) -> Painted<T> {
+        Painted {
+            sgr: style.ansi_style().filter(|_| self.enabled),
+            text,
+        }
+    }
+}
+
+/// Text displayed in a style, see [`Painter::paint`].
+pub struct Painted<T> {
+    sgr: Option<&'static str>,
+    text: T,
+}
+
+impl<T: Display> Display for Painted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sgr {
+            Some(sgr) => write!(f, "\x1b[{sgr}m{}\x1b[0m", self.text),
+            None => self.text.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_style {
+    use crate::{
+        diag::Severity,
+        lexer::Highlight,
+        style::{ColorChoice, Painter, Style},
+    };
+
+    #[test]
+    fn choices() {
+        let choices = [ColorChoice::Always, ColorChoice::Auto, ColorChoice::Never];
+        let colors = |is_terminal, no_color| choices.map(|c| c.colors(is_terminal, no_color));
+        assert_eq!(colors(true, false), [true, true, false]);
+        assert_eq!(colors(true, true), [true, false, false]);
+        assert_eq!(colors(false, false), [true, false, false]);
+        assert_eq!(ColorChoice::from_name("never"), Some(ColorChoice::Never));
+        assert_eq!(ColorChoice::from_name("yes"), None);
+    }
+
+    #[test]
+    fn painting() {
+        let error = Style::Severity(Severity::Error);
+        assert_eq!(
+            Painter::COLORED.paint(error, "e").to_string(),
+            "\x1b[1;31me\x1b[0m"
+        );
+        assert_eq!(Painter::PLAIN.paint(error, "e").to_string(), "e");
+        // unstyled code is left as is
+        let ident = Style::Code(Highlight::Ident);
+        assert_eq!(Painter::COLORED.paint(ident, 1).to_string(), "1");
+    }
+}